@@ -0,0 +1,79 @@
+use bytes::Bytes;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+
+use http_body_util::{BodyExt, Empty};
+
+use hyper::{header, Request};
+
+use hyper_util::rt::{TokioExecutor, TokioIo};
+
+use rhs::test_util::{serve_duplex_connection, HelloHandler};
+
+fn bench_http1(c: &mut Criterion) {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+
+    let mut sender = runtime.block_on(async {
+        let client_stream = serve_duplex_connection(Box::new(HelloHandler)).await;
+
+        let (sender, client_connection) =
+            hyper::client::conn::http1::handshake(TokioIo::new(client_stream))
+                .await
+                .unwrap();
+        tokio::spawn(client_connection);
+
+        sender
+    });
+
+    c.bench_function("connection_http1_request", |b| {
+        b.iter(|| {
+            runtime.block_on(async {
+                sender.ready().await.unwrap();
+
+                let request = Request::builder()
+                    .uri("/hello")
+                    .header(header::HOST, "localhost")
+                    .body(Empty::<Bytes>::new())
+                    .unwrap();
+
+                let response = sender.send_request(request).await.unwrap();
+                response.into_body().collect().await.unwrap().to_bytes()
+            })
+        })
+    });
+}
+
+fn bench_http2(c: &mut Criterion) {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+
+    let mut sender = runtime.block_on(async {
+        let client_stream = serve_duplex_connection(Box::new(HelloHandler)).await;
+
+        let (sender, client_connection) = hyper::client::conn::http2::handshake(
+            TokioExecutor::new(),
+            TokioIo::new(client_stream),
+        )
+        .await
+        .unwrap();
+        tokio::spawn(client_connection);
+
+        sender
+    });
+
+    c.bench_function("connection_http2_request", |b| {
+        b.iter(|| {
+            runtime.block_on(async {
+                let request = Request::builder()
+                    .uri("http://localhost/hello")
+                    .body(Empty::<Bytes>::new())
+                    .unwrap();
+
+                let response = sender.send_request(request).await.unwrap();
+                response.into_body().collect().await.unwrap().to_bytes()
+            })
+        })
+    });
+}
+
+criterion_group!(benches, bench_http1, bench_http2);
+criterion_main!(benches);
@@ -0,0 +1,60 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+
+use serde::Serialize;
+
+use std::hint::black_box;
+
+use rhs::test_util::{
+    build_json_response, format_json_body, CacheControl, JsonOutputOptions, QueryParams,
+};
+
+#[derive(Serialize)]
+struct ConnectionDTO {
+    connection_id: usize,
+    server_socket_type: &'static str,
+    peer_addr: String,
+    num_requests: usize,
+    age: String,
+}
+
+fn connections() -> Vec<ConnectionDTO> {
+    (0..100)
+        .map(|i| ConnectionDTO {
+            connection_id: i,
+            server_socket_type: "TCP",
+            peer_addr: format!("192.0.2.{}:{}", i, 40000 + i),
+            num_requests: i * 7,
+            age: format!("{}s", i * 3),
+        })
+        .collect()
+}
+
+fn bench_build_json_response(c: &mut Criterion) {
+    let connections = connections();
+
+    c.bench_function("build_json_response", |b| {
+        b.iter(|| build_json_response(black_box(&connections), CacheControl::NoCache))
+    });
+}
+
+fn bench_format_json_body(c: &mut Criterion) {
+    let json_body = serde_json::to_vec(&connections()).unwrap();
+
+    let mut group = c.benchmark_group("format_json_body");
+
+    for (name, query) in [
+        ("pretty", "pretty=true"),
+        ("fields", "fields=connection_id,num_requests"),
+    ] {
+        let options = JsonOutputOptions::from(&QueryParams::parse(Some(query)));
+
+        group.bench_function(name, |b| {
+            b.iter(|| format_json_body(black_box(&json_body), &options).unwrap())
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_build_json_response, bench_format_json_body);
+criterion_main!(benches);
@@ -0,0 +1,59 @@
+use bytes::Bytes;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+
+use http_body_util::{BodyExt, Empty};
+
+use hyper::{header, Request};
+
+use hyper_util::rt::TokioIo;
+
+use rhs::test_util::{hello_router, serve_duplex_connection};
+
+// Requests go through a duplex connection, compare against the connection bench
+// for the router's share.
+fn bench_router(c: &mut Criterion) {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+
+    let mut group = c.benchmark_group("router");
+
+    for num_routes in [10, 100] {
+        let mut sender = runtime.block_on(async {
+            let router = hello_router(num_routes).unwrap();
+
+            let client_stream = serve_duplex_connection(Box::new(router)).await;
+
+            let (sender, client_connection) =
+                hyper::client::conn::http1::handshake(TokioIo::new(client_stream))
+                    .await
+                    .unwrap();
+            tokio::spawn(client_connection);
+
+            sender
+        });
+
+        for (name, path) in [("matched", "/api/v1/route_5"), ("unmatched", "/index.html")] {
+            group.bench_with_input(BenchmarkId::new(name, num_routes), &path, |b, path| {
+                b.iter(|| {
+                    runtime.block_on(async {
+                        sender.ready().await.unwrap();
+
+                        let request = Request::builder()
+                            .uri(*path)
+                            .header(header::HOST, "localhost")
+                            .body(Empty::<Bytes>::new())
+                            .unwrap();
+
+                        let response = sender.send_request(request).await.unwrap();
+                        response.into_body().collect().await.unwrap().to_bytes()
+                    })
+                })
+            });
+        }
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_router);
+criterion_main!(benches);
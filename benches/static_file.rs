@@ -0,0 +1,80 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+
+use hyper_staticfile::{AcceptEncoding, ResolveResult, Resolver};
+
+use std::path::PathBuf;
+
+use rhs::test_util::{
+    create_rules_service, ChunkedFileOpener, StaticFileConfiguration, DEFAULT_READ_CHUNK_SIZE,
+};
+
+const FILES: [&str; 3] = ["error.html", "vnstat/vnstat.png", "js/app.js"];
+
+fn create_root() -> PathBuf {
+    let root = std::env::temp_dir().join(format!("rhs-bench-static-{}", std::process::id()));
+
+    for file in FILES {
+        let path = root.join(file);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, file).unwrap();
+    }
+
+    root
+}
+
+fn static_file_configuration(root: &str) -> StaticFileConfiguration {
+    toml::from_str(&format!(
+        r#"
+root = "{}"
+precompressed = {{ br = false, gz = false }}
+client_error_page_path = "/error.html"
+cache_rules = [
+    {{ path_regex = '^/?error\.html', rule_type = "FIXED_TIME", duration = "5min" }},
+    {{ path_regex = '^/?vnstat/.*\.png$', rule_type = "MOD_TIME_PLUS_DELTA", duration = "15min" }},
+    {{ path_regex = '.*', rule_type = "FIXED_TIME", duration = "1hour", immutable = true }},
+]
+"#,
+        root
+    ))
+    .unwrap()
+}
+
+// Resolves each file and finds its cache rule, like the static file handler.
+fn bench_resolve_with_cache_rules(c: &mut Criterion) {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+
+    let root = create_root();
+
+    let rules_service =
+        create_rules_service(&static_file_configuration(root.to_str().unwrap())).unwrap();
+
+    let resolver = Resolver::with_opener(ChunkedFileOpener::new(
+        &root,
+        DEFAULT_READ_CHUNK_SIZE,
+        false,
+    ));
+
+    c.bench_function("static_file_resolve_with_cache_rules", |b| {
+        b.iter(|| {
+            runtime.block_on(async {
+                for file in FILES {
+                    let resolve_result = resolver
+                        .resolve_path(file, AcceptEncoding::none())
+                        .await
+                        .unwrap();
+
+                    let ResolveResult::Found(resolved_file) = resolve_result else {
+                        panic!("{} not found", file);
+                    };
+
+                    assert!(rules_service.find_cache_rule(&resolved_file).is_some());
+                }
+            })
+        })
+    });
+
+    std::fs::remove_dir_all(root).unwrap();
+}
+
+criterion_group!(benches, bench_resolve_with_cache_rules);
+criterion_main!(benches);
@@ -8,6 +8,16 @@ fn main() -> Result<(), Box<dyn Error>> {
         .all_cargo()
         .all_rustc()
         .all_sysinfo()
+        .git_sha(false)
+        .git_dirty(false)
         .emit()?;
+
+    // Separated by 0x1f, empty when no flags were set.
+    let rustflags = std::env::var("CARGO_ENCODED_RUSTFLAGS").unwrap_or_default();
+    println!(
+        "cargo:rustc-env=RHS_RUSTFLAGS={}",
+        rustflags.replace('\x1f', " ")
+    );
+
     Ok(())
 }
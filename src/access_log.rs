@@ -0,0 +1,588 @@
+use anyhow::Context as _;
+
+use bytes::Bytes;
+
+use chrono::{DateTime, Local};
+
+use hyper::{
+    body::{Body, Frame, SizeHint},
+    http::{Method, StatusCode, Uri, Version},
+};
+
+use serde::Serialize;
+
+use tokio::time::{Duration, Instant};
+
+use tracing::{debug, warn, Level};
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufWriter, Write},
+    net::IpAddr,
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{self, Receiver, SyncSender, TrySendError},
+        Arc, OnceLock,
+    },
+    task::{Context, Poll},
+    time::SystemTime,
+};
+
+use crate::{
+    config::{AccessLogConfiguration, AccessLogFormat},
+    connection::ConnectionID,
+    request::RequestID,
+    response::{DenyReason, ResponseBody, ResponseBodyError},
+    tracing_config::SyslogSender,
+};
+
+// Records queued for the writer thread, more are dropped.
+const QUEUE_CAPACITY: usize = 4096;
+
+#[derive(Debug)]
+pub struct AccessLogRecord {
+    pub time: SystemTime,
+    pub client: Option<IpAddr>,
+    pub method: Method,
+    pub uri: Uri,
+    pub version: Version,
+    pub connection_id: ConnectionID,
+    pub request_id: RequestID,
+    pub external_request_id: Option<Arc<str>>,
+    pub status: StatusCode,
+    pub deny_reason: Option<DenyReason>,
+    // set when the response body is finished or dropped
+    pub response_bytes: u64,
+    pub duration: Duration,
+}
+
+impl AccessLogRecord {
+    fn path_and_query(&self) -> &str {
+        self.uri
+            .path_and_query()
+            .map_or("/", |path_and_query| path_and_query.as_str())
+    }
+}
+
+// Common Log Format followed by conn_id, req_id, xreq_id and micros.
+fn format_common(record: &AccessLogRecord) -> String {
+    let time = DateTime::<Local>::from(record.time).format("%d/%b/%Y:%H:%M:%S %z");
+
+    let client = record
+        .client
+        .map_or_else(|| "-".to_owned(), |client| client.to_string());
+
+    format!(
+        "{} - - [{}] \"{} {} {:?}\" {} {} conn_id={} req_id={} xreq_id={} micros={}",
+        client,
+        time,
+        record.method,
+        record.path_and_query(),
+        record.version,
+        record.status.as_u16(),
+        record.response_bytes,
+        record.connection_id.as_usize(),
+        record.request_id.as_usize(),
+        record.external_request_id.as_deref().unwrap_or("-"),
+        record.duration.as_micros(),
+    )
+}
+
+#[derive(Serialize)]
+struct AccessLogJsonLine<'a> {
+    time: String,
+    client: Option<IpAddr>,
+    method: &'a str,
+    uri: &'a str,
+    version: String,
+    status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    deny_reason: Option<DenyReason>,
+    bytes: u64,
+    micros: u128,
+    conn_id: usize,
+    req_id: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    xreq_id: Option<&'a str>,
+}
+
+fn format_json(record: &AccessLogRecord) -> String {
+    let line = AccessLogJsonLine {
+        time: DateTime::<Local>::from(record.time).to_rfc3339(),
+        client: record.client,
+        method: record.method.as_str(),
+        uri: record.path_and_query(),
+        version: format!("{:?}", record.version),
+        status: record.status.as_u16(),
+        deny_reason: record.deny_reason,
+        bytes: record.response_bytes,
+        micros: record.duration.as_micros(),
+        conn_id: record.connection_id.as_usize(),
+        req_id: record.request_id.as_usize(),
+        xreq_id: record.external_request_id.as_deref(),
+    };
+
+    serde_json::to_string(&line).unwrap_or_default()
+}
+
+struct AccessLogFile {
+    path: PathBuf,
+    max_file_size: Option<u64>,
+    max_files: usize,
+    writer: BufWriter<File>,
+    file_size: u64,
+}
+
+fn open_append(path: &Path) -> std::io::Result<(BufWriter<File>, u64)> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+
+    let file_size = file.metadata()?.len();
+
+    Ok((BufWriter::new(file), file_size))
+}
+
+fn rotated_path(path: &Path, index: usize) -> PathBuf {
+    let mut rotated = path.as_os_str().to_owned();
+    rotated.push(format!(".{}", index));
+    PathBuf::from(rotated)
+}
+
+impl AccessLogFile {
+    // path.1 is the newest rotated file, path.max_files the oldest.
+    fn rotate(&mut self) -> std::io::Result<()> {
+        self.writer.flush()?;
+
+        for index in (1..self.max_files).rev() {
+            let from = rotated_path(&self.path, index);
+            if from.exists() {
+                std::fs::rename(&from, rotated_path(&self.path, index + 1))?;
+            }
+        }
+
+        std::fs::rename(&self.path, rotated_path(&self.path, 1))?;
+
+        (self.writer, self.file_size) = open_append(&self.path)?;
+
+        Ok(())
+    }
+
+    fn write_line(&mut self, line: &str) -> std::io::Result<()> {
+        let length = line.len() as u64 + 1;
+
+        if self
+            .max_file_size
+            .is_some_and(|max_file_size| self.file_size + length > max_file_size)
+            && self.file_size > 0
+        {
+            self.rotate()?;
+        }
+
+        self.writer.write_all(line.as_bytes())?;
+        self.writer.write_all(b"\n")?;
+        self.file_size += length;
+
+        Ok(())
+    }
+}
+
+enum AccessLogDestination {
+    File(AccessLogFile),
+    // one message per record
+    Syslog(SyslogSender),
+}
+
+impl AccessLogDestination {
+    fn new(access_log_configuration: &AccessLogConfiguration) -> anyhow::Result<Self> {
+        if let Some(syslog_configuration) = &access_log_configuration.syslog {
+            return Ok(Self::Syslog(SyslogSender::new(syslog_configuration)?));
+        }
+
+        let path = PathBuf::from(
+            access_log_configuration
+                .path
+                .as_ref()
+                .context("access log path or syslog required")?,
+        );
+
+        let (writer, file_size) =
+            open_append(&path).with_context(|| format!("error opening access log {:?}", path))?;
+
+        Ok(Self::File(AccessLogFile {
+            path,
+            max_file_size: access_log_configuration.max_file_size,
+            max_files: access_log_configuration.max_files,
+            writer,
+            file_size,
+        }))
+    }
+}
+
+impl std::fmt::Display for AccessLogDestination {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::File(file) => write!(f, "path = {:?}", file.path),
+            Self::Syslog(_) => write!(f, "syslog"),
+        }
+    }
+}
+
+struct AccessLogWriter {
+    format: AccessLogFormat,
+    destination: AccessLogDestination,
+}
+
+impl AccessLogWriter {
+    fn write_record(&mut self, record: &AccessLogRecord) -> std::io::Result<()> {
+        let line = match self.format {
+            AccessLogFormat::Common => format_common(record),
+            AccessLogFormat::Json => format_json(record),
+        };
+
+        match &mut self.destination {
+            AccessLogDestination::File(file) => file.write_line(&line),
+            AccessLogDestination::Syslog(sender) => sender.send(&Level::INFO, &line),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match &mut self.destination {
+            AccessLogDestination::File(file) => file.writer.flush(),
+            AccessLogDestination::Syslog(_) => Ok(()),
+        }
+    }
+
+    fn run(mut self, receiver: Receiver<AccessLogRecord>) {
+        while let Ok(record) = receiver.recv() {
+            let mut result = self.write_record(&record);
+
+            // flush once the queue is drained
+            while let (Ok(()), Ok(record)) = (&result, receiver.try_recv()) {
+                result = self.write_record(&record);
+            }
+
+            if let Err(e) = result.and_then(|_| self.flush()) {
+                warn!("access log write error {}: {}", self.destination, e);
+            }
+        }
+    }
+}
+
+pub struct AccessLog {
+    sender: SyncSender<AccessLogRecord>,
+    dropped_records: AtomicUsize,
+}
+
+impl AccessLog {
+    fn new(access_log_configuration: &AccessLogConfiguration) -> anyhow::Result<Self> {
+        debug!("access_log_configuration = {:?}", access_log_configuration);
+
+        let access_log_writer = AccessLogWriter {
+            format: access_log_configuration.format,
+            destination: AccessLogDestination::new(access_log_configuration)?,
+        };
+
+        let (sender, receiver) = mpsc::sync_channel(QUEUE_CAPACITY);
+
+        std::thread::Builder::new()
+            .name("access-log".to_owned())
+            .spawn(move || access_log_writer.run(receiver))
+            .context("error spawning access log thread")?;
+
+        Ok(Self {
+            sender,
+            dropped_records: AtomicUsize::new(0),
+        })
+    }
+
+    fn log(&self, record: AccessLogRecord) {
+        match self.sender.try_send(record) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => {
+                if self.dropped_records.fetch_add(1, Ordering::Relaxed) == 0 {
+                    warn!("access log queue full, dropping records");
+                }
+            }
+            Err(TrySendError::Disconnected(_)) => {}
+        }
+    }
+}
+
+struct RouteGroupAccessLog {
+    path_prefixes: Vec<String>,
+    access_log: AccessLog,
+}
+
+// The logging_configuration access log and those of virtual host sites and
+// route groups.
+pub struct AccessLogs {
+    default: Option<AccessLog>,
+    // by index into virtual_host_configuration.sites
+    sites: Vec<Option<AccessLog>>,
+    route_groups: Vec<RouteGroupAccessLog>,
+}
+
+impl AccessLogs {
+    // The log of the first route group matching path, then of the site at
+    // site_index, then the default log.
+    pub fn find(&self, site_index: Option<usize>, path: &str) -> Option<&AccessLog> {
+        let route_group = self.route_groups.iter().find(|route_group| {
+            route_group
+                .path_prefixes
+                .iter()
+                .any(|path_prefix| path.starts_with(path_prefix.as_str()))
+        });
+
+        if let Some(route_group) = route_group {
+            return Some(&route_group.access_log);
+        }
+
+        site_index
+            .and_then(|site_index| self.sites.get(site_index))
+            .and_then(Option::as_ref)
+            .or(self.default.as_ref())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.default.is_none()
+            && self.sites.iter().all(Option::is_none)
+            && self.route_groups.is_empty()
+    }
+}
+
+static ACCESS_LOGS_INSTANCE: OnceLock<AccessLogs> = OnceLock::new();
+
+pub fn create_access_log_instance() -> anyhow::Result<()> {
+    let configuration = crate::config::instance();
+
+    let default = configuration
+        .logging_configuration
+        .access_log
+        .as_ref()
+        .map(AccessLog::new)
+        .transpose()?;
+
+    let virtual_host_configuration = &configuration.virtual_host_configuration;
+
+    let sites = virtual_host_configuration
+        .sites
+        .iter()
+        .map(|site| {
+            site.access_log
+                .as_ref()
+                .map(AccessLog::new)
+                .transpose()
+                .with_context(|| format!("virtual host site '{}'", site.name))
+        })
+        .collect::<anyhow::Result<_>>()?;
+
+    let route_groups = virtual_host_configuration
+        .access_log_route_groups
+        .iter()
+        .map(|route_group| {
+            Ok(RouteGroupAccessLog {
+                path_prefixes: route_group.path_prefixes.clone(),
+                access_log: AccessLog::new(&route_group.access_log)
+                    .with_context(|| format!("access log route group '{}'", route_group.name))?,
+            })
+        })
+        .collect::<anyhow::Result<_>>()?;
+
+    ACCESS_LOGS_INSTANCE
+        .set(AccessLogs {
+            default,
+            sites,
+            route_groups,
+        })
+        .map_err(|_| anyhow::anyhow!("ACCESS_LOGS_INSTANCE.set error"))?;
+
+    Ok(())
+}
+
+// Empty until create_access_log_instance is called.
+pub fn access_logs_instance() -> &'static AccessLogs {
+    static NO_ACCESS_LOGS: AccessLogs = AccessLogs {
+        default: None,
+        sites: Vec::new(),
+        route_groups: Vec::new(),
+    };
+
+    ACCESS_LOGS_INSTANCE.get().unwrap_or(&NO_ACCESS_LOGS)
+}
+
+// Counts response bytes and logs the record when the body is dropped,
+// after hyper finished writing it or the client went away.
+pub struct AccessLogBody {
+    inner: ResponseBody,
+    access_log: &'static AccessLog,
+    record: Option<AccessLogRecord>,
+    start_time: Instant,
+}
+
+impl AccessLogBody {
+    pub fn new(
+        inner: ResponseBody,
+        access_log: &'static AccessLog,
+        record: AccessLogRecord,
+        start_time: Instant,
+    ) -> Self {
+        Self {
+            inner,
+            access_log,
+            record: Some(record),
+            start_time,
+        }
+    }
+}
+
+impl Body for AccessLogBody {
+    type Data = Bytes;
+    type Error = ResponseBodyError;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let poll = Pin::new(&mut self.inner).poll_frame(cx);
+
+        if let Poll::Ready(Some(Ok(frame))) = &poll {
+            if let (Some(data), Some(record)) = (frame.data_ref(), self.record.as_mut()) {
+                record.response_bytes += data.len() as u64;
+            }
+        }
+
+        poll
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+impl Drop for AccessLogBody {
+    fn drop(&mut self) {
+        if let Some(mut record) = self.record.take() {
+            record.duration = self.start_time.elapsed();
+            self.access_log.log(record);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::net::UdpSocket;
+
+    use crate::{
+        config::{SyslogConfiguration, SyslogTransport},
+        request::RequestIDFactory,
+    };
+
+    fn test_record() -> AccessLogRecord {
+        AccessLogRecord {
+            time: SystemTime::UNIX_EPOCH,
+            client: Some(IpAddr::from([127, 0, 0, 1])),
+            method: Method::GET,
+            uri: "/a/b?c=d".parse().unwrap(),
+            version: Version::HTTP_11,
+            connection_id: ConnectionID::new_for_test(3),
+            request_id: RequestIDFactory::new().new_request_id(),
+            external_request_id: None,
+            status: StatusCode::OK,
+            deny_reason: None,
+            response_bytes: 2326,
+            duration: Duration::from_micros(1520),
+        }
+    }
+
+    #[test]
+    fn test_format_common() {
+        let record = test_record();
+
+        let line = format_common(&record);
+        assert!(line.starts_with("127.0.0.1 - - ["));
+        assert!(line.ends_with(
+            "] \"GET /a/b?c=d HTTP/1.1\" 200 2326 conn_id=3 req_id=1 xreq_id=- micros=1520"
+        ));
+
+        let line: serde_json::Value = serde_json::from_str(&format_json(&record)).unwrap();
+        assert_eq!(line["uri"], "/a/b?c=d");
+        assert_eq!(line["bytes"], 2326);
+        assert!(line.get("deny_reason").is_none());
+    }
+
+    #[test]
+    fn test_find() {
+        let access_log = || AccessLog {
+            sender: mpsc::sync_channel(1).0,
+            dropped_records: AtomicUsize::new(0),
+        };
+
+        let access_logs = AccessLogs {
+            default: Some(access_log()),
+            sites: vec![Some(access_log()), None],
+            route_groups: vec![RouteGroupAccessLog {
+                path_prefixes: vec!["/api/v1/commands/".to_owned()],
+                access_log: access_log(),
+            }],
+        };
+
+        let find = |site_index, path| access_logs.find(site_index, path).unwrap() as *const _;
+
+        let default = access_logs.default.as_ref().unwrap() as *const _;
+        let site = access_logs.sites[0].as_ref().unwrap() as *const _;
+        let route_group = &access_logs.route_groups[0].access_log as *const _;
+
+        assert_eq!(find(None, "/index.html"), default);
+        assert_eq!(find(Some(0), "/index.html"), site);
+        assert_eq!(find(Some(1), "/index.html"), default);
+        assert_eq!(find(None, "/api/v1/commands/w"), route_group);
+        assert_eq!(find(Some(0), "/api/v1/commands/w"), route_group);
+    }
+
+    #[test]
+    fn test_syslog_destination() {
+        let syslog = UdpSocket::bind("127.0.0.1:0").unwrap();
+        syslog
+            .set_read_timeout(Some(std::time::Duration::from_secs(5)))
+            .unwrap();
+
+        let access_log = AccessLog::new(&AccessLogConfiguration {
+            path: None,
+            syslog: Some(SyslogConfiguration {
+                transport: SyslogTransport::Udp,
+                address: syslog.local_addr().unwrap().to_string(),
+                // local0
+                facility: 16,
+                app_name: Some("rhs-access".to_owned()),
+            }),
+            format: AccessLogFormat::Common,
+            max_file_size: None,
+            max_files: 1,
+        })
+        .unwrap();
+
+        access_log.log(test_record());
+
+        let mut buf = [0; 1024];
+        let length = syslog.recv(&mut buf).unwrap();
+        let message = std::str::from_utf8(&buf[..length]).unwrap();
+
+        // local0.info
+        assert!(message.starts_with("<134>1 "), "{}", message);
+        assert!(message.contains(" rhs-access "), "{}", message);
+        assert!(
+            message.ends_with(
+                "\"GET /a/b?c=d HTTP/1.1\" 200 2326 conn_id=3 req_id=1 xreq_id=- micros=1520"
+            ),
+            "{}",
+            message
+        );
+    }
+}
@@ -0,0 +1,242 @@
+use std::{
+    io::{Read, Seek, SeekFrom},
+    path::Path,
+    sync::LazyLock,
+};
+
+const ELF_MAGIC: [u8; 4] = [0x7f, b'E', b'L', b'F'];
+const ELF_CLASS_64: u8 = 2;
+const ELF_DATA_BIG_ENDIAN: u8 = 2;
+const PT_NOTE: u32 = 4;
+const NT_GNU_BUILD_ID: u32 = 3;
+const GNU_NOTE_NAME: &[u8] = b"GNU\0";
+
+// Note segments are small, anything larger is not a note we want.
+const MAX_NOTE_SEGMENT_SIZE: u64 = 64 * 1024;
+
+struct ElfReader {
+    is_64: bool,
+    big_endian: bool,
+}
+
+impl ElfReader {
+    fn u16(&self, bytes: &[u8], offset: usize) -> Option<u16> {
+        let bytes: [u8; 2] = bytes.get(offset..offset + 2)?.try_into().ok()?;
+        Some(if self.big_endian {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        })
+    }
+
+    fn u32(&self, bytes: &[u8], offset: usize) -> Option<u32> {
+        let bytes: [u8; 4] = bytes.get(offset..offset + 4)?.try_into().ok()?;
+        Some(if self.big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        })
+    }
+
+    // Addresses and offsets are 4 bytes in ELF32 and 8 bytes in ELF64.
+    fn word(&self, bytes: &[u8], offset: usize) -> Option<u64> {
+        if self.is_64 {
+            let bytes: [u8; 8] = bytes.get(offset..offset + 8)?.try_into().ok()?;
+            Some(if self.big_endian {
+                u64::from_be_bytes(bytes)
+            } else {
+                u64::from_le_bytes(bytes)
+            })
+        } else {
+            self.u32(bytes, offset).map(u64::from)
+        }
+    }
+}
+
+fn align4(value: usize) -> usize {
+    (value + 3) & !3
+}
+
+// The NT_GNU_BUILD_ID descriptor in a note segment.
+fn find_build_id_note(elf_reader: &ElfReader, notes: &[u8]) -> Option<Vec<u8>> {
+    let mut offset = 0;
+
+    while offset + 12 <= notes.len() {
+        let name_size = elf_reader.u32(notes, offset)? as usize;
+        let desc_size = elf_reader.u32(notes, offset + 4)? as usize;
+        let note_type = elf_reader.u32(notes, offset + 8)?;
+
+        let name_offset = offset + 12;
+        let desc_offset = name_offset + align4(name_size);
+
+        if note_type == NT_GNU_BUILD_ID
+            && notes.get(name_offset..name_offset + name_size) == Some(GNU_NOTE_NAME)
+        {
+            return notes
+                .get(desc_offset..desc_offset + desc_size)
+                .map(<[u8]>::to_vec);
+        }
+
+        offset = desc_offset + align4(desc_size);
+    }
+
+    None
+}
+
+fn read_at(file: &mut (impl Read + Seek), offset: u64, len: usize) -> std::io::Result<Vec<u8>> {
+    let mut buffer = vec![0; len];
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(&mut buffer)?;
+    Ok(buffer)
+}
+
+// The GNU build-id of an ELF file as lowercase hex, None if the file is not
+// ELF or was linked without a build-id.
+fn read_build_id(file: &mut (impl Read + Seek)) -> std::io::Result<Option<String>> {
+    let header = read_at(file, 0, 64)?;
+
+    if header[0..4] != ELF_MAGIC {
+        return Ok(None);
+    }
+
+    let elf_reader = ElfReader {
+        is_64: header[4] == ELF_CLASS_64,
+        big_endian: header[5] == ELF_DATA_BIG_ENDIAN,
+    };
+
+    let (program_header_offset, program_header_size, program_header_count) = if elf_reader.is_64 {
+        (
+            elf_reader.word(&header, 0x20),
+            elf_reader.u16(&header, 0x36),
+            elf_reader.u16(&header, 0x38),
+        )
+    } else {
+        (
+            elf_reader.word(&header, 0x1c),
+            elf_reader.u16(&header, 0x2a),
+            elf_reader.u16(&header, 0x2c),
+        )
+    };
+
+    let (Some(program_header_offset), Some(program_header_size), Some(program_header_count)) = (
+        program_header_offset,
+        program_header_size,
+        program_header_count,
+    ) else {
+        return Ok(None);
+    };
+
+    if program_header_size == 0 {
+        return Ok(None);
+    }
+
+    let program_headers = read_at(
+        file,
+        program_header_offset,
+        usize::from(program_header_size) * usize::from(program_header_count),
+    )?;
+
+    for program_header in program_headers.chunks_exact(usize::from(program_header_size)) {
+        if elf_reader.u32(program_header, 0) != Some(PT_NOTE) {
+            continue;
+        }
+
+        let (segment_offset, segment_size) = if elf_reader.is_64 {
+            (
+                elf_reader.word(program_header, 0x08),
+                elf_reader.word(program_header, 0x20),
+            )
+        } else {
+            (
+                elf_reader.word(program_header, 0x04),
+                elf_reader.word(program_header, 0x10),
+            )
+        };
+
+        let (Some(segment_offset), Some(segment_size)) = (segment_offset, segment_size) else {
+            continue;
+        };
+
+        if segment_size > MAX_NOTE_SEGMENT_SIZE {
+            continue;
+        }
+
+        let notes = read_at(file, segment_offset, segment_size as usize)?;
+
+        if let Some(build_id) = find_build_id_note(&elf_reader, &notes) {
+            return Ok(Some(
+                build_id
+                    .iter()
+                    .map(|byte| format!("{:02x}", byte))
+                    .collect(),
+            ));
+        }
+    }
+
+    Ok(None)
+}
+
+fn read_executable_build_id(executable: &Path) -> Option<String> {
+    let mut file = std::fs::File::open(executable).ok()?;
+
+    read_build_id(&mut file).ok().flatten()
+}
+
+// The running executable's build-id, read once.
+pub fn executable_build_id() -> Option<&'static str> {
+    static INSTANCE: LazyLock<Option<String>> = LazyLock::new(|| {
+        std::env::current_exe()
+            .ok()
+            .and_then(|executable| read_executable_build_id(&executable))
+    });
+
+    INSTANCE.as_deref()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::io::Cursor;
+
+    #[test]
+    fn test_read_build_id() {
+        let mut elf = vec![0; 64];
+        elf[0..4].copy_from_slice(&ELF_MAGIC);
+        elf[4] = ELF_CLASS_64;
+        elf[5] = 1;
+        // one program header at offset 64
+        elf[0x20..0x28].copy_from_slice(&64u64.to_le_bytes());
+        elf[0x36..0x38].copy_from_slice(&56u16.to_le_bytes());
+        elf[0x38..0x3a].copy_from_slice(&1u16.to_le_bytes());
+
+        let mut notes = Vec::new();
+        // a note with another type comes first
+        notes.extend_from_slice(&4u32.to_le_bytes());
+        notes.extend_from_slice(&3u32.to_le_bytes());
+        notes.extend_from_slice(&1u32.to_le_bytes());
+        notes.extend_from_slice(GNU_NOTE_NAME);
+        notes.extend_from_slice(&[9, 9, 9, 0]);
+        notes.extend_from_slice(&4u32.to_le_bytes());
+        notes.extend_from_slice(&4u32.to_le_bytes());
+        notes.extend_from_slice(&NT_GNU_BUILD_ID.to_le_bytes());
+        notes.extend_from_slice(GNU_NOTE_NAME);
+        notes.extend_from_slice(&[0xde, 0xad, 0xbe, 0xef]);
+
+        let mut program_header = vec![0; 56];
+        program_header[0..4].copy_from_slice(&PT_NOTE.to_le_bytes());
+        program_header[0x08..0x10].copy_from_slice(&120u64.to_le_bytes());
+        program_header[0x20..0x28].copy_from_slice(&(notes.len() as u64).to_le_bytes());
+
+        elf.extend_from_slice(&program_header);
+        elf.extend_from_slice(&notes);
+
+        assert_eq!(
+            read_build_id(&mut Cursor::new(elf)).unwrap(),
+            Some("deadbeef".to_owned())
+        );
+
+        let not_elf = vec![0; 64];
+        assert_eq!(read_build_id(&mut Cursor::new(not_elf)).unwrap(), None);
+    }
+}
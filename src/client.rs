@@ -0,0 +1,306 @@
+use anyhow::Context;
+
+use bytes::Bytes;
+
+use http_body_util::{BodyExt, Empty};
+
+use hyper::http::{header, Method, Request, StatusCode};
+
+use std::{io::Write, path::Path};
+
+use crate::config::{Configuration, ServerSocketType};
+
+const USAGE: &str = "client <config file> [--post] <endpoint> [name=value ...]
+  client <config file> drain
+  client <config file> reload
+  client <config file> log-level [filter]
+  client <config file> routes [disable <prefix> [status] | enable <prefix>]";
+
+#[derive(Debug, PartialEq)]
+struct ClientArgs {
+    config_file: String,
+    method: Method,
+    endpoint: String,
+    query: Vec<(String, String)>,
+}
+
+fn usage_error() -> anyhow::Error {
+    anyhow::anyhow!("usage: {}", USAGE)
+}
+
+// Control subcommands for the admin endpoints, None for other endpoints.
+fn parse_control_command(
+    config_file: &str,
+    command: &str,
+    args: &mut impl Iterator<Item = String>,
+) -> anyhow::Result<Option<ClientArgs>> {
+    let mut query = Vec::new();
+
+    let (method, endpoint) = match command {
+        "drain" => (Method::POST, "admin/drain"),
+        "reload" => (Method::POST, "admin/config/reload"),
+        "log-level" => match args.next() {
+            None => (Method::GET, "admin/log_level"),
+            Some(filter) => {
+                query.push(("filter".to_owned(), filter));
+                (Method::POST, "admin/log_level")
+            }
+        },
+        "routes" => match args.next().as_deref() {
+            None => (Method::GET, "admin/routes/disabled"),
+            Some("disable") => {
+                query.push(("prefix".to_owned(), args.next().ok_or_else(usage_error)?));
+                if let Some(status) = args.next() {
+                    query.push(("status".to_owned(), status));
+                }
+                (Method::POST, "admin/routes/disable")
+            }
+            Some("enable") => {
+                query.push(("prefix".to_owned(), args.next().ok_or_else(usage_error)?));
+                (Method::POST, "admin/routes/enable")
+            }
+            Some(_) => return Err(usage_error()),
+        },
+        _ => return Ok(None),
+    };
+
+    if args.next().is_some() {
+        return Err(usage_error());
+    }
+
+    Ok(Some(ClientArgs {
+        config_file: config_file.to_owned(),
+        method,
+        endpoint: endpoint.to_owned(),
+        query,
+    }))
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> anyhow::Result<ClientArgs> {
+    let config_file = args.next().ok_or_else(usage_error)?;
+
+    let mut endpoint = args.next().ok_or_else(usage_error)?;
+
+    if let Some(client_args) = parse_control_command(&config_file, &endpoint, &mut args)? {
+        return Ok(client_args);
+    }
+
+    let mut method = Method::GET;
+
+    if endpoint == "--post" {
+        method = Method::POST;
+        endpoint = args.next().ok_or_else(usage_error)?;
+    }
+
+    let query = args
+        .map(|arg| match arg.split_once('=') {
+            Some((name, value)) => Ok((name.to_owned(), value.to_owned())),
+            None => anyhow::bail!("invalid query parameter '{}', expected name=value", arg),
+        })
+        .collect::<anyhow::Result<_>>()?;
+
+    Ok(ClientArgs {
+        config_file,
+        method,
+        endpoint,
+        query,
+    })
+}
+
+// "connection-info" is the connection_info route, endpoints may also be
+// route paths such as "admin/faults".
+fn request_path(dynamic_route_context: &str, endpoint: &str, query: &[(String, String)]) -> String {
+    let path_suffix = endpoint.trim_start_matches('/').replace('-', "_");
+
+    let mut path = Path::new(dynamic_route_context)
+        .join(path_suffix)
+        .to_string_lossy()
+        .into_owned();
+
+    if !query.is_empty() {
+        path.push('?');
+        path.push_str(
+            &form_urlencoded::Serializer::new(String::new())
+                .extend_pairs(query)
+                .finish(),
+        );
+    }
+
+    path
+}
+
+fn unix_socket_path(configuration: &Configuration) -> anyhow::Result<&str> {
+    configuration
+        .server_configuration
+        .listeners
+        .iter()
+        .find(|listener| matches!(listener.socket_type, ServerSocketType::Unix))
+        .map(|listener| listener.bind_address.as_str())
+        .context("no UNIX listener in configuration")
+}
+
+#[cfg(unix)]
+async fn send_request(
+    socket_path: &str,
+    method: Method,
+    path: &str,
+) -> anyhow::Result<(StatusCode, bool, Bytes)> {
+    let stream = tokio::net::UnixStream::connect(socket_path)
+        .await
+        .with_context(|| format!("error connecting to '{}'", socket_path))?;
+
+    let (mut sender, connection) =
+        hyper::client::conn::http1::handshake(hyper_util::rt::TokioIo::new(stream)).await?;
+
+    tokio::spawn(connection);
+
+    let request = Request::builder()
+        .method(method)
+        .uri(path)
+        .header(header::HOST, "localhost")
+        .body(Empty::<Bytes>::new())?;
+
+    let response = sender.send_request(request).await?;
+
+    let status = response.status();
+
+    let is_json = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .is_some_and(|content_type| content_type == "application/json");
+
+    let body = response.into_body().collect().await?.to_bytes();
+
+    Ok((status, is_json, body))
+}
+
+#[cfg(not(unix))]
+async fn send_request(
+    _socket_path: &str,
+    _method: Method,
+    _path: &str,
+) -> anyhow::Result<(StatusCode, bool, Bytes)> {
+    anyhow::bail!("client requires unix domain sockets")
+}
+
+fn format_body(body: &[u8], is_json: bool) -> String {
+    if is_json {
+        if let Ok(value) = serde_json::from_slice::<serde_json::Value>(body) {
+            if let Ok(pretty) = serde_json::to_string_pretty(&value) {
+                return pretty;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(body).into_owned()
+}
+
+// Sends one request to the server's UNIX listener and prints the response,
+// JSON is pretty printed.
+pub fn run(args: impl Iterator<Item = String>) -> anyhow::Result<()> {
+    let client_args = parse_args(args)?;
+
+    crate::config::read_configuration(client_args.config_file, None)
+        .context("read_configuration error")?;
+
+    let configuration = crate::config::instance();
+
+    let socket_path = unix_socket_path(configuration)?;
+
+    let path = request_path(
+        &configuration.context_configuration.dynamic_route_context,
+        &client_args.endpoint,
+        &client_args.query,
+    );
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+
+    let (status, is_json, body) =
+        runtime.block_on(send_request(socket_path, client_args.method, &path))?;
+
+    let output = format_body(&body, is_json);
+
+    if !output.is_empty() {
+        // e.g. piped into head
+        if let Err(e) = writeln!(std::io::stdout().lock(), "{}", output) {
+            if e.kind() != std::io::ErrorKind::BrokenPipe {
+                return Err(e.into());
+            }
+        }
+    }
+
+    if !status.is_success() {
+        anyhow::bail!("{} {} returned {}", socket_path, path, status);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn args(args: &[&str]) -> impl Iterator<Item = String> {
+        args.iter()
+            .map(|arg| arg.to_string())
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let client_args = parse_args(args(&[
+            "rhs.toml",
+            "--post",
+            "admin/faults/clear",
+            "path_prefix=/a b/",
+        ]))
+        .unwrap();
+
+        assert_eq!(client_args.method, Method::POST);
+        assert_eq!(
+            request_path("/api/v1", &client_args.endpoint, &client_args.query),
+            "/api/v1/admin/faults/clear?path_prefix=%2Fa+b%2F"
+        );
+
+        let client_args = parse_args(args(&["rhs.toml", "connection-info"])).unwrap();
+        assert_eq!(client_args.method, Method::GET);
+        assert_eq!(
+            request_path("/api/v1", &client_args.endpoint, &client_args.query),
+            "/api/v1/connection_info"
+        );
+
+        let client_args =
+            parse_args(args(&["rhs.toml", "log-level", "info,rhs::handlers=debug"])).unwrap();
+        assert_eq!(client_args.method, Method::POST);
+        assert_eq!(
+            request_path("/api/v1", &client_args.endpoint, &client_args.query),
+            "/api/v1/admin/log_level?filter=info%2Crhs%3A%3Ahandlers%3Ddebug"
+        );
+
+        let client_args = parse_args(args(&["rhs.toml", "routes"])).unwrap();
+        assert_eq!(client_args.method, Method::GET);
+        assert_eq!(client_args.endpoint, "admin/routes/disabled");
+
+        let client_args = parse_args(args(&[
+            "rhs.toml",
+            "routes",
+            "disable",
+            "commands",
+            "NOT_FOUND",
+        ]))
+        .unwrap();
+        assert_eq!(client_args.method, Method::POST);
+        assert_eq!(
+            request_path("/api/v1", &client_args.endpoint, &client_args.query),
+            "/api/v1/admin/routes/disable?prefix=commands&status=NOT_FOUND"
+        );
+
+        assert!(parse_args(args(&["rhs.toml", "routes", "enable"])).is_err());
+        assert!(parse_args(args(&["rhs.toml", "drain", "now"])).is_err());
+        assert!(parse_args(args(&["rhs.toml"])).is_err());
+        assert!(parse_args(args(&["rhs.toml", "request-info", "pretty"])).is_err());
+    }
+}
@@ -1,53 +1,256 @@
+mod profile;
+mod units;
+mod validate;
+
 use anyhow::Context;
 
-use tracing::debug;
+use ipnet::IpNet;
+
+use schemars::JsonSchema;
 
 use serde::{Deserialize, Serialize};
 
-use tokio::{fs::File, io::AsyncReadExt, sync::OnceCell, time::Duration};
+use sha2::{Digest, Sha256};
+
+use tokio::{sync::OnceCell, time::Duration};
+
+use std::{
+    collections::VecDeque,
+    io::Read,
+    net::IpAddr,
+    sync::{Arc, Mutex, OnceLock, RwLock},
+    time::SystemTime,
+};
+
+fn default_openapi_path() -> String {
+    "/api/openapi.json".to_owned()
+}
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct ContextConfiguration {
     pub dynamic_route_context: String,
+    #[serde(default = "default_openapi_path")]
+    pub openapi_path: String,
+    // path suffixes relative to dynamic_route_context, e.g. "commands" or "request_info"
+    #[serde(default)]
+    pub disabled_routes: Vec<String>,
 }
 
-#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, JsonSchema, Serialize)]
 pub enum ServerSocketType {
     #[serde(rename = "TCP")]
     Tcp,
 
     #[serde(rename = "UNIX")]
     Unix,
+
+    // Windows only, bind_address is the pipe name e.g. \\.\pipe\rhs
+    #[serde(rename = "NAMED_PIPE")]
+    NamedPipe,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct TcpKeepaliveConfiguration {
+    #[serde(default, with = "humantime_serde")]
+    pub time: Option<Duration>,
+    #[serde(default, with = "humantime_serde")]
+    pub interval: Option<Duration>,
+    pub retries: Option<u32>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct ListenerSocketOptions {
+    pub backlog: Option<u32>,
+    #[serde(default)]
+    pub reuse_port: bool,
+    #[serde(default, deserialize_with = "units::deserialize_option_byte_size")]
+    pub recv_buffer_size: Option<usize>,
+    #[serde(default, deserialize_with = "units::deserialize_option_byte_size")]
+    pub send_buffer_size: Option<usize>,
+    pub tcp_nodelay: Option<bool>,
+    pub tcp_keepalive: Option<TcpKeepaliveConfiguration>,
+}
+
+fn default_tls_handshake_timeout() -> Duration {
+    Duration::from_secs(10)
+}
+
+// PEM files, the certificate file holds the full chain.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ListenerTlsConfiguration {
+    pub cert_path: String,
+    pub key_path: String,
+    #[serde(with = "humantime_serde", default = "default_tls_handshake_timeout")]
+    pub handshake_timeout: Duration,
+}
+
+fn default_proxy_protocol_header_timeout() -> Duration {
+    Duration::from_secs(5)
+}
+
+// Connections must start with a HAProxy PROXY protocol v1 or v2 header, the
+// client address it reports replaces the peer address.  Only enable behind a
+// proxy that sends it, as clients could otherwise choose their own address.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ListenerProxyProtocolConfiguration {
+    #[serde(
+        with = "humantime_serde",
+        default = "default_proxy_protocol_header_timeout"
+    )]
+    pub header_timeout: Duration,
+    // TCP listeners only, connections from other peers are closed.  Empty
+    // accepts every peer.
+    #[serde(default)]
+    pub allowed_sources: Vec<IpNet>,
+}
+
+impl ListenerProxyProtocolConfiguration {
+    pub fn allows_source(&self, peer: IpAddr) -> bool {
+        // IPv4 peers of dual stack listeners are IPv4-mapped IPv6 addresses
+        let peer = peer.to_canonical();
+
+        self.allowed_sources.is_empty()
+            || self
+                .allowed_sources
+                .iter()
+                .any(|network| network.contains(&peer))
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct ServerListenerConfiguration {
     pub socket_type: ServerSocketType,
     pub bind_address: String,
+    pub accept_tasks: Option<usize>,
+    #[serde(default)]
+    pub socket_options: ListenerSocketOptions,
+    // TCP listeners only, serves HTTPS when set
+    pub tls: Option<ListenerTlsConfiguration>,
+    // TCP and UNIX listeners, the header comes before the TLS handshake
+    pub proxy_protocol: Option<ListenerProxyProtocolConfiguration>,
+}
+
+impl ServerListenerConfiguration {
+    pub fn accept_tasks(&self) -> usize {
+        self.accept_tasks.unwrap_or(1).max(1)
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct ServerConnectionConfiguration {
     pub limit: usize,
+    // Open connections kept individually for connection_info, defaults to
+    // limit.  Connections above it are still served but only summarized into
+    // the tracker totals when they close.
+    pub max_tracked_connections: Option<usize>,
     #[serde(with = "humantime_serde")]
     pub max_lifetime: Duration,
     #[serde(with = "humantime_serde")]
     pub graceful_shutdown_timeout: Duration,
 }
 
+// Requests over these limits are answered with 431, 414 or 413 before
+// reaching a handler.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ServerLimitsConfiguration {
+    // request line and headers, at least 8KiB since this sizes the HTTP/1 read buffer
+    #[serde(
+        default = "default_max_header_bytes",
+        deserialize_with = "units::deserialize_byte_size"
+    )]
+    pub max_header_bytes: usize,
+    #[serde(default = "default_max_uri_length")]
+    pub max_uri_length: usize,
+    // bodies without a content-length are cut off when they pass this
+    #[serde(
+        default = "default_max_body_bytes",
+        deserialize_with = "units::deserialize_byte_size"
+    )]
+    pub max_body_bytes: usize,
+}
+
+// hyper's smallest allowed HTTP/1 read buffer.
+pub const MIN_MAX_HEADER_BYTES: usize = 8 * 1024;
+
+fn default_max_header_bytes() -> usize {
+    64 * 1024
+}
+
+fn default_max_uri_length() -> usize {
+    8 * 1024
+}
+
+fn default_max_body_bytes() -> usize {
+    10 * 1024 * 1024
+}
+
+impl Default for ServerLimitsConfiguration {
+    fn default() -> Self {
+        Self {
+            max_header_bytes: default_max_header_bytes(),
+            max_uri_length: default_max_uri_length(),
+            max_body_bytes: default_max_body_bytes(),
+        }
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct ServerConfiguration {
     pub listeners: Vec<ServerListenerConfiguration>,
     pub connection: ServerConnectionConfiguration,
+    #[serde(default)]
+    pub limits: ServerLimitsConfiguration,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+pub const PRLIMIT_PATH: &str = "/usr/bin/prlimit";
+
+pub const NICE_PATH: &str = "/usr/bin/nice";
+
+pub const BWRAP_PATH: &str = "/usr/bin/bwrap";
+
+// Applied with prlimit(1) and nice(1) when the command is spawned.
+#[derive(Debug, Default, Deserialize, JsonSchema, Serialize)]
+pub struct CommandLimits {
+    // RLIMIT_CPU, rounded up to whole seconds
+    #[serde(default, alias = "cpu_seconds", with = "units::option_duration")]
+    #[schemars(with = "Option<String>")]
+    pub cpu_time: Option<Duration>,
+    // RLIMIT_AS, address space
+    #[serde(default, deserialize_with = "units::deserialize_option_byte_size")]
+    pub memory_bytes: Option<u64>,
+    // per output stream; the command is killed once exceeded
+    #[serde(default, deserialize_with = "units::deserialize_option_byte_size")]
+    pub max_output_bytes: Option<usize>,
+    pub nice: Option<i32>,
+}
+
+impl CommandLimits {
+    pub fn has_rlimits(&self) -> bool {
+        self.cpu_time.is_some() || self.memory_bytes.is_some()
+    }
+}
+
+// Runs the command under bubblewrap in new user, pid, ipc and uts namespaces.
+#[derive(Debug, Default, Deserialize, JsonSchema, Serialize)]
+pub struct CommandSandbox {
+    #[serde(default)]
+    pub no_network: bool,
+    #[serde(default)]
+    pub read_only_root: bool,
+    #[serde(default)]
+    pub private_tmp: bool,
+}
+
+#[derive(Debug, Deserialize, JsonSchema, Serialize)]
 pub struct CommandInfo {
     pub id: String,
     pub description: String,
     pub command: String,
     #[serde(default)]
     pub args: Vec<String>,
+    #[serde(default)]
+    pub limits: CommandLimits,
+    pub sandbox: Option<CommandSandbox>,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -57,6 +260,10 @@ pub struct CommandConfiguration {
     #[serde(with = "humantime_serde")]
     pub semaphore_acquire_timeout: Duration,
 
+    // requests waiting for a semaphore permit beyond this get 503, None for no limit
+    #[serde(default)]
+    pub max_queued_commands: Option<usize>,
+
     pub commands: Vec<CommandInfo>,
 }
 
@@ -67,14 +274,132 @@ pub enum StaticFileCacheRuleType {
 
     #[serde(rename = "FIXED_TIME")]
     FixedTime,
+
+    #[serde(rename = "NO_STORE")]
+    NoStore,
+
+    // cache_control is sent as is
+    #[serde(rename = "CUSTOM")]
+    Custom,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize)]
+pub enum CacheVisibility {
+    #[default]
+    #[serde(rename = "PUBLIC")]
+    Public,
+
+    #[serde(rename = "PRIVATE")]
+    Private,
+}
+
+// Order cache rules are tried in, the first matching rule wins.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize)]
+pub enum CacheRuleMatching {
+    // configured order
+    #[default]
+    #[serde(rename = "FIRST_MATCH")]
+    FirstMatch,
+
+    // longest path_regex first
+    #[serde(rename = "LONGEST_PATTERN")]
+    LongestPattern,
+
+    // highest priority first, ties in configured order
+    #[serde(rename = "PRIORITY")]
+    Priority,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct StaticFileCacheRule {
+    // shown in the x-cache-rule debug header, defaults to the rule's index
+    pub name: Option<String>,
     pub path_regex: String,
     pub rule_type: StaticFileCacheRuleType,
-    #[serde(with = "humantime_serde")]
+    // max-age for MOD_TIME_PLUS_DELTA and FIXED_TIME
+    #[serde(default, with = "humantime_serde")]
     pub duration: Duration,
+    #[serde(default)]
+    pub visibility: CacheVisibility,
+    #[serde(default)]
+    pub immutable: bool,
+    #[serde(default, with = "humantime_serde")]
+    pub stale_while_revalidate: Option<Duration>,
+    // required for CUSTOM
+    pub cache_control: Option<String>,
+    #[serde(default)]
+    pub priority: i32,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct PreloadLink {
+    pub href: String,
+    // preload destination, e.g. "script", "style" or "font"
+    #[serde(rename = "as")]
+    pub destination: String,
+    #[serde(default)]
+    pub crossorigin: bool,
+}
+
+// Link: rel=preload headers for HTML entry points.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct StaticFilePreloadRule {
+    // matched against the resolved file path like cache rules
+    pub path_regex: String,
+    pub links: Vec<PreloadLink>,
+}
+
+// Handling of Range requests for more than one range.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize)]
+pub enum MultiRangePolicy {
+    // multipart/byteranges with one part per requested range
+    #[default]
+    #[serde(rename = "MULTIPART")]
+    Multipart,
+
+    // merge overlapping and adjacent ranges first
+    #[serde(rename = "COALESCE")]
+    Coalesce,
+
+    // ignore the Range header and send the whole file
+    #[serde(rename = "REJECT")]
+    Reject,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+pub enum StaticFileFilterActionType {
+    #[serde(rename = "DENY")]
+    Deny,
+
+    #[serde(rename = "REDIRECT")]
+    Redirect,
+
+    #[serde(rename = "REWRITE")]
+    Rewrite,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct StaticFileFilterHeaderMatch {
+    pub name: String,
+    // a missing header does not match
+    pub value_regex: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct StaticFileFilterRule {
+    pub name: Option<String>,
+    // matched against the request path, including the leading '/'
+    pub path_regex: String,
+    // empty matches any method
+    #[serde(default)]
+    pub methods: Vec<String>,
+    #[serde(default)]
+    pub headers: Vec<StaticFileFilterHeaderMatch>,
+    pub action: StaticFileFilterActionType,
+    // DENY defaults to 403, REDIRECT to 302
+    pub status: Option<u16>,
+    // required for REDIRECT and REWRITE, may use path_regex captures like $1
+    pub target: Option<String>,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -88,7 +413,833 @@ pub struct StaticFileConfiguration {
     pub root: String,
     pub precompressed: StaticFilePrecompressedConfiguration,
     pub client_error_page_path: String,
+    // tried in order before the request is resolved, first match wins
+    #[serde(default)]
+    pub filter_rules: Vec<StaticFileFilterRule>,
     pub cache_rules: Vec<StaticFileCacheRule>,
+    // all matching rules add their links
+    #[serde(default)]
+    pub preload_rules: Vec<StaticFilePreloadRule>,
+    #[serde(default)]
+    pub cache_rule_matching: CacheRuleMatching,
+    // adds x-cache-rule and x-cache-max-age headers to static file responses
+    #[serde(default)]
+    pub debug_headers: bool,
+    // bytes read from a file per response body chunk, 8KiB if not set
+    #[serde(default, deserialize_with = "units::deserialize_option_byte_size")]
+    pub read_chunk_size: Option<usize>,
+    // advise the kernel files are read sequentially, which enlarges readahead on Linux
+    #[serde(default)]
+    pub sequential_readahead: bool,
+    #[serde(default)]
+    pub multi_range_policy: MultiRangePolicy,
+    // requests for more ranges get the whole file
+    pub max_ranges: Option<usize>,
+    // records the filesystem path a request resolved to in the request log
+    #[serde(default)]
+    pub log_resolved_path: bool,
+    // directories without an index.html under these prefixes are listed instead of 404
+    #[serde(default)]
+    pub directory_listings: Vec<StaticFileDirectoryListingRule>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct StaticFileDirectoryListingRule {
+    // request path prefix, e.g. "/downloads/"
+    pub path_prefix: String,
+    // also list as JSON for requests with Accept: application/json
+    #[serde(default)]
+    pub json: bool,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct PeerCredentialRule {
+    pub path_prefix: String,
+    #[serde(default)]
+    pub allowed_uids: Vec<u32>,
+    #[serde(default)]
+    pub allowed_gids: Vec<u32>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct GeoIpRule {
+    pub path_prefix: String,
+    #[serde(default)]
+    pub allowed_countries: Vec<String>,
+    #[serde(default)]
+    pub denied_countries: Vec<String>,
+    #[serde(default)]
+    pub allowed_asns: Vec<u32>,
+    #[serde(default)]
+    pub denied_asns: Vec<u32>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct AuthorizationConfiguration {
+    #[serde(default)]
+    pub peer_credential_rules: Vec<PeerCredentialRule>,
+    #[serde(default)]
+    pub geoip_rules: Vec<GeoIpRule>,
+}
+
+#[derive(Default, Deserialize, Serialize)]
+pub struct EndpointAuthConfiguration {
+    #[serde(default, skip_serializing)]
+    pub bearer_tokens: Vec<String>,
+    #[serde(default)]
+    pub allowed_uids: Vec<u32>,
+    #[serde(default)]
+    pub allowed_gids: Vec<u32>,
+}
+
+// Hide bearer tokens from configuration logging.
+impl std::fmt::Debug for EndpointAuthConfiguration {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EndpointAuthConfiguration")
+            .field(
+                "bearer_tokens",
+                &format!("[{} redacted]", self.bearer_tokens.len()),
+            )
+            .field("allowed_uids", &self.allowed_uids)
+            .field("allowed_gids", &self.allowed_gids)
+            .finish()
+    }
+}
+
+fn default_endpoint_enabled() -> bool {
+    true
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct EndpointConfiguration {
+    #[serde(default = "default_endpoint_enabled")]
+    pub enabled: bool,
+    pub auth: Option<EndpointAuthConfiguration>,
+}
+
+impl Default for EndpointConfiguration {
+    fn default() -> Self {
+        Self {
+            enabled: default_endpoint_enabled(),
+            auth: None,
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct EndpointsConfiguration {
+    // without auth, admin routes only serve unix socket peers running as root
+    // or as the server's user
+    #[serde(default)]
+    pub admin: EndpointConfiguration,
+    #[serde(default)]
+    pub commands: EndpointConfiguration,
+    #[serde(default)]
+    pub connection_info: EndpointConfiguration,
+    #[serde(default)]
+    pub request_info: EndpointConfiguration,
+}
+
+impl EndpointsConfiguration {
+    // name is the first path_suffix component of a built-in route
+    pub fn endpoint(&self, name: &str) -> Option<&EndpointConfiguration> {
+        match name {
+            "admin" => Some(&self.admin),
+            "commands" => Some(&self.commands),
+            "connection_info" => Some(&self.connection_info),
+            "request_info" => Some(&self.request_info),
+            _ => None,
+        }
+    }
+}
+
+fn default_compression_content_types() -> Vec<String> {
+    vec!["application/json".to_owned(), "text/".to_owned()]
+}
+
+// On the fly brotli or gzip of dynamic route responses, static files use
+// precompressed.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CompressionConfiguration {
+    #[serde(default)]
+    pub enabled: bool,
+    // responses of known length below this are sent uncompressed, 1KiB if not set
+    #[serde(default, deserialize_with = "units::deserialize_option_byte_size")]
+    pub min_size: Option<u64>,
+    // Content-Type prefixes that are compressed
+    #[serde(default = "default_compression_content_types")]
+    pub content_types: Vec<String>,
+    // zstd dictionaries for clients supporting compression dictionary
+    // transport (RFC 9842)
+    #[serde(default)]
+    pub dictionaries: Vec<CompressionDictionaryConfiguration>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CompressionDictionaryConfiguration {
+    // served at compression/dictionaries/<id> relative to dynamic_route_context
+    pub id: String,
+    // used as raw content, e.g. a sample response or a trained zstd dictionary
+    pub path: String,
+    // responses to requests whose path starts with path_prefix may use it
+    pub path_prefix: String,
+}
+
+impl Default for CompressionConfiguration {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            min_size: None,
+            content_types: default_compression_content_types(),
+            dictionaries: Vec::new(),
+        }
+    }
+}
+
+fn default_metrics_path() -> String {
+    "metrics".to_owned()
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct MetricsConfiguration {
+    // Prometheus text format route, relative to dynamic_route_context unless
+    // absolute, e.g. "/metrics"
+    #[serde(default = "default_metrics_path")]
+    pub path: String,
+    // Latency buckets carry the trace id of a W3C traceparent request header
+    // as an exemplar, exposed when scraped as OpenMetrics.
+    #[serde(default)]
+    pub exemplars: bool,
+    #[serde(default)]
+    pub resource_usage: ResourceUsageConfiguration,
+}
+
+impl Default for MetricsConfiguration {
+    fn default() -> Self {
+        Self {
+            path: default_metrics_path(),
+            exemplars: false,
+            resource_usage: ResourceUsageConfiguration::default(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct ResourceUsageConfiguration {
+    // CPU time per request in route_metrics, and allocations when built with
+    // the alloc-tracking feature
+    #[serde(default)]
+    pub enabled: bool,
+    // requests over a budget are logged and counted per route
+    #[serde(default, with = "humantime_serde")]
+    pub cpu_time_budget: Option<Duration>,
+    #[serde(default, deserialize_with = "units::deserialize_option_byte_size")]
+    pub allocated_bytes_budget: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct GeoIpConfiguration {
+    pub country_database_path: Option<String>,
+    pub asn_database_path: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize)]
+pub enum AbsoluteFormTargetAction {
+    #[default]
+    #[serde(rename = "NORMALIZE")]
+    Normalize,
+
+    #[serde(rename = "REJECT")]
+    Reject,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize)]
+pub enum AsteriskFormTargetAction {
+    #[default]
+    #[serde(rename = "RESPOND")]
+    Respond,
+
+    #[serde(rename = "REJECT")]
+    Reject,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize)]
+pub enum UnknownHostAction {
+    // 421 for a Host not in allowed_hosts, 400 for a missing Host
+    #[default]
+    #[serde(rename = "REJECT")]
+    Reject,
+
+    // continue as if default_host had been sent
+    #[serde(rename = "DEFAULT_HOST")]
+    DefaultHost,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct RequestTargetConfiguration {
+    #[serde(default)]
+    pub absolute_form: AbsoluteFormTargetAction,
+    #[serde(default)]
+    pub asterisk_form: AsteriskFormTargetAction,
+    // empty allows any Host; entries without a port match any port
+    #[serde(default)]
+    pub allowed_hosts: Vec<String>,
+    #[serde(default)]
+    pub unknown_host: UnknownHostAction,
+    // required for DEFAULT_HOST
+    pub default_host: Option<String>,
+    // requests for other hosts are redirected here with a 301, e.g. "www.example.com"
+    pub canonical_host: Option<String>,
+    // requests not received over TLS are redirected to https with a 301
+    #[serde(default)]
+    pub force_https: bool,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, JsonSchema, Serialize)]
+pub enum RuntimeFlavor {
+    #[default]
+    #[serde(rename = "MULTI_THREAD")]
+    MultiThread,
+
+    #[serde(rename = "CURRENT_THREAD")]
+    CurrentThread,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct RuntimeConfiguration {
+    #[serde(default)]
+    pub flavor: RuntimeFlavor,
+    pub worker_threads: Option<usize>,
+    pub max_blocking_threads: Option<usize>,
+    pub thread_name: Option<String>,
+    #[serde(default)]
+    pub cpu_affinity: Vec<usize>,
+}
+
+fn default_fd_usage_warning_percent() -> u8 {
+    80
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct FileDescriptorConfiguration {
+    // raise the RLIMIT_NOFILE soft limit to this value at startup, capped at the hard limit
+    pub nofile_limit: Option<u64>,
+    #[serde(default = "default_fd_usage_warning_percent")]
+    pub usage_warning_percent: u8,
+    #[serde(with = "humantime_serde", default = "default_fd_usage_check_interval")]
+    pub usage_check_interval: Duration,
+}
+
+fn default_fd_usage_check_interval() -> Duration {
+    Duration::from_secs(10)
+}
+
+impl Default for FileDescriptorConfiguration {
+    fn default() -> Self {
+        Self {
+            nofile_limit: None,
+            usage_warning_percent: default_fd_usage_warning_percent(),
+            usage_check_interval: default_fd_usage_check_interval(),
+        }
+    }
+}
+
+// A principal is identified by bearer token or unix peer uid.
+#[derive(Deserialize, Serialize)]
+pub struct PrincipalRateLimit {
+    pub name: String,
+    #[serde(default, skip_serializing)]
+    pub bearer_token: Option<String>,
+    pub uid: Option<u32>,
+    pub requests: u32,
+    #[serde(with = "humantime_serde")]
+    pub period: Duration,
+}
+
+// Hide bearer tokens from configuration logging.
+impl std::fmt::Debug for PrincipalRateLimit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PrincipalRateLimit")
+            .field("name", &self.name)
+            .field(
+                "bearer_token",
+                &self.bearer_token.as_ref().map(|_| "[redacted]"),
+            )
+            .field("uid", &self.uid)
+            .field("requests", &self.requests)
+            .field("period", &self.period)
+            .finish()
+    }
+}
+
+// Requests under path_prefix get their own bucket per client.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct RouteClientRateLimit {
+    pub path_prefix: String,
+    pub requests_per_second: f64,
+    pub burst: u32,
+}
+
+// Token bucket per client IP, checked before routing.  Clients are
+// identified as in client_address_configuration, so requests from its
+// trusted_proxies are limited by the forwarded address.  Requests on UNIX
+// sockets are not limited.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ClientRateLimitConfiguration {
+    pub requests_per_second: f64,
+    pub burst: u32,
+    // first matching path_prefix wins
+    #[serde(default)]
+    pub routes: Vec<RouteClientRateLimit>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct RateLimitConfiguration {
+    #[serde(default)]
+    pub principals: Vec<PrincipalRateLimit>,
+    pub per_client: Option<ClientRateLimitConfiguration>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct RequestIdConfiguration {
+    // response header carrying the request ID, e.g. "x-request-id"
+    pub header: Option<String>,
+    // reuse a valid ID from the same request header instead of generating
+    // one, so servers chained behind each other share it
+    #[serde(default)]
+    pub accept_inbound: bool,
+}
+
+// Expensive routes shed with 503 while the server is busy, cheaper traffic
+// keeps being served.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct LoadSheddingRouteGroup {
+    pub name: String,
+    // matched against the request path, e.g. "/api/v1/commands/"
+    pub path_prefixes: Vec<String>,
+    // shed the group while more requests than this are in flight server wide
+    pub max_in_flight_requests: usize,
+}
+
+fn default_load_shedding_retry_after() -> Duration {
+    Duration::from_secs(5)
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct LoadSheddingConfiguration {
+    #[serde(default)]
+    pub route_groups: Vec<LoadSheddingRouteGroup>,
+    #[serde(
+        default = "default_load_shedding_retry_after",
+        with = "humantime_serde"
+    )]
+    pub retry_after: Duration,
+}
+
+impl Default for LoadSheddingConfiguration {
+    fn default() -> Self {
+        Self {
+            route_groups: Vec::new(),
+            retry_after: default_load_shedding_retry_after(),
+        }
+    }
+}
+
+// STDOUT output format, defaults to the LOG_FORMAT environment variable.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+pub enum LogFormat {
+    #[serde(rename = "DEV")]
+    Dev,
+
+    #[serde(rename = "PROD")]
+    Prod,
+
+    #[serde(rename = "JSON")]
+    Json,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+pub enum LogOutput {
+    #[serde(rename = "STDOUT")]
+    Stdout,
+
+    #[serde(rename = "SYSLOG")]
+    Syslog,
+
+    #[serde(rename = "JOURNALD")]
+    Journald,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+pub enum SyslogTransport {
+    #[serde(rename = "UDP")]
+    Udp,
+
+    #[serde(rename = "UNIX")]
+    Unix,
+}
+
+fn default_syslog_facility() -> u8 {
+    // daemon
+    3
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SyslogConfiguration {
+    pub transport: SyslogTransport,
+    pub address: String,
+    #[serde(default = "default_syslog_facility")]
+    pub facility: u8,
+    pub app_name: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize)]
+pub enum AccessLogFormat {
+    // Common Log Format with connection and request IDs and latency appended
+    #[default]
+    #[serde(rename = "COMMON")]
+    Common,
+
+    // one JSON object per line
+    #[serde(rename = "JSON")]
+    Json,
+}
+
+fn default_access_log_max_files() -> usize {
+    5
+}
+
+// Either a file at path or syslog, e.g. with facility 16 (local0) to keep
+// access logs apart from the server log.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct AccessLogConfiguration {
+    pub path: Option<String>,
+    pub syslog: Option<SyslogConfiguration>,
+    #[serde(default)]
+    pub format: AccessLogFormat,
+    // the file is rotated to path.1, path.2, ... once this size is reached
+    #[serde(default, deserialize_with = "units::deserialize_option_byte_size")]
+    pub max_file_size: Option<u64>,
+    // rotated files kept
+    #[serde(default = "default_access_log_max_files")]
+    pub max_files: usize,
+}
+
+fn default_log_outputs() -> Vec<LogOutput> {
+    vec![LogOutput::Stdout]
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct LoggingConfiguration {
+    #[serde(default = "default_log_outputs")]
+    pub outputs: Vec<LogOutput>,
+    pub format: Option<LogFormat>,
+    pub syslog: Option<SyslogConfiguration>,
+    #[serde(default)]
+    pub connection_events: bool,
+    pub access_log: Option<AccessLogConfiguration>,
+    // RUST_LOG syntax, replaces RUST_LOG when set, e.g. "info,rhs::handlers=debug"
+    pub filter: Option<String>,
+}
+
+impl Default for LoggingConfiguration {
+    fn default() -> Self {
+        Self {
+            outputs: default_log_outputs(),
+            format: None,
+            syslog: None,
+            connection_events: false,
+            access_log: None,
+            filter: None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct TrafficStatsConfiguration {
+    #[serde(with = "humantime_serde")]
+    pub window: Duration,
+    pub num_buckets: u32,
+}
+
+impl Default for TrafficStatsConfiguration {
+    fn default() -> Self {
+        Self {
+            window: Duration::from_secs(5 * 60),
+            num_buckets: 5,
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct ShutdownConfiguration {
+    pub report_path: Option<String>,
+    // when set a panic anywhere writes a crash report here and aborts
+    pub crash_report_path: Option<String>,
+}
+
+// Reported by the debug/buildinfo route so debuggers and profilers can find
+// the symbols for this binary.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct DebugConfiguration {
+    pub symbols_path: Option<String>,
+}
+
+// Periodic JSON stats snapshots, written only when directory is set.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SnapshotConfiguration {
+    pub directory: Option<String>,
+    #[serde(with = "humantime_serde", default = "default_snapshot_interval")]
+    pub interval: Duration,
+    // oldest snapshots beyond this are deleted
+    #[serde(default = "default_snapshot_max_files")]
+    pub max_files: usize,
+}
+
+fn default_snapshot_interval() -> Duration {
+    Duration::from_secs(60)
+}
+
+fn default_snapshot_max_files() -> usize {
+    60
+}
+
+impl Default for SnapshotConfiguration {
+    fn default() -> Self {
+        Self {
+            directory: None,
+            interval: default_snapshot_interval(),
+            max_files: default_snapshot_max_files(),
+        }
+    }
+}
+
+fn default_proxy_timeout() -> Duration {
+    Duration::from_secs(30)
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ProxyCanaryHeader {
+    pub name: String,
+    pub value: String,
+}
+
+// Second upstream for a proxy route.  A request goes to the canary when it
+// has the header, otherwise by its sticky cookie, otherwise by percent.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ProxyCanary {
+    pub upstream_url: String,
+    // share of requests sent to the canary, 0 to 100
+    #[serde(default)]
+    pub percent: f64,
+    pub header: Option<ProxyCanaryHeader>,
+    // cookie holding "canary" or "stable", set on responses so clients keep
+    // their assignment
+    pub sticky_cookie: Option<String>,
+}
+
+// Copies a share of a route's requests to a second upstream and discards
+// the responses, for trying a new backend with real traffic.  A mirror that
+// falls behind reading the request body is abandoned instead of slowing
+// the request.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ProxyMirror {
+    pub upstream_url: String,
+    // share of requests mirrored, 0 to 100
+    #[serde(default)]
+    pub percent: f64,
+}
+
+fn default_circuit_breaker_window_requests() -> usize {
+    20
+}
+
+fn default_circuit_breaker_open_duration() -> Duration {
+    Duration::from_secs(30)
+}
+
+// Cuts off each of a route's upstreams once it keeps failing: connection
+// errors, timeouts and 5xx responses count as failures.  An open breaker
+// answers 503 for open_duration, then lets one probe request through at a
+// time; a successful probe closes it, a failed one opens it again.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ProxyCircuitBreaker {
+    // opens after this many failures in a row
+    pub consecutive_failures: Option<u32>,
+    // opens when this share of the last window_requests requests failed, 0 to 100
+    pub error_rate_percent: Option<f64>,
+    #[serde(default = "default_circuit_breaker_window_requests")]
+    pub window_requests: usize,
+    #[serde(
+        with = "humantime_serde",
+        default = "default_circuit_breaker_open_duration"
+    )]
+    pub open_duration: Duration,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ProxyRoute {
+    // requests whose path is path_prefix or continues it with a new path
+    // segment are proxied, e.g. "/internal" matches "/internal/a" but not
+    // "/internalx"
+    pub path_prefix: String,
+    // http only, path_prefix is replaced with the URL's path, e.g. "http://127.0.0.1:9000/"
+    pub upstream_url: String,
+    // until the upstream response headers arrive, shortened to what is left
+    // of the request's deadline: the connection closing after max_lifetime
+    // and graceful_shutdown_timeout, or a client's grpc-timeout
+    #[serde(with = "humantime_serde", default = "default_proxy_timeout")]
+    pub timeout: Duration,
+    // send the client's Host header instead of the upstream's
+    #[serde(default)]
+    pub preserve_host: bool,
+    pub canary: Option<ProxyCanary>,
+    // source address of upstream connections, for multi-homed hosts and
+    // source IP firewall rules; only used for upstreams of the same family
+    pub local_address: Option<IpAddr>,
+    // Linux only, binds upstream connections to a network interface with
+    // SO_BINDTODEVICE, e.g. "eth1"
+    pub interface: Option<String>,
+    // one breaker each for upstream_url and the canary
+    pub circuit_breaker: Option<ProxyCircuitBreaker>,
+    pub mirror: Option<ProxyMirror>,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize)]
+pub enum ProxyDnsStrategy {
+    // connect to the addresses in the order the resolver returns them
+    #[default]
+    #[serde(rename = "FIRST")]
+    First,
+
+    // start each new connection at the next address, spreading connections
+    // across a host's A/AAAA records
+    #[serde(rename = "ROUND_ROBIN")]
+    RoundRobin,
+}
+
+fn default_proxy_dns_refresh_interval() -> Duration {
+    Duration::from_secs(30)
+}
+
+fn default_proxy_pool_idle_timeout() -> Duration {
+    Duration::from_secs(90)
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ProxyDnsConfiguration {
+    // upstream host addresses are re-resolved once older than this
+    #[serde(
+        with = "humantime_serde",
+        default = "default_proxy_dns_refresh_interval"
+    )]
+    pub refresh_interval: Duration,
+    #[serde(default)]
+    pub strategy: ProxyDnsStrategy,
+}
+
+impl Default for ProxyDnsConfiguration {
+    fn default() -> Self {
+        Self {
+            refresh_interval: default_proxy_dns_refresh_interval(),
+            strategy: ProxyDnsStrategy::default(),
+        }
+    }
+}
+
+// Routes forwarded to upstream HTTP servers, tried in order after the
+// dynamic routes and before static files.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ProxyConfiguration {
+    #[serde(default)]
+    pub routes: Vec<ProxyRoute>,
+    #[serde(default)]
+    pub dns: ProxyDnsConfiguration,
+    // idle upstream connections are closed after this, so connections to
+    // addresses no longer resolved age out
+    #[serde(with = "humantime_serde", default = "default_proxy_pool_idle_timeout")]
+    pub pool_idle_timeout: Duration,
+}
+
+impl Default for ProxyConfiguration {
+    fn default() -> Self {
+        Self {
+            routes: Vec::new(),
+            dns: ProxyDnsConfiguration::default(),
+            pool_idle_timeout: default_proxy_pool_idle_timeout(),
+        }
+    }
+}
+
+fn default_cgi_timeout() -> Duration {
+    Duration::from_secs(30)
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CgiRoute {
+    // requests whose path starts with path_prefix run a script, e.g. "/cgi-bin/"
+    pub path_prefix: String,
+    // the path segment after path_prefix names a script in directory, the
+    // rest of the path is passed as PATH_INFO
+    pub directory: String,
+    // runs scripts with an interpreter instead of executing them, e.g. "/usr/bin/php-cgi"
+    pub interpreter: Option<String>,
+    // until the script's response headers arrive
+    #[serde(with = "humantime_serde", default = "default_cgi_timeout")]
+    pub timeout: Duration,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct CgiConfiguration {
+    #[serde(default)]
+    pub routes: Vec<CgiRoute>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct ClientAddressConfiguration {
+    // requests from peers in these networks take their client address from
+    // the Forwarded or X-Forwarded-For header, skipping addresses in them
+    #[serde(default)]
+    pub trusted_proxies: Vec<IpNet>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct VirtualHostSite {
+    // shown in logs
+    pub name: String,
+    // Host header values without port, "*.example.com" matches any subdomain
+    // of example.com
+    pub hosts: Vec<String>,
+    pub static_file_configuration: StaticFileConfiguration,
+    // path_suffix prefixes of the dynamic routes served for this site, all
+    // routes when unset.  proxy_configuration and cgi_configuration routes
+    // run first and serve every host.
+    pub routes: Option<Vec<String>>,
+    // requests for this site are logged here instead of
+    // logging_configuration.access_log
+    pub access_log: Option<AccessLogConfiguration>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct AccessLogRouteGroup {
+    // shown in logs
+    pub name: String,
+    // request path prefixes, e.g. "/api/v1/commands/"
+    pub path_prefixes: Vec<String>,
+    pub access_log: AccessLogConfiguration,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct VirtualHostConfiguration {
+    // first matching site wins, requests for other hosts use the top level
+    // static_file_configuration and all routes
+    #[serde(default)]
+    pub sites: Vec<VirtualHostSite>,
+    // requests matching a group's path_prefixes are logged to its access_log
+    // instead of their site's or logging_configuration.access_log, first
+    // matching group wins
+    #[serde(default)]
+    pub access_log_route_groups: Vec<AccessLogRouteGroup>,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -97,38 +1248,380 @@ pub struct Configuration {
     pub static_file_configuration: StaticFileConfiguration,
     pub context_configuration: ContextConfiguration,
     pub command_configuration: CommandConfiguration,
+    #[serde(default)]
+    pub authorization_configuration: AuthorizationConfiguration,
+    #[serde(default)]
+    pub runtime_configuration: RuntimeConfiguration,
+    #[serde(default)]
+    pub request_target_configuration: RequestTargetConfiguration,
+    #[serde(default)]
+    pub logging_configuration: LoggingConfiguration,
+    #[serde(default)]
+    pub geoip_configuration: GeoIpConfiguration,
+    #[serde(default)]
+    pub traffic_stats_configuration: TrafficStatsConfiguration,
+    #[serde(default)]
+    pub endpoints_configuration: EndpointsConfiguration,
+    #[serde(default)]
+    pub shutdown_configuration: ShutdownConfiguration,
+    #[serde(default)]
+    pub snapshot_configuration: SnapshotConfiguration,
+    #[serde(default)]
+    pub debug_configuration: DebugConfiguration,
+    #[serde(default)]
+    pub file_descriptor_configuration: FileDescriptorConfiguration,
+    #[serde(default)]
+    pub rate_limit_configuration: RateLimitConfiguration,
+    #[serde(default)]
+    pub load_shedding_configuration: LoadSheddingConfiguration,
+    #[serde(default)]
+    pub request_id_configuration: RequestIdConfiguration,
+    #[serde(default)]
+    pub proxy_configuration: ProxyConfiguration,
+    #[serde(default)]
+    pub cgi_configuration: CgiConfiguration,
+    #[serde(default)]
+    pub metrics_configuration: MetricsConfiguration,
+    #[serde(default)]
+    pub compression_configuration: CompressionConfiguration,
+    #[serde(default)]
+    pub virtual_host_configuration: VirtualHostConfiguration,
+    #[serde(default)]
+    pub client_address_configuration: ClientAddressConfiguration,
+}
+
+static CONFIGURATION_INSTANCE: OnceCell<Arc<Configuration>> = OnceCell::const_new();
+
+#[derive(Clone, Copy, Debug)]
+pub struct ConfigurationGeneration {
+    pub generation: u64,
+    pub load_time: SystemTime,
+}
+
+#[derive(Clone, Debug)]
+pub struct ConfigurationLoadRecord {
+    pub time: SystemTime,
+    pub config_file: String,
+    // None if the file could not be read
+    pub sha256: Option<String>,
+    // generation number applied, or the error that rejected the file
+    pub result: Result<u64, String>,
+}
+
+pub use profile::{ConfigurationProfile, PROFILE_ENV_VAR};
+pub use validate::validate_configuration_file;
+
+use validate::validate_parsed_configuration;
+
+static CONFIG_FILE: OnceLock<String> = OnceLock::new();
+
+// config_file "-" reads the configuration from stdin, "env:NAME" from the
+// environment variable NAME.
+const STDIN_CONFIG_FILE: &str = "-";
+const ENV_CONFIG_FILE_PREFIX: &str = "env:";
+
+// Used when no config file argument is given.
+pub const CONFIG_ENV_VAR: &str = "RHS_CONFIG";
+
+// With a profile the config file is optional, its defaults are used alone.
+pub const NO_CONFIG_FILE: &str = "<none>";
+
+static PROFILE: OnceLock<ConfigurationProfile> = OnceLock::new();
+
+// stdin can only be read once, validation and reloads reuse what was read.
+static STDIN_CONTENTS: OnceLock<Vec<u8>> = OnceLock::new();
+
+// The latest reloaded configuration, None until the first reload.  Replaced
+// configurations are dropped once no reader holds them.
+static RELOADED_CONFIGURATION: RwLock<Option<Arc<Configuration>>> = RwLock::new(None);
+
+const MAX_LOAD_HISTORY: usize = 20;
+
+struct ConfigurationHistory {
+    current: Option<ConfigurationGeneration>,
+    records: VecDeque<ConfigurationLoadRecord>,
+}
+
+static CONFIGURATION_HISTORY: Mutex<ConfigurationHistory> = Mutex::new(ConfigurationHistory {
+    current: None,
+    records: VecDeque::new(),
+});
+
+fn record_load(config_file: &str, sha256: Option<String>, result: &anyhow::Result<()>) {
+    let mut history = CONFIGURATION_HISTORY.lock().unwrap();
+
+    let time = SystemTime::now();
+
+    let result = match result {
+        Err(e) => Err(format!("{:#}", e)),
+        Ok(()) => {
+            let generation = history.current.map_or(1, |current| current.generation + 1);
+
+            history.current = Some(ConfigurationGeneration {
+                generation,
+                load_time: time,
+            });
+
+            Ok(generation)
+        }
+    };
+
+    if history.records.len() >= MAX_LOAD_HISTORY {
+        history.records.pop_front();
+    }
+
+    history.records.push_back(ConfigurationLoadRecord {
+        time,
+        config_file: config_file.to_owned(),
+        sha256,
+        result,
+    });
 }
 
-static CONFIGURATION_INSTANCE: OnceCell<Configuration> = OnceCell::const_new();
+pub fn env_config_file(name: &str) -> String {
+    format!("{}{}", ENV_CONFIG_FILE_PREFIX, name)
+}
+
+fn read_config_file(config_file: &str) -> anyhow::Result<Vec<u8>> {
+    if config_file == NO_CONFIG_FILE {
+        return Ok(Vec::new());
+    }
 
-pub async fn read_configuration(config_file: String) -> anyhow::Result<()> {
-    debug!("reading '{}'", config_file);
+    if config_file == STDIN_CONFIG_FILE {
+        if let Some(stdin_contents) = STDIN_CONTENTS.get() {
+            return Ok(stdin_contents.clone());
+        }
 
-    let mut file = File::open(&config_file)
-        .await
-        .with_context(|| format!("error opening '{}'", config_file))?;
+        let mut stdin_contents = Vec::new();
+        std::io::stdin()
+            .read_to_end(&mut stdin_contents)
+            .context("error reading configuration from stdin")?;
 
-    let mut file_contents = Vec::new();
+        return Ok(STDIN_CONTENTS.get_or_init(|| stdin_contents).clone());
+    }
 
-    file.read_to_end(&mut file_contents)
-        .await
-        .with_context(|| format!("error reading '{}'", config_file))?;
+    if let Some(name) = config_file.strip_prefix(ENV_CONFIG_FILE_PREFIX) {
+        return std::env::var(name)
+            .map(String::into_bytes)
+            .with_context(|| format!("error reading environment variable '{}'", name));
+    }
+
+    std::fs::read(config_file).with_context(|| format!("error reading '{}'", config_file))
+}
 
+fn parse_configuration(file_contents: Vec<u8>, config_file: &str) -> anyhow::Result<Configuration> {
     let file_contents_string = String::from_utf8(file_contents)
         .with_context(|| format!("String::from_utf8 error reading '{}'", config_file))?;
 
-    let configuration: Configuration = ::toml::from_str(&file_contents_string)
-        .with_context(|| format!("error unmarshalling '{}'", config_file))?;
+    let configuration: Configuration = match PROFILE.get() {
+        None => ::toml::from_str(&file_contents_string)
+            .with_context(|| format!("error unmarshalling '{}'", config_file))?,
+        Some(profile) => {
+            let overrides = ::toml::from_str(&file_contents_string)
+                .with_context(|| format!("error unmarshalling '{}'", config_file))?;
 
-    debug!("configuration\n{:#?}", configuration);
+            ::toml::Value::Table(profile.merge(overrides)?)
+                .try_into()
+                .with_context(|| {
+                    format!("error applying profile {:?} to '{}'", profile, config_file)
+                })?
+        }
+    };
 
-    CONFIGURATION_INSTANCE
-        .set(configuration)
-        .context("CONFIGURATION_INSTANCE.set error")?;
+    Ok(configuration)
+}
+
+// Rejects configurations with validation errors, warnings are only reported
+// by POST admin/config/validate.
+fn check_configuration(configuration: &Configuration) -> anyhow::Result<()> {
+    let report = validate_parsed_configuration(configuration);
+
+    if !report.errors.is_empty() {
+        anyhow::bail!("invalid configuration: {}", report.errors.join("; "));
+    }
 
     Ok(())
 }
 
+// Synchronous so the configuration is available before the tokio runtime is built.
+// Called before tracing is initialized, so nothing is logged here.
+pub fn read_configuration(
+    config_file: String,
+    profile: Option<ConfigurationProfile>,
+) -> anyhow::Result<()> {
+    if let Some(profile) = profile {
+        let _ = PROFILE.set(profile);
+    }
+
+    let file_contents = read_config_file(&config_file);
+
+    let sha256 = file_contents
+        .as_ref()
+        .ok()
+        .map(|file_contents| format!("{:x}", Sha256::digest(file_contents)));
+
+    let result = file_contents
+        .and_then(|file_contents| parse_configuration(file_contents, &config_file))
+        .and_then(|configuration| {
+            check_configuration(&configuration)?;
+
+            CONFIGURATION_INSTANCE
+                .set(Arc::new(configuration))
+                .context("CONFIGURATION_INSTANCE.set error")
+        });
+
+    record_load(&config_file, sha256, &result);
+
+    let _ = CONFIG_FILE.set(config_file);
+
+    result
+}
+
+// Re-reads config_file() and validates it, stdin is not read again.  apply is called with the new
+// configuration, which becomes current() once apply succeeds.
+pub fn reload_configuration(
+    apply: impl FnOnce(&Configuration) -> anyhow::Result<()>,
+) -> anyhow::Result<u64> {
+    let config_file = config_file();
+
+    let file_contents = read_config_file(config_file);
+
+    let sha256 = file_contents
+        .as_ref()
+        .ok()
+        .map(|file_contents| format!("{:x}", Sha256::digest(file_contents)));
+
+    let result = file_contents
+        .and_then(|file_contents| parse_configuration(file_contents, config_file))
+        .and_then(|configuration| {
+            check_configuration(&configuration)?;
+
+            apply(&configuration)?;
+
+            let configuration = Arc::new(configuration);
+
+            *RELOADED_CONFIGURATION.write().unwrap() = Some(configuration);
+
+            Ok(())
+        });
+
+    record_load(config_file, sha256, &result);
+
+    result?;
+
+    Ok(generation().generation)
+}
+
+// Unit tests and benches that need the global configuration use config/test.toml.
+pub fn set_test_instance() {
+    let configuration =
+        parse_configuration(include_bytes!("../config/test.toml").to_vec(), "test.toml").unwrap();
+
+    let _ = CONFIGURATION_INSTANCE.set(Arc::new(configuration));
+}
+
+pub fn config_file() -> &'static str {
+    CONFIG_FILE.get().unwrap()
+}
+
+pub fn profile() -> Option<ConfigurationProfile> {
+    PROFILE.get().copied()
+}
+
+// The configuration read at startup.  Only the parts applied by a reload
+// should be read from current() instead.
 pub fn instance() -> &'static Configuration {
     CONFIGURATION_INSTANCE.get().unwrap()
 }
+
+pub fn current() -> Arc<Configuration> {
+    RELOADED_CONFIGURATION
+        .read()
+        .unwrap()
+        .clone()
+        .unwrap_or_else(|| Arc::clone(CONFIGURATION_INSTANCE.get().unwrap()))
+}
+
+pub fn generation() -> ConfigurationGeneration {
+    CONFIGURATION_HISTORY.lock().unwrap().current.unwrap()
+}
+
+// Oldest first.
+pub fn load_history() -> Vec<ConfigurationLoadRecord> {
+    CONFIGURATION_HISTORY
+        .lock()
+        .unwrap()
+        .records
+        .iter()
+        .cloned()
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_read_configuration_rejects_invalid() {
+        let invalid_sections = [
+            // below hyper's minimum buffer size and over the HTTP/2 u32 limit
+            (
+                "[server_configuration.limits]\nmax_header_bytes = 1024",
+                "max_header_bytes",
+            ),
+            (
+                "[server_configuration.limits]\nmax_header_bytes = 4294967296",
+                "max_header_bytes",
+            ),
+            (
+                "[rate_limit_configuration.per_client]\nrequests_per_second = 0.0\nburst = 1",
+                "requests_per_second",
+            ),
+            (
+                "[logging_configuration.access_log]\nformat = \"JSON\"",
+                "exactly one of path and syslog",
+            ),
+        ];
+
+        for (index, (section, expected_error)) in invalid_sections.into_iter().enumerate() {
+            let contents = format!("{}\n{}\n", include_str!("../config/test.toml"), section);
+
+            let config_file = std::env::temp_dir().join(format!(
+                "rhs-test-invalid-{}-{}.toml",
+                std::process::id(),
+                index
+            ));
+            std::fs::write(&config_file, contents).unwrap();
+
+            let result = read_configuration(config_file.to_string_lossy().into_owned(), None);
+
+            std::fs::remove_file(&config_file).unwrap();
+
+            let error = format!("{:#}", result.unwrap_err());
+            assert!(error.contains(expected_error), "{}", error);
+            assert!(load_history().last().unwrap().result.is_err());
+        }
+    }
+
+    #[test]
+    fn test_proxy_protocol_allows_source() {
+        let mut proxy_protocol = ListenerProxyProtocolConfiguration {
+            header_timeout: default_proxy_protocol_header_timeout(),
+            allowed_sources: Vec::new(),
+        };
+
+        assert!(proxy_protocol.allows_source("192.0.2.1".parse().unwrap()));
+
+        proxy_protocol.allowed_sources = vec![
+            "10.0.0.0/8".parse().unwrap(),
+            "2001:db8::1/128".parse().unwrap(),
+        ];
+
+        assert!(proxy_protocol.allows_source("10.1.2.3".parse().unwrap()));
+        assert!(proxy_protocol.allows_source("::ffff:10.1.2.3".parse().unwrap()));
+        assert!(proxy_protocol.allows_source("2001:db8::1".parse().unwrap()));
+        assert!(!proxy_protocol.allows_source("192.0.2.1".parse().unwrap()));
+        assert!(!proxy_protocol.allows_source("::ffff:192.0.2.1".parse().unwrap()));
+        assert!(!proxy_protocol.allows_source("2001:db8::2".parse().unwrap()));
+    }
+}
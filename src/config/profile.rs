@@ -0,0 +1,140 @@
+use anyhow::Context;
+
+use std::str::FromStr;
+
+// Selected with --profile or RHS_PROFILE.
+pub const PROFILE_ENV_VAR: &str = "RHS_PROFILE";
+
+// JSON logs to stdout and one TCP listener on PORT, no UNIX socket.
+const CONTAINER_PROFILE_TOML: &str = r#"
+[server_configuration]
+listeners = [{ socket_type = "TCP", bind_address = "0.0.0.0:${PORT}" }]
+connection = { limit = 1024, max_lifetime = "1h", graceful_shutdown_timeout = "10s" }
+
+[static_file_configuration]
+root = "/srv/www"
+precompressed = { br = false, gz = false }
+client_error_page_path = "/error.html"
+cache_rules = []
+
+[context_configuration]
+dynamic_route_context = "/api/v1"
+
+[command_configuration]
+max_concurrent_commands = 1
+semaphore_acquire_timeout = "200ms"
+commands = []
+
+[logging_configuration]
+outputs = ["STDOUT"]
+format = "JSON"
+"#;
+
+const DEFAULT_CONTAINER_PORT: u16 = 8080;
+
+// Defaults the configuration file is merged over, so only what differs from
+// them needs to be configured.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ConfigurationProfile {
+    Container,
+}
+
+impl FromStr for ConfigurationProfile {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> anyhow::Result<Self> {
+        match value {
+            "container" => Ok(Self::Container),
+            _ => anyhow::bail!("unknown configuration profile '{}'", value),
+        }
+    }
+}
+
+impl ConfigurationProfile {
+    fn defaults(&self, port: Option<&str>) -> anyhow::Result<toml::Table> {
+        match self {
+            Self::Container => {
+                let port = match port {
+                    None => DEFAULT_CONTAINER_PORT,
+                    Some(port) => port
+                        .parse()
+                        .with_context(|| format!("invalid PORT '{}'", port))?,
+                };
+
+                toml::from_str(&CONTAINER_PROFILE_TOML.replace("${PORT}", &port.to_string()))
+                    .context("error parsing container profile")
+            }
+        }
+    }
+
+    // overrides replaces arrays such as listeners as a whole.
+    pub fn merge(&self, overrides: toml::Table) -> anyhow::Result<toml::Table> {
+        let mut table = self.defaults(std::env::var("PORT").ok().as_deref())?;
+
+        merge_tables(&mut table, overrides);
+
+        Ok(table)
+    }
+}
+
+fn merge_tables(table: &mut toml::Table, overrides: toml::Table) {
+    for (key, value) in overrides {
+        match (table.get_mut(&key), value) {
+            (Some(toml::Value::Table(table)), toml::Value::Table(overrides)) => {
+                merge_tables(table, overrides)
+            }
+            (_, value) => {
+                table.insert(key, value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::config::Configuration;
+
+    #[test]
+    fn test_container_profile() {
+        let mut table = ConfigurationProfile::Container
+            .defaults(Some("9090"))
+            .unwrap();
+
+        merge_tables(
+            &mut table,
+            toml::from_str(
+                r#"
+                [static_file_configuration]
+                root = "/var/www"
+
+                [context_configuration]
+                disabled_routes = ["commands"]
+                "#,
+            )
+            .unwrap(),
+        );
+
+        let configuration: Configuration = toml::Value::Table(table).try_into().unwrap();
+
+        let listeners = &configuration.server_configuration.listeners;
+        assert_eq!(listeners.len(), 1);
+        assert_eq!(listeners[0].bind_address, "0.0.0.0:9090");
+
+        assert_eq!(configuration.static_file_configuration.root, "/var/www");
+        assert!(!configuration.static_file_configuration.precompressed.gz);
+        assert_eq!(
+            configuration.context_configuration.dynamic_route_context,
+            "/api/v1"
+        );
+        assert_eq!(
+            configuration.context_configuration.disabled_routes,
+            vec!["commands"]
+        );
+
+        assert!(ConfigurationProfile::Container
+            .defaults(Some("http"))
+            .is_err());
+    }
+}
@@ -0,0 +1,106 @@
+use serde::{de, Deserialize, Deserializer};
+
+use std::time::Duration;
+
+// Byte sizes and the durations that used to be plain integers accept either
+// an integer (bytes or seconds) or a string such as "10MiB" or "250ms".
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum IntegerOrString {
+    Integer(u64),
+    String(String),
+}
+
+fn parse_byte_size(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+
+    let unit_start = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+
+    let (number, unit) = value.split_at(unit_start);
+
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("invalid byte size '{}'", value))?;
+
+    let multiplier: u64 = match unit.trim() {
+        "" | "B" => 1,
+        "KB" => 1_000,
+        "MB" => 1_000_000,
+        "GB" => 1_000_000_000,
+        "KiB" => 1 << 10,
+        "MiB" => 1 << 20,
+        "GiB" => 1 << 30,
+        unit => return Err(format!("unknown byte size unit '{}' in '{}'", unit, value)),
+    };
+
+    number
+        .checked_mul(multiplier)
+        .ok_or_else(|| format!("byte size '{}' out of range", value))
+}
+
+pub fn deserialize_option_byte_size<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: TryFrom<u64>,
+{
+    let bytes = match Option::<IntegerOrString>::deserialize(deserializer)? {
+        None => return Ok(None),
+        Some(IntegerOrString::Integer(bytes)) => bytes,
+        Some(IntegerOrString::String(value)) => {
+            parse_byte_size(&value).map_err(de::Error::custom)?
+        }
+    };
+
+    T::try_from(bytes)
+        .map(Some)
+        .map_err(|_| de::Error::custom(format!("byte size {} out of range", bytes)))
+}
+
+pub fn deserialize_byte_size<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: TryFrom<u64>,
+{
+    deserialize_option_byte_size(deserializer)?
+        .ok_or_else(|| de::Error::custom("byte size required"))
+}
+
+// Integer seconds or a humantime string, serialized as a humantime string.
+pub mod option_duration {
+    use super::*;
+
+    pub use humantime_serde::serialize;
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<Duration>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        match Option::<IntegerOrString>::deserialize(deserializer)? {
+            None => Ok(None),
+            Some(IntegerOrString::Integer(seconds)) => Ok(Some(Duration::from_secs(seconds))),
+            Some(IntegerOrString::String(value)) => {
+                humantime_serde::re::humantime::parse_duration(&value)
+                    .map(Some)
+                    .map_err(|e| de::Error::custom(format!("invalid duration '{}': {}", value, e)))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_byte_size() {
+        assert_eq!(parse_byte_size("512"), Ok(512));
+        assert_eq!(parse_byte_size("64KiB"), Ok(64 * 1024));
+        assert_eq!(parse_byte_size("10 MiB"), Ok(10 * 1024 * 1024));
+        assert_eq!(parse_byte_size("2GB"), Ok(2_000_000_000));
+        assert!(parse_byte_size("10mb").is_err());
+        assert!(parse_byte_size("MiB").is_err());
+        assert!(parse_byte_size("99999999999GiB").is_err());
+    }
+}
@@ -0,0 +1,804 @@
+use std::{collections::HashSet, path::Path};
+
+use super::{
+    parse_configuration, read_config_file, Configuration, LogOutput, ServerSocketType,
+    StaticFileConfiguration, UnknownHostAction, BWRAP_PATH, MIN_MAX_HEADER_BYTES, NICE_PATH,
+    PRLIMIT_PATH,
+};
+
+const NAMED_PIPE_PREFIX: &str = r"\\.\pipe\";
+
+#[derive(Debug, Default)]
+pub struct ValidationReport {
+    pub errors: Vec<String>,
+    pub warnings: Vec<String>,
+}
+
+impl ValidationReport {
+    fn error(&mut self, message: String) {
+        self.errors.push(message);
+    }
+
+    fn warning(&mut self, message: String) {
+        self.warnings.push(message);
+    }
+}
+
+fn validate_server(configuration: &Configuration, report: &mut ValidationReport) {
+    let server_configuration = &configuration.server_configuration;
+
+    if server_configuration.listeners.is_empty() {
+        report.error("server_configuration.listeners is empty".to_owned());
+    }
+
+    for listener in &server_configuration.listeners {
+        if listener.socket_type == ServerSocketType::Unix && !cfg!(unix) {
+            report.error(format!(
+                "unix listener '{}' is not supported on this platform",
+                listener.bind_address
+            ));
+        }
+
+        let socket_options = &listener.socket_options;
+
+        // SO_RCVBUF and SO_SNDBUF take a c int
+        if [
+            socket_options.recv_buffer_size,
+            socket_options.send_buffer_size,
+        ]
+        .into_iter()
+        .flatten()
+        .any(|buffer_size| buffer_size == 0 || buffer_size > i32::MAX as usize)
+        {
+            report.error(format!(
+                "listener '{}' buffer sizes must be between 1 and {} bytes",
+                listener.bind_address,
+                i32::MAX
+            ));
+        }
+
+        if listener.socket_type == ServerSocketType::NamedPipe && !cfg!(windows) {
+            report.error(format!(
+                "named pipe listener '{}' is not supported on this platform",
+                listener.bind_address
+            ));
+        }
+
+        if listener.socket_type == ServerSocketType::NamedPipe
+            && !listener.bind_address.starts_with(NAMED_PIPE_PREFIX)
+        {
+            report.error(format!(
+                "named pipe listener '{}' must start with {}",
+                listener.bind_address, NAMED_PIPE_PREFIX
+            ));
+        }
+
+        if let Some(tls) = &listener.tls {
+            if listener.socket_type != ServerSocketType::Tcp {
+                report.error(format!(
+                    "listener '{}' tls is only supported for TCP listeners",
+                    listener.bind_address
+                ));
+            }
+
+            for path in [&tls.cert_path, &tls.key_path] {
+                if !Path::new(path).is_file() {
+                    report.error(format!(
+                        "listener '{}' tls file '{}' not found",
+                        listener.bind_address, path
+                    ));
+                }
+            }
+        }
+
+        if let Some(proxy_protocol) = &listener.proxy_protocol {
+            if listener.socket_type == ServerSocketType::NamedPipe {
+                report.error(format!(
+                    "listener '{}' proxy_protocol is not supported for named pipe listeners",
+                    listener.bind_address
+                ));
+            }
+
+            if proxy_protocol.header_timeout.is_zero() {
+                report.error(format!(
+                    "listener '{}' proxy_protocol header_timeout must be greater than 0",
+                    listener.bind_address
+                ));
+            }
+
+            if listener.socket_type != ServerSocketType::Tcp {
+                if !proxy_protocol.allowed_sources.is_empty() {
+                    report.error(format!(
+                        "listener '{}' proxy_protocol allowed_sources is only supported for tcp listeners",
+                        listener.bind_address
+                    ));
+                }
+            } else if proxy_protocol.allowed_sources.is_empty() {
+                report.warning(format!(
+                    "listener '{}' proxy_protocol has no allowed_sources, any peer can choose its client address",
+                    listener.bind_address
+                ));
+            }
+        }
+
+        if listener.socket_type == ServerSocketType::Unix {
+            let parent = Path::new(&listener.bind_address).parent();
+
+            if parent.is_some_and(|parent| !parent.as_os_str().is_empty() && !parent.is_dir()) {
+                report.error(format!(
+                    "unix listener directory does not exist for '{}'",
+                    listener.bind_address
+                ));
+            }
+        }
+    }
+
+    for network in &configuration.client_address_configuration.trusted_proxies {
+        if network.prefix_len() == 0 {
+            report.warning(format!(
+                "client_address_configuration.trusted_proxies '{}' lets every client choose its address",
+                network
+            ));
+        }
+    }
+
+    let limits = &server_configuration.limits;
+
+    // also the HTTP/2 header list size, which is a u32
+    if limits.max_header_bytes < MIN_MAX_HEADER_BYTES || limits.max_header_bytes > u32::MAX as usize
+    {
+        report.error(format!(
+            "server_configuration.limits.max_header_bytes must be between {} and {}",
+            MIN_MAX_HEADER_BYTES,
+            u32::MAX
+        ));
+    }
+
+    if limits.max_uri_length == 0 {
+        report
+            .error("server_configuration.limits.max_uri_length must be greater than 0".to_owned());
+    }
+
+    if limits.max_body_bytes == 0 {
+        report
+            .error("server_configuration.limits.max_body_bytes must be greater than 0".to_owned());
+    }
+}
+
+// name is the configuration path of static_file_configuration, for messages.
+fn validate_static_files(
+    name: &str,
+    static_file_configuration: &StaticFileConfiguration,
+    report: &mut ValidationReport,
+) {
+    let root = Path::new(&static_file_configuration.root);
+
+    if !root.is_dir() {
+        report.error(format!(
+            "{}.root '{}' is not a directory",
+            name, static_file_configuration.root
+        ));
+    } else {
+        let client_error_page = root.join(
+            static_file_configuration
+                .client_error_page_path
+                .trim_start_matches('/'),
+        );
+
+        if !client_error_page.is_file() {
+            report.warning(format!(
+                "{}.client_error_page_path '{}' not found under root",
+                name, static_file_configuration.client_error_page_path
+            ));
+        }
+    }
+
+    if static_file_configuration.read_chunk_size == Some(0) {
+        report.error(format!("{}.read_chunk_size must be greater than 0", name));
+    }
+
+    for directory_listing in &static_file_configuration.directory_listings {
+        if !directory_listing.path_prefix.starts_with('/')
+            || !directory_listing.path_prefix.ends_with('/')
+        {
+            report.error(format!(
+                "directory listing path_prefix '{}' must start and end with '/'",
+                directory_listing.path_prefix
+            ));
+        }
+    }
+
+    if static_file_configuration.max_ranges == Some(0) {
+        report.error(format!("{}.max_ranges must be greater than 0", name));
+    }
+
+    for (index, filter_rule) in static_file_configuration.filter_rules.iter().enumerate() {
+        if let Err(e) = crate::static_file::FilterRule::new(index, filter_rule) {
+            report.error(format!(
+                "invalid filter rule for path_regex '{}': {:#}",
+                filter_rule.path_regex, e
+            ));
+        }
+    }
+
+    for preload_rule in &static_file_configuration.preload_rules {
+        if let Err(e) = crate::static_file::PreloadRule::new(preload_rule) {
+            report.error(format!(
+                "invalid preload rule for path_regex '{}': {:#}",
+                preload_rule.path_regex, e
+            ));
+        }
+    }
+
+    for cache_rule in &static_file_configuration.cache_rules {
+        if let Err(e) = regex::Regex::new(&cache_rule.path_regex) {
+            report.error(format!(
+                "invalid cache_rules path_regex '{}': {}",
+                cache_rule.path_regex, e
+            ));
+        }
+
+        if let Err(e) = crate::static_file::build_cache_control(cache_rule, None) {
+            report.error(format!(
+                "invalid cache rule for path_regex '{}': {}",
+                cache_rule.path_regex, e
+            ));
+        }
+    }
+
+    for (shadowed, shadowing) in crate::static_file::shadowed_cache_rules(
+        &static_file_configuration.cache_rules,
+        static_file_configuration.cache_rule_matching,
+    ) {
+        report.warning(format!(
+            "cache rule '{}' is shadowed by earlier rule '{}' and never matches",
+            shadowed, shadowing
+        ));
+    }
+}
+
+fn validate_virtual_hosts(configuration: &Configuration, report: &mut ValidationReport) {
+    let mut names = HashSet::new();
+    let mut hosts = HashSet::new();
+
+    for (index, site) in configuration
+        .virtual_host_configuration
+        .sites
+        .iter()
+        .enumerate()
+    {
+        if !names.insert(&site.name) {
+            report.error(format!("duplicate virtual host site name '{}'", site.name));
+        }
+
+        if site.hosts.is_empty() {
+            report.error(format!("virtual host site '{}' hosts is empty", site.name));
+        }
+
+        for host in &site.hosts {
+            let host_without_wildcard = host.strip_prefix("*.").unwrap_or(host);
+
+            if host_without_wildcard.is_empty()
+                || !host_without_wildcard
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'.')
+            {
+                report.error(format!(
+                    "virtual host site '{}' host '{}' must be a host name without port, optionally starting with '*.'",
+                    site.name, host
+                ));
+            }
+
+            if !hosts.insert(host.to_ascii_lowercase()) {
+                report.warning(format!(
+                    "virtual host site '{}' host '{}' is already used by an earlier site",
+                    site.name, host
+                ));
+            }
+        }
+
+        validate_static_files(
+            &format!(
+                "virtual_host_configuration.sites[{}].static_file_configuration",
+                index
+            ),
+            &site.static_file_configuration,
+            report,
+        );
+    }
+}
+
+fn validate_routes(configuration: &Configuration, report: &mut ValidationReport) {
+    let context_configuration = &configuration.context_configuration;
+
+    for (name, path) in [
+        (
+            "dynamic_route_context",
+            &context_configuration.dynamic_route_context,
+        ),
+        ("openapi_path", &context_configuration.openapi_path),
+    ] {
+        if !path.starts_with('/') {
+            report.error(format!(
+                "context_configuration.{} '{}' must start with '/'",
+                name, path
+            ));
+        }
+    }
+
+    let mut command_ids = HashSet::new();
+
+    for command_info in &configuration.command_configuration.commands {
+        if !command_ids.insert(&command_info.id) {
+            report.error(format!("duplicate command id '{}'", command_info.id));
+        }
+
+        if !Path::new(&command_info.command).is_file() {
+            report.warning(format!(
+                "command '{}' for id '{}' not found",
+                command_info.command, command_info.id
+            ));
+        }
+
+        let limits = &command_info.limits;
+
+        if limits.cpu_time.is_some_and(|cpu_time| cpu_time.is_zero())
+            || limits.memory_bytes == Some(0)
+            || limits.max_output_bytes == Some(0)
+        {
+            report.error(format!(
+                "command id '{}' limits must be greater than zero",
+                command_info.id
+            ));
+        }
+
+        for (needed, helper) in [
+            (command_info.limits.has_rlimits(), PRLIMIT_PATH),
+            (command_info.limits.nice.is_some(), NICE_PATH),
+            (command_info.sandbox.is_some(), BWRAP_PATH),
+        ] {
+            if needed && !Path::new(helper).is_file() {
+                report.error(format!(
+                    "command id '{}' needs '{}' which was not found",
+                    command_info.id, helper
+                ));
+            }
+        }
+    }
+
+    let mut dictionary_ids = HashSet::new();
+
+    for dictionary in &configuration.compression_configuration.dictionaries {
+        if dictionary.id.is_empty()
+            || !dictionary
+                .id
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_' || b == b'.')
+        {
+            report.error(format!(
+                "compression dictionary id '{}' must be non-empty and contain only [A-Za-z0-9._-]",
+                dictionary.id
+            ));
+        }
+
+        if !dictionary_ids.insert(&dictionary.id) {
+            report.error(format!(
+                "duplicate compression dictionary id '{}'",
+                dictionary.id
+            ));
+        }
+
+        // the prefix is sent as a URL pattern in Use-As-Dictionary
+        if !dictionary.path_prefix.starts_with('/')
+            || dictionary
+                .path_prefix
+                .contains(['*', ':', '(', ')', '{', '}', '?', '#', '\\', '"'])
+        {
+            report.error(format!(
+                "compression dictionary '{}' path_prefix '{}' must start with '/' and not contain pattern characters",
+                dictionary.id, dictionary.path_prefix
+            ));
+        }
+
+        if !Path::new(&dictionary.path).is_file() {
+            report.error(format!(
+                "compression dictionary '{}' file '{}' not found",
+                dictionary.id, dictionary.path
+            ));
+        }
+    }
+}
+
+fn validate_other(configuration: &Configuration, report: &mut ValidationReport) {
+    let logging_configuration = &configuration.logging_configuration;
+
+    if logging_configuration
+        .outputs
+        .iter()
+        .any(|output| matches!(output, LogOutput::Syslog))
+        && logging_configuration.syslog.is_none()
+    {
+        report.error("SYSLOG output requires logging_configuration.syslog".to_owned());
+    }
+
+    if logging_configuration
+        .outputs
+        .iter()
+        .any(|output| matches!(output, LogOutput::Journald))
+        && !cfg!(unix)
+    {
+        report.error("JOURNALD output is not supported on this platform".to_owned());
+    }
+
+    let virtual_host_configuration = &configuration.virtual_host_configuration;
+
+    let access_logs = logging_configuration
+        .access_log
+        .iter()
+        .map(|access_log| ("logging_configuration.access_log".to_owned(), access_log))
+        .chain(virtual_host_configuration.sites.iter().filter_map(|site| {
+            site.access_log.as_ref().map(|access_log| {
+                (
+                    format!("virtual host site '{}' access_log", site.name),
+                    access_log,
+                )
+            })
+        }))
+        .chain(
+            virtual_host_configuration
+                .access_log_route_groups
+                .iter()
+                .map(|route_group| {
+                    (
+                        format!("access log route group '{}'", route_group.name),
+                        &route_group.access_log,
+                    )
+                }),
+        );
+
+    let mut access_log_paths = HashSet::new();
+
+    for (name, access_log) in access_logs {
+        let path = match (&access_log.path, &access_log.syslog) {
+            (Some(path), None) => path,
+            (None, Some(_)) => continue,
+            _ => {
+                report.error(format!("{} must set exactly one of path and syslog", name));
+                continue;
+            }
+        };
+
+        let parent = Path::new(path)
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty());
+
+        if parent.is_some_and(|parent| !parent.is_dir()) {
+            report.error(format!("{} path '{}' directory not found", name, path));
+        }
+
+        // each log has its own writer and rotation
+        if !access_log_paths.insert(path) {
+            report.error(format!(
+                "{} path '{}' is used by another access log",
+                name, path
+            ));
+        }
+
+        if access_log.max_files == 0 {
+            report.error(format!("{} max_files must be at least 1", name));
+        }
+
+        if access_log.max_file_size == Some(0) {
+            report.error(format!("{} max_file_size must be greater than 0", name));
+        }
+    }
+
+    for route_group in &virtual_host_configuration.access_log_route_groups {
+        if route_group.path_prefixes.is_empty() {
+            report.error(format!(
+                "access log route group '{}' has no path_prefixes",
+                route_group.name
+            ));
+        }
+
+        for path_prefix in &route_group.path_prefixes {
+            if !path_prefix.starts_with('/') {
+                report.error(format!(
+                    "access log route group '{}' path_prefix '{}' must start with '/'",
+                    route_group.name, path_prefix
+                ));
+            }
+        }
+    }
+
+    if let Some(filter) = &configuration.logging_configuration.filter {
+        if let Err(e) = tracing_subscriber::EnvFilter::builder().parse(filter) {
+            report.error(format!("invalid logging filter '{}': {}", filter, e));
+        }
+    }
+
+    let snapshot_configuration = &configuration.snapshot_configuration;
+
+    if let Some(directory) = &snapshot_configuration.directory {
+        if !Path::new(directory).is_dir() {
+            report.error(format!("snapshot directory '{}' not found", directory));
+        }
+
+        if snapshot_configuration.interval.is_zero() {
+            report.error("snapshot interval must be greater than 0".to_owned());
+        }
+
+        if snapshot_configuration.max_files == 0 {
+            report.error("snapshot max_files must be at least 1".to_owned());
+        }
+    }
+
+    if let Some(symbols_path) = &configuration.debug_configuration.symbols_path {
+        if !Path::new(symbols_path).exists() {
+            report.warning(format!("debug symbols_path '{}' not found", symbols_path));
+        }
+    }
+
+    let geoip_configuration = &configuration.geoip_configuration;
+
+    for path in [
+        &geoip_configuration.country_database_path,
+        &geoip_configuration.asn_database_path,
+    ]
+    .into_iter()
+    .flatten()
+    {
+        if !Path::new(path).is_file() {
+            report.error(format!("geoip database '{}' not found", path));
+        }
+    }
+
+    for principal in &configuration.rate_limit_configuration.principals {
+        if principal.bearer_token.is_some() == principal.uid.is_some() {
+            report.error(format!(
+                "rate limit principal '{}' needs exactly one of bearer_token or uid",
+                principal.name
+            ));
+        }
+        if principal.requests == 0 || principal.period.is_zero() {
+            report.error(format!(
+                "rate limit principal '{}' needs requests > 0 and a non-zero period",
+                principal.name
+            ));
+        }
+    }
+
+    if let Some(per_client) = &configuration.rate_limit_configuration.per_client {
+        let rates = std::iter::once((
+            "per_client",
+            per_client.requests_per_second,
+            per_client.burst,
+        ))
+        .chain(per_client.routes.iter().map(|route| {
+            (
+                route.path_prefix.as_str(),
+                route.requests_per_second,
+                route.burst,
+            )
+        }));
+
+        for (name, requests_per_second, burst) in rates {
+            if !(requests_per_second.is_finite() && requests_per_second > 0.0) || burst == 0 {
+                report.error(format!(
+                    "rate limit '{}' needs requests_per_second > 0 and burst > 0",
+                    name
+                ));
+            }
+        }
+    }
+
+    let request_target_configuration = &configuration.request_target_configuration;
+
+    if matches!(
+        request_target_configuration.unknown_host,
+        UnknownHostAction::DefaultHost
+    ) && request_target_configuration.default_host.is_none()
+    {
+        report.error("unknown_host DEFAULT_HOST requires default_host".to_owned());
+    }
+
+    for route_group in &configuration.load_shedding_configuration.route_groups {
+        if route_group.path_prefixes.is_empty() {
+            report.warning(format!(
+                "load shedding route group '{}' has no path_prefixes",
+                route_group.name
+            ));
+        }
+        if route_group
+            .path_prefixes
+            .iter()
+            .any(|path_prefix| !path_prefix.starts_with('/'))
+        {
+            report.error(format!(
+                "load shedding route group '{}' path_prefixes must start with '/'",
+                route_group.name
+            ));
+        }
+    }
+
+    for proxy_route in &configuration.proxy_configuration.routes {
+        if !proxy_route.path_prefix.starts_with('/') {
+            report.error(format!(
+                "proxy route path_prefix '{}' must start with '/'",
+                proxy_route.path_prefix
+            ));
+        }
+
+        if let Err(e) = crate::handlers::parse_upstream_url(&proxy_route.upstream_url) {
+            report.error(format!(
+                "proxy route '{}': {:#}",
+                proxy_route.path_prefix, e
+            ));
+        }
+
+        if proxy_route.interface.is_some() && !cfg!(target_os = "linux") {
+            report.error(format!(
+                "proxy route '{}' interface is only supported on Linux",
+                proxy_route.path_prefix
+            ));
+        }
+
+        if let Some(canary) = &proxy_route.canary {
+            if let Err(e) = crate::handlers::parse_upstream_url(&canary.upstream_url) {
+                report.error(format!(
+                    "proxy route '{}' canary: {:#}",
+                    proxy_route.path_prefix, e
+                ));
+            }
+
+            if !(0.0..=100.0).contains(&canary.percent) {
+                report.error(format!(
+                    "proxy route '{}' canary percent must be 0 to 100",
+                    proxy_route.path_prefix
+                ));
+            }
+        }
+
+        if let Some(mirror) = &proxy_route.mirror {
+            if let Err(e) = crate::handlers::parse_upstream_url(&mirror.upstream_url) {
+                report.error(format!(
+                    "proxy route '{}' mirror: {:#}",
+                    proxy_route.path_prefix, e
+                ));
+            }
+
+            if !(0.0..=100.0).contains(&mirror.percent) {
+                report.error(format!(
+                    "proxy route '{}' mirror percent must be 0 to 100",
+                    proxy_route.path_prefix
+                ));
+            }
+        }
+
+        if let Some(circuit_breaker) = &proxy_route.circuit_breaker {
+            if circuit_breaker.consecutive_failures.is_none()
+                && circuit_breaker.error_rate_percent.is_none()
+            {
+                report.warning(format!(
+                    "proxy route '{}' circuit_breaker has no consecutive_failures or error_rate_percent and never opens",
+                    proxy_route.path_prefix
+                ));
+            }
+
+            if circuit_breaker.consecutive_failures == Some(0) {
+                report.error(format!(
+                    "proxy route '{}' circuit_breaker consecutive_failures must be greater than 0",
+                    proxy_route.path_prefix
+                ));
+            }
+
+            if circuit_breaker
+                .error_rate_percent
+                .is_some_and(|percent| !(0.0..=100.0).contains(&percent))
+            {
+                report.error(format!(
+                    "proxy route '{}' circuit_breaker error_rate_percent must be 0 to 100",
+                    proxy_route.path_prefix
+                ));
+            }
+
+            if circuit_breaker.window_requests == 0 {
+                report.error(format!(
+                    "proxy route '{}' circuit_breaker window_requests must be greater than 0",
+                    proxy_route.path_prefix
+                ));
+            }
+
+            if circuit_breaker.open_duration.is_zero() {
+                report.error(format!(
+                    "proxy route '{}' circuit_breaker open_duration must be greater than 0",
+                    proxy_route.path_prefix
+                ));
+            }
+        }
+    }
+
+    for cgi_route in &configuration.cgi_configuration.routes {
+        if !cgi_route.path_prefix.starts_with('/') || !cgi_route.path_prefix.ends_with('/') {
+            report.error(format!(
+                "cgi route path_prefix '{}' must start and end with '/'",
+                cgi_route.path_prefix
+            ));
+        }
+
+        if !Path::new(&cgi_route.directory).is_dir() {
+            report.warning(format!(
+                "cgi route '{}' directory '{}' is not a directory",
+                cgi_route.path_prefix, cgi_route.directory
+            ));
+        }
+
+        if let Some(interpreter) = &cgi_route.interpreter {
+            if !Path::new(interpreter).is_file() {
+                report.warning(format!(
+                    "cgi route '{}' interpreter '{}' not found",
+                    cgi_route.path_prefix, interpreter
+                ));
+            }
+        }
+    }
+
+    let resource_usage = &configuration.metrics_configuration.resource_usage;
+
+    if !resource_usage.enabled
+        && (resource_usage.cpu_time_budget.is_some()
+            || resource_usage.allocated_bytes_budget.is_some())
+    {
+        report.warning(
+            "metrics_configuration.resource_usage budgets are ignored unless enabled".to_owned(),
+        );
+    }
+
+    if resource_usage.allocated_bytes_budget.is_some()
+        && !crate::resource_usage::ALLOCATIONS_TRACKED
+    {
+        report.warning(
+            "metrics_configuration.resource_usage.allocated_bytes_budget needs the alloc-tracking feature".to_owned(),
+        );
+    }
+
+    if configuration.traffic_stats_configuration.num_buckets == 0 {
+        report.warning("traffic_stats_configuration.num_buckets = 0, using 1".to_owned());
+    }
+}
+
+fn validate_configuration(configuration: &Configuration, report: &mut ValidationReport) {
+    validate_server(configuration, report);
+    validate_static_files(
+        "static_file_configuration",
+        &configuration.static_file_configuration,
+        report,
+    );
+    validate_virtual_hosts(configuration, report);
+    validate_routes(configuration, report);
+    validate_other(configuration, report);
+}
+
+// Checks an already parsed configuration, used before applying a reload.
+pub fn validate_parsed_configuration(configuration: &Configuration) -> ValidationReport {
+    let mut report = ValidationReport::default();
+
+    validate_configuration(configuration, &mut report);
+
+    report
+}
+
+// Reads and checks a configuration file without applying it.
+pub fn validate_configuration_file(config_file: &str) -> ValidationReport {
+    let mut report = ValidationReport::default();
+
+    let configuration = read_config_file(config_file)
+        .and_then(|file_contents| parse_configuration(file_contents, config_file));
+
+    match configuration {
+        Err(e) => report.error(format!("{:#}", e)),
+        Ok(configuration) => validate_configuration(&configuration, &mut report),
+    }
+
+    report
+}
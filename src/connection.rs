@@ -1,19 +1,36 @@
 mod internal;
+mod observer;
+mod streams;
 
 use tokio::{
     sync::{OnceCell, RwLock},
     time::{Duration, Instant},
 };
 
+use hyper::http::Version;
+
+use schemars::JsonSchema;
+
+use serde::Serialize;
+
 use std::{
+    collections::BTreeMap,
+    net::SocketAddr,
+    path::PathBuf,
     sync::{
-        atomic::{AtomicUsize, Ordering},
-        Arc,
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc, OnceLock,
     },
     time::SystemTime,
 };
 
-use crate::config::ServerSocketType;
+use crate::{config::ServerSocketType, geoip::GeoInfo};
+
+pub use observer::{
+    notify_connection_observers, register_connection_observer, AcceptedConnection,
+    ClosedConnection, CompletedRequest, ConnectionEventLogObserver, ConnectionObserver,
+};
+pub use streams::{StreamGuard, StreamStatsSnapshot};
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Ord, PartialOrd)]
 pub struct ConnectionID(usize);
@@ -22,6 +39,63 @@ impl ConnectionID {
     pub fn as_usize(&self) -> usize {
         self.0
     }
+
+    #[cfg(test)]
+    pub fn new_for_test(id: usize) -> Self {
+        Self(id)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Ord, PartialOrd, JsonSchema, Serialize)]
+pub enum ConnectionProtocol {
+    #[serde(rename = "HTTP1")]
+    Http1,
+
+    #[serde(rename = "HTTP2")]
+    Http2,
+}
+
+impl From<Version> for ConnectionProtocol {
+    fn from(version: Version) -> Self {
+        match version {
+            Version::HTTP_2 => Self::Http2,
+            _ => Self::Http1,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, JsonSchema, Serialize)]
+pub struct PeerCredentials {
+    pub uid: u32,
+    pub gid: u32,
+    pub pid: Option<i32>,
+}
+
+#[derive(Clone, Debug, JsonSchema, Serialize)]
+pub struct TlsInfo {
+    pub sni: Option<String>,
+    pub alpn: Option<String>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct SocketMetadata {
+    // the client reported in a PROXY protocol header if the listener reads one
+    pub peer_addr: Option<SocketAddr>,
+    // the proxy that sent the PROXY protocol header
+    pub proxy_addr: Option<SocketAddr>,
+    pub local_addr: Option<SocketAddr>,
+    pub local_path: Option<PathBuf>,
+    pub peer_credentials: Option<PeerCredentials>,
+    pub geo_info: Option<GeoInfo>,
+    pub tls_info: Option<TlsInfo>,
+}
+
+// Incremented for every change to the tracked connections, connection_info
+// clients pass the last value they saw to receive only later changes.
+static CHANGE_SEQUENCE: AtomicU64 = AtomicU64::new(0);
+
+fn next_change_sequence() -> u64 {
+    CHANGE_SEQUENCE.fetch_add(1, Ordering::Relaxed) + 1
 }
 
 #[derive(Debug)]
@@ -30,24 +104,56 @@ pub struct ConnectionInfo {
     pub creation_time: SystemTime,
     pub creation_instant: Instant,
     pub server_socket_type: ServerSocketType,
+    pub socket_metadata: Arc<SocketMetadata>,
     num_requests: Arc<AtomicUsize>,
+    negotiated_protocol: Arc<OnceLock<ConnectionProtocol>>,
+    had_error: Arc<AtomicBool>,
+    stream_stats: Arc<streams::StreamStats>,
+    // change sequence of the connection's last request, error or its creation
+    last_change: Arc<AtomicU64>,
 }
 
 impl ConnectionInfo {
-    fn new(id: ConnectionID, server_socket_type: ServerSocketType) -> Self {
+    fn new(
+        id: ConnectionID,
+        server_socket_type: ServerSocketType,
+        socket_metadata: SocketMetadata,
+    ) -> Self {
         Self {
             id,
             creation_time: SystemTime::now(),
             creation_instant: Instant::now(),
             server_socket_type,
+            socket_metadata: Arc::new(socket_metadata),
             num_requests: Arc::new(AtomicUsize::new(0)),
+            negotiated_protocol: Arc::new(OnceLock::new()),
+            had_error: Arc::new(AtomicBool::new(false)),
+            stream_stats: Arc::default(),
+            last_change: Arc::new(AtomicU64::new(next_change_sequence())),
         }
     }
 
+    pub fn last_change(&self) -> u64 {
+        self.last_change.load(Ordering::Relaxed)
+    }
+
     pub fn num_requests(&self) -> usize {
         self.num_requests.load(Ordering::Relaxed)
     }
 
+    pub fn had_error(&self) -> bool {
+        self.had_error.load(Ordering::Relaxed)
+    }
+
+    pub fn stream_stats(&self) -> StreamStatsSnapshot {
+        self.stream_stats.snapshot()
+    }
+
+    /// Protocol hyper selected for this connection, known once the first request arrives.
+    pub fn negotiated_protocol(&self) -> Option<ConnectionProtocol> {
+        self.negotiated_protocol.get().copied()
+    }
+
     pub fn age(&self, now: Instant) -> Duration {
         now - self.creation_instant
     }
@@ -55,25 +161,63 @@ impl ConnectionInfo {
 
 pub struct ConnectionGuard {
     pub id: ConnectionID,
+    pub creation_instant: Instant,
     pub server_socket_type: ServerSocketType,
+    pub socket_metadata: Arc<SocketMetadata>,
     num_requests: Arc<AtomicUsize>,
+    negotiated_protocol: Arc<OnceLock<ConnectionProtocol>>,
+    had_error: Arc<AtomicBool>,
+    stream_stats: Arc<streams::StreamStats>,
+    last_change: Arc<AtomicU64>,
+    // Set for connections above max_tracked_connections, summarized on drop.
+    untracked_info: Option<Arc<ConnectionInfo>>,
 }
 
 impl ConnectionGuard {
-    fn new(
-        id: ConnectionID,
-        server_socket_type: ServerSocketType,
-        num_requests: Arc<AtomicUsize>,
-    ) -> Self {
+    fn new(connection_info: &Arc<ConnectionInfo>, untracked: bool) -> Self {
         Self {
-            id,
-            server_socket_type,
-            num_requests,
+            id: connection_info.id,
+            creation_instant: connection_info.creation_instant,
+            server_socket_type: connection_info.server_socket_type,
+            socket_metadata: Arc::clone(&connection_info.socket_metadata),
+            num_requests: Arc::clone(&connection_info.num_requests),
+            negotiated_protocol: Arc::clone(&connection_info.negotiated_protocol),
+            had_error: Arc::clone(&connection_info.had_error),
+            stream_stats: Arc::clone(&connection_info.stream_stats),
+            last_change: Arc::clone(&connection_info.last_change),
+            untracked_info: untracked.then(|| Arc::clone(connection_info)),
         }
     }
 
     pub fn increment_num_requests(&self) {
         self.num_requests.fetch_add(1, Ordering::Relaxed);
+        self.record_change();
+    }
+
+    fn record_change(&self) {
+        self.last_change
+            .store(next_change_sequence(), Ordering::Relaxed);
+    }
+
+    pub fn open_stream(&self) -> StreamGuard {
+        StreamGuard::new(Arc::clone(&self.stream_stats))
+    }
+
+    pub fn record_error(&self) {
+        self.had_error.store(true, Ordering::Relaxed);
+        self.record_change();
+    }
+
+    pub fn had_error(&self) -> bool {
+        self.had_error.load(Ordering::Relaxed)
+    }
+
+    pub fn record_request_version(&self, version: Version) {
+        let _ = self.negotiated_protocol.set(version.into());
+    }
+
+    pub fn negotiated_protocol(&self) -> Option<ConnectionProtocol> {
+        self.negotiated_protocol.get().copied()
     }
 
     pub fn num_requests(&self) -> usize {
@@ -84,11 +228,40 @@ impl ConnectionGuard {
 impl Drop for ConnectionGuard {
     fn drop(&mut self) {
         let id = self.id;
+        let untracked_info = self.untracked_info.take();
+
+        tokio::task::spawn(async move {
+            let connection_tracker = ConnectionTracker::instance().await;
+
+            match untracked_info {
+                None => connection_tracker.remove_connection(id).await,
+                Some(connection_info) => {
+                    connection_tracker
+                        .remove_untracked_connection(&connection_info)
+                        .await
+                }
+            }
+        });
+    }
+}
+
+// A connection_limit slot held by an accepted connection while its PROXY
+// protocol header or TLS handshake is read, released on drop.
+pub struct PendingConnection {
+    server_socket_type: ServerSocketType,
+    released: bool,
+}
+
+impl Drop for PendingConnection {
+    fn drop(&mut self) {
+        if self.released {
+            return;
+        }
 
         tokio::task::spawn(async move {
             ConnectionTracker::instance()
                 .await
-                .remove_connection(id)
+                .release_pending_connection()
                 .await;
         });
     }
@@ -108,10 +281,45 @@ impl ConnectionTracker {
     pub async fn add_connection(
         &self,
         server_socket_type: ServerSocketType,
+        socket_metadata: SocketMetadata,
+    ) -> Option<ConnectionGuard> {
+        let mut state = self.state.write().await;
+
+        state.add_connection(server_socket_type, socket_metadata)
+    }
+
+    pub async fn reserve_connection(
+        &self,
+        server_socket_type: ServerSocketType,
+    ) -> Option<PendingConnection> {
+        let mut state = self.state.write().await;
+
+        state
+            .reserve_connection(server_socket_type)
+            .then_some(PendingConnection {
+                server_socket_type,
+                released: false,
+            })
+    }
+
+    // Moves a pending connection's slot to the connection.
+    pub async fn add_pending_connection(
+        &self,
+        mut pending_connection: PendingConnection,
+        socket_metadata: SocketMetadata,
     ) -> Option<ConnectionGuard> {
         let mut state = self.state.write().await;
 
-        state.add_connection(server_socket_type)
+        state.release_pending_connection();
+        pending_connection.released = true;
+
+        state.add_connection(pending_connection.server_socket_type, socket_metadata)
+    }
+
+    async fn release_pending_connection(&self) {
+        let mut state = self.state.write().await;
+
+        state.release_pending_connection();
     }
 
     async fn remove_connection(&self, connection_id: ConnectionID) {
@@ -120,29 +328,92 @@ impl ConnectionTracker {
         state.remove_connection(connection_id);
     }
 
+    async fn remove_untracked_connection(&self, connection_info: &ConnectionInfo) {
+        let mut state = self.state.write().await;
+
+        state.remove_untracked_connection(connection_info);
+    }
+
     pub async fn state(&self) -> ConnectionTrackerState {
         let state = self.state.read().await;
 
+        self.build_state(&state, None)
+    }
+
+    // Also lists the connections removed after the since change sequence,
+    // if they are still known.
+    pub async fn state_since(&self, since: u64) -> ConnectionTrackerState {
+        let state = self.state.read().await;
+
+        self.build_state(&state, Some(since))
+    }
+
+    fn build_state(
+        &self,
+        state: &internal::ConnectionTrackerState,
+        since: Option<u64>,
+    ) -> ConnectionTrackerState {
+        // removals take the write lock, so none happen after this is read
+        let change_sequence = CHANGE_SEQUENCE.load(Ordering::Relaxed);
+
         ConnectionTrackerState {
+            change_sequence,
+            removed_connections: since
+                .and_then(|since| state.removed_connections_since(since, change_sequence)),
             max_open_connections: state.max_open_connections(),
             connection_limit_hits: state.connection_limit_hits(),
+            tracking_limit_hits: state.tracking_limit_hits(),
+            untracked_connections: state.untracked_connections(),
             max_connection_age: state.max_connection_age(),
             max_requests_per_connection: state.max_requests_per_connection(),
+            connections_by_protocol: state.connections_by_protocol(),
+            total_connections: state.total_connections(),
+            total_requests: state.total_requests(),
+            connection_errors: state.connection_errors(),
+            client_aborted_requests: state.client_aborted_requests(),
+            h2_stream_stats: state.h2_stream_stats(),
             open_connections: state.open_connections().cloned().collect(),
         }
     }
 
-    pub async fn instance() -> &'static Self {
-        static INSTANCE: OnceCell<ConnectionTracker> = OnceCell::const_new();
+    // For the panic hook, which cannot wait for the lock.
+    pub fn try_open_connections() -> Option<usize> {
+        let state = CONNECTION_TRACKER_INSTANCE.get()?.state.try_read().ok()?;
 
-        INSTANCE.get_or_init(Self::new).await
+        Some(state.num_open_connections())
     }
+
+    pub async fn instance() -> &'static Self {
+        CONNECTION_TRACKER_INSTANCE.get_or_init(Self::new).await
+    }
+}
+
+static CONNECTION_TRACKER_INSTANCE: OnceCell<ConnectionTracker> = OnceCell::const_new();
+
+// Stream totals over closed and open HTTP/2 connections.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct H2StreamTotals {
+    pub max_concurrent_streams: usize,
+    pub reset_streams: usize,
+    pub write_stall_time: Duration,
 }
 
 pub struct ConnectionTrackerState {
+    pub change_sequence: u64,
+    // from state_since, None when since is older than the removals kept
+    pub removed_connections: Option<Vec<ConnectionID>>,
     pub max_open_connections: usize,
     pub connection_limit_hits: usize,
+    pub tracking_limit_hits: usize,
+    // open but not in open_connections, see max_tracked_connections
+    pub untracked_connections: usize,
     pub max_connection_age: Duration,
     pub max_requests_per_connection: usize,
+    pub connections_by_protocol: BTreeMap<ConnectionProtocol, usize>,
+    pub total_connections: usize,
+    pub total_requests: usize,
+    pub connection_errors: usize,
+    pub client_aborted_requests: usize,
+    pub h2_stream_stats: H2StreamTotals,
     pub open_connections: Vec<Arc<ConnectionInfo>>,
 }
@@ -1,19 +1,43 @@
 use tokio::time::{Duration, Instant};
 
-use tracing::{debug, warn};
+use tracing::{debug, info, warn};
 
-use std::{cmp, collections::HashMap, sync::Arc};
+use std::{
+    cmp,
+    collections::{BTreeMap, HashMap, VecDeque},
+    sync::Arc,
+};
 
 use crate::config::ServerSocketType;
 
-use super::{ConnectionGuard, ConnectionID, ConnectionInfo};
+use super::{
+    next_change_sequence, ConnectionGuard, ConnectionID, ConnectionInfo, ConnectionProtocol,
+    H2StreamTotals, SocketMetadata, StreamStatsSnapshot,
+};
 
 #[derive(Default)]
 struct ConnectionTrackerMetrics {
     max_open_connections: usize,
     connection_limit_hits: usize,
+    tracking_limit_hits: usize,
     past_max_connection_age: Duration,
     past_max_requests_per_connection: usize,
+    past_connections_by_protocol: BTreeMap<ConnectionProtocol, usize>,
+    past_total_requests: usize,
+    past_connection_errors: usize,
+    past_client_aborted_requests: usize,
+    past_h2_stream_stats: H2StreamTotals,
+}
+
+impl H2StreamTotals {
+    fn add(&mut self, stream_stats: &StreamStatsSnapshot) {
+        self.max_concurrent_streams = cmp::max(
+            self.max_concurrent_streams,
+            stream_stats.max_concurrent_streams,
+        );
+        self.reset_streams += stream_stats.reset_streams;
+        self.write_stall_time += stream_stats.write_stall_time;
+    }
 }
 
 impl ConnectionTrackerMetrics {
@@ -31,31 +55,73 @@ impl ConnectionTrackerMetrics {
             self.past_max_requests_per_connection,
             removed_connection_info.num_requests(),
         );
+
+        self.past_total_requests += removed_connection_info.num_requests();
+
+        if removed_connection_info.had_error() {
+            self.past_connection_errors += 1;
+        }
+
+        self.past_client_aborted_requests += removed_connection_info.stream_stats().reset_streams;
+
+        if removed_connection_info.negotiated_protocol() == Some(ConnectionProtocol::Http2) {
+            self.past_h2_stream_stats
+                .add(&removed_connection_info.stream_stats());
+        }
+
+        if let Some(protocol) = removed_connection_info.negotiated_protocol() {
+            *self
+                .past_connections_by_protocol
+                .entry(protocol)
+                .or_default() += 1;
+        }
     }
 
     fn increment_connection_limit_hits(&mut self) {
         self.connection_limit_hits += 1;
     }
+
+    fn increment_tracking_limit_hits(&mut self) {
+        self.tracking_limit_hits += 1;
+    }
 }
 
+// Removals kept for connection_info delta responses.
+const MAX_REMOVED_CONNECTIONS: usize = 1024;
+
 #[derive(Default)]
 pub struct ConnectionTrackerState {
     next_connection_id: usize,
     connection_limit: usize,
+    max_tracked_connections: usize,
     id_to_connection_info: HashMap<ConnectionID, Arc<ConnectionInfo>>,
+    // open connections above max_tracked_connections, not in id_to_connection_info
+    untracked_connections: usize,
+    // accepted connections still reading a PROXY protocol header or TLS handshake
+    pending_connections: usize,
+    // change sequence and id of recently removed tracked connections
+    removed_connections: VecDeque<(u64, ConnectionID)>,
+    // removals at or before this change sequence are no longer kept
+    removed_connections_start: u64,
     metrics: ConnectionTrackerMetrics,
 }
 
 impl ConnectionTrackerState {
     pub fn new() -> Self {
-        let connection_limit = crate::config::instance()
-            .server_configuration
-            .connection
-            .limit;
+        let connection_configuration = &crate::config::instance().server_configuration.connection;
+
+        let connection_limit = connection_configuration.limit;
+
+        let max_tracked_connections = connection_configuration
+            .max_tracked_connections
+            .unwrap_or(connection_limit)
+            .min(connection_limit);
+
         Self {
             next_connection_id: 1,
             connection_limit,
-            id_to_connection_info: HashMap::with_capacity(connection_limit),
+            max_tracked_connections,
+            id_to_connection_info: HashMap::with_capacity(max_tracked_connections),
             ..Default::default()
         }
     }
@@ -66,33 +132,81 @@ impl ConnectionTrackerState {
         ConnectionID(connection_id)
     }
 
-    fn new_connection_exceeds_connection_limit(&self) -> bool {
-        (self.id_to_connection_info.len() + 1) > self.connection_limit
+    pub fn num_open_connections(&self) -> usize {
+        self.id_to_connection_info.len() + self.untracked_connections
     }
 
-    pub fn add_connection(
+    fn new_connection_exceeds_connection_limit(
         &mut self,
         server_socket_type: ServerSocketType,
-    ) -> Option<ConnectionGuard> {
-        if self.new_connection_exceeds_connection_limit() {
+    ) -> bool {
+        let exceeds =
+            (self.num_open_connections() + self.pending_connections + 1) > self.connection_limit;
+
+        if exceeds {
             warn!(
                 "add_connection hit connection_limit = {} server_socket_type = {:?}",
                 self.connection_limit, server_socket_type
             );
             self.metrics.increment_connection_limit_hits();
+        }
+
+        exceeds
+    }
+
+    // Holds a connection_limit slot until the connection is added or closed.
+    pub fn reserve_connection(&mut self, server_socket_type: ServerSocketType) -> bool {
+        if self.new_connection_exceeds_connection_limit(server_socket_type) {
+            return false;
+        }
+
+        self.pending_connections += 1;
+
+        true
+    }
+
+    pub fn release_pending_connection(&mut self) {
+        self.pending_connections = self.pending_connections.saturating_sub(1);
+    }
+
+    pub fn add_connection(
+        &mut self,
+        server_socket_type: ServerSocketType,
+        socket_metadata: SocketMetadata,
+    ) -> Option<ConnectionGuard> {
+        if self.new_connection_exceeds_connection_limit(server_socket_type) {
             return None;
         }
 
         let connection_id = self.next_connection_id();
 
-        let connection_info = Arc::new(ConnectionInfo::new(connection_id, server_socket_type));
+        let connection_info = Arc::new(ConnectionInfo::new(
+            connection_id,
+            server_socket_type,
+            socket_metadata,
+        ));
 
-        let num_requests = Arc::clone(&connection_info.num_requests);
+        let connection_guard = if self.id_to_connection_info.len() < self.max_tracked_connections {
+            let connection_guard = ConnectionGuard::new(&connection_info, false);
 
-        self.id_to_connection_info
-            .insert(connection_id, connection_info);
+            self.id_to_connection_info
+                .insert(connection_id, connection_info);
 
-        let new_num_connections = self.id_to_connection_info.len();
+            connection_guard
+        } else {
+            if self.untracked_connections == 0 {
+                warn!(
+                    "add_connection hit max_tracked_connections = {}, summarizing new connections",
+                    self.max_tracked_connections
+                );
+            }
+            self.metrics.increment_tracking_limit_hits();
+            self.untracked_connections += 1;
+
+            ConnectionGuard::new(&connection_info, true)
+        };
+
+        let new_num_connections = self.num_open_connections();
 
         self.metrics.update_for_new_connection(new_num_connections);
 
@@ -101,16 +215,21 @@ impl ConnectionTrackerState {
             new_num_connections
         );
 
-        Some(ConnectionGuard::new(
-            connection_id,
-            server_socket_type,
-            num_requests,
-        ))
+        Some(connection_guard)
     }
 
     pub fn remove_connection(&mut self, connection_id: ConnectionID) {
         if let Some(connection_info) = self.id_to_connection_info.remove(&connection_id) {
             self.metrics.update_for_removed_connection(&connection_info);
+
+            self.removed_connections
+                .push_back((next_change_sequence(), connection_id));
+
+            if self.removed_connections.len() > MAX_REMOVED_CONNECTIONS {
+                if let Some((change_sequence, _)) = self.removed_connections.pop_front() {
+                    self.removed_connections_start = change_sequence;
+                }
+            }
         }
 
         debug!(
@@ -119,6 +238,16 @@ impl ConnectionTrackerState {
         );
     }
 
+    pub fn remove_untracked_connection(&mut self, connection_info: &ConnectionInfo) {
+        self.untracked_connections = self.untracked_connections.saturating_sub(1);
+
+        self.metrics.update_for_removed_connection(connection_info);
+
+        if self.untracked_connections == 0 {
+            info!("all open connections are tracked again");
+        }
+    }
+
     pub fn max_open_connections(&self) -> usize {
         self.metrics.max_open_connections
     }
@@ -127,6 +256,14 @@ impl ConnectionTrackerState {
         self.metrics.connection_limit_hits
     }
 
+    pub fn tracking_limit_hits(&self) -> usize {
+        self.metrics.tracking_limit_hits
+    }
+
+    pub fn untracked_connections(&self) -> usize {
+        self.untracked_connections
+    }
+
     pub fn max_connection_age(&self) -> Duration {
         let now = Instant::now();
         cmp::max(
@@ -150,7 +287,210 @@ impl ConnectionTrackerState {
         )
     }
 
+    pub fn total_connections(&self) -> usize {
+        self.next_connection_id.saturating_sub(1)
+    }
+
+    pub fn total_requests(&self) -> usize {
+        self.metrics.past_total_requests
+            + self
+                .id_to_connection_info
+                .values()
+                .map(|c| c.num_requests())
+                .sum::<usize>()
+    }
+
+    pub fn connection_errors(&self) -> usize {
+        self.metrics.past_connection_errors
+            + self
+                .id_to_connection_info
+                .values()
+                .filter(|c| c.had_error())
+                .count()
+    }
+
+    pub fn client_aborted_requests(&self) -> usize {
+        self.metrics.past_client_aborted_requests
+            + self
+                .id_to_connection_info
+                .values()
+                .map(|c| c.stream_stats().reset_streams)
+                .sum::<usize>()
+    }
+
+    pub fn h2_stream_stats(&self) -> H2StreamTotals {
+        let mut h2_stream_stats = self.metrics.past_h2_stream_stats;
+
+        for connection_info in self
+            .id_to_connection_info
+            .values()
+            .filter(|c| c.negotiated_protocol() == Some(ConnectionProtocol::Http2))
+        {
+            h2_stream_stats.add(&connection_info.stream_stats());
+        }
+
+        h2_stream_stats
+    }
+
+    // None if removals after since were dropped, or since is from the future.
+    pub fn removed_connections_since(
+        &self,
+        since: u64,
+        change_sequence: u64,
+    ) -> Option<Vec<ConnectionID>> {
+        if since < self.removed_connections_start || since > change_sequence {
+            return None;
+        }
+
+        Some(
+            self.removed_connections
+                .iter()
+                .filter(|(removed_change_sequence, _)| *removed_change_sequence > since)
+                .map(|(_, connection_id)| *connection_id)
+                .collect(),
+        )
+    }
+
     pub fn open_connections(&self) -> impl Iterator<Item = &Arc<ConnectionInfo>> {
         self.id_to_connection_info.values()
     }
+
+    /// Connection counts by negotiated protocol, including both closed and open connections.
+    pub fn connections_by_protocol(&self) -> BTreeMap<ConnectionProtocol, usize> {
+        let mut connections_by_protocol = self.metrics.past_connections_by_protocol.clone();
+
+        for protocol in self
+            .id_to_connection_info
+            .values()
+            .filter_map(|c| c.negotiated_protocol())
+        {
+            *connections_by_protocol.entry(protocol).or_default() += 1;
+        }
+
+        connections_by_protocol
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::sync::atomic::Ordering;
+
+    use crate::connection::CHANGE_SEQUENCE;
+
+    #[tokio::test]
+    async fn test_untracked_connections_above_max_tracked() {
+        crate::config::set_test_instance();
+
+        let mut state = ConnectionTrackerState {
+            next_connection_id: 1,
+            connection_limit: 3,
+            max_tracked_connections: 1,
+            ..Default::default()
+        };
+
+        let guards: Vec<_> = (0..3)
+            .map(|_| {
+                state
+                    .add_connection(ServerSocketType::Tcp, SocketMetadata::default())
+                    .unwrap()
+            })
+            .collect();
+
+        assert!(state
+            .add_connection(ServerSocketType::Tcp, SocketMetadata::default())
+            .is_none());
+
+        assert_eq!(state.open_connections().count(), 1);
+        assert_eq!(state.untracked_connections(), 2);
+        assert_eq!(state.tracking_limit_hits(), 2);
+        assert_eq!(state.max_open_connections(), 3);
+
+        let untracked_info = guards[2].untracked_info.clone().unwrap();
+        untracked_info.num_requests.store(5, Ordering::Relaxed);
+
+        state.remove_untracked_connection(&untracked_info);
+
+        assert_eq!(state.untracked_connections(), 1);
+        assert_eq!(state.total_requests(), 5);
+    }
+
+    #[tokio::test]
+    async fn test_removed_connections_since() {
+        crate::config::set_test_instance();
+
+        let mut state = ConnectionTrackerState {
+            next_connection_id: 1,
+            connection_limit: 2,
+            max_tracked_connections: 2,
+            ..Default::default()
+        };
+
+        let guards: Vec<_> = (0..2)
+            .map(|_| {
+                state
+                    .add_connection(ServerSocketType::Tcp, SocketMetadata::default())
+                    .unwrap()
+            })
+            .collect();
+
+        let since = guards[1].last_change.load(Ordering::Relaxed);
+        assert!(since > guards[0].last_change.load(Ordering::Relaxed));
+
+        state.remove_connection(guards[0].id);
+
+        let change_sequence = CHANGE_SEQUENCE.load(Ordering::Relaxed);
+
+        assert_eq!(
+            state.removed_connections_since(since, change_sequence),
+            Some(vec![guards[0].id])
+        );
+        assert_eq!(
+            state.removed_connections_since(change_sequence, change_sequence),
+            Some(Vec::new())
+        );
+        assert_eq!(
+            state.removed_connections_since(change_sequence + 1, change_sequence),
+            None
+        );
+
+        state.removed_connections_start = since + 1;
+        assert_eq!(
+            state.removed_connections_since(since, change_sequence),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn test_pending_connections_count_against_limit() {
+        crate::config::set_test_instance();
+
+        let mut state = ConnectionTrackerState {
+            next_connection_id: 1,
+            connection_limit: 2,
+            max_tracked_connections: 2,
+            ..Default::default()
+        };
+
+        assert!(state.reserve_connection(ServerSocketType::Tcp));
+        assert!(state.reserve_connection(ServerSocketType::Tcp));
+
+        assert!(!state.reserve_connection(ServerSocketType::Tcp));
+        assert!(state
+            .add_connection(ServerSocketType::Tcp, SocketMetadata::default())
+            .is_none());
+        assert_eq!(state.connection_limit_hits(), 2);
+
+        // a pending connection that is added keeps its slot
+        state.release_pending_connection();
+        let _guard = state
+            .add_connection(ServerSocketType::Tcp, SocketMetadata::default())
+            .unwrap();
+        assert!(!state.reserve_connection(ServerSocketType::Tcp));
+
+        // one that closes frees it
+        state.release_pending_connection();
+        assert!(state.reserve_connection(ServerSocketType::Tcp));
+    }
 }
@@ -0,0 +1,101 @@
+use hyper::http::{Method, StatusCode};
+
+use tokio::time::Duration;
+
+use tracing::info;
+
+use std::sync::{Arc, RwLock};
+
+use crate::{config::ServerSocketType, response::DenyReason};
+
+use super::{ConnectionID, ConnectionProtocol, SocketMetadata};
+
+pub struct AcceptedConnection<'a> {
+    pub id: ConnectionID,
+    pub server_socket_type: ServerSocketType,
+    pub socket_metadata: &'a SocketMetadata,
+}
+
+pub struct CompletedRequest<'a> {
+    pub connection_id: ConnectionID,
+    pub method: &'a Method,
+    pub route: &'a str,
+    pub status: StatusCode,
+    pub deny_reason: Option<DenyReason>,
+    // time until the handler returned the response, not including the body write
+    pub duration: Duration,
+}
+
+pub struct ClosedConnection {
+    pub id: ConnectionID,
+    pub server_socket_type: ServerSocketType,
+    pub age: Duration,
+    pub num_requests: usize,
+    pub negotiated_protocol: Option<ConnectionProtocol>,
+    pub had_error: bool,
+}
+
+// Lifecycle hooks for custom accounting or notification.  Hooks run inline on
+// connection tasks so they must not block.
+pub trait ConnectionObserver: Send + Sync {
+    fn on_accept(&self, _connection: &AcceptedConnection<'_>) {}
+
+    fn on_request_complete(&self, _request: &CompletedRequest<'_>) {}
+
+    fn on_close(&self, _connection: &ClosedConnection) {}
+}
+
+static OBSERVERS: RwLock<Vec<Arc<dyn ConnectionObserver>>> = RwLock::new(Vec::new());
+
+pub fn register_connection_observer(observer: Arc<dyn ConnectionObserver>) {
+    OBSERVERS.write().unwrap().push(observer);
+}
+
+pub fn notify_connection_observers(notify: impl Fn(&dyn ConnectionObserver)) {
+    for observer in OBSERVERS.read().unwrap().iter() {
+        notify(observer.as_ref());
+    }
+}
+
+// Built-in observer that logs every lifecycle event, enabled with
+// logging_configuration.connection_events.
+pub struct ConnectionEventLogObserver;
+
+impl ConnectionObserver for ConnectionEventLogObserver {
+    fn on_accept(&self, connection: &AcceptedConnection<'_>) {
+        info!(
+            event = "accept",
+            conn_id = connection.id.as_usize(),
+            sock = ?connection.server_socket_type,
+            peer = ?connection.socket_metadata.peer_addr,
+            peer_credentials = ?connection.socket_metadata.peer_credentials,
+            "connection event",
+        );
+    }
+
+    fn on_request_complete(&self, request: &CompletedRequest<'_>) {
+        info!(
+            event = "request_complete",
+            conn_id = request.connection_id.as_usize(),
+            method = %request.method,
+            route = request.route,
+            status = request.status.as_u16(),
+            deny_reason = request.deny_reason.map(|deny_reason| deny_reason.as_str()),
+            micros = request.duration.as_micros(),
+            "connection event",
+        );
+    }
+
+    fn on_close(&self, connection: &ClosedConnection) {
+        info!(
+            event = "close",
+            conn_id = connection.id.as_usize(),
+            sock = ?connection.server_socket_type,
+            age = ?connection.age,
+            num_requests = connection.num_requests,
+            proto = ?connection.negotiated_protocol,
+            had_error = connection.had_error,
+            "connection event",
+        );
+    }
+}
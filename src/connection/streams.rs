@@ -0,0 +1,102 @@
+use tokio::time::Duration;
+
+use tokio_util::sync::CancellationToken;
+
+use tracing::{info, Span};
+
+use std::sync::{
+    atomic::{AtomicU64, AtomicUsize, Ordering},
+    Arc,
+};
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct StreamStatsSnapshot {
+    pub active_streams: usize,
+    pub max_concurrent_streams: usize,
+    pub reset_streams: usize,
+    pub write_stall_time: Duration,
+}
+
+// Per connection request stream accounting.  For HTTP/2 a reset stream is one
+// the client cancelled before the response finished; write stalls are gaps
+// where hyper stopped pulling the response body, i.e. flow control windows
+// or socket buffers were full.
+#[derive(Debug, Default)]
+pub struct StreamStats {
+    active_streams: AtomicUsize,
+    max_concurrent_streams: AtomicUsize,
+    reset_streams: AtomicUsize,
+    write_stall_micros: AtomicU64,
+}
+
+impl StreamStats {
+    pub fn snapshot(&self) -> StreamStatsSnapshot {
+        StreamStatsSnapshot {
+            active_streams: self.active_streams.load(Ordering::Relaxed),
+            max_concurrent_streams: self.max_concurrent_streams.load(Ordering::Relaxed),
+            reset_streams: self.reset_streams.load(Ordering::Relaxed),
+            write_stall_time: Duration::from_micros(
+                self.write_stall_micros.load(Ordering::Relaxed),
+            ),
+        }
+    }
+}
+
+// Open for the life of one request, from the service call until the response
+// body is finished or dropped.  Dropping an unfinished stream means the client
+// went away: it counts a reset and cancels the request's cancellation token.
+pub struct StreamGuard {
+    stats: Arc<StreamStats>,
+    finished: bool,
+    cancellation_token: CancellationToken,
+    span: Span,
+}
+
+impl StreamGuard {
+    pub(super) fn new(stats: Arc<StreamStats>) -> Self {
+        let active_streams = stats.active_streams.fetch_add(1, Ordering::Relaxed) + 1;
+
+        stats
+            .max_concurrent_streams
+            .fetch_max(active_streams, Ordering::Relaxed);
+
+        Self {
+            stats,
+            finished: false,
+            cancellation_token: CancellationToken::new(),
+            span: Span::none(),
+        }
+    }
+
+    pub fn cancellation_token(&self) -> CancellationToken {
+        self.cancellation_token.clone()
+    }
+
+    // Request span used to log an abort after the request future is gone.
+    pub fn set_span(&mut self, span: Span) {
+        self.span = span;
+    }
+
+    pub fn finish(&mut self) {
+        self.finished = true;
+    }
+
+    pub fn record_write_stall(&self, stall: Duration) {
+        self.stats.write_stall_micros.fetch_add(
+            stall.as_micros().try_into().unwrap_or(u64::MAX),
+            Ordering::Relaxed,
+        );
+    }
+}
+
+impl Drop for StreamGuard {
+    fn drop(&mut self) {
+        self.stats.active_streams.fetch_sub(1, Ordering::Relaxed);
+
+        if !self.finished {
+            self.stats.reset_streams.fetch_add(1, Ordering::Relaxed);
+            self.cancellation_token.cancel();
+            info!(parent: &self.span, "request aborted by client");
+        }
+    }
+}
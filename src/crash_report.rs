@@ -0,0 +1,96 @@
+use serde::Serialize;
+
+use std::{
+    backtrace::Backtrace,
+    panic::PanicHookInfo,
+    time::{Duration, SystemTime},
+};
+
+use crate::{
+    connection::ConnectionTracker,
+    handlers::time_utils::{local_date_time_to_string, LocalDateTime},
+    version::VersionInfoMap,
+};
+
+#[derive(Debug, Serialize)]
+struct CrashReport {
+    time: String,
+    thread: Option<String>,
+    message: String,
+    location: Option<String>,
+    #[serde(with = "humantime_serde")]
+    uptime: Duration,
+    // None if the connection tracker was locked when the panic happened
+    open_connections: Option<usize>,
+    version_info: &'static VersionInfoMap,
+    backtrace: Vec<String>,
+}
+
+fn panic_message(panic_hook_info: &PanicHookInfo<'_>) -> String {
+    let payload = panic_hook_info.payload();
+
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "[non-string panic payload]".to_owned()
+    }
+}
+
+impl CrashReport {
+    fn new(panic_hook_info: &PanicHookInfo<'_>) -> Self {
+        Self {
+            time: local_date_time_to_string(&LocalDateTime::from(SystemTime::now())),
+            thread: std::thread::current().name().map(str::to_owned),
+            message: panic_message(panic_hook_info),
+            location: panic_hook_info
+                .location()
+                .map(|location| location.to_string()),
+            // truncate to milliseconds
+            uptime: Duration::from_millis(crate::uptime::uptime().as_millis() as u64),
+            open_connections: ConnectionTracker::try_open_connections(),
+            version_info: crate::version::get_verison_info(),
+            backtrace: Backtrace::force_capture()
+                .to_string()
+                .lines()
+                .map(str::to_owned)
+                .collect(),
+        }
+    }
+}
+
+// With shutdown_configuration.crash_report_path set, any panic writes a
+// crash report there and aborts the process instead of only ending the
+// panicking task.  Runs before the default hook so the report exists even if
+// printing the panic fails.
+pub fn install_panic_hook() {
+    let Some(crash_report_path) = &crate::config::instance()
+        .shutdown_configuration
+        .crash_report_path
+    else {
+        return;
+    };
+
+    let default_hook = std::panic::take_hook();
+
+    std::panic::set_hook(Box::new(move |panic_hook_info| {
+        let report = CrashReport::new(panic_hook_info);
+
+        // tracing may be what panicked, write to stderr directly
+        match serde_json::to_vec_pretty(&report) {
+            Err(e) => eprintln!("error serializing crash report: {}", e),
+            Ok(json) => match std::fs::write(crash_report_path, json) {
+                Err(e) => eprintln!(
+                    "error writing crash report to {:?}: {}",
+                    crash_report_path, e
+                ),
+                Ok(()) => eprintln!("wrote crash report to {:?}", crash_report_path),
+            },
+        }
+
+        default_hook(panic_hook_info);
+
+        std::process::abort();
+    }));
+}
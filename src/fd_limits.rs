@@ -0,0 +1,148 @@
+#[cfg(unix)]
+use anyhow::Context;
+
+#[cfg(unix)]
+use nix::sys::resource::{getrlimit, setrlimit, Resource};
+
+use tracing::{info, warn};
+
+use crate::config::FileDescriptorConfiguration;
+
+#[derive(Clone, Copy, Debug)]
+pub struct FdUsage {
+    pub open_fds: Option<u64>,
+    pub soft_limit: u64,
+    pub hard_limit: u64,
+}
+
+impl FdUsage {
+    pub fn usage_percent(&self) -> Option<f64> {
+        let open_fds = self.open_fds?;
+
+        if self.soft_limit == 0 {
+            return None;
+        }
+
+        Some((open_fds as f64 * 100.0) / self.soft_limit as f64)
+    }
+}
+
+// Counting /proc/self/fd is linux specific, open_fds is None elsewhere.
+#[cfg(unix)]
+fn count_open_fds() -> Option<u64> {
+    std::fs::read_dir("/proc/self/fd")
+        .ok()
+        .map(|entries| entries.count() as u64)
+}
+
+// None on platforms without RLIMIT_NOFILE.
+#[cfg(unix)]
+pub fn current_fd_usage() -> anyhow::Result<Option<FdUsage>> {
+    let (soft_limit, hard_limit) =
+        getrlimit(Resource::RLIMIT_NOFILE).context("getrlimit RLIMIT_NOFILE error")?;
+
+    Ok(Some(FdUsage {
+        open_fds: count_open_fds(),
+        soft_limit,
+        hard_limit,
+    }))
+}
+
+#[cfg(not(unix))]
+pub fn current_fd_usage() -> anyhow::Result<Option<FdUsage>> {
+    Ok(None)
+}
+
+#[cfg(unix)]
+pub fn raise_nofile_limit(
+    file_descriptor_configuration: &FileDescriptorConfiguration,
+) -> anyhow::Result<()> {
+    let Some(nofile_limit) = file_descriptor_configuration.nofile_limit else {
+        return Ok(());
+    };
+
+    let (soft_limit, hard_limit) =
+        getrlimit(Resource::RLIMIT_NOFILE).context("getrlimit RLIMIT_NOFILE error")?;
+
+    let new_soft_limit = nofile_limit.min(hard_limit);
+
+    if new_soft_limit < nofile_limit {
+        warn!(
+            "nofile_limit {} exceeds hard limit {}, using hard limit",
+            nofile_limit, hard_limit
+        );
+    }
+
+    if new_soft_limit <= soft_limit {
+        info!(
+            "RLIMIT_NOFILE soft limit {} already >= {}",
+            soft_limit, new_soft_limit
+        );
+        return Ok(());
+    }
+
+    setrlimit(Resource::RLIMIT_NOFILE, new_soft_limit, hard_limit)
+        .context("setrlimit RLIMIT_NOFILE error")?;
+
+    info!(
+        "raised RLIMIT_NOFILE soft limit from {} to {}",
+        soft_limit, new_soft_limit
+    );
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn raise_nofile_limit(
+    file_descriptor_configuration: &FileDescriptorConfiguration,
+) -> anyhow::Result<()> {
+    if file_descriptor_configuration.nofile_limit.is_some() {
+        warn!("nofile_limit is not supported on this platform, ignoring");
+    }
+
+    Ok(())
+}
+
+// Warns once each time fd usage rises above the configured percent of the soft limit.
+pub fn spawn_fd_usage_monitor() {
+    let file_descriptor_configuration = &crate::config::instance().file_descriptor_configuration;
+
+    let warning_percent = f64::from(file_descriptor_configuration.usage_warning_percent);
+
+    let mut interval = tokio::time::interval(file_descriptor_configuration.usage_check_interval);
+
+    tokio::spawn(async move {
+        let mut above_threshold = false;
+
+        loop {
+            interval.tick().await;
+
+            let fd_usage = match current_fd_usage() {
+                Err(e) => {
+                    warn!("fd usage monitor error: {:#}", e);
+                    return;
+                }
+                Ok(None) => return,
+                Ok(Some(fd_usage)) => fd_usage,
+            };
+
+            let Some(usage_percent) = fd_usage.usage_percent() else {
+                return;
+            };
+
+            if usage_percent >= warning_percent && !above_threshold {
+                warn!(
+                    "fd usage {:.1}% above {}% open_fds = {:?} soft_limit = {}",
+                    usage_percent, warning_percent, fd_usage.open_fds, fd_usage.soft_limit,
+                );
+            } else if usage_percent < warning_percent && above_threshold {
+                info!(
+                    "fd usage {:.1}% back below {}%",
+                    usage_percent, warning_percent
+                );
+            }
+
+            above_threshold = usage_percent >= warning_percent;
+        }
+    });
+}
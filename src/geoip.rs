@@ -0,0 +1,132 @@
+use anyhow::Context;
+
+use maxminddb::{geoip2, MaxMindDBError, Reader};
+
+use schemars::JsonSchema;
+
+use serde::Serialize;
+
+use tokio::sync::OnceCell;
+
+use tracing::{debug, info};
+
+use std::net::IpAddr;
+
+#[derive(Clone, Debug, Default, JsonSchema, Serialize)]
+pub struct GeoInfo {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub country: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub asn: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub as_organization: Option<String>,
+}
+
+impl GeoInfo {
+    fn is_empty(&self) -> bool {
+        self.country.is_none() && self.asn.is_none()
+    }
+}
+
+fn open_database(path: &Option<String>) -> anyhow::Result<Option<Reader<Vec<u8>>>> {
+    match path {
+        None => Ok(None),
+        Some(path) => {
+            let reader = Reader::open_readfile(path)
+                .with_context(|| format!("error opening geoip database '{}'", path))?;
+            info!(
+                "opened geoip database '{}' type = {}",
+                path, reader.metadata.database_type
+            );
+            Ok(Some(reader))
+        }
+    }
+}
+
+fn lookup_result<T>(result: Result<T, MaxMindDBError>) -> Option<T> {
+    match result {
+        Ok(value) => Some(value),
+        Err(MaxMindDBError::AddressNotFoundError(_)) => None,
+        Err(e) => {
+            debug!("geoip lookup error: {}", e);
+            None
+        }
+    }
+}
+
+pub struct GeoIpService {
+    country_reader: Option<Reader<Vec<u8>>>,
+    asn_reader: Option<Reader<Vec<u8>>>,
+}
+
+impl std::fmt::Debug for GeoIpService {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("GeoIpService")
+            .field("country_reader", &self.country_reader.is_some())
+            .field("asn_reader", &self.asn_reader.is_some())
+            .finish()
+    }
+}
+
+impl GeoIpService {
+    fn new() -> anyhow::Result<Self> {
+        let geoip_configuration = &crate::config::instance().geoip_configuration;
+
+        Ok(Self {
+            country_reader: open_database(&geoip_configuration.country_database_path)?,
+            asn_reader: open_database(&geoip_configuration.asn_database_path)?,
+        })
+    }
+
+    pub fn lookup(&self, ip_addr: IpAddr) -> Option<GeoInfo> {
+        if self.country_reader.is_none() && self.asn_reader.is_none() {
+            return None;
+        }
+
+        let ip_addr = match ip_addr {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip_addr, IpAddr::V4),
+            IpAddr::V4(_) => ip_addr,
+        };
+
+        let mut geo_info = GeoInfo::default();
+
+        if let Some(country_reader) = &self.country_reader {
+            if let Some(country) = lookup_result(country_reader.lookup::<geoip2::Country>(ip_addr))
+            {
+                geo_info.country = country
+                    .country
+                    .and_then(|country| country.iso_code)
+                    .map(str::to_owned);
+            }
+        }
+
+        if let Some(asn_reader) = &self.asn_reader {
+            if let Some(asn) = lookup_result(asn_reader.lookup::<geoip2::Asn>(ip_addr)) {
+                geo_info.asn = asn.autonomous_system_number;
+                geo_info.as_organization = asn.autonomous_system_organization.map(str::to_owned);
+            }
+        }
+
+        if geo_info.is_empty() {
+            None
+        } else {
+            Some(geo_info)
+        }
+    }
+}
+
+static GEOIP_SERVICE_INSTANCE: OnceCell<GeoIpService> = OnceCell::const_new();
+
+pub fn create_geoip_service_instance() -> anyhow::Result<()> {
+    let geoip_service = GeoIpService::new()?;
+
+    GEOIP_SERVICE_INSTANCE
+        .set(geoip_service)
+        .context("GEOIP_SERVICE_INSTANCE.set error")?;
+
+    Ok(())
+}
+
+pub fn geoip_service_instance() -> &'static GeoIpService {
+    GEOIP_SERVICE_INSTANCE.get().unwrap()
+}
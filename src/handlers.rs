@@ -1,36 +1,156 @@
+mod admin;
+mod authorization;
+mod build_info;
+mod cgi;
 mod commands;
+mod compression;
 mod connection_info;
+mod etag;
+mod fault_injection;
+mod json_output;
+mod load_shedding;
+mod metrics;
+mod openapi;
+mod proxy;
+mod random;
+mod rate_limit;
+mod request_id;
 mod request_info;
 mod route;
+mod route_metrics;
+mod route_toggles;
+mod runtime_info;
+mod server_stats;
 mod static_file;
-mod time_utils;
+pub mod time_utils;
+mod traffic_stats;
 mod version_info;
+mod virtual_host;
+mod websocket;
 
 use async_trait::async_trait;
 
-use hyper::http::Response;
+use hyper::{http::Response, upgrade::Upgraded};
+
+use hyper_util::rt::TokioIo;
+
+use tracing::info;
+
+use std::path::Path;
 
 use crate::{request::HttpRequest, response::ResponseBody};
 
+pub use proxy::parse_upstream_url;
+pub use request_id::ExternalRequestID;
+pub use route::{MatchedRoute, RouteInfo, Router};
+pub use virtual_host::VirtualHosts;
+
 #[async_trait]
 pub trait RequestHandler: Send + Sync {
     async fn handle(&self, request: &HttpRequest) -> Response<ResponseBody>;
 }
 
+// Takes over the connection after a route answers with 101 Switching Protocols.
+#[async_trait]
+pub trait UpgradeHandler: Send + Sync {
+    async fn handle_upgrade(&self, stream: TokioIo<Upgraded>);
+}
+
 pub async fn create_handlers() -> anyhow::Result<Box<dyn RequestHandler>> {
+    let compression_dictionaries = compression::load_dictionaries()?;
+
+    let proxy_handler = proxy::ProxyHandler::new(Box::new(cgi::CgiHandler::new(
+        static_file::create_default_route()?,
+    )?))?;
+
     let mut routes = Vec::new();
 
+    routes.extend(admin::create_routes());
+
+    routes.extend(build_info::create_routes());
+
     routes.extend(commands::create_routes().await?);
 
+    routes.extend(compression::create_routes(&compression_dictionaries));
+
     routes.extend(connection_info::create_routes().await);
 
+    routes.extend(metrics::create_routes().await);
+
+    routes.extend(proxy_handler.create_routes());
+
     routes.extend(request_info::create_routes());
 
+    routes.extend(route_metrics::create_routes().await);
+
+    routes.extend(runtime_info::create_routes());
+
+    routes.extend(server_stats::create_routes().await);
+
+    routes.extend(traffic_stats::create_routes().await);
+
     routes.extend(version_info::create_routes().await);
 
-    let default_route = static_file::create_default_route();
+    routes.extend(websocket::create_routes());
+
+    let disabled_routes = &crate::config::instance()
+        .context_configuration
+        .disabled_routes;
+
+    routes.retain(|route| {
+        let disabled = disabled_routes
+            .iter()
+            .any(|disabled| route.path_suffix.starts_with(Path::new(disabled)));
+
+        if disabled {
+            info!(
+                "disabled route method = {} path_suffix = {:?}",
+                route.method, route.path_suffix
+            );
+        }
+
+        !disabled
+    });
+
+    let mut routes = authorization::apply_endpoint_configuration(routes);
+
+    routes.push(openapi::create_route(&routes)?);
+
+    let router = Box::new(route::Router::new(routes, Box::new(proxy_handler))?);
+
+    let json_output_handler = Box::new(json_output::JsonOutputHandler::new(router));
+
+    let etag_handler = Box::new(etag::JsonETagHandler::new(json_output_handler));
+
+    let compression_handler = Box::new(compression::CompressionHandler::new(
+        etag_handler,
+        compression_dictionaries,
+    ));
+
+    let fault_injection_handler = Box::new(fault_injection::FaultInjectionHandler::new(
+        compression_handler,
+    ));
+
+    let rate_limit_handler = Box::new(rate_limit::PrincipalRateLimitHandler::new(
+        fault_injection_handler,
+    ));
+
+    let client_rate_limit_handler =
+        Box::new(rate_limit::ClientRateLimitHandler::new(rate_limit_handler));
+
+    let geoip_authorization_handler = Box::new(authorization::GeoIpAuthorizationHandler::new(
+        client_rate_limit_handler,
+    ));
+
+    let authorization_handler = Box::new(authorization::PeerCredentialAuthorizationHandler::new(
+        geoip_authorization_handler,
+    ));
+
+    let load_shedding_handler = Box::new(load_shedding::LoadSheddingHandler::new(
+        authorization_handler,
+    ));
 
-    let router = Box::new(route::Router::new(routes, default_route)?);
+    let request_id_handler = Box::new(request_id::RequestIdHandler::new(load_shedding_handler)?);
 
-    Ok(router)
+    Ok(request_id_handler)
 }
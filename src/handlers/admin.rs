@@ -0,0 +1,524 @@
+use async_trait::async_trait;
+
+use hyper::http::{Method, Response, StatusCode};
+
+use schemars::JsonSchema;
+
+use serde::Serialize;
+
+use tracing::{info, warn};
+
+use std::{path::PathBuf, time::Duration};
+
+use crate::{
+    config::ConfigurationLoadRecord,
+    handlers::{
+        fault_injection::{self, Fault, FaultAction, FaultKind},
+        route::{RouteApiDoc, RouteInfo},
+        route_toggles::{self, DisabledRouteStatus, RouteToggle},
+        time_utils::{local_date_time_to_string, LocalDateTime},
+        HttpRequest, RequestHandler, ResponseBody,
+    },
+    response::{build_json_response, build_status_code_response, CacheControl},
+};
+
+#[derive(Debug, JsonSchema, Serialize)]
+enum ConfigLoadOutcome {
+    #[serde(rename = "APPLIED")]
+    Applied,
+
+    #[serde(rename = "REJECTED")]
+    Rejected,
+}
+
+#[derive(Debug, JsonSchema, Serialize)]
+struct ConfigLoadRecordDTO {
+    time: String,
+    config_file: String,
+    sha256: Option<String>,
+    outcome: ConfigLoadOutcome,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    generation: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl From<ConfigurationLoadRecord> for ConfigLoadRecordDTO {
+    fn from(record: ConfigurationLoadRecord) -> Self {
+        let (outcome, generation, error) = match record.result {
+            Ok(generation) => (ConfigLoadOutcome::Applied, Some(generation), None),
+            Err(error) => (ConfigLoadOutcome::Rejected, None, Some(error)),
+        };
+
+        Self {
+            time: local_date_time_to_string(&LocalDateTime::from(record.time)),
+            config_file: record.config_file,
+            sha256: record.sha256,
+            outcome,
+            generation,
+            error,
+        }
+    }
+}
+
+#[derive(Debug, JsonSchema, Serialize)]
+struct ConfigHistoryDTO {
+    current_generation: u64,
+    // newest first
+    loads: Vec<ConfigLoadRecordDTO>,
+}
+
+struct ConfigHistoryHandler;
+
+#[async_trait]
+impl RequestHandler for ConfigHistoryHandler {
+    async fn handle(&self, _request: &HttpRequest) -> Response<ResponseBody> {
+        let dto = ConfigHistoryDTO {
+            current_generation: crate::config::generation().generation,
+            loads: crate::config::load_history()
+                .into_iter()
+                .rev()
+                .map(ConfigLoadRecordDTO::from)
+                .collect(),
+        };
+
+        build_json_response(dto, CacheControl::NoCache)
+    }
+}
+
+#[derive(Debug, JsonSchema, Serialize)]
+struct ConfigValidateDTO {
+    config_file: &'static str,
+    valid: bool,
+    errors: Vec<String>,
+    warnings: Vec<String>,
+}
+
+// Re-reads and checks the configuration file without applying it.
+struct ConfigValidateHandler;
+
+#[async_trait]
+impl RequestHandler for ConfigValidateHandler {
+    async fn handle(&self, _request: &HttpRequest) -> Response<ResponseBody> {
+        let config_file = crate::config::config_file();
+
+        let report = match tokio::task::spawn_blocking(move || {
+            crate::config::validate_configuration_file(config_file)
+        })
+        .await
+        {
+            Err(e) => {
+                warn!("ConfigValidateHandler spawn_blocking error: {}", e);
+                return build_status_code_response(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    CacheControl::NoCache,
+                );
+            }
+            Ok(report) => report,
+        };
+
+        let dto = ConfigValidateDTO {
+            config_file,
+            valid: report.errors.is_empty(),
+            errors: report.errors,
+            warnings: report.warnings,
+        };
+
+        build_json_response(dto, CacheControl::NoCache)
+    }
+}
+
+// POST admin/config/reload
+// Re-reads the configuration file like SIGHUP and responds with the load
+// record, 422 if the file was rejected.
+struct ConfigReloadHandler;
+
+#[async_trait]
+impl RequestHandler for ConfigReloadHandler {
+    async fn handle(&self, _request: &HttpRequest) -> Response<ResponseBody> {
+        let result = match tokio::task::spawn_blocking(crate::reload::reload).await {
+            Err(e) => {
+                warn!("ConfigReloadHandler spawn_blocking error: {}", e);
+                return build_status_code_response(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    CacheControl::NoCache,
+                );
+            }
+            Ok(result) => result,
+        };
+
+        let Some(record) = crate::config::load_history().pop() else {
+            return build_status_code_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                CacheControl::NoCache,
+            );
+        };
+
+        let mut response =
+            build_json_response(ConfigLoadRecordDTO::from(record), CacheControl::NoCache);
+
+        if result.is_err() {
+            *response.status_mut() = StatusCode::UNPROCESSABLE_ENTITY;
+        }
+
+        response
+    }
+}
+
+#[derive(Debug, JsonSchema, Serialize)]
+struct DisabledRouteDTO {
+    prefix: String,
+    status: DisabledRouteStatus,
+    disabled_time: String,
+}
+
+impl From<RouteToggle> for DisabledRouteDTO {
+    fn from(toggle: RouteToggle) -> Self {
+        Self {
+            prefix: toggle.prefix,
+            status: toggle.status,
+            disabled_time: local_date_time_to_string(&LocalDateTime::from(toggle.disabled_time)),
+        }
+    }
+}
+
+fn build_disabled_routes_response() -> Response<ResponseBody> {
+    let disabled_routes: Vec<DisabledRouteDTO> = route_toggles::disabled_routes()
+        .into_iter()
+        .map(DisabledRouteDTO::from)
+        .collect();
+
+    build_json_response(disabled_routes, CacheControl::NoCache)
+}
+
+struct DisabledRoutesHandler;
+
+#[async_trait]
+impl RequestHandler for DisabledRoutesHandler {
+    async fn handle(&self, _request: &HttpRequest) -> Response<ResponseBody> {
+        build_disabled_routes_response()
+    }
+}
+
+// POST admin/routes/disable?prefix=commands[&status=NOT_FOUND]
+// prefix matches route path suffixes, status defaults to SERVICE_UNAVAILABLE.
+struct DisableRoutesHandler;
+
+#[async_trait]
+impl RequestHandler for DisableRoutesHandler {
+    async fn handle(&self, request: &HttpRequest) -> Response<ResponseBody> {
+        let query_params = request.query_params();
+
+        let Some(prefix) = query_params.get("prefix") else {
+            return build_status_code_response(StatusCode::BAD_REQUEST, CacheControl::NoCache);
+        };
+
+        let status = match query_params.get("status") {
+            None => DisabledRouteStatus::ServiceUnavailable,
+            Some(status) => match DisabledRouteStatus::parse(status) {
+                None => {
+                    return build_status_code_response(
+                        StatusCode::BAD_REQUEST,
+                        CacheControl::NoCache,
+                    )
+                }
+                Some(status) => status,
+            },
+        };
+
+        if let Err(e) = route_toggles::disable_routes(prefix, status) {
+            warn!("DisableRoutesHandler prefix = {:?} error: {}", prefix, e);
+            return build_status_code_response(StatusCode::FORBIDDEN, CacheControl::NoCache);
+        }
+
+        build_disabled_routes_response()
+    }
+}
+
+// POST admin/routes/enable?prefix=commands
+struct EnableRoutesHandler;
+
+#[async_trait]
+impl RequestHandler for EnableRoutesHandler {
+    async fn handle(&self, request: &HttpRequest) -> Response<ResponseBody> {
+        let Some(prefix) = request.query_params().get("prefix") else {
+            return build_status_code_response(StatusCode::BAD_REQUEST, CacheControl::NoCache);
+        };
+
+        if !route_toggles::enable_routes(prefix) {
+            return build_status_code_response(StatusCode::NOT_FOUND, CacheControl::NoCache);
+        }
+
+        build_disabled_routes_response()
+    }
+}
+
+#[derive(Debug, JsonSchema, Serialize)]
+struct FaultDTO {
+    path_prefix: String,
+    kind: FaultKind,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(with = "humantime_serde")]
+    #[schemars(with = "Option<String>")]
+    latency: Option<Duration>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    status: Option<u16>,
+    rate: f64,
+    injected_time: String,
+}
+
+impl From<Fault> for FaultDTO {
+    fn from(fault: Fault) -> Self {
+        let (latency, status) = match fault.action {
+            FaultAction::Latency(latency) => (Some(latency), None),
+            FaultAction::Error(status_code) => (None, Some(status_code.as_u16())),
+            FaultAction::Reset => (None, None),
+        };
+
+        Self {
+            path_prefix: fault.path_prefix,
+            kind: fault.action.kind(),
+            latency,
+            status,
+            rate: fault.rate,
+            injected_time: local_date_time_to_string(&LocalDateTime::from(fault.injected_time)),
+        }
+    }
+}
+
+fn build_faults_response() -> Response<ResponseBody> {
+    let faults: Vec<FaultDTO> = fault_injection::faults()
+        .into_iter()
+        .map(FaultDTO::from)
+        .collect();
+
+    build_json_response(faults, CacheControl::NoCache)
+}
+
+struct FaultsHandler;
+
+#[async_trait]
+impl RequestHandler for FaultsHandler {
+    async fn handle(&self, _request: &HttpRequest) -> Response<ResponseBody> {
+        build_faults_response()
+    }
+}
+
+// POST admin/faults/inject?path_prefix=/internal/&kind=LATENCY&latency=500ms[&rate=0.1]
+// kind is LATENCY, ERROR with an optional status (default 503) or RESET,
+// rate defaults to 1.
+struct InjectFaultHandler;
+
+#[async_trait]
+impl RequestHandler for InjectFaultHandler {
+    async fn handle(&self, request: &HttpRequest) -> Response<ResponseBody> {
+        let query_params = request.query_params();
+
+        let action = query_params.get("kind").and_then(|kind| {
+            FaultAction::parse(
+                kind,
+                query_params.get("latency"),
+                query_params.get("status"),
+            )
+        });
+
+        let rate = match query_params.get("rate") {
+            None => Some(1.0),
+            Some(rate) => rate
+                .parse::<f64>()
+                .ok()
+                .filter(|rate| *rate > 0.0 && *rate <= 1.0),
+        };
+
+        let (Some(path_prefix), Some(action), Some(rate)) =
+            (query_params.get("path_prefix"), action, rate)
+        else {
+            return build_status_code_response(StatusCode::BAD_REQUEST, CacheControl::NoCache);
+        };
+
+        fault_injection::inject_fault(path_prefix, action, rate);
+
+        build_faults_response()
+    }
+}
+
+// POST admin/faults/clear?path_prefix=/internal/
+struct ClearFaultHandler;
+
+#[async_trait]
+impl RequestHandler for ClearFaultHandler {
+    async fn handle(&self, request: &HttpRequest) -> Response<ResponseBody> {
+        let Some(path_prefix) = request.query_params().get("path_prefix") else {
+            return build_status_code_response(StatusCode::BAD_REQUEST, CacheControl::NoCache);
+        };
+
+        if !fault_injection::clear_fault(path_prefix) {
+            return build_status_code_response(StatusCode::NOT_FOUND, CacheControl::NoCache);
+        }
+
+        build_faults_response()
+    }
+}
+
+#[derive(Debug, JsonSchema, Serialize)]
+struct LogFilterDTO {
+    filter: String,
+}
+
+fn build_log_filter_response(result: anyhow::Result<String>) -> Response<ResponseBody> {
+    match result {
+        Err(e) => {
+            warn!("log filter error: {:#}", e);
+            build_status_code_response(StatusCode::BAD_REQUEST, CacheControl::NoCache)
+        }
+        Ok(filter) => build_json_response(LogFilterDTO { filter }, CacheControl::NoCache),
+    }
+}
+
+struct LogFilterHandler;
+
+#[async_trait]
+impl RequestHandler for LogFilterHandler {
+    async fn handle(&self, _request: &HttpRequest) -> Response<ResponseBody> {
+        build_log_filter_response(crate::tracing_config::log_filter())
+    }
+}
+
+// POST admin/log_level?filter=info,rhs::handlers=debug
+// filter uses RUST_LOG syntax and replaces the current filter.
+struct SetLogFilterHandler;
+
+#[async_trait]
+impl RequestHandler for SetLogFilterHandler {
+    async fn handle(&self, request: &HttpRequest) -> Response<ResponseBody> {
+        let Some(filter) = request.query_params().get("filter") else {
+            return build_status_code_response(StatusCode::BAD_REQUEST, CacheControl::NoCache);
+        };
+
+        let result = crate::tracing_config::set_log_filter(filter);
+
+        if let Ok(filter) = &result {
+            info!("log filter set to '{}'", filter);
+        }
+
+        build_log_filter_response(result)
+    }
+}
+
+#[derive(Debug, JsonSchema, Serialize)]
+struct DrainDTO {
+    already_draining: bool,
+    #[serde(with = "humantime_serde")]
+    #[schemars(with = "String")]
+    graceful_shutdown_timeout: Duration,
+}
+
+// POST admin/drain
+// Closes listeners, gracefully shuts down open connections and exits once
+// they are closed or graceful_shutdown_timeout has passed.  Like every admin
+// route it is only served over a unix socket unless admin auth is configured.
+struct DrainHandler;
+
+#[async_trait]
+impl RequestHandler for DrainHandler {
+    async fn handle(&self, _request: &HttpRequest) -> Response<ResponseBody> {
+        let dto = DrainDTO {
+            already_draining: !crate::shutdown::start_drain(),
+            graceful_shutdown_timeout: crate::config::current()
+                .server_configuration
+                .connection
+                .graceful_shutdown_timeout,
+        };
+
+        build_json_response(dto, CacheControl::NoCache)
+    }
+}
+
+pub fn create_routes() -> Vec<RouteInfo> {
+    vec![
+        RouteInfo {
+            method: &Method::GET,
+            path_suffix: PathBuf::from("admin/config/history"),
+            handler: Box::new(ConfigHistoryHandler),
+            api_doc: RouteApiDoc::json::<ConfigHistoryDTO>("Configuration load history"),
+        },
+        RouteInfo {
+            method: &Method::POST,
+            path_suffix: PathBuf::from("admin/config/validate"),
+            handler: Box::new(ConfigValidateHandler),
+            api_doc: RouteApiDoc::json::<ConfigValidateDTO>(
+                "Validate the configuration file without applying it",
+            ),
+        },
+        RouteInfo {
+            method: &Method::POST,
+            path_suffix: PathBuf::from("admin/config/reload"),
+            handler: Box::new(ConfigReloadHandler),
+            api_doc: RouteApiDoc::json::<ConfigLoadRecordDTO>(
+                "Reload the configuration file, applying the parts that can change at runtime",
+            ),
+        },
+        RouteInfo {
+            method: &Method::GET,
+            path_suffix: PathBuf::from("admin/routes/disabled"),
+            handler: Box::new(DisabledRoutesHandler),
+            api_doc: RouteApiDoc::json::<Vec<DisabledRouteDTO>>("Routes disabled at runtime"),
+        },
+        RouteInfo {
+            method: &Method::POST,
+            path_suffix: PathBuf::from("admin/routes/disable"),
+            handler: Box::new(DisableRoutesHandler),
+            api_doc: RouteApiDoc::json::<Vec<DisabledRouteDTO>>(
+                "Disable routes matching a path prefix",
+            ),
+        },
+        RouteInfo {
+            method: &Method::POST,
+            path_suffix: PathBuf::from("admin/routes/enable"),
+            handler: Box::new(EnableRoutesHandler),
+            api_doc: RouteApiDoc::json::<Vec<DisabledRouteDTO>>(
+                "Re-enable routes disabled at runtime",
+            ),
+        },
+        RouteInfo {
+            method: &Method::GET,
+            path_suffix: PathBuf::from("admin/faults"),
+            handler: Box::new(FaultsHandler),
+            api_doc: RouteApiDoc::json::<Vec<FaultDTO>>("Faults injected at runtime"),
+        },
+        RouteInfo {
+            method: &Method::POST,
+            path_suffix: PathBuf::from("admin/faults/inject"),
+            handler: Box::new(InjectFaultHandler),
+            api_doc: RouteApiDoc::json::<Vec<FaultDTO>>(
+                "Inject latency, errors or resets for a path prefix",
+            ),
+        },
+        RouteInfo {
+            method: &Method::POST,
+            path_suffix: PathBuf::from("admin/faults/clear"),
+            handler: Box::new(ClearFaultHandler),
+            api_doc: RouteApiDoc::json::<Vec<FaultDTO>>("Clear an injected fault"),
+        },
+        RouteInfo {
+            method: &Method::GET,
+            path_suffix: PathBuf::from("admin/log_level"),
+            handler: Box::new(LogFilterHandler),
+            api_doc: RouteApiDoc::json::<LogFilterDTO>("Current log filter"),
+        },
+        RouteInfo {
+            method: &Method::POST,
+            path_suffix: PathBuf::from("admin/log_level"),
+            handler: Box::new(SetLogFilterHandler),
+            api_doc: RouteApiDoc::json::<LogFilterDTO>("Replace the log filter"),
+        },
+        RouteInfo {
+            method: &Method::POST,
+            path_suffix: PathBuf::from("admin/drain"),
+            handler: Box::new(DrainHandler),
+            api_doc: RouteApiDoc::json::<DrainDTO>(
+                "Stop accepting connections and exit once open connections close",
+            ),
+        },
+    ]
+}
@@ -0,0 +1,469 @@
+use async_trait::async_trait;
+
+use hyper::http::{header, Response, StatusCode};
+
+use tracing::{debug, info, warn};
+
+use std::{
+    path::{Component, Path},
+    sync::OnceLock,
+};
+
+use crate::{
+    config::{
+        EndpointAuthConfiguration, EndpointConfiguration, EndpointsConfiguration, GeoIpRule,
+        PeerCredentialRule,
+    },
+    connection::PeerCredentials,
+    geoip::GeoInfo,
+    handlers::{route::RouteInfo, HttpRequest, RequestHandler, ResponseBody},
+    response::{build_deny_response, DenyReason},
+};
+
+fn rule_allows_peer(rule: &PeerCredentialRule, peer_credentials: Option<PeerCredentials>) -> bool {
+    match peer_credentials {
+        None => false,
+        Some(peer_credentials) => {
+            rule.allowed_uids.contains(&peer_credentials.uid)
+                || rule.allowed_gids.contains(&peer_credentials.gid)
+        }
+    }
+}
+
+// Restricts configured path prefixes to unix socket peers with allowed uids/gids.
+// Requests without peer credentials (e.g. TCP connections) never match an allow list.
+pub struct PeerCredentialAuthorizationHandler {
+    rules: &'static [PeerCredentialRule],
+    next: Box<dyn RequestHandler>,
+}
+
+impl PeerCredentialAuthorizationHandler {
+    pub fn new(next: Box<dyn RequestHandler>) -> Self {
+        let rules = &crate::config::instance()
+            .authorization_configuration
+            .peer_credential_rules;
+
+        debug!("peer_credential_rules = {:?}", rules);
+
+        Self { rules, next }
+    }
+
+    fn find_rule(&self, path: &str) -> Option<&'static PeerCredentialRule> {
+        self.rules
+            .iter()
+            .find(|rule| path.starts_with(&rule.path_prefix))
+    }
+}
+
+#[async_trait]
+impl RequestHandler for PeerCredentialAuthorizationHandler {
+    async fn handle(&self, request: &HttpRequest) -> Response<ResponseBody> {
+        if let Some(rule) = self.find_rule(request.hyper_request.uri().path()) {
+            let peer_credentials = request.peer_credentials();
+
+            if !rule_allows_peer(rule, peer_credentials) {
+                warn!(
+                    "peer credential authorization denied path_prefix = {:?} peer_credentials = {:?}",
+                    rule.path_prefix, peer_credentials,
+                );
+                return build_deny_response(StatusCode::FORBIDDEN, DenyReason::PeerCredential);
+            }
+        }
+
+        self.next.handle(request).await
+    }
+}
+
+fn rule_allows_geo_info(rule: &GeoIpRule, geo_info: Option<&GeoInfo>) -> bool {
+    let country = geo_info.and_then(|geo_info| geo_info.country.as_deref());
+    let asn = geo_info.and_then(|geo_info| geo_info.asn);
+
+    let country_denied = country.is_some_and(|country| {
+        rule.denied_countries
+            .iter()
+            .any(|denied| denied.eq_ignore_ascii_case(country))
+    });
+    let asn_denied = asn.is_some_and(|asn| rule.denied_asns.contains(&asn));
+
+    if country_denied || asn_denied {
+        return false;
+    }
+
+    let country_allowed = rule.allowed_countries.is_empty()
+        || country.is_some_and(|country| {
+            rule.allowed_countries
+                .iter()
+                .any(|allowed| allowed.eq_ignore_ascii_case(country))
+        });
+    let asn_allowed =
+        rule.allowed_asns.is_empty() || asn.is_some_and(|asn| rule.allowed_asns.contains(&asn));
+
+    country_allowed && asn_allowed
+}
+
+// Allows or denies configured path prefixes by client country and ASN.
+// With an allow list, clients with unknown country or ASN are denied.
+pub struct GeoIpAuthorizationHandler {
+    rules: &'static [GeoIpRule],
+    next: Box<dyn RequestHandler>,
+}
+
+impl GeoIpAuthorizationHandler {
+    pub fn new(next: Box<dyn RequestHandler>) -> Self {
+        let rules = &crate::config::instance()
+            .authorization_configuration
+            .geoip_rules;
+
+        debug!("geoip_rules = {:?}", rules);
+
+        Self { rules, next }
+    }
+
+    fn find_rule(&self, path: &str) -> Option<&'static GeoIpRule> {
+        self.rules
+            .iter()
+            .find(|rule| path.starts_with(&rule.path_prefix))
+    }
+}
+
+#[async_trait]
+impl RequestHandler for GeoIpAuthorizationHandler {
+    async fn handle(&self, request: &HttpRequest) -> Response<ResponseBody> {
+        if let Some(rule) = self.find_rule(request.hyper_request.uri().path()) {
+            let geo_info = request.geo_info();
+
+            if !rule_allows_geo_info(rule, geo_info) {
+                warn!(
+                    "geoip authorization denied path_prefix = {:?} geo_info = {:?}",
+                    rule.path_prefix, geo_info,
+                );
+                return build_deny_response(StatusCode::FORBIDDEN, DenyReason::GeoIp);
+            }
+        }
+
+        self.next.handle(request).await
+    }
+}
+
+// Compare every byte so response timing does not leak a matching prefix.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+pub fn bearer_token(authorization_header: Option<&str>) -> Option<&str> {
+    authorization_header
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(str::trim)
+}
+
+fn endpoint_auth_allows(
+    auth: &EndpointAuthConfiguration,
+    peer_credentials: Option<PeerCredentials>,
+    authorization_header: Option<&str>,
+) -> bool {
+    let peer_allowed = peer_credentials.is_some_and(|peer_credentials| {
+        auth.allowed_uids.contains(&peer_credentials.uid)
+            || auth.allowed_gids.contains(&peer_credentials.gid)
+    });
+
+    let token_allowed = bearer_token(authorization_header).is_some_and(|token| {
+        auth.bearer_tokens
+            .iter()
+            .any(|allowed| constant_time_eq(allowed.as_bytes(), token.as_bytes()))
+    });
+
+    peer_allowed || token_allowed
+}
+
+// Admin routes without endpoints_configuration.admin.auth only serve unix
+// socket peers running as root or as the server's user.
+fn default_admin_auth() -> &'static EndpointAuthConfiguration {
+    static DEFAULT_ADMIN_AUTH: OnceLock<EndpointAuthConfiguration> = OnceLock::new();
+
+    DEFAULT_ADMIN_AUTH.get_or_init(|| {
+        #[cfg(unix)]
+        let allowed_uids = vec![0, nix::unistd::geteuid().as_raw()];
+
+        #[cfg(not(unix))]
+        let allowed_uids = Vec::new();
+
+        EndpointAuthConfiguration {
+            allowed_uids,
+            ..Default::default()
+        }
+    })
+}
+
+// None if the request is allowed, 401 if a bearer token could allow it.
+fn endpoint_deny_response(
+    auth: &EndpointAuthConfiguration,
+    peer_credentials: Option<PeerCredentials>,
+    authorization_header: Option<&str>,
+) -> Option<Response<ResponseBody>> {
+    if endpoint_auth_allows(auth, peer_credentials, authorization_header) {
+        return None;
+    }
+
+    if auth.bearer_tokens.is_empty() {
+        return Some(build_deny_response(
+            StatusCode::FORBIDDEN,
+            DenyReason::EndpointAuth,
+        ));
+    }
+
+    let mut response = build_deny_response(StatusCode::UNAUTHORIZED, DenyReason::EndpointAuth);
+    response.headers_mut().insert(
+        header::WWW_AUTHENTICATE,
+        header::HeaderValue::from_static("Bearer"),
+    );
+    Some(response)
+}
+
+// Requires an allowed peer uid/gid or bearer token for one built-in endpoint.
+struct EndpointAuthorizationHandler {
+    auth: &'static EndpointAuthConfiguration,
+    next: Box<dyn RequestHandler>,
+}
+
+#[async_trait]
+impl RequestHandler for EndpointAuthorizationHandler {
+    async fn handle(&self, request: &HttpRequest) -> Response<ResponseBody> {
+        let authorization_header = request
+            .hyper_request
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok());
+
+        let Some(response) =
+            endpoint_deny_response(self.auth, request.peer_credentials(), authorization_header)
+        else {
+            return self.next.handle(request).await;
+        };
+
+        warn!(
+            "endpoint authorization denied path = {:?} peer_credentials = {:?}",
+            request.hyper_request.uri().path(),
+            request.peer_credentials(),
+        );
+
+        response
+    }
+}
+
+// The built-in endpoint named by path_suffix's first component.
+fn find_endpoint<'c, 'p>(
+    endpoints_configuration: &'c EndpointsConfiguration,
+    path_suffix: &'p Path,
+) -> Option<(&'p str, &'c EndpointConfiguration)> {
+    match path_suffix.components().next() {
+        Some(Component::Normal(name)) => name.to_str().and_then(|name| {
+            endpoints_configuration
+                .endpoint(name)
+                .map(|endpoint| (name, endpoint))
+        }),
+        _ => None,
+    }
+}
+
+fn endpoint_auth<'a>(
+    name: &str,
+    endpoint: &'a EndpointConfiguration,
+) -> Option<&'a EndpointAuthConfiguration> {
+    match &endpoint.auth {
+        Some(auth) => Some(auth),
+        None if name == "admin" => Some(default_admin_auth()),
+        None => None,
+    }
+}
+
+// Drops disabled built-in endpoints and wraps auth-gated ones, per
+// endpoints_configuration.  Admin routes are always gated.
+pub fn apply_endpoint_configuration(routes: Vec<RouteInfo>) -> Vec<RouteInfo> {
+    let endpoints_configuration = &crate::config::instance().endpoints_configuration;
+
+    routes
+        .into_iter()
+        .filter_map(|mut route| {
+            let Some((name, endpoint)) = find_endpoint(endpoints_configuration, &route.path_suffix)
+            else {
+                return Some(route);
+            };
+
+            if !endpoint.enabled {
+                info!(
+                    "endpoint disabled method = {} path_suffix = {:?}",
+                    route.method, route.path_suffix
+                );
+                return None;
+            }
+
+            if let Some(auth) = endpoint_auth(name, endpoint) {
+                route.handler = Box::new(EndpointAuthorizationHandler {
+                    auth,
+                    next: route.handler,
+                });
+            }
+
+            Some(route)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_rule_allows_peer() {
+        let rule = PeerCredentialRule {
+            path_prefix: "/api/v1/commands".to_owned(),
+            allowed_uids: vec![1000],
+            allowed_gids: vec![27],
+        };
+
+        let peer = |uid, gid| {
+            Some(PeerCredentials {
+                uid,
+                gid,
+                pid: None,
+            })
+        };
+
+        assert!(rule_allows_peer(&rule, peer(1000, 1000)));
+        assert!(rule_allows_peer(&rule, peer(1001, 27)));
+        assert!(!rule_allows_peer(&rule, peer(1001, 1001)));
+        assert!(!rule_allows_peer(&rule, None));
+    }
+
+    #[test]
+    fn test_rule_allows_geo_info() {
+        let geo_info = |country: &str, asn| GeoInfo {
+            country: Some(country.to_owned()),
+            asn: Some(asn),
+            as_organization: None,
+        };
+
+        let allow_rule = GeoIpRule {
+            path_prefix: "/".to_owned(),
+            allowed_countries: vec!["US".to_owned()],
+            denied_countries: vec![],
+            allowed_asns: vec![],
+            denied_asns: vec![64512],
+        };
+
+        assert!(rule_allows_geo_info(&allow_rule, Some(&geo_info("US", 1))));
+        assert!(rule_allows_geo_info(&allow_rule, Some(&geo_info("us", 1))));
+        assert!(!rule_allows_geo_info(
+            &allow_rule,
+            Some(&geo_info("US", 64512))
+        ));
+        assert!(!rule_allows_geo_info(&allow_rule, Some(&geo_info("DE", 1))));
+        assert!(!rule_allows_geo_info(&allow_rule, None));
+
+        let deny_rule = GeoIpRule {
+            path_prefix: "/".to_owned(),
+            allowed_countries: vec![],
+            denied_countries: vec!["DE".to_owned()],
+            allowed_asns: vec![],
+            denied_asns: vec![],
+        };
+
+        assert!(rule_allows_geo_info(&deny_rule, Some(&geo_info("US", 1))));
+        assert!(!rule_allows_geo_info(&deny_rule, Some(&geo_info("DE", 1))));
+        assert!(rule_allows_geo_info(&deny_rule, None));
+    }
+
+    #[test]
+    fn test_endpoint_auth_allows() {
+        let auth = EndpointAuthConfiguration {
+            bearer_tokens: vec!["secret".to_owned()],
+            allowed_uids: vec![1000],
+            allowed_gids: vec![],
+        };
+
+        let peer = Some(PeerCredentials {
+            uid: 1000,
+            gid: 1000,
+            pid: None,
+        });
+
+        assert!(endpoint_auth_allows(&auth, peer, None));
+        assert!(endpoint_auth_allows(&auth, None, Some("Bearer secret")));
+        assert!(!endpoint_auth_allows(&auth, None, Some("Bearer secreT")));
+        assert!(!endpoint_auth_allows(&auth, None, Some("Basic secret")));
+        assert!(!endpoint_auth_allows(&auth, None, None));
+    }
+
+    #[test]
+    fn test_endpoint_deny_response() {
+        let peer = |uid| {
+            Some(PeerCredentials {
+                uid,
+                gid: uid,
+                pid: None,
+            })
+        };
+
+        // admin routes without auth: TCP requests and other unix users are forbidden
+        let auth = default_admin_auth();
+        let server_uid = *auth.allowed_uids.last().unwrap();
+
+        let response = endpoint_deny_response(auth, None, Some("Bearer secret")).unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert!(!response.headers().contains_key(header::WWW_AUTHENTICATE));
+
+        let other_uid = (1..).find(|uid| !auth.allowed_uids.contains(uid)).unwrap();
+        assert!(endpoint_deny_response(auth, peer(other_uid), None).is_some());
+        assert!(endpoint_deny_response(auth, peer(server_uid), None).is_none());
+        assert!(endpoint_deny_response(auth, peer(0), None).is_none());
+
+        let auth = EndpointAuthConfiguration {
+            bearer_tokens: vec!["secret".to_owned()],
+            ..Default::default()
+        };
+
+        let response = endpoint_deny_response(&auth, None, None).unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(response.headers()[header::WWW_AUTHENTICATE], "Bearer");
+        assert!(endpoint_deny_response(&auth, None, Some("Bearer secret")).is_none());
+    }
+
+    // Admin routes a TCP client must not reach without configured auth.
+    fn assert_admin_routes_gated(path_suffix_prefix: &str, expected_routes: usize) {
+        crate::config::set_test_instance();
+
+        let endpoints_configuration = &crate::config::instance().endpoints_configuration;
+        assert!(endpoints_configuration.admin.auth.is_none());
+
+        let routes: Vec<_> = crate::handlers::admin::create_routes()
+            .into_iter()
+            .filter(|route| route.path_suffix.starts_with(path_suffix_prefix))
+            .collect();
+        assert_eq!(routes.len(), expected_routes);
+
+        for route in routes {
+            let (name, endpoint) =
+                find_endpoint(endpoints_configuration, &route.path_suffix).unwrap();
+            let auth = endpoint_auth(name, endpoint).unwrap();
+
+            let response = endpoint_deny_response(auth, None, None).unwrap();
+            assert_eq!(
+                response.status(),
+                StatusCode::FORBIDDEN,
+                "{:?}",
+                route.path_suffix
+            );
+        }
+    }
+
+    #[test]
+    fn test_fault_routes_gated() {
+        assert_admin_routes_gated("admin/faults", 3);
+    }
+
+    #[test]
+    fn test_drain_and_log_level_routes_gated() {
+        assert_admin_routes_gated("admin/drain", 1);
+        assert_admin_routes_gated("admin/log_level", 2);
+    }
+}
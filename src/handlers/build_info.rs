@@ -0,0 +1,63 @@
+use async_trait::async_trait;
+
+use hyper::http::{Method, Response};
+
+use schemars::JsonSchema;
+
+use serde::Serialize;
+
+use std::path::PathBuf;
+
+use crate::{
+    handlers::{
+        route::{RouteApiDoc, RouteInfo},
+        HttpRequest, RequestHandler, ResponseBody,
+    },
+    response::{build_json_response, CacheControl},
+    version::{get_verison_info, VersionInfoMap},
+};
+
+#[derive(Debug, JsonSchema, Serialize)]
+struct BuildInfoDTO {
+    // GNU build-id of the running executable, absent if not linked with one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    build_id: Option<&'static str>,
+    executable: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    symbols_path: Option<&'static str>,
+    rustflags: &'static str,
+    version_info: &'static VersionInfoMap,
+}
+
+struct BuildInfoHandler;
+
+#[async_trait]
+impl RequestHandler for BuildInfoHandler {
+    async fn handle(&self, _request: &HttpRequest) -> Response<ResponseBody> {
+        let dto = BuildInfoDTO {
+            build_id: crate::build_id::executable_build_id(),
+            executable: std::env::current_exe()
+                .ok()
+                .map(|executable| executable.to_string_lossy().into_owned()),
+            symbols_path: crate::config::instance()
+                .debug_configuration
+                .symbols_path
+                .as_deref(),
+            rustflags: env!("RHS_RUSTFLAGS"),
+            version_info: get_verison_info(),
+        };
+
+        build_json_response(dto, CacheControl::NoCache)
+    }
+}
+
+pub fn create_routes() -> Vec<RouteInfo> {
+    vec![RouteInfo {
+        method: &Method::GET,
+        path_suffix: PathBuf::from("debug/buildinfo"),
+        handler: Box::new(BuildInfoHandler),
+        api_doc: RouteApiDoc::json::<BuildInfoDTO>(
+            "Build-id, symbols path and build information for matching debug symbols",
+        ),
+    }]
+}
@@ -0,0 +1,690 @@
+use anyhow::Context;
+
+use async_trait::async_trait;
+
+use bytes::Bytes;
+
+use http_body_util::{BodyExt, Limited};
+
+use hyper::{
+    body::Incoming,
+    http::{header, HeaderMap, HeaderName, HeaderValue, Request, Response, StatusCode},
+};
+
+use tokio::{
+    io::{
+        AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWriteExt, BufReader, ReadBuf,
+    },
+    process::{Child, ChildStderr, ChildStdin, ChildStdout, Command},
+};
+
+use tracing::{debug, warn, Instrument};
+
+use std::{
+    net::{IpAddr, SocketAddr},
+    path::{Path, PathBuf},
+    pin::Pin,
+    process::Stdio,
+    task::Poll,
+    time::Duration,
+};
+
+use crate::{
+    config::CgiRoute,
+    handlers::{HttpRequest, RequestHandler, ResponseBody},
+    request::RequestBodyError,
+    response::{async_read_response_body, build_status_code_response, CacheControl},
+};
+
+const CHUNK_SIZE: usize = 8 * 1024;
+
+// Scripts sending more header bytes than this get 502.
+const MAX_RESPONSE_HEADER_BYTES: usize = 64 * 1024;
+
+const SERVER_SOFTWARE: &str = concat!("rhs/", env!("CARGO_PKG_VERSION"));
+
+// Credentials are not passed to scripts, RFC 3875 4.1.18, and the body is
+// described by CONTENT_LENGTH and CONTENT_TYPE.  Proxy would become
+// HTTP_PROXY, which HTTP clients in scripts take as their outbound proxy
+// (httpoxy).
+const NOT_PASSED_HEADERS: [HeaderName; 6] = [
+    header::AUTHORIZATION,
+    header::PROXY_AUTHORIZATION,
+    header::CONTENT_LENGTH,
+    header::CONTENT_TYPE,
+    header::TRANSFER_ENCODING,
+    HeaderName::from_static("proxy"),
+];
+
+// A request path resolved to a script under a route.
+#[derive(Debug, PartialEq)]
+struct CgiScript {
+    script_name: String,
+    script_filename: PathBuf,
+    path_info: String,
+}
+
+#[derive(Debug)]
+struct CgiRouteEntry {
+    path_prefix: &'static str,
+    directory: &'static Path,
+    interpreter: Option<&'static str>,
+    timeout: Duration,
+}
+
+impl CgiRouteEntry {
+    fn new(cgi_route: &'static CgiRoute) -> anyhow::Result<Self> {
+        if !cgi_route.path_prefix.starts_with('/') || !cgi_route.path_prefix.ends_with('/') {
+            anyhow::bail!(
+                "cgi route path_prefix '{}' must start and end with '/'",
+                cgi_route.path_prefix
+            );
+        }
+
+        Ok(Self {
+            path_prefix: &cgi_route.path_prefix,
+            directory: Path::new(&cgi_route.directory),
+            interpreter: cgi_route.interpreter.as_deref(),
+            timeout: cgi_route.timeout,
+        })
+    }
+
+    // The first segment after path_prefix is the script, hidden and encoded
+    // names are never run.
+    fn resolve_script(&self, path: &str) -> Option<CgiScript> {
+        let rest = path.strip_prefix(self.path_prefix)?;
+
+        let (name, path_info) = match rest.find('/') {
+            Some(index) => rest.split_at(index),
+            None => (rest, ""),
+        };
+
+        if name.is_empty() || name.starts_with('.') || name.contains(['%', '\\']) {
+            return None;
+        }
+
+        Some(CgiScript {
+            script_name: format!("{}{}", self.path_prefix, name),
+            script_filename: self.directory.join(name),
+            path_info: percent_encoding::percent_decode_str(path_info)
+                .decode_utf8_lossy()
+                .into_owned(),
+        })
+    }
+}
+
+// The meta-variables of RFC 3875 section 4.1, plus REQUEST_URI,
+// SCRIPT_FILENAME and REDIRECT_STATUS that PHP expects.
+fn cgi_environment(
+    hyper_request: &Request<()>,
+    script: &CgiScript,
+    content_length: Option<usize>,
+    client_address: Option<IpAddr>,
+    peer_addr: Option<SocketAddr>,
+    local_addr: Option<SocketAddr>,
+    https: bool,
+) -> Vec<(String, String)> {
+    let uri = hyper_request.uri();
+
+    let host = hyper_request
+        .headers()
+        .get(header::HOST)
+        .and_then(|value| value.to_str().ok())
+        .or_else(|| uri.host())
+        .unwrap_or_default();
+
+    let server_name = match host.rsplit_once(':') {
+        Some((server_name, port)) if port.bytes().all(|b| b.is_ascii_digit()) => server_name,
+        _ => host,
+    };
+
+    let mut environment: Vec<(String, String)> = [
+        ("GATEWAY_INTERFACE", "CGI/1.1".to_owned()),
+        ("SERVER_SOFTWARE", SERVER_SOFTWARE.to_owned()),
+        ("SERVER_PROTOCOL", format!("{:?}", hyper_request.version())),
+        ("SERVER_NAME", server_name.to_owned()),
+        ("REQUEST_METHOD", hyper_request.method().to_string()),
+        (
+            "REQUEST_URI",
+            uri.path_and_query()
+                .map_or_else(|| uri.path().to_owned(), |p| p.to_string()),
+        ),
+        ("QUERY_STRING", uri.query().unwrap_or_default().to_owned()),
+        ("SCRIPT_NAME", script.script_name.clone()),
+        (
+            "SCRIPT_FILENAME",
+            script.script_filename.to_string_lossy().into_owned(),
+        ),
+        ("REDIRECT_STATUS", "200".to_owned()),
+    ]
+    .into_iter()
+    .map(|(name, value)| (name.to_owned(), value))
+    .collect();
+
+    let mut push = |name: &str, value: String| environment.push((name.to_owned(), value));
+
+    if !script.path_info.is_empty() {
+        push("PATH_INFO", script.path_info.clone());
+    }
+
+    if let Some(content_length) = content_length {
+        push("CONTENT_LENGTH", content_length.to_string());
+    }
+
+    if let Some(content_type) = hyper_request
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+    {
+        push("CONTENT_TYPE", content_type.to_owned());
+    }
+
+    // The peer port is not the client's when a trusted proxy forwarded the
+    // request.
+    if let Some(client_address) = client_address {
+        push("REMOTE_ADDR", client_address.to_string());
+
+        if let Some(peer_addr) = peer_addr.filter(|peer_addr| peer_addr.ip() == client_address) {
+            push("REMOTE_PORT", peer_addr.port().to_string());
+        }
+    }
+
+    if let Some(local_addr) = local_addr {
+        push("SERVER_PORT", local_addr.port().to_string());
+    }
+
+    if https {
+        push("HTTPS", "on".to_owned());
+    }
+
+    for (name, value) in hyper_request.headers() {
+        // names with '_' would share a variable with the '-' spelling, so
+        // X_Forwarded_For could add to HTTP_X_FORWARDED_FOR
+        if NOT_PASSED_HEADERS.contains(name) || name.as_str().contains('_') {
+            continue;
+        }
+
+        let Ok(value) = value.to_str() else {
+            continue;
+        };
+
+        let name = format!(
+            "HTTP_{}",
+            name.as_str().to_ascii_uppercase().replace('-', "_")
+        );
+
+        // repeated headers are joined as one variable
+        match environment
+            .iter_mut()
+            .find(|(existing, _)| *existing == name)
+        {
+            Some((_, existing)) => {
+                existing.push_str(", ");
+                existing.push_str(value);
+            }
+            None => environment.push((name, value.to_owned())),
+        }
+    }
+
+    environment
+}
+
+// Reads the CGI response header block, RFC 3875 section 6.3.  The reader is
+// left at the start of the body.
+async fn read_cgi_headers(
+    reader: &mut (impl AsyncBufRead + Unpin),
+) -> anyhow::Result<(StatusCode, HeaderMap)> {
+    let mut status = None;
+    let mut headers = HeaderMap::new();
+    let mut header_bytes = 0;
+    let mut line = String::new();
+
+    loop {
+        line.clear();
+
+        // a line without a newline stops at the limit
+        let read = (&mut *reader)
+            .take((MAX_RESPONSE_HEADER_BYTES + 1 - header_bytes) as u64)
+            .read_line(&mut line)
+            .await
+            .context("error reading script output")?;
+
+        header_bytes += read;
+        if header_bytes > MAX_RESPONSE_HEADER_BYTES {
+            anyhow::bail!("script headers exceed {} bytes", MAX_RESPONSE_HEADER_BYTES);
+        }
+
+        let line = line.trim_end_matches(['\r', '\n']);
+
+        if line.is_empty() {
+            if read == 0 && headers.is_empty() && status.is_none() {
+                anyhow::bail!("script produced no output");
+            }
+            break;
+        }
+
+        let (name, value) = line
+            .split_once(':')
+            .with_context(|| format!("invalid script header line '{}'", line))?;
+
+        let value = value.trim();
+
+        if name.eq_ignore_ascii_case("status") {
+            let code = value.split_whitespace().next().unwrap_or_default();
+            status = Some(
+                StatusCode::from_bytes(code.as_bytes())
+                    .with_context(|| format!("invalid script status '{}'", value))?,
+            );
+            continue;
+        }
+
+        headers.append(
+            HeaderName::try_from(name)
+                .with_context(|| format!("invalid script header name '{}'", name))?,
+            HeaderValue::try_from(value)
+                .with_context(|| format!("invalid script header value '{}'", value))?,
+        );
+    }
+
+    let status = match status {
+        Some(status) => status,
+        // a client redirect response
+        None if headers.contains_key(header::LOCATION) => StatusCode::FOUND,
+        None => StatusCode::OK,
+    };
+
+    Ok((status, headers))
+}
+
+// Owns the running script for as long as stdout is being streamed.
+// Dropping this kills the script if it is still running.
+struct CgiOutput {
+    stdout: BufReader<ChildStdout>,
+    _child: Child,
+}
+
+impl AsyncRead for CgiOutput {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.stdout).poll_read(cx, buf)
+    }
+}
+
+enum CgiRequestBody {
+    Empty,
+    Collected(Bytes),
+    // requests with a content-length are streamed to the script
+    Streaming(Incoming, usize),
+}
+
+impl CgiRequestBody {
+    async fn new(request: &HttpRequest) -> Result<Self, RequestBodyError> {
+        let content_length = request
+            .hyper_request
+            .headers()
+            .get(header::CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<usize>().ok());
+
+        match content_length {
+            Some(0) => Ok(Self::Empty),
+            Some(content_length) => match request.take_body() {
+                Some(body) => Ok(Self::Streaming(body, content_length)),
+                None => Err(RequestBodyError::AlreadyTaken),
+            },
+            // CGI needs CONTENT_LENGTH up front
+            None => {
+                let body = request.collect_body(request.max_body_bytes()).await?;
+                Ok(if body.is_empty() {
+                    Self::Empty
+                } else {
+                    Self::Collected(body)
+                })
+            }
+        }
+    }
+
+    fn content_length(&self) -> Option<usize> {
+        match self {
+            Self::Empty => None,
+            Self::Collected(body) => Some(body.len()),
+            Self::Streaming(_, content_length) => Some(*content_length),
+        }
+    }
+
+    async fn write_to(self, mut stdin: ChildStdin) {
+        let result = match self {
+            Self::Empty => Ok(()),
+            Self::Collected(body) => stdin.write_all(&body).await,
+            Self::Streaming(body, content_length) => {
+                let mut body = Limited::new(body, content_length);
+                let mut result = Ok(());
+
+                while let Some(frame) = body.frame().await {
+                    match frame {
+                        Ok(frame) => {
+                            if let Some(data) = frame.data_ref() {
+                                result = stdin.write_all(data).await;
+                                if result.is_err() {
+                                    break;
+                                }
+                            }
+                        }
+                        Err(e) => {
+                            warn!("cgi request body error: {}", e);
+                            break;
+                        }
+                    }
+                }
+
+                result
+            }
+        };
+
+        // scripts may exit without reading their input
+        if let Err(e) = result {
+            debug!("cgi stdin write error: {}", e);
+        }
+    }
+}
+
+async fn log_stderr(script_name: String, stderr: ChildStderr) {
+    let mut lines = BufReader::new(stderr).lines();
+
+    while let Ok(Some(line)) = lines.next_line().await {
+        warn!("cgi script {} stderr: {}", script_name, line);
+    }
+}
+
+pub struct CgiHandler {
+    routes: Vec<CgiRouteEntry>,
+    next: Box<dyn RequestHandler>,
+}
+
+impl CgiHandler {
+    pub fn new(next: Box<dyn RequestHandler>) -> anyhow::Result<Self> {
+        let routes = crate::config::instance()
+            .cgi_configuration
+            .routes
+            .iter()
+            .map(CgiRouteEntry::new)
+            .collect::<anyhow::Result<Vec<_>>>()
+            .context("CgiHandler::new: invalid cgi route")?;
+
+        Ok(Self { routes, next })
+    }
+
+    fn build_command(&self, route: &CgiRouteEntry, script: &CgiScript) -> Command {
+        let mut command = match route.interpreter {
+            Some(interpreter) => {
+                let mut command = Command::new(interpreter);
+                command.arg(&script.script_filename);
+                command
+            }
+            None => Command::new(&script.script_filename),
+        };
+
+        command
+            .current_dir(route.directory)
+            .env_clear()
+            .env("PATH", std::env::var_os("PATH").unwrap_or_default())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+
+        command
+    }
+
+    async fn run_script(
+        &self,
+        route: &CgiRouteEntry,
+        script: CgiScript,
+        request: &HttpRequest,
+    ) -> Response<ResponseBody> {
+        if !script.script_filename.is_file() {
+            return build_status_code_response(StatusCode::NOT_FOUND, CacheControl::NoCache);
+        }
+
+        let body = match CgiRequestBody::new(request).await {
+            Ok(body) => body,
+            Err(RequestBodyError::TooLarge(_)) => {
+                return build_status_code_response(
+                    StatusCode::PAYLOAD_TOO_LARGE,
+                    CacheControl::NoCache,
+                );
+            }
+            Err(e) => {
+                warn!("CgiHandler request body error: {}", e);
+                return build_status_code_response(StatusCode::BAD_REQUEST, CacheControl::NoCache);
+            }
+        };
+
+        let environment = cgi_environment(
+            &request.hyper_request,
+            &script,
+            body.content_length(),
+            request.client_address(),
+            request.peer_addr(),
+            request.local_addr(),
+            request.tls_info().is_some(),
+        );
+
+        let mut child = match self.build_command(route, &script).envs(environment).spawn() {
+            Ok(child) => child,
+            Err(e) => {
+                warn!("CgiHandler spawn {:?} error: {}", script.script_filename, e);
+                return build_status_code_response(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    CacheControl::NoCache,
+                );
+            }
+        };
+
+        let (Some(stdin), Some(stdout), Some(stderr)) =
+            (child.stdin.take(), child.stdout.take(), child.stderr.take())
+        else {
+            warn!("CgiHandler child stdio not captured");
+            return build_status_code_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                CacheControl::NoCache,
+            );
+        };
+
+        tokio::spawn(body.write_to(stdin).in_current_span());
+
+        tokio::spawn(log_stderr(script.script_name.clone(), stderr).in_current_span());
+
+        let mut stdout = BufReader::new(stdout);
+
+        let (status, headers) =
+            match tokio::time::timeout(route.timeout, read_cgi_headers(&mut stdout)).await {
+                Ok(Ok(result)) => result,
+                Ok(Err(e)) => {
+                    warn!("CgiHandler script {} error: {:#}", script.script_name, e);
+                    return build_status_code_response(
+                        StatusCode::BAD_GATEWAY,
+                        CacheControl::NoCache,
+                    );
+                }
+                Err(_) => {
+                    warn!(
+                        "CgiHandler script {} timeout after {:?}",
+                        script.script_name, route.timeout
+                    );
+                    return build_status_code_response(
+                        StatusCode::GATEWAY_TIMEOUT,
+                        CacheControl::NoCache,
+                    );
+                }
+            };
+
+        let output = CgiOutput {
+            stdout,
+            _child: child,
+        };
+
+        let mut response = Response::new(async_read_response_body(
+            output,
+            CHUNK_SIZE,
+            request.cancellation_token().clone(),
+        ));
+        *response.status_mut() = status;
+        *response.headers_mut() = headers;
+
+        response
+    }
+}
+
+#[async_trait]
+impl RequestHandler for CgiHandler {
+    async fn handle(&self, request: &HttpRequest) -> Response<ResponseBody> {
+        let path = request.hyper_request.uri().path();
+
+        let Some(route) = self
+            .routes
+            .iter()
+            .find(|route| path.starts_with(route.path_prefix))
+        else {
+            return self.next.handle(request).await;
+        };
+
+        match route.resolve_script(path) {
+            None => build_status_code_response(StatusCode::NOT_FOUND, CacheControl::NoCache),
+            Some(script) => self.run_script(route, script, request).await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn route_entry() -> CgiRouteEntry {
+        CgiRouteEntry {
+            path_prefix: "/cgi-bin/",
+            directory: Path::new("/srv/cgi-bin"),
+            interpreter: None,
+            timeout: Duration::from_secs(1),
+        }
+    }
+
+    #[test]
+    fn test_resolve_script() {
+        let route = route_entry();
+
+        assert_eq!(
+            route.resolve_script("/cgi-bin/test.sh/a%20b/c"),
+            Some(CgiScript {
+                script_name: "/cgi-bin/test.sh".to_owned(),
+                script_filename: PathBuf::from("/srv/cgi-bin/test.sh"),
+                path_info: "/a b/c".to_owned(),
+            })
+        );
+        assert_eq!(
+            route.resolve_script("/cgi-bin/test.sh").unwrap().path_info,
+            ""
+        );
+        assert_eq!(route.resolve_script("/cgi-bin/"), None);
+        assert_eq!(route.resolve_script("/cgi-bin/../etc/passwd"), None);
+        assert_eq!(route.resolve_script("/cgi-bin/%2e%2e/x"), None);
+        assert_eq!(route.resolve_script("/other/test.sh"), None);
+    }
+
+    #[test]
+    fn test_cgi_environment() {
+        let request = Request::builder()
+            .method("POST")
+            .uri("/cgi-bin/test.sh/extra?a=1")
+            .header(header::HOST, "example.com:8080")
+            .header(header::CONTENT_TYPE, "text/plain")
+            .header(header::AUTHORIZATION, "Basic secret")
+            .header("x-test", "1")
+            .header("x-test", "2")
+            .header("proxy", "http://attacker.example:8080")
+            .header("x-forwarded-for", "192.0.2.1")
+            .header("x_forwarded_for", "127.0.0.1")
+            .body(())
+            .unwrap();
+
+        let script = route_entry().resolve_script(request.uri().path()).unwrap();
+
+        let environment = cgi_environment(
+            &request,
+            &script,
+            Some(5),
+            Some("10.0.0.1".parse().unwrap()),
+            Some("10.0.0.1:5000".parse().unwrap()),
+            Some("10.0.0.2:8080".parse().unwrap()),
+            false,
+        );
+
+        let get = |name: &str| {
+            environment
+                .iter()
+                .find(|(existing, _)| existing == name)
+                .map(|(_, value)| value.as_str())
+        };
+
+        assert_eq!(get("REQUEST_METHOD"), Some("POST"));
+        assert_eq!(get("SERVER_NAME"), Some("example.com"));
+        assert_eq!(get("SERVER_PORT"), Some("8080"));
+        assert_eq!(get("SCRIPT_NAME"), Some("/cgi-bin/test.sh"));
+        assert_eq!(get("PATH_INFO"), Some("/extra"));
+        assert_eq!(get("QUERY_STRING"), Some("a=1"));
+        assert_eq!(get("REQUEST_URI"), Some("/cgi-bin/test.sh/extra?a=1"));
+        assert_eq!(get("CONTENT_LENGTH"), Some("5"));
+        assert_eq!(get("CONTENT_TYPE"), Some("text/plain"));
+        assert_eq!(get("REMOTE_ADDR"), Some("10.0.0.1"));
+        assert_eq!(get("REMOTE_PORT"), Some("5000"));
+        assert_eq!(get("HTTP_X_TEST"), Some("1, 2"));
+        assert_eq!(get("HTTP_AUTHORIZATION"), None);
+        assert_eq!(get("HTTP_PROXY"), None);
+        assert_eq!(get("HTTP_X_FORWARDED_FOR"), Some("192.0.2.1"));
+        assert_eq!(get("HTTP_CONTENT_TYPE"), None);
+        assert_eq!(get("HTTPS"), None);
+
+        // forwarded by a trusted proxy at the peer address
+        let environment = cgi_environment(
+            &request,
+            &script,
+            Some(5),
+            Some("192.0.2.1".parse().unwrap()),
+            Some("10.0.0.1:5000".parse().unwrap()),
+            Some("10.0.0.2:8080".parse().unwrap()),
+            false,
+        );
+
+        let get = |name: &str| {
+            environment
+                .iter()
+                .find(|(existing, _)| existing == name)
+                .map(|(_, value)| value.as_str())
+        };
+
+        assert_eq!(get("REMOTE_ADDR"), Some("192.0.2.1"));
+        assert_eq!(get("REMOTE_PORT"), None);
+    }
+
+    #[tokio::test]
+    async fn test_read_cgi_headers() {
+        let mut output: &[u8] = b"Status: 404 Not Found\r\nContent-Type: text/plain\r\n\r\nbody";
+        let (status, headers) = read_cgi_headers(&mut output).await.unwrap();
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(headers[header::CONTENT_TYPE], "text/plain");
+        assert_eq!(output, b"body");
+
+        let mut output: &[u8] = b"Location: /elsewhere\n\n";
+        let (status, _) = read_cgi_headers(&mut output).await.unwrap();
+        assert_eq!(status, StatusCode::FOUND);
+
+        let mut output: &[u8] = b"";
+        assert!(read_cgi_headers(&mut output).await.is_err());
+
+        let mut output: &[u8] = b"not a header\n\n";
+        assert!(read_cgi_headers(&mut output).await.is_err());
+    }
+}
@@ -1,29 +1,45 @@
+mod spawn;
+
 use anyhow::Context;
 
 use async_trait::async_trait;
 
-use hyper::http::{Method, Response, StatusCode};
+use hyper::http::{header, Method, Response, StatusCode};
 
 use tracing::warn;
 
 use tokio::{
-    process::Command,
-    sync::{OnceCell, Semaphore, SemaphorePermit},
+    io::{AsyncRead, AsyncReadExt, ReadBuf, Take},
+    process::{Child, ChildStdout},
+    sync::{OnceCell, OwnedSemaphorePermit, Semaphore},
     time::{Duration, Instant},
 };
 
+use schemars::JsonSchema;
+
 use serde::Serialize;
 
-use std::{path::PathBuf, process::Stdio, sync::Arc};
+use std::{
+    path::PathBuf,
+    pin::Pin,
+    process::Stdio,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    task::Poll,
+};
 
 use crate::{
     handlers::{
-        route::RouteInfo, time_utils::current_local_date_time_string, HttpRequest, RequestHandler,
-        ResponseBody,
+        route::{RouteApiDoc, RouteInfo},
+        time_utils::current_local_date_time_string,
+        HttpRequest, RequestHandler, ResponseBody,
     },
     response::{
-        build_json_body_response, build_json_response, build_status_code_response,
-        static_string_response_body, CacheControl,
+        async_read_response_body, build_deny_response, build_json_body_response,
+        build_json_response, build_status_code_response, static_string_response_body, CacheControl,
+        DenyReason,
     },
 };
 
@@ -64,6 +80,9 @@ impl RequestHandler for AllCommandsHandler {
 
 #[derive(thiserror::Error, Debug)]
 enum RunCommandSemaporeAcquireError {
+    #[error("queue full: {0} requests waiting")]
+    QueueFull(usize),
+
     #[error("acquire timeout: {0}")]
     Timeout(#[from] tokio::time::error::Elapsed),
 
@@ -71,21 +90,56 @@ enum RunCommandSemaporeAcquireError {
     AcquireError(#[from] tokio::sync::AcquireError),
 }
 
+// Counts a request as queued until it gets a permit or gives up.
+struct QueuedCommandGuard<'a>(&'a AtomicUsize);
+
+impl Drop for QueuedCommandGuard<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+// Limits concurrent commands.  Requests wait in a bounded queue for at most
+// acquire_timeout, so a burst cannot pile up unbounded process spawns.
 struct RunCommandSemapore {
-    semapore: Semaphore,
+    semapore: Arc<Semaphore>,
     acquire_timeout: Duration,
+    max_queued: Option<usize>,
+    queued: AtomicUsize,
 }
 
 impl RunCommandSemapore {
     fn new(command_configuration: &crate::config::CommandConfiguration) -> Arc<Self> {
         Arc::new(Self {
-            semapore: Semaphore::new(command_configuration.max_concurrent_commands),
+            semapore: Arc::new(Semaphore::new(
+                command_configuration.max_concurrent_commands,
+            )),
             acquire_timeout: command_configuration.semaphore_acquire_timeout,
+            max_queued: command_configuration.max_queued_commands,
+            queued: AtomicUsize::new(0),
         })
     }
 
-    async fn acquire(&self) -> Result<SemaphorePermit<'_>, RunCommandSemaporeAcquireError> {
-        let result = tokio::time::timeout(self.acquire_timeout, self.semapore.acquire()).await?;
+    async fn acquire(&self) -> Result<OwnedSemaphorePermit, RunCommandSemaporeAcquireError> {
+        if let Ok(permit) = Arc::clone(&self.semapore).try_acquire_owned() {
+            return Ok(permit);
+        }
+
+        let queued = self.queued.fetch_add(1, Ordering::Relaxed);
+        let _queued_guard = QueuedCommandGuard(&self.queued);
+
+        if self
+            .max_queued
+            .is_some_and(|max_queued| queued >= max_queued)
+        {
+            return Err(RunCommandSemaporeAcquireError::QueueFull(queued));
+        }
+
+        let result = tokio::time::timeout(
+            self.acquire_timeout,
+            Arc::clone(&self.semapore).acquire_owned(),
+        )
+        .await?;
 
         let permit = result?;
 
@@ -93,7 +147,7 @@ impl RunCommandSemapore {
     }
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, JsonSchema, Serialize)]
 struct RunCommandResponse<'a> {
     now: String,
     command_duration_ms: u128,
@@ -118,14 +172,12 @@ impl RunCommandHandler {
     }
 
     async fn run_command(&self) -> Result<std::process::Output, std::io::Error> {
-        let output = Command::new(&self.command_info.command)
-            .kill_on_drop(true)
-            .stdin(Stdio::null())
-            .args(&self.command_info.args)
-            .output()
-            .await?;
-
-        Ok(output)
+        let mut command = spawn::build_command(self.command_info);
+
+        match self.command_info.limits.max_output_bytes {
+            None => command.output().await,
+            Some(max_output_bytes) => spawn::output_limited(command, max_output_bytes).await,
+        }
     }
 
     fn handle_command_result(
@@ -162,9 +214,9 @@ impl RequestHandler for RunCommandHandler {
         let run_command_permit = match self.run_command_semaphore.acquire().await {
             Err(err) => {
                 warn!("run_command_semaphore.acquire error: {}", err);
-                return build_status_code_response(
-                    StatusCode::TOO_MANY_REQUESTS,
-                    CacheControl::NoCache,
+                return build_deny_response(
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    DenyReason::CommandQueue,
                 );
             }
             Ok(permit) => permit,
@@ -180,15 +232,119 @@ impl RequestHandler for RunCommandHandler {
     }
 }
 
+// Owns the running child and semaphore permit for as long as stdout is being
+// streamed.  Dropping this kills the child if it is still running.
+struct StreamCommandOutput {
+    // limited to max_output_bytes, reaching the limit ends the stream
+    stdout: Take<ChildStdout>,
+    _child: Child,
+    _run_command_permit: OwnedSemaphorePermit,
+}
+
+impl AsyncRead for StreamCommandOutput {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.stdout).poll_read(cx, buf)
+    }
+}
+
+struct StreamCommandHandler {
+    run_command_semaphore: Arc<RunCommandSemapore>,
+    command_info: &'static crate::config::CommandInfo,
+}
+
+impl StreamCommandHandler {
+    const CHUNK_SIZE: usize = 8 * 1024;
+
+    fn new(
+        run_command_semaphore: Arc<RunCommandSemapore>,
+        command_info: &'static crate::config::CommandInfo,
+    ) -> Self {
+        Self {
+            run_command_semaphore,
+            command_info,
+        }
+    }
+
+    fn spawn_command(&self) -> Result<(Child, ChildStdout), std::io::Error> {
+        let mut child = spawn::build_command(self.command_info)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()?;
+
+        let stdout = child
+            .stdout
+            .take()
+            .ok_or_else(|| std::io::Error::other("child stdout not captured"))?;
+
+        Ok((child, stdout))
+    }
+}
+
+#[async_trait]
+impl RequestHandler for StreamCommandHandler {
+    async fn handle(&self, request: &HttpRequest) -> Response<ResponseBody> {
+        let run_command_permit = match self.run_command_semaphore.acquire().await {
+            Err(err) => {
+                warn!("run_command_semaphore.acquire error: {}", err);
+                return build_deny_response(
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    DenyReason::CommandQueue,
+                );
+            }
+            Ok(permit) => permit,
+        };
+
+        let (child, stdout) = match self.spawn_command() {
+            Err(err) => {
+                warn!("StreamCommandHandler spawn_command error: {}", err);
+                return build_status_code_response(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    CacheControl::NoCache,
+                );
+            }
+            Ok(result) => result,
+        };
+
+        let max_output_bytes = self
+            .command_info
+            .limits
+            .max_output_bytes
+            .map_or(u64::MAX, |max_output_bytes| max_output_bytes as u64);
+
+        let output = StreamCommandOutput {
+            stdout: stdout.take(max_output_bytes),
+            _child: child,
+            _run_command_permit: run_command_permit,
+        };
+
+        Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "text/plain; charset=utf-8")
+            .header(header::CACHE_CONTROL, CacheControl::NoCache.header_value())
+            .body(async_read_response_body(
+                output,
+                Self::CHUNK_SIZE,
+                request.cancellation_token().clone(),
+            ))
+            .unwrap()
+    }
+}
+
 pub async fn create_routes() -> anyhow::Result<Vec<RouteInfo>> {
     let command_configuration = &crate::config::instance().command_configuration;
 
-    let mut routes: Vec<RouteInfo> = Vec::with_capacity(1 + command_configuration.commands.len());
+    let mut routes: Vec<RouteInfo> =
+        Vec::with_capacity(1 + (2 * command_configuration.commands.len()));
 
     routes.push(RouteInfo {
         method: &Method::GET,
         path_suffix: PathBuf::from("commands"),
         handler: Box::new(AllCommandsHandler::instance().await?),
+        api_doc: RouteApiDoc::json::<Vec<crate::config::CommandInfo>>("List configured commands"),
     });
 
     let run_command_semaphore = RunCommandSemapore::new(command_configuration);
@@ -203,6 +359,19 @@ pub async fn create_routes() -> anyhow::Result<Vec<RouteInfo>> {
                 Arc::clone(&run_command_semaphore),
                 command_info,
             )),
+            api_doc: RouteApiDoc::json::<RunCommandResponse<'static>>(&command_info.description),
+        });
+
+        routes.push(RouteInfo {
+            method: &Method::GET,
+            path_suffix: PathBuf::from("commands")
+                .join(&command_info.id)
+                .join("stream"),
+            handler: Box::new(StreamCommandHandler::new(
+                Arc::clone(&run_command_semaphore),
+                command_info,
+            )),
+            api_doc: RouteApiDoc::text(&command_info.description),
         });
     }
 
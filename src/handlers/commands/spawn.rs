@@ -0,0 +1,187 @@
+use tokio::{
+    io::{AsyncRead, AsyncReadExt},
+    process::Command,
+};
+
+use std::{pin::pin, process::Stdio};
+
+use crate::config::{CommandInfo, CommandSandbox, BWRAP_PATH, NICE_PATH, PRLIMIT_PATH};
+
+const TRUNCATED_MESSAGE: &[u8] = b"\n[output truncated]\n";
+
+fn sandbox_argv(sandbox: &CommandSandbox) -> Vec<&'static str> {
+    let mut argv = vec![
+        BWRAP_PATH,
+        "--die-with-parent",
+        "--unshare-user",
+        "--unshare-pid",
+        "--unshare-ipc",
+        "--unshare-uts",
+    ];
+
+    if sandbox.no_network {
+        argv.push("--unshare-net");
+    }
+
+    argv.extend(if sandbox.read_only_root {
+        ["--ro-bind", "/", "/"]
+    } else {
+        ["--bind", "/", "/"]
+    });
+
+    argv.extend(["--dev", "/dev", "--proc", "/proc"]);
+
+    if sandbox.private_tmp {
+        argv.extend(["--tmpfs", "/tmp"]);
+    }
+
+    argv.push("--");
+    argv
+}
+
+// Builds the command with its limits and sandbox applied by wrapping it in
+// nice(1), prlimit(1) and bwrap(1), so they are in place before the command
+// is exec'd.
+pub fn build_command(command_info: &CommandInfo) -> Command {
+    let limits = &command_info.limits;
+
+    let mut argv: Vec<String> = Vec::with_capacity(6 + command_info.args.len());
+
+    if let Some(nice) = limits.nice {
+        argv.extend([NICE_PATH.to_owned(), "-n".to_owned(), nice.to_string()]);
+    }
+
+    if limits.has_rlimits() {
+        argv.push(PRLIMIT_PATH.to_owned());
+        if let Some(cpu_time) = limits.cpu_time {
+            let cpu_seconds = cpu_time.as_secs() + u64::from(cpu_time.subsec_nanos() > 0);
+            argv.push(format!("--cpu={}", cpu_seconds));
+        }
+        if let Some(memory_bytes) = limits.memory_bytes {
+            argv.push(format!("--as={}", memory_bytes));
+        }
+        argv.push("--".to_owned());
+    }
+
+    if let Some(sandbox) = &command_info.sandbox {
+        argv.extend(sandbox_argv(sandbox).into_iter().map(str::to_owned));
+    }
+
+    argv.push(command_info.command.clone());
+    argv.extend(command_info.args.iter().cloned());
+
+    let mut command = Command::new(&argv[0]);
+    command
+        .args(&argv[1..])
+        .kill_on_drop(true)
+        .stdin(Stdio::null());
+    command
+}
+
+// Returns at most max bytes, and whether the reader had more.
+async fn read_limited(
+    reader: impl AsyncRead + Unpin,
+    max: usize,
+) -> std::io::Result<(Vec<u8>, bool)> {
+    let mut buf = Vec::new();
+
+    reader.take(max as u64 + 1).read_to_end(&mut buf).await?;
+
+    let truncated = buf.len() > max;
+    buf.truncate(max);
+
+    Ok((buf, truncated))
+}
+
+// Like Command::output but keeps at most max_output_bytes of stdout and
+// stderr each, killing the command as soon as either stream exceeds it.
+pub async fn output_limited(
+    mut command: Command,
+    max_output_bytes: usize,
+) -> std::io::Result<std::process::Output> {
+    let mut child = command
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    let (Some(stdout), Some(stderr)) = (child.stdout.take(), child.stderr.take()) else {
+        return Err(std::io::Error::other("child output not captured"));
+    };
+
+    let mut read_stdout = pin!(read_limited(stdout, max_output_bytes));
+    let mut read_stderr = pin!(read_limited(stderr, max_output_bytes));
+
+    let mut stdout_result = None;
+    let mut stderr_result = None;
+
+    while stdout_result.is_none() || stderr_result.is_none() {
+        let (result, slot) = tokio::select! {
+            result = &mut read_stdout, if stdout_result.is_none() => (result?, &mut stdout_result),
+            result = &mut read_stderr, if stderr_result.is_none() => (result?, &mut stderr_result),
+        };
+
+        let (mut output, truncated) = result;
+
+        if truncated {
+            // the command may still be writing, stop it instead of waiting
+            let _ = child.start_kill();
+            output.extend_from_slice(TRUNCATED_MESSAGE);
+        }
+
+        *slot = Some(output);
+    }
+
+    let status = child.wait().await?;
+
+    Ok(std::process::Output {
+        status,
+        stdout: stdout_result.unwrap_or_default(),
+        stderr: stderr_result.unwrap_or_default(),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::time::Duration;
+
+    use crate::config::CommandLimits;
+
+    #[test]
+    fn test_build_command_wrapping_order() {
+        let command_info = CommandInfo {
+            id: "test".to_owned(),
+            description: "test".to_owned(),
+            command: "/bin/echo".to_owned(),
+            args: vec!["hello".to_owned()],
+            limits: CommandLimits {
+                cpu_time: Some(Duration::from_millis(1500)),
+                nice: Some(5),
+                ..Default::default()
+            },
+            sandbox: Some(CommandSandbox {
+                no_network: true,
+                ..Default::default()
+            }),
+        };
+
+        let command = build_command(&command_info);
+        let command = command.as_std();
+
+        assert_eq!(command.get_program(), NICE_PATH);
+
+        let args: Vec<_> = command
+            .get_args()
+            .map(|arg| arg.to_str().unwrap())
+            .collect();
+
+        let prlimit = args.iter().position(|arg| *arg == PRLIMIT_PATH).unwrap();
+        let bwrap = args.iter().position(|arg| *arg == BWRAP_PATH).unwrap();
+
+        assert_eq!(&args[..prlimit], ["-n", "5"]);
+        assert!(args[prlimit..bwrap].contains(&"--cpu=2"));
+        assert!(args[bwrap..].contains(&"--unshare-net"));
+        assert_eq!(&args[args.len() - 2..], ["/bin/echo", "hello"]);
+    }
+}
@@ -0,0 +1,630 @@
+mod br;
+mod gzip;
+mod zstd;
+
+use anyhow::Context as _;
+
+use async_trait::async_trait;
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+use bytes::Bytes;
+
+use http_body_util::{BodyExt, Full};
+
+use hyper::{
+    body::{Body, Frame, SizeHint},
+    http::{header, HeaderMap, HeaderName, HeaderValue, Method, Response, StatusCode},
+};
+
+use sha2::{Digest, Sha256};
+
+use tracing::warn;
+
+use std::{
+    path::PathBuf,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use crate::{
+    config::{CompressionConfiguration, CompressionDictionaryConfiguration},
+    handlers::{route::RouteInfo, HttpRequest, MatchedRoute, RequestHandler, ResponseBody},
+    response::{build_status_code_response, CacheControl, ResponseBodyError},
+};
+
+use br::BrotliEncoder;
+use gzip::GzipEncoder;
+use zstd::ZstdDictionary;
+
+const DEFAULT_MIN_SIZE: u64 = 1024;
+
+const AVAILABLE_DICTIONARY: HeaderName = HeaderName::from_static("available-dictionary");
+
+const USE_AS_DICTIONARY: HeaderName = HeaderName::from_static("use-as-dictionary");
+
+// A zstd skippable frame holding the dictionary's SHA-256, RFC 9842 section 5.2.
+const DCZ_HEADER: [u8; 8] = [0x5e, 0x2a, 0x4d, 0x18, 0x20, 0x00, 0x00, 0x00];
+
+// Clients only need to support dcz windows up to 8MB.
+const MAX_DICTIONARY_COMPRESSED_SIZE: u64 = 8 * 1024 * 1024;
+
+const DICTIONARY_MAX_AGE_SECONDS: u32 = 24 * 60 * 60;
+
+// The Accept-Encoding q-value of coding, falling back to "*", 0 if not
+// accepted.
+fn encoding_quality(request_headers: &HeaderMap, coding: &str) -> f64 {
+    let mut coding_q = None;
+    let mut wildcard_q = None;
+
+    for item in request_headers
+        .get_all(header::ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+    {
+        let mut params = item.split(';').map(str::trim);
+
+        let item_coding = params.next().unwrap_or_default();
+
+        let q = params
+            .find_map(|param| param.strip_prefix("q="))
+            .map_or(1.0, |q| q.parse::<f64>().unwrap_or(0.0));
+
+        if item_coding.eq_ignore_ascii_case(coding) {
+            coding_q = Some(q);
+        } else if item_coding == "*" {
+            wildcard_q = Some(q);
+        }
+    }
+
+    coding_q.or(wildcard_q).unwrap_or(0.0)
+}
+
+fn accepts_encoding(request_headers: &HeaderMap, coding: &str) -> bool {
+    encoding_quality(request_headers, coding) > 0.0
+}
+
+// brotli unless the client prefers gzip.
+fn choose_stream_encoder(request_headers: &HeaderMap) -> Option<StreamEncoder> {
+    let br_q = encoding_quality(request_headers, "br");
+    let gzip_q = encoding_quality(request_headers, "gzip");
+
+    if br_q > 0.0 && br_q >= gzip_q {
+        Some(StreamEncoder::Brotli(BrotliEncoder::default()))
+    } else if gzip_q > 0.0 {
+        Some(StreamEncoder::Gzip(GzipEncoder::default()))
+    } else {
+        None
+    }
+}
+
+enum StreamEncoder {
+    Brotli(BrotliEncoder),
+    Gzip(GzipEncoder),
+}
+
+impl StreamEncoder {
+    fn content_encoding(&self) -> &'static str {
+        match self {
+            Self::Brotli(_) => "br",
+            Self::Gzip(_) => "gzip",
+        }
+    }
+
+    fn compress_chunk(&mut self, data: &[u8]) -> Bytes {
+        match self {
+            Self::Brotli(encoder) => encoder.compress_chunk(data),
+            Self::Gzip(encoder) => encoder.compress_chunk(data),
+        }
+    }
+
+    fn finish(self) -> Bytes {
+        match self {
+            Self::Brotli(encoder) => encoder.finish(),
+            Self::Gzip(encoder) => encoder.finish(),
+        }
+    }
+}
+
+// Wraps a response body, compressing each data frame as it is polled so
+// streamed command output is still sent as it is produced.
+struct CompressedBody {
+    inner: ResponseBody,
+    encoder: Option<StreamEncoder>,
+}
+
+impl Body for CompressedBody {
+    type Data = Bytes;
+    type Error = ResponseBodyError;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.get_mut();
+
+        let Some(encoder) = this.encoder.as_mut() else {
+            return Poll::Ready(None);
+        };
+
+        match Pin::new(&mut this.inner).poll_frame(cx) {
+            Poll::Ready(Some(Ok(frame))) => match frame.into_data() {
+                Ok(data) => Poll::Ready(Some(Ok(Frame::data(encoder.compress_chunk(&data))))),
+                Err(frame) => Poll::Ready(Some(Ok(frame))),
+            },
+            Poll::Ready(None) => {
+                let encoder = this.encoder.take().unwrap();
+                Poll::Ready(Some(Ok(Frame::data(encoder.finish()))))
+            }
+            poll => poll,
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.encoder.is_none()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        SizeHint::default()
+    }
+}
+
+// A dictionary configured for responses under path_prefix.  Clients fetch
+// it from its route and then send its hash in Available-Dictionary.
+pub struct CompressionDictionary {
+    id: &'static str,
+    path_prefix: &'static str,
+    content: Bytes,
+    zstd_dictionary: ZstdDictionary,
+    sha256: [u8; 32],
+    // the Available-Dictionary value of clients holding it, ":<base64>:"
+    available_dictionary: String,
+}
+
+impl CompressionDictionary {
+    fn load(configuration: &'static CompressionDictionaryConfiguration) -> anyhow::Result<Self> {
+        let content = std::fs::read(&configuration.path).with_context(|| {
+            format!(
+                "error reading compression dictionary '{}'",
+                configuration.path
+            )
+        })?;
+
+        let sha256: [u8; 32] = Sha256::digest(&content).into();
+
+        Ok(Self {
+            id: &configuration.id,
+            path_prefix: &configuration.path_prefix,
+            zstd_dictionary: ZstdDictionary::new(&content),
+            content: Bytes::from(content),
+            sha256,
+            available_dictionary: format!(":{}:", STANDARD.encode(sha256)),
+        })
+    }
+
+    // A dcz body: the header, the dictionary hash and a zstd frame.
+    fn compress(&self, data: &[u8]) -> std::io::Result<Vec<u8>> {
+        let frame = self.zstd_dictionary.compress(data)?;
+
+        let mut output = Vec::with_capacity(DCZ_HEADER.len() + self.sha256.len() + frame.len());
+        output.extend_from_slice(&DCZ_HEADER);
+        output.extend_from_slice(&self.sha256);
+        output.extend_from_slice(&frame);
+        Ok(output)
+    }
+}
+
+pub fn load_dictionaries() -> anyhow::Result<Vec<Arc<CompressionDictionary>>> {
+    crate::config::instance()
+        .compression_configuration
+        .dictionaries
+        .iter()
+        .map(|configuration| CompressionDictionary::load(configuration).map(Arc::new))
+        .collect()
+}
+
+struct DictionaryHandler {
+    dictionary: Arc<CompressionDictionary>,
+}
+
+#[async_trait]
+impl RequestHandler for DictionaryHandler {
+    async fn handle(&self, _request: &HttpRequest) -> Response<ResponseBody> {
+        let use_as_dictionary = format!(
+            "match=\"{}*\", id=\"{}\"",
+            self.dictionary.path_prefix, self.dictionary.id
+        );
+
+        Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "application/octet-stream")
+            .header(
+                header::CACHE_CONTROL,
+                CacheControl::Cache {
+                    max_age_seconds: DICTIONARY_MAX_AGE_SECONDS,
+                }
+                .header_value(),
+            )
+            .header(USE_AS_DICTIONARY, use_as_dictionary)
+            .body(
+                Full::new(self.dictionary.content.clone())
+                    .map_err(|never| never.into())
+                    .boxed(),
+            )
+            .unwrap()
+    }
+}
+
+pub fn create_routes(dictionaries: &[Arc<CompressionDictionary>]) -> Vec<RouteInfo> {
+    dictionaries
+        .iter()
+        .map(|dictionary| RouteInfo {
+            method: &Method::GET,
+            path_suffix: PathBuf::from(format!("compression/dictionaries/{}", dictionary.id)),
+            handler: Box::new(DictionaryHandler {
+                dictionary: Arc::clone(dictionary),
+            }),
+            api_doc: None,
+        })
+        .collect()
+}
+
+// Strong ETags become weak since the encoded bytes differ from the identity
+// response.
+fn set_content_encoding(headers: &mut HeaderMap, content_encoding: &'static str) {
+    headers.insert(
+        header::CONTENT_ENCODING,
+        HeaderValue::from_static(content_encoding),
+    );
+    headers.remove(header::CONTENT_LENGTH);
+
+    weaken_etag(headers);
+}
+
+fn weaken_etag(headers: &mut HeaderMap) {
+    if let Some(etag) = headers.get(header::ETAG) {
+        if !etag.as_bytes().starts_with(b"W/") {
+            let mut weak_etag = b"W/".to_vec();
+            weak_etag.extend_from_slice(etag.as_bytes());
+            if let Ok(weak_etag) = HeaderValue::from_bytes(&weak_etag) {
+                headers.insert(header::ETAG, weak_etag);
+            }
+        }
+    }
+}
+
+// Compresses dynamic route responses with brotli or gzip for clients that
+// accept it, or with a zstd dictionary the client already holds.
+pub struct CompressionHandler {
+    compression_configuration: &'static CompressionConfiguration,
+    min_size: u64,
+    dictionaries: Vec<Arc<CompressionDictionary>>,
+    next: Box<dyn RequestHandler>,
+}
+
+impl CompressionHandler {
+    pub fn new(
+        next: Box<dyn RequestHandler>,
+        dictionaries: Vec<Arc<CompressionDictionary>>,
+    ) -> Self {
+        let compression_configuration = &crate::config::instance().compression_configuration;
+
+        Self {
+            compression_configuration,
+            min_size: compression_configuration
+                .min_size
+                .unwrap_or(DEFAULT_MIN_SIZE),
+            dictionaries,
+            next,
+        }
+    }
+
+    fn find_dictionary(&self, request: &HttpRequest) -> Option<Arc<CompressionDictionary>> {
+        let headers = request.hyper_request.headers();
+
+        if !accepts_encoding(headers, "dcz") {
+            return None;
+        }
+
+        let available_dictionary = headers.get(AVAILABLE_DICTIONARY)?.to_str().ok()?.trim();
+
+        let path = request.hyper_request.uri().path();
+
+        self.dictionaries
+            .iter()
+            .find(|dictionary| {
+                dictionary.available_dictionary == available_dictionary
+                    && path.starts_with(dictionary.path_prefix)
+            })
+            .cloned()
+    }
+
+    fn append_vary(&self, headers: &mut HeaderMap) {
+        headers.append(header::VARY, HeaderValue::from_static("accept-encoding"));
+
+        if !self.dictionaries.is_empty() {
+            headers.append(
+                header::VARY,
+                HeaderValue::from_static("available-dictionary"),
+            );
+        }
+    }
+
+    fn is_compressible(&self, response: &Response<ResponseBody>) -> bool {
+        let headers = response.headers();
+
+        response.status() == StatusCode::OK
+            && !headers.contains_key(header::CONTENT_ENCODING)
+            && headers
+                .get(header::CONTENT_TYPE)
+                .and_then(|content_type| content_type.to_str().ok())
+                .is_some_and(|content_type| {
+                    self.compression_configuration
+                        .content_types
+                        .iter()
+                        .any(|prefix| content_type.starts_with(prefix.as_str()))
+                })
+            && response
+                .body()
+                .size_hint()
+                .exact()
+                .is_none_or(|length| length >= self.min_size)
+    }
+}
+
+#[async_trait]
+impl RequestHandler for CompressionHandler {
+    async fn handle(&self, request: &HttpRequest) -> Response<ResponseBody> {
+        let stream_encoder = choose_stream_encoder(request.hyper_request.headers());
+
+        let dictionary = self.find_dictionary(request);
+
+        let response = self.next.handle(request).await;
+
+        if !self.compression_configuration.enabled
+            || request.hyper_request.method() == Method::HEAD
+            || request.extension::<MatchedRoute>().is_none()
+        {
+            return response;
+        }
+
+        // A 304 has no body or Content-Type to judge by, it gets the Vary and
+        // ETag a 200 would have had for a client accepting an encoding.
+        if response.status() == StatusCode::NOT_MODIFIED {
+            let (mut parts, body) = response.into_parts();
+
+            self.append_vary(&mut parts.headers);
+
+            if stream_encoder.is_some() || dictionary.is_some() {
+                weaken_etag(&mut parts.headers);
+            }
+
+            return Response::from_parts(parts, body);
+        }
+
+        if !self.is_compressible(&response) {
+            return response;
+        }
+
+        let (mut parts, mut body) = response.into_parts();
+
+        self.append_vary(&mut parts.headers);
+
+        if let Some(dictionary) = dictionary.filter(|_| {
+            body.size_hint()
+                .exact()
+                .is_some_and(|length| length <= MAX_DICTIONARY_COMPRESSED_SIZE)
+        }) {
+            let data = match body.collect().await {
+                Ok(collected) => collected.to_bytes(),
+                Err(e) => {
+                    warn!("error collecting response body: {}", e);
+                    return build_status_code_response(
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        CacheControl::NoCache,
+                    );
+                }
+            };
+
+            match dictionary.compress(&data) {
+                // incompressible data can come out larger
+                Ok(compressed) if compressed.len() < data.len() => {
+                    set_content_encoding(&mut parts.headers, "dcz");
+                    parts
+                        .headers
+                        .insert(header::CONTENT_LENGTH, compressed.len().into());
+
+                    return Response::from_parts(
+                        parts,
+                        Full::from(compressed).map_err(|never| never.into()).boxed(),
+                    );
+                }
+                Ok(_) => {}
+                Err(e) => warn!("zstd dictionary compression error: {}", e),
+            }
+
+            body = Full::new(data).map_err(|never| never.into()).boxed();
+        }
+
+        let Some(stream_encoder) = stream_encoder else {
+            return Response::from_parts(parts, body);
+        };
+
+        set_content_encoding(&mut parts.headers, stream_encoder.content_encoding());
+
+        let body = CompressedBody {
+            inner: body,
+            encoder: Some(stream_encoder),
+        };
+
+        Response::from_parts(parts, body.boxed())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use http_body_util::Empty;
+
+    use hyper::Request;
+
+    use hyper_util::rt::TokioIo;
+
+    use crate::{
+        handlers::{etag::JsonETagHandler, Router},
+        response::static_string_response_body,
+        test_util::serve_duplex_connection,
+    };
+
+    struct JsonHandler;
+
+    #[async_trait]
+    impl RequestHandler for JsonHandler {
+        async fn handle(&self, _request: &HttpRequest) -> Response<ResponseBody> {
+            Response::builder()
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(static_string_response_body("{\"hello\":\"world\"}"))
+                .unwrap()
+        }
+    }
+
+    #[test]
+    fn test_accepts_encoding() {
+        let mut headers = HeaderMap::new();
+        assert!(!accepts_encoding(&headers, "gzip"));
+
+        headers.insert(
+            header::ACCEPT_ENCODING,
+            HeaderValue::from_static("gzip, deflate, br"),
+        );
+        assert!(accepts_encoding(&headers, "gzip"));
+
+        headers.insert(
+            header::ACCEPT_ENCODING,
+            HeaderValue::from_static("br;q=1.0, GZIP;q=0.5"),
+        );
+        assert!(accepts_encoding(&headers, "gzip"));
+
+        headers.insert(
+            header::ACCEPT_ENCODING,
+            HeaderValue::from_static("*, gzip;q=0"),
+        );
+        assert!(!accepts_encoding(&headers, "gzip"));
+
+        headers.insert(header::ACCEPT_ENCODING, HeaderValue::from_static("*"));
+        assert!(accepts_encoding(&headers, "gzip"));
+
+        headers.insert(header::ACCEPT_ENCODING, HeaderValue::from_static("br"));
+        assert!(!accepts_encoding(&headers, "gzip"));
+
+        headers.insert(
+            header::ACCEPT_ENCODING,
+            HeaderValue::from_static("gzip, br, zstd, dcz"),
+        );
+        assert!(accepts_encoding(&headers, "dcz"));
+        assert!(!accepts_encoding(&headers, "deflate"));
+    }
+
+    #[test]
+    fn test_choose_stream_encoder() {
+        let content_encoding = |accept_encoding: &'static str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(
+                header::ACCEPT_ENCODING,
+                HeaderValue::from_static(accept_encoding),
+            );
+            choose_stream_encoder(&headers).map(|encoder| encoder.content_encoding())
+        };
+
+        assert_eq!(content_encoding("gzip, deflate, br"), Some("br"));
+        assert_eq!(content_encoding("br;q=0.5, gzip"), Some("gzip"));
+        assert_eq!(content_encoding("gzip"), Some("gzip"));
+        assert_eq!(content_encoding("br, gzip;q=0"), Some("br"));
+        assert_eq!(content_encoding("*"), Some("br"));
+        assert_eq!(content_encoding("deflate"), None);
+        assert_eq!(choose_stream_encoder(&HeaderMap::new()).map(|_| ()), None);
+    }
+
+    #[tokio::test]
+    async fn test_not_modified_vary_and_etag() {
+        crate::config::set_test_instance();
+
+        let router = Router::new(
+            vec![RouteInfo {
+                method: &Method::GET,
+                path_suffix: PathBuf::from("json"),
+                handler: Box::new(JsonHandler),
+                api_doc: None,
+            }],
+            Box::new(JsonHandler),
+        )
+        .unwrap();
+
+        let compression_configuration = Box::leak(Box::new(CompressionConfiguration {
+            enabled: true,
+            min_size: Some(0),
+            ..Default::default()
+        }));
+
+        let handler = CompressionHandler {
+            compression_configuration,
+            min_size: 0,
+            dictionaries: Vec::new(),
+            next: Box::new(JsonETagHandler::new(Box::new(router))),
+        };
+
+        let client_stream = serve_duplex_connection(Box::new(handler)).await;
+
+        let (mut sender, client_connection) =
+            hyper::client::conn::http1::handshake(TokioIo::new(client_stream))
+                .await
+                .unwrap();
+        tokio::spawn(client_connection);
+
+        let request = Request::builder()
+            .uri("/api/v1/json")
+            .header(header::HOST, "localhost")
+            .header(header::ACCEPT_ENCODING, "gzip")
+            .body(Empty::<Bytes>::new())
+            .unwrap();
+
+        let response = sender.send_request(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_ENCODING], "gzip");
+        assert_eq!(response.headers()[header::VARY], "accept-encoding");
+
+        let etag = response.headers()[header::ETAG].clone();
+        assert!(etag.as_bytes().starts_with(b"W/\""));
+
+        response.into_body().collect().await.unwrap();
+
+        // the 304 matches the compressed 200
+        for accept_encoding in ["gzip", "identity"] {
+            sender.ready().await.unwrap();
+
+            let request = Request::builder()
+                .uri("/api/v1/json")
+                .header(header::HOST, "localhost")
+                .header(header::ACCEPT_ENCODING, accept_encoding)
+                .header(header::IF_NONE_MATCH, etag.clone())
+                .body(Empty::<Bytes>::new())
+                .unwrap();
+
+            let response = sender.send_request(request).await.unwrap();
+            assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+            assert_eq!(response.headers()[header::VARY], "accept-encoding");
+
+            let expected_etag = if accept_encoding == "gzip" {
+                etag.as_bytes()
+            } else {
+                &etag.as_bytes()[2..]
+            };
+            assert_eq!(response.headers()[header::ETAG].as_bytes(), expected_etag);
+
+            response.into_body().collect().await.unwrap();
+        }
+    }
+}
@@ -0,0 +1,82 @@
+use bytes::Bytes;
+
+use std::io::Write;
+
+// Brotli quality 0 to 11, low levels keep on the fly compression cheap.
+const QUALITY: u32 = 4;
+
+// 4MiB window, the brotli default.
+const LG_WINDOW_SIZE: u32 = 22;
+
+const BUFFER_SIZE: usize = 4096;
+
+// A streaming brotli encoder, see RFC 7932.  Like GzipEncoder each chunk is
+// flushed so its output can be sent immediately.
+pub struct BrotliEncoder {
+    // boxed, the encoder state is several KiB
+    writer: Box<brotli::CompressorWriter<Vec<u8>>>,
+}
+
+impl Default for BrotliEncoder {
+    fn default() -> Self {
+        Self {
+            writer: Box::new(brotli::CompressorWriter::new(
+                Vec::new(),
+                BUFFER_SIZE,
+                QUALITY,
+                LG_WINDOW_SIZE,
+            )),
+        }
+    }
+}
+
+impl BrotliEncoder {
+    pub fn compress_chunk(&mut self, data: &[u8]) -> Bytes {
+        // writes to a Vec do not fail
+        if !data.is_empty() {
+            self.writer.write_all(data).unwrap();
+            self.writer.flush().unwrap();
+        }
+
+        Bytes::from(std::mem::take(self.writer.get_mut()))
+    }
+
+    pub fn finish(self) -> Bytes {
+        Bytes::from(self.writer.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::io::Read;
+
+    fn decompress(compressed: &[u8]) -> Vec<u8> {
+        let mut output = Vec::new();
+        brotli::Decompressor::new(compressed, BUFFER_SIZE)
+            .read_to_end(&mut output)
+            .unwrap();
+        output
+    }
+
+    #[test]
+    fn test_brotli_encoder() {
+        let data = br#"{"a":"aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa","b":"aaaaaaaa"}"#;
+
+        let mut encoder = BrotliEncoder::default();
+
+        let first = encoder.compress_chunk(data);
+        assert!(!first.is_empty());
+        assert!(encoder.compress_chunk(b"").is_empty());
+
+        let mut output = first.to_vec();
+        output.extend_from_slice(&encoder.compress_chunk(data));
+        output.extend_from_slice(&encoder.finish());
+
+        assert_eq!(decompress(&output), [&data[..], &data[..]].concat());
+        assert!(output.len() < 2 * data.len());
+
+        assert_eq!(decompress(&BrotliEncoder::default().finish()), b"");
+    }
+}
@@ -0,0 +1,73 @@
+use bytes::Bytes;
+
+use flate2::{write::GzEncoder, Compression};
+
+use std::io::Write;
+
+// zlib level 0 to 9, low levels keep on the fly compression cheap.
+const LEVEL: u32 = 4;
+
+// A streaming gzip encoder.  Each chunk is followed by a sync flush so its
+// output can be sent immediately.
+pub struct GzipEncoder {
+    writer: GzEncoder<Vec<u8>>,
+}
+
+impl Default for GzipEncoder {
+    fn default() -> Self {
+        Self {
+            writer: GzEncoder::new(Vec::new(), Compression::new(LEVEL)),
+        }
+    }
+}
+
+impl GzipEncoder {
+    pub fn compress_chunk(&mut self, data: &[u8]) -> Bytes {
+        // writes to a Vec do not fail, flush is a deflate sync flush
+        if !data.is_empty() {
+            self.writer.write_all(data).unwrap();
+            self.writer.flush().unwrap();
+        }
+
+        Bytes::from(std::mem::take(self.writer.get_mut()))
+    }
+
+    pub fn finish(self) -> Bytes {
+        Bytes::from(self.writer.finish().unwrap())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::io::Read;
+
+    fn decompress(compressed: &[u8]) -> Vec<u8> {
+        let mut output = Vec::new();
+        flate2::read::GzDecoder::new(compressed)
+            .read_to_end(&mut output)
+            .unwrap();
+        output
+    }
+
+    #[test]
+    fn test_gzip_encoder() {
+        let data = br#"{"a":"aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa","b":"aaaaaaaa"}"#;
+
+        let mut encoder = GzipEncoder::default();
+
+        let first = encoder.compress_chunk(data);
+        assert!(!first.is_empty());
+        assert!(encoder.compress_chunk(b"").is_empty());
+
+        let mut output = first.to_vec();
+        output.extend_from_slice(&encoder.compress_chunk(data));
+        output.extend_from_slice(&encoder.finish());
+
+        assert_eq!(decompress(&output), [&data[..], &data[..]].concat());
+        assert!(output.len() < 2 * data.len());
+
+        assert_eq!(decompress(&GzipEncoder::default().finish()), b"");
+    }
+}
@@ -0,0 +1,58 @@
+use ::zstd::{bulk::Compressor, dict::EncoderDictionary};
+
+// zstd level 1 to 22, the zstd default.
+const LEVEL: i32 = 3;
+
+// A raw content dictionary, see RFC 8878, prepared once and shared by the
+// responses compressed with it.
+pub struct ZstdDictionary {
+    dictionary: EncoderDictionary<'static>,
+}
+
+impl ZstdDictionary {
+    pub fn new(content: &[u8]) -> Self {
+        Self {
+            dictionary: EncoderDictionary::copy(content, LEVEL),
+        }
+    }
+
+    // One frame holding all of data.
+    pub fn compress(&self, data: &[u8]) -> std::io::Result<Vec<u8>> {
+        Compressor::with_prepared_dictionary(&self.dictionary)?.compress(data)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_compress() {
+        let content: Vec<u8> = (0..200)
+            .flat_map(|i: u32| {
+                format!(
+                    "{{\"connection_id\":{},\"server_socket_type\":\"TCP\",\"num_requests\":{}}},",
+                    i,
+                    i * 7
+                )
+                .into_bytes()
+            })
+            .collect();
+
+        let data = &content[1000..3000];
+
+        let with_dictionary = ZstdDictionary::new(&content).compress(data).unwrap();
+        let without_dictionary = ZstdDictionary::new(&[]).compress(data).unwrap();
+
+        // the reference decoder loads dictionaries without the dictionary
+        // magic number as raw content
+        let decompressed = ::zstd::bulk::Decompressor::with_dictionary(&content)
+            .unwrap()
+            .decompress(&with_dictionary, data.len())
+            .unwrap();
+
+        assert_eq!(decompressed, data);
+        assert!(with_dictionary.len() < 100);
+        assert!(with_dictionary.len() < without_dictionary.len());
+    }
+}
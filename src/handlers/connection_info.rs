@@ -4,31 +4,92 @@ use async_trait::async_trait;
 
 use hyper::http::{Method, Response};
 
+use schemars::JsonSchema;
+
 use serde::Serialize;
 
 use tokio::time::Instant;
 
-use std::{collections::BTreeMap, sync::Arc, time::Duration};
+use std::{collections::BTreeMap, net::SocketAddr, sync::Arc, time::Duration};
 
 use crate::{
     config::ServerSocketType,
-    connection::{ConnectionID, ConnectionInfo, ConnectionTracker, ConnectionTrackerState},
+    connection::{
+        ConnectionID, ConnectionInfo, ConnectionProtocol, ConnectionTracker,
+        ConnectionTrackerState, H2StreamTotals, PeerCredentials, StreamStatsSnapshot, TlsInfo,
+    },
+    geoip::GeoInfo,
     handlers::{
-        route::RouteInfo,
+        route::{RouteApiDoc, RouteInfo},
         time_utils::{local_date_time_to_string, LocalDateTime},
         HttpRequest, RequestHandler, ResponseBody,
     },
-    response::{build_json_response, CacheControl},
+    request::QueryParams,
+    response::{build_json_response, build_ndjson_response, CacheControl},
 };
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, JsonSchema, Serialize)]
+struct H2StreamStatsDTO {
+    active_streams: usize,
+    max_concurrent_streams: usize,
+    reset_streams: usize,
+    #[serde(with = "humantime_serde")]
+    #[schemars(with = "String")]
+    write_stall_time: Duration,
+}
+
+impl From<StreamStatsSnapshot> for H2StreamStatsDTO {
+    fn from(stream_stats: StreamStatsSnapshot) -> Self {
+        Self {
+            active_streams: stream_stats.active_streams,
+            max_concurrent_streams: stream_stats.max_concurrent_streams,
+            reset_streams: stream_stats.reset_streams,
+            write_stall_time: stream_stats.write_stall_time,
+        }
+    }
+}
+
+#[derive(Debug, JsonSchema, Serialize)]
+struct H2StreamTotalsDTO {
+    max_concurrent_streams: usize,
+    reset_streams: usize,
+    #[serde(with = "humantime_serde")]
+    #[schemars(with = "String")]
+    write_stall_time: Duration,
+}
+
+impl From<H2StreamTotals> for H2StreamTotalsDTO {
+    fn from(h2_stream_totals: H2StreamTotals) -> Self {
+        Self {
+            max_concurrent_streams: h2_stream_totals.max_concurrent_streams,
+            reset_streams: h2_stream_totals.reset_streams,
+            write_stall_time: h2_stream_totals.write_stall_time,
+        }
+    }
+}
+
+#[derive(Debug, JsonSchema, Serialize)]
 struct ConnectionInfoDTO {
     id: usize,
     server_socket_type: ServerSocketType,
+    negotiated_protocol: Option<ConnectionProtocol>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    peer_addr: Option<SocketAddr>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    proxy_addr: Option<SocketAddr>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    peer_credentials: Option<PeerCredentials>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    geo_info: Option<GeoInfo>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tls_info: Option<TlsInfo>,
     creation_time: String,
     #[serde(with = "humantime_serde")]
+    #[schemars(with = "String")]
     age: Duration,
     num_requests: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    h2_streams: Option<H2StreamStatsDTO>,
 }
 
 impl From<Arc<ConnectionInfo>> for ConnectionInfoDTO {
@@ -39,42 +100,132 @@ impl From<Arc<ConnectionInfo>> for ConnectionInfoDTO {
         Self {
             id: connection_info.id.as_usize(),
             server_socket_type: connection_info.server_socket_type,
+            negotiated_protocol: connection_info.negotiated_protocol(),
+            peer_addr: connection_info.socket_metadata.peer_addr,
+            proxy_addr: connection_info.socket_metadata.proxy_addr,
+            peer_credentials: connection_info.socket_metadata.peer_credentials,
+            geo_info: connection_info.socket_metadata.geo_info.clone(),
+            tls_info: connection_info.socket_metadata.tls_info.clone(),
             creation_time: local_date_time_to_string(&LocalDateTime::from(
                 connection_info.creation_time,
             )),
             age,
             num_requests: connection_info.num_requests(),
+            h2_streams: (connection_info.negotiated_protocol() == Some(ConnectionProtocol::Http2))
+                .then(|| connection_info.stream_stats().into()),
         }
     }
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, JsonSchema, Serialize)]
 struct ConnectionTrackerStateDTO {
     max_open_connections: usize,
     connection_limit_hits: usize,
+    tracking_limit_hits: usize,
     #[serde(with = "humantime_serde")]
+    #[schemars(with = "String")]
     max_connection_lifetime: Duration,
     max_requests_per_connection: usize,
+    connections_by_protocol: BTreeMap<ConnectionProtocol, usize>,
+    total_connections: usize,
+    total_requests: usize,
+    connection_errors: usize,
+    h2_stream_totals: H2StreamTotalsDTO,
     num_open_connections: usize,
+    // open connections above max_tracked_connections, not listed individually
+    num_untracked_connections: usize,
+    // pass as since to receive only the connections changed after this response
+    cursor: u64,
+    // with since, open_connections only lists connections opened or updated
+    // after it and these were closed.  Absent when since is too old,
+    // open_connections is then complete.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    removed_connections: Option<Vec<usize>>,
     open_connections: Vec<ConnectionInfoDTO>,
 }
 
-impl From<ConnectionTrackerState> for ConnectionTrackerStateDTO {
-    fn from(state: ConnectionTrackerState) -> Self {
-        let id_to_open_connection: BTreeMap<ConnectionID, Arc<ConnectionInfo>> = state
-            .open_connections
-            .into_iter()
-            .map(|c| (c.id, c))
-            .collect();
+const DEFAULT_OPEN_CONNECTIONS_LIMIT: usize = 20;
 
-        let num_open_connections = id_to_open_connection.len();
+struct ConnectionInfoQuery {
+    limit: Option<usize>,
+    server_socket_type: Option<ServerSocketType>,
+    ndjson: bool,
+    // cursor of an earlier response, lists all matching connections
+    since: Option<u64>,
+}
 
-        // 20 newest connections with descending ids in reverse order
-        let open_connections = id_to_open_connection
-            .into_iter()
+impl ConnectionInfoQuery {
+    // newest connections first, filtered by socket type
+    fn filter_open_connections(
+        &self,
+        open_connections: Vec<Arc<ConnectionInfo>>,
+        default_limit: usize,
+    ) -> Vec<Arc<ConnectionInfo>> {
+        let id_to_open_connection: BTreeMap<ConnectionID, Arc<ConnectionInfo>> =
+            open_connections.into_iter().map(|c| (c.id, c)).collect();
+
+        id_to_open_connection
+            .into_values()
             .rev()
-            .take(20)
-            .map(|(_, v)| v.into())
+            .filter(|v| {
+                self.server_socket_type
+                    .is_none_or(|server_socket_type| v.server_socket_type == server_socket_type)
+            })
+            .take(self.limit.unwrap_or(default_limit))
+            .collect()
+    }
+}
+
+impl From<&QueryParams> for ConnectionInfoQuery {
+    fn from(query_params: &QueryParams) -> Self {
+        let limit = query_params
+            .get("limit")
+            .and_then(|limit| limit.parse().ok());
+
+        let server_socket_type =
+            query_params
+                .get("socket_type")
+                .and_then(|socket_type| match socket_type {
+                    "TCP" => Some(ServerSocketType::Tcp),
+                    "UNIX" => Some(ServerSocketType::Unix),
+                    "NAMED_PIPE" => Some(ServerSocketType::NamedPipe),
+                    _ => None,
+                });
+
+        let ndjson = query_params.get("format") == Some("ndjson");
+
+        let since = query_params
+            .get("since")
+            .and_then(|since| since.parse().ok());
+
+        Self {
+            limit,
+            server_socket_type,
+            ndjson,
+            since,
+        }
+    }
+}
+
+impl ConnectionTrackerStateDTO {
+    fn new(state: ConnectionTrackerState, query: ConnectionInfoQuery) -> Self {
+        let num_open_connections = state.open_connections.len();
+
+        let mut open_connections = state.open_connections;
+
+        let default_limit = match (query.since, &state.removed_connections) {
+            (None, _) => DEFAULT_OPEN_CONNECTIONS_LIMIT,
+            (Some(since), Some(_)) => {
+                open_connections.retain(|c| c.last_change() > since);
+                usize::MAX
+            }
+            (Some(_), None) => usize::MAX,
+        };
+
+        let open_connections = query
+            .filter_open_connections(open_connections, default_limit)
+            .into_iter()
+            .map(|v| v.into())
             .collect();
 
         // truncate to seconds
@@ -83,9 +234,23 @@ impl From<ConnectionTrackerState> for ConnectionTrackerStateDTO {
         Self {
             max_open_connections: state.max_open_connections,
             connection_limit_hits: state.connection_limit_hits,
+            tracking_limit_hits: state.tracking_limit_hits,
             max_connection_lifetime,
             max_requests_per_connection: state.max_requests_per_connection,
+            connections_by_protocol: state.connections_by_protocol,
+            total_connections: state.total_connections,
+            total_requests: state.total_requests,
+            connection_errors: state.connection_errors,
+            h2_stream_totals: state.h2_stream_stats.into(),
             num_open_connections,
+            num_untracked_connections: state.untracked_connections,
+            cursor: state.change_sequence,
+            removed_connections: state.removed_connections.map(|removed_connections| {
+                removed_connections
+                    .into_iter()
+                    .map(|connection_id| connection_id.as_usize())
+                    .collect()
+            }),
             open_connections,
         }
     }
@@ -105,9 +270,26 @@ impl ServerInfoHandler {
 
 #[async_trait]
 impl RequestHandler for ServerInfoHandler {
-    async fn handle(&self, _request: &HttpRequest) -> Response<ResponseBody> {
-        let connection_tracker_state_dto: ConnectionTrackerStateDTO =
-            self.connection_tracker.state().await.into();
+    async fn handle(&self, request: &HttpRequest) -> Response<ResponseBody> {
+        let query = ConnectionInfoQuery::from(request.query_params());
+
+        let state = match query.since {
+            Some(since) if !query.ndjson => self.connection_tracker.state_since(since).await,
+            _ => self.connection_tracker.state().await,
+        };
+
+        // ndjson streams one connection per line with no default limit
+        if query.ndjson {
+            let open_connections =
+                query.filter_open_connections(state.open_connections, usize::MAX);
+
+            return build_ndjson_response(
+                open_connections.into_iter().map(ConnectionInfoDTO::from),
+                CacheControl::NoCache,
+            );
+        }
+
+        let connection_tracker_state_dto = ConnectionTrackerStateDTO::new(state, query);
 
         build_json_response(connection_tracker_state_dto, CacheControl::NoCache)
     }
@@ -118,5 +300,6 @@ pub async fn create_routes() -> Vec<RouteInfo> {
         method: &Method::GET,
         path_suffix: PathBuf::from("connection_info"),
         handler: Box::new(ServerInfoHandler::new().await),
+        api_doc: RouteApiDoc::json::<ConnectionTrackerStateDTO>("Connection tracker state"),
     }]
 }
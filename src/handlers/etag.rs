@@ -0,0 +1,124 @@
+use async_trait::async_trait;
+
+use http_body_util::{BodyExt, Full};
+
+use hyper::http::{header, HeaderMap, HeaderValue, Method, Response, StatusCode};
+
+use tracing::warn;
+
+use std::hash::{DefaultHasher, Hasher};
+
+use crate::{
+    handlers::{HttpRequest, MatchedRoute, RequestHandler, ResponseBody},
+    response::{build_status_code_response, empty_response_body, CacheControl},
+};
+
+fn compute_etag(body: &[u8]) -> HeaderValue {
+    let mut hasher = DefaultHasher::new();
+    hasher.write(body);
+
+    HeaderValue::from_str(&format!("\"{:016x}\"", hasher.finish())).unwrap()
+}
+
+// Weak comparison per RFC 9110 13.1.2, If-None-Match may list several etags.
+fn if_none_match_matches(request_headers: &HeaderMap, etag: &HeaderValue) -> bool {
+    let etag = etag.as_bytes();
+
+    request_headers
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .any(|candidate| candidate == "*" || candidate.trim_start_matches("W/").as_bytes() == etag)
+}
+
+fn is_json_response(response: &Response<ResponseBody>) -> bool {
+    response.status() == StatusCode::OK
+        && !response.headers().contains_key(header::ETAG)
+        && response
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .is_some_and(|content_type| content_type == "application/json")
+}
+
+// Adds a content hash ETag to JSON API responses and answers matching
+// If-None-Match requests with 304.  Static files already carry their own ETags.
+pub struct JsonETagHandler {
+    next: Box<dyn RequestHandler>,
+}
+
+impl JsonETagHandler {
+    pub fn new(next: Box<dyn RequestHandler>) -> Self {
+        Self { next }
+    }
+}
+
+#[async_trait]
+impl RequestHandler for JsonETagHandler {
+    async fn handle(&self, request: &HttpRequest) -> Response<ResponseBody> {
+        let response = self.next.handle(request).await;
+
+        let method = request.hyper_request.method();
+
+        if !(method == Method::GET || method == Method::HEAD)
+            || request.extension::<MatchedRoute>().is_none()
+            || !is_json_response(&response)
+        {
+            return response;
+        }
+
+        let (mut parts, body) = response.into_parts();
+
+        let body = match body.collect().await {
+            Err(e) => {
+                warn!("JsonETagHandler body collect error {}", e);
+                return build_status_code_response(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    CacheControl::NoCache,
+                );
+            }
+            Ok(collected) => collected.to_bytes(),
+        };
+
+        let etag = compute_etag(&body);
+
+        let not_modified = if_none_match_matches(request.hyper_request.headers(), &etag);
+
+        parts.headers.insert(header::ETAG, etag);
+
+        if not_modified {
+            parts.status = StatusCode::NOT_MODIFIED;
+            parts.headers.remove(header::CONTENT_TYPE);
+            return Response::from_parts(parts, empty_response_body());
+        }
+
+        Response::from_parts(parts, Full::from(body).map_err(|e| e.into()).boxed())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_if_none_match_matches() {
+        let etag = compute_etag(b"{}");
+        let etag_str = etag.to_str().unwrap().to_owned();
+
+        let mut headers = HeaderMap::new();
+        assert!(!if_none_match_matches(&headers, &etag));
+
+        headers.insert(header::IF_NONE_MATCH, HeaderValue::from_static("\"other\""));
+        assert!(!if_none_match_matches(&headers, &etag));
+
+        headers.insert(
+            header::IF_NONE_MATCH,
+            HeaderValue::from_str(&format!("\"other\", W/{}", etag_str)).unwrap(),
+        );
+        assert!(if_none_match_matches(&headers, &etag));
+
+        headers.insert(header::IF_NONE_MATCH, HeaderValue::from_static("*"));
+        assert!(if_none_match_matches(&headers, &etag));
+    }
+}
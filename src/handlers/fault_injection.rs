@@ -0,0 +1,226 @@
+use async_trait::async_trait;
+
+use hyper::http::{Response, StatusCode};
+
+use schemars::JsonSchema;
+
+use serde::Serialize;
+
+use tracing::{debug, info};
+
+use std::{
+    path::Path,
+    sync::RwLock,
+    time::{Duration, SystemTime},
+};
+
+use crate::{
+    handlers::{random::random_fraction, HttpRequest, RequestHandler, ResponseBody},
+    response::{build_deny_response, channel_response_body, DenyReason, ResponseBodyError},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, JsonSchema, Serialize)]
+pub enum FaultKind {
+    #[serde(rename = "LATENCY")]
+    Latency,
+
+    #[serde(rename = "ERROR")]
+    Error,
+
+    #[serde(rename = "RESET")]
+    Reset,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FaultAction {
+    // delays the request, then serves it normally
+    Latency(Duration),
+    Error(StatusCode),
+    // aborts the response after its head, clients see the connection or
+    // HTTP/2 stream reset
+    Reset,
+}
+
+impl FaultAction {
+    // kind is LATENCY with latency e.g. "500ms", ERROR with status
+    // defaulting to 503, or RESET.
+    pub fn parse(kind: &str, latency: Option<&str>, status: Option<&str>) -> Option<Self> {
+        match kind {
+            "LATENCY" => humantime_serde::re::humantime::parse_duration(latency?)
+                .ok()
+                .map(Self::Latency),
+            "ERROR" => {
+                let status_code = match status {
+                    None => StatusCode::SERVICE_UNAVAILABLE,
+                    Some(status) => StatusCode::from_bytes(status.as_bytes()).ok()?,
+                };
+
+                (status_code.is_client_error() || status_code.is_server_error())
+                    .then_some(Self::Error(status_code))
+            }
+            "RESET" => Some(Self::Reset),
+            _ => None,
+        }
+    }
+
+    pub fn kind(&self) -> FaultKind {
+        match self {
+            Self::Latency(_) => FaultKind::Latency,
+            Self::Error(_) => FaultKind::Error,
+            Self::Reset => FaultKind::Reset,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Fault {
+    // matched against the full request path
+    pub path_prefix: String,
+    pub action: FaultAction,
+    // fraction of matching requests the fault applies to, 0 < rate <= 1
+    pub rate: f64,
+    pub injected_time: SystemTime,
+}
+
+// Faults injected at runtime through the admin routes, at most one per
+// path_prefix.  Cleared on restart.
+static FAULTS: RwLock<Vec<Fault>> = RwLock::new(Vec::new());
+
+pub fn inject_fault(path_prefix: &str, action: FaultAction, rate: f64) {
+    info!(
+        "inject_fault path_prefix = {:?} action = {:?} rate = {}",
+        path_prefix, action, rate
+    );
+
+    let mut faults = FAULTS.write().unwrap();
+
+    faults.retain(|fault| fault.path_prefix != path_prefix);
+    faults.push(Fault {
+        path_prefix: path_prefix.to_owned(),
+        action,
+        rate,
+        injected_time: SystemTime::now(),
+    });
+}
+
+// Returns false if no fault was injected for path_prefix.
+pub fn clear_fault(path_prefix: &str) -> bool {
+    let mut faults = FAULTS.write().unwrap();
+
+    let len_before = faults.len();
+
+    faults.retain(|fault| fault.path_prefix != path_prefix);
+
+    let cleared = faults.len() != len_before;
+
+    if cleared {
+        info!("clear_fault path_prefix = {:?}", path_prefix);
+    }
+
+    cleared
+}
+
+pub fn faults() -> Vec<Fault> {
+    FAULTS.read().unwrap().clone()
+}
+
+fn find_fault_action(path: &str) -> Option<FaultAction> {
+    FAULTS
+        .read()
+        .unwrap()
+        .iter()
+        .find(|fault| path.starts_with(&fault.path_prefix))
+        .filter(|fault| random_fraction() < fault.rate)
+        .map(|fault| fault.action)
+}
+
+fn build_reset_response() -> Response<ResponseBody> {
+    let (sender, body) = channel_response_body(1);
+
+    let _ = sender.try_send(Err(ResponseBodyError::IoError(std::io::Error::from(
+        std::io::ErrorKind::ConnectionReset,
+    ))));
+
+    Response::new(body)
+}
+
+pub struct FaultInjectionHandler {
+    // admin routes are never faulted, they are how faults are cleared
+    admin_path: String,
+    next: Box<dyn RequestHandler>,
+}
+
+impl FaultInjectionHandler {
+    pub fn new(next: Box<dyn RequestHandler>) -> Self {
+        let admin_path = Path::new(
+            &crate::config::instance()
+                .context_configuration
+                .dynamic_route_context,
+        )
+        .join("admin")
+        .to_string_lossy()
+        .into_owned();
+
+        Self { admin_path, next }
+    }
+}
+
+#[async_trait]
+impl RequestHandler for FaultInjectionHandler {
+    async fn handle(&self, request: &HttpRequest) -> Response<ResponseBody> {
+        let path = request.hyper_request.uri().path();
+
+        if path.starts_with(&self.admin_path) {
+            return self.next.handle(request).await;
+        }
+
+        let Some(action) = find_fault_action(path) else {
+            return self.next.handle(request).await;
+        };
+
+        debug!("injecting fault path = {:?} action = {:?}", path, action);
+
+        match action {
+            FaultAction::Latency(latency) => {
+                tokio::time::sleep(latency).await;
+                self.next.handle(request).await
+            }
+            FaultAction::Error(status_code) => {
+                build_deny_response(status_code, DenyReason::FaultInjection)
+            }
+            FaultAction::Reset => {
+                let mut response = build_reset_response();
+                response.extensions_mut().insert(DenyReason::FaultInjection);
+                response
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_fault_action() {
+        assert_eq!(
+            FaultAction::parse("LATENCY", Some("250ms"), None),
+            Some(FaultAction::Latency(Duration::from_millis(250)))
+        );
+        assert_eq!(FaultAction::parse("LATENCY", None, None), None);
+        assert_eq!(
+            FaultAction::parse("ERROR", None, None),
+            Some(FaultAction::Error(StatusCode::SERVICE_UNAVAILABLE))
+        );
+        assert_eq!(
+            FaultAction::parse("ERROR", None, Some("429")),
+            Some(FaultAction::Error(StatusCode::TOO_MANY_REQUESTS))
+        );
+        assert_eq!(FaultAction::parse("ERROR", None, Some("200")), None);
+        assert_eq!(
+            FaultAction::parse("RESET", None, None),
+            Some(FaultAction::Reset)
+        );
+        assert_eq!(FaultAction::parse("reset", None, None), None);
+    }
+}
@@ -0,0 +1,62 @@
+use async_trait::async_trait;
+
+use http_body_util::{BodyExt, Full};
+
+use hyper::http::{header, Response, StatusCode};
+
+use tracing::warn;
+
+use crate::{
+    handlers::{HttpRequest, MatchedRoute, RequestHandler, ResponseBody},
+    response::{build_status_code_response, format_json_body, CacheControl, JsonOutputOptions},
+};
+
+// Applies ?pretty and ?fields query options to JSON API responses.
+pub struct JsonOutputHandler {
+    next: Box<dyn RequestHandler>,
+}
+
+impl JsonOutputHandler {
+    pub fn new(next: Box<dyn RequestHandler>) -> Self {
+        Self { next }
+    }
+}
+
+#[async_trait]
+impl RequestHandler for JsonOutputHandler {
+    async fn handle(&self, request: &HttpRequest) -> Response<ResponseBody> {
+        let response = self.next.handle(request).await;
+
+        let options = JsonOutputOptions::from(request.query_params());
+
+        if options.is_default()
+            || request.extension::<MatchedRoute>().is_none()
+            || response
+                .headers()
+                .get(header::CONTENT_TYPE)
+                .is_none_or(|content_type| content_type != "application/json")
+        {
+            return response;
+        }
+
+        let (mut parts, body) = response.into_parts();
+
+        let result = match body.collect().await {
+            Err(e) => Err(e.to_string()),
+            Ok(collected) => {
+                format_json_body(&collected.to_bytes(), &options).map_err(|e| e.to_string())
+            }
+        };
+
+        match result {
+            Err(e) => {
+                warn!("JsonOutputHandler format error {}", e);
+                build_status_code_response(StatusCode::INTERNAL_SERVER_ERROR, CacheControl::NoCache)
+            }
+            Ok(json_body) => {
+                parts.headers.remove(header::CONTENT_LENGTH);
+                Response::from_parts(parts, Full::from(json_body).map_err(|e| e.into()).boxed())
+            }
+        }
+    }
+}
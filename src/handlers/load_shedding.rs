@@ -0,0 +1,84 @@
+use async_trait::async_trait;
+
+use hyper::http::{header, HeaderValue, Response, StatusCode};
+
+use tracing::{debug, warn};
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::{
+    config::LoadSheddingRouteGroup,
+    handlers::{HttpRequest, RequestHandler, ResponseBody},
+    response::{build_deny_response, DenyReason},
+};
+
+struct InFlightGuard<'a>(&'a AtomicUsize);
+
+impl Drop for InFlightGuard<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+// Counts requests in flight and sheds load_shedding_configuration route
+// groups with 503 while the count is above their limit.
+pub struct LoadSheddingHandler {
+    route_groups: &'static [LoadSheddingRouteGroup],
+    retry_after: HeaderValue,
+    in_flight_requests: AtomicUsize,
+    next: Box<dyn RequestHandler>,
+}
+
+impl LoadSheddingHandler {
+    pub fn new(next: Box<dyn RequestHandler>) -> Self {
+        let load_shedding_configuration = &crate::config::instance().load_shedding_configuration;
+
+        debug!(
+            "load_shedding_configuration = {:?}",
+            load_shedding_configuration
+        );
+
+        Self {
+            route_groups: &load_shedding_configuration.route_groups,
+            retry_after: HeaderValue::from(load_shedding_configuration.retry_after.as_secs()),
+            in_flight_requests: AtomicUsize::new(0),
+            next,
+        }
+    }
+
+    fn find_route_group(&self, path: &str) -> Option<&'static LoadSheddingRouteGroup> {
+        self.route_groups.iter().find(|route_group| {
+            route_group
+                .path_prefixes
+                .iter()
+                .any(|path_prefix| path.starts_with(path_prefix))
+        })
+    }
+}
+
+#[async_trait]
+impl RequestHandler for LoadSheddingHandler {
+    async fn handle(&self, request: &HttpRequest) -> Response<ResponseBody> {
+        // includes this request
+        let in_flight_requests = self.in_flight_requests.fetch_add(1, Ordering::Relaxed) + 1;
+        let _guard = InFlightGuard(&self.in_flight_requests);
+
+        if let Some(route_group) = self.find_route_group(request.hyper_request.uri().path()) {
+            if in_flight_requests > route_group.max_in_flight_requests {
+                warn!(
+                    "shedding route group {:?} in_flight_requests = {}",
+                    route_group.name, in_flight_requests
+                );
+
+                let mut response =
+                    build_deny_response(StatusCode::SERVICE_UNAVAILABLE, DenyReason::LoadShedding);
+                response
+                    .headers_mut()
+                    .insert(header::RETRY_AFTER, self.retry_after.clone());
+                return response;
+            }
+        }
+
+        self.next.handle(request).await
+    }
+}
@@ -0,0 +1,283 @@
+use async_trait::async_trait;
+
+use http_body_util::{BodyExt, Full};
+
+use hyper::http::{header, Method, Response, StatusCode};
+
+use std::{fmt::Write, path::PathBuf, time::UNIX_EPOCH};
+
+use crate::{
+    connection::ConnectionTracker,
+    handlers::{
+        route::{RouteApiDoc, RouteInfo},
+        HttpRequest, RequestHandler, ResponseBody,
+    },
+    request_metrics::{LatencyExemplar, RequestMetrics, LATENCY_BUCKETS},
+    response::CacheControl,
+    traffic_stats::TrafficStats,
+};
+
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+// Negotiated by scrapers that read exemplars.
+const OPEN_METRICS_CONTENT_TYPE: &str =
+    "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', r"\\")
+        .replace('"', r#"\""#)
+        .replace('\n', r"\n")
+}
+
+// OpenMetrics names counter families without the _total sample suffix.
+fn write_header(
+    output: &mut String,
+    name: &str,
+    metric_type: &str,
+    help: &str,
+    open_metrics: bool,
+) {
+    let name = if open_metrics && metric_type == "counter" {
+        name.strip_suffix("_total").unwrap_or(name)
+    } else {
+        name
+    };
+
+    let _ = writeln!(output, "# HELP {} {}", name, help);
+    let _ = writeln!(output, "# TYPE {} {}", name, metric_type);
+}
+
+// Ends a bucket line.
+fn write_exemplar(output: &mut String, exemplar: Option<&LatencyExemplar>) {
+    if let Some(exemplar) = exemplar {
+        let _ = write!(
+            output,
+            " # {{trace_id=\"{:032x}\"}} {} {:.3}",
+            exemplar.trace_id,
+            exemplar.duration.as_secs_f64(),
+            exemplar
+                .time
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs_f64()
+        );
+    }
+    output.push('\n');
+}
+
+struct PrometheusMetricsHandler {
+    connection_tracker: &'static ConnectionTracker,
+    request_metrics: &'static RequestMetrics,
+    traffic_stats: &'static TrafficStats,
+    exemplars: bool,
+}
+
+impl PrometheusMetricsHandler {
+    async fn render(&self, open_metrics: bool) -> String {
+        let mut output = String::new();
+
+        let request_metrics = self.request_metrics.snapshot();
+
+        write_header(
+            &mut output,
+            "rhs_requests_total",
+            "counter",
+            "Requests by route and status.",
+            open_metrics,
+        );
+        for (route, entry) in &request_metrics {
+            let route = escape_label_value(route);
+            for (status, count) in &entry.status_counts {
+                let _ = writeln!(
+                    output,
+                    "rhs_requests_total{{route=\"{}\",status=\"{}\"}} {}",
+                    route, status, count
+                );
+            }
+        }
+
+        write_header(
+            &mut output,
+            "rhs_request_duration_seconds",
+            "histogram",
+            "Time until the handler returned response headers.",
+            open_metrics,
+        );
+        for (route, entry) in &request_metrics {
+            let route = escape_label_value(route);
+            let latency = &entry.latency;
+
+            let mut cumulative_count = 0;
+            for (index, (bound, bucket_count)) in LATENCY_BUCKETS
+                .iter()
+                .zip(latency.bucket_counts)
+                .enumerate()
+            {
+                cumulative_count += bucket_count;
+                let _ = write!(
+                    output,
+                    "rhs_request_duration_seconds_bucket{{route=\"{}\",le=\"{}\"}} {}",
+                    route,
+                    bound.as_secs_f64(),
+                    cumulative_count
+                );
+                write_exemplar(
+                    &mut output,
+                    latency.exemplars[index].as_ref().filter(|_| open_metrics),
+                );
+            }
+            let _ = write!(
+                output,
+                "rhs_request_duration_seconds_bucket{{route=\"{}\",le=\"+Inf\"}} {}",
+                route, latency.count
+            );
+            write_exemplar(
+                &mut output,
+                latency.exemplars[LATENCY_BUCKETS.len()]
+                    .as_ref()
+                    .filter(|_| open_metrics),
+            );
+            let _ = writeln!(
+                output,
+                "rhs_request_duration_seconds_sum{{route=\"{}\"}} {}",
+                route,
+                latency.sum.as_secs_f64()
+            );
+            let _ = writeln!(
+                output,
+                "rhs_request_duration_seconds_count{{route=\"{}\"}} {}",
+                route, latency.count
+            );
+        }
+
+        write_header(
+            &mut output,
+            "rhs_response_bytes_total",
+            "counter",
+            "Response body bytes written by route, static files are the [default] route.",
+            open_metrics,
+        );
+        for (route, bytes) in self.traffic_stats.route_total_bytes() {
+            let _ = writeln!(
+                output,
+                "rhs_response_bytes_total{{route=\"{}\"}} {}",
+                escape_label_value(&route),
+                bytes
+            );
+        }
+
+        let state = self.connection_tracker.state().await;
+
+        write_header(
+            &mut output,
+            "rhs_open_connections",
+            "gauge",
+            "Open client connections.",
+            open_metrics,
+        );
+        let _ = writeln!(
+            output,
+            "rhs_open_connections {}",
+            state.open_connections.len() + state.untracked_connections
+        );
+
+        write_header(
+            &mut output,
+            "rhs_connections_total",
+            "counter",
+            "Accepted client connections.",
+            open_metrics,
+        );
+        let _ = writeln!(output, "rhs_connections_total {}", state.total_connections);
+
+        write_header(
+            &mut output,
+            "rhs_connection_errors_total",
+            "counter",
+            "Client connections closed with an error.",
+            open_metrics,
+        );
+        let _ = writeln!(
+            output,
+            "rhs_connection_errors_total {}",
+            state.connection_errors
+        );
+
+        write_header(
+            &mut output,
+            "rhs_uptime_seconds",
+            "gauge",
+            "Seconds since the server started.",
+            open_metrics,
+        );
+        let _ = writeln!(
+            output,
+            "rhs_uptime_seconds {}",
+            crate::uptime::uptime().as_secs()
+        );
+
+        if open_metrics {
+            output.push_str("# EOF\n");
+        }
+
+        output
+    }
+}
+
+#[async_trait]
+impl RequestHandler for PrometheusMetricsHandler {
+    async fn handle(&self, request: &HttpRequest) -> Response<ResponseBody> {
+        // exemplars can only be exposed as OpenMetrics
+        let open_metrics = self.exemplars
+            && request
+                .hyper_request
+                .headers()
+                .get(header::ACCEPT)
+                .and_then(|accept| accept.to_str().ok())
+                .is_some_and(|accept| accept.contains("application/openmetrics-text"));
+
+        let output = self.render(open_metrics).await;
+
+        Response::builder()
+            .status(StatusCode::OK)
+            .header(
+                header::CONTENT_TYPE,
+                if open_metrics {
+                    OPEN_METRICS_CONTENT_TYPE
+                } else {
+                    CONTENT_TYPE
+                },
+            )
+            .header(header::CACHE_CONTROL, CacheControl::NoCache.header_value())
+            .body(Full::from(output).map_err(|never| never.into()).boxed())
+            .unwrap()
+    }
+}
+
+pub async fn create_routes() -> Vec<RouteInfo> {
+    let metrics_configuration = &crate::config::instance().metrics_configuration;
+
+    vec![RouteInfo {
+        method: &Method::GET,
+        path_suffix: PathBuf::from(&metrics_configuration.path),
+        handler: Box::new(PrometheusMetricsHandler {
+            connection_tracker: ConnectionTracker::instance().await,
+            request_metrics: RequestMetrics::instance().await,
+            traffic_stats: TrafficStats::instance().await,
+            exemplars: metrics_configuration.exemplars,
+        }),
+        api_doc: RouteApiDoc::text("Prometheus metrics"),
+    }]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_escape_label_value() {
+        assert_eq!(escape_label_value("/api/v1/a"), "/api/v1/a");
+        assert_eq!(escape_label_value("a\"b\\c\nd"), r#"a\"b\\c\nd"#);
+    }
+}
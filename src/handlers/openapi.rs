@@ -0,0 +1,124 @@
+use async_trait::async_trait;
+
+use bytes::Bytes;
+
+use http_body_util::{BodyExt, Full};
+
+use hyper::http::{Method, Response};
+
+use schemars::generate::SchemaSettings;
+
+use serde_json::{json, Map, Value};
+
+use std::path::PathBuf;
+
+use crate::{
+    handlers::{
+        route::{route_path, RouteInfo},
+        HttpRequest, RequestHandler, ResponseBody,
+    },
+    response::{build_json_body_response, CacheControl},
+};
+
+fn build_operation(route: &RouteInfo, generator: &mut schemars::SchemaGenerator) -> Value {
+    match &route.api_doc {
+        None => json!({
+            "responses": {
+                "200": { "description": "OK" },
+            },
+        }),
+        Some(api_doc) => {
+            let mut media_type = Map::new();
+
+            if let Some(response_schema) = api_doc.response_schema {
+                media_type.insert("schema".to_owned(), response_schema(generator).into());
+            }
+
+            json!({
+                "summary": api_doc.summary,
+                "responses": {
+                    "200": {
+                        "description": api_doc.summary,
+                        "content": {
+                            api_doc.content_type: media_type,
+                        },
+                    },
+                },
+            })
+        }
+    }
+}
+
+fn build_openapi_document(routes: &[RouteInfo], openapi_path: &str) -> anyhow::Result<Value> {
+    let mut generator = SchemaSettings::openapi3().for_serialize().into_generator();
+
+    let mut paths = Map::new();
+
+    for route in routes {
+        let operation = build_operation(route, &mut generator);
+
+        let path_item = paths
+            .entry(route_path(route)?)
+            .or_insert_with(|| Value::Object(Map::new()));
+
+        if let Value::Object(path_item) = path_item {
+            path_item.insert(route.method.as_str().to_ascii_lowercase(), operation);
+        }
+    }
+
+    paths.insert(
+        openapi_path.to_owned(),
+        json!({
+            "get": {
+                "summary": "OpenAPI document for this server",
+                "responses": {
+                    "200": { "description": "OK" },
+                },
+            },
+        }),
+    );
+
+    Ok(json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": env!("CARGO_PKG_NAME"),
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "paths": paths,
+        "components": {
+            "schemas": generator.take_definitions(true),
+        },
+    }))
+}
+
+struct OpenApiHandler {
+    json_bytes: Bytes,
+}
+
+#[async_trait]
+impl RequestHandler for OpenApiHandler {
+    async fn handle(&self, _request: &HttpRequest) -> Response<ResponseBody> {
+        build_json_body_response(
+            Full::from(self.json_bytes.clone())
+                .map_err(|e| e.into())
+                .boxed(),
+            CacheControl::NoCache,
+        )
+    }
+}
+
+pub fn create_route(routes: &[RouteInfo]) -> anyhow::Result<RouteInfo> {
+    let openapi_path = &crate::config::instance().context_configuration.openapi_path;
+
+    let document = build_openapi_document(routes, openapi_path)?;
+
+    // An absolute path_suffix replaces the dynamic route context when joined.
+    Ok(RouteInfo {
+        method: &Method::GET,
+        path_suffix: PathBuf::from(openapi_path),
+        handler: Box::new(OpenApiHandler {
+            json_bytes: Bytes::from(serde_json::to_vec(&document)?),
+        }),
+        api_doc: None,
+    })
+}
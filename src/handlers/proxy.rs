@@ -0,0 +1,1388 @@
+mod circuit_breaker;
+mod dns;
+mod mirror;
+
+use anyhow::Context;
+
+use async_trait::async_trait;
+
+use bytes::Bytes;
+
+use http_body_util::{combinators::BoxBody, BodyExt, Empty, LengthLimitError, Limited};
+
+use hyper::http::{
+    header, uri::PathAndQuery, HeaderMap, HeaderName, HeaderValue, Method, Request, Response,
+    StatusCode, Uri, Version,
+};
+
+use hyper_util::{
+    client::legacy::{connect::HttpConnector, Client},
+    rt::TokioExecutor,
+};
+
+use tracing::{debug, warn};
+
+use schemars::JsonSchema;
+
+use serde::Serialize;
+
+use tokio::time::Instant;
+
+use std::{net::IpAddr, path::PathBuf, sync::Arc, time::Duration};
+
+use crate::{
+    config::{ProxyCanary, ProxyRoute},
+    handlers::{
+        random::random_fraction,
+        request_id::ExternalRequestID,
+        route::{RouteApiDoc, RouteInfo},
+        HttpRequest, RequestHandler, ResponseBody,
+    },
+    request::{format_grpc_timeout, RequestDeadline, GRPC_TIMEOUT},
+    response::{
+        build_deny_response, build_json_response, build_status_code_response, CacheControl,
+        DenyReason,
+    },
+};
+
+use circuit_breaker::{CircuitBreaker, CircuitBreakerSnapshot};
+
+use dns::CachingResolver;
+
+use mirror::{spawn_mirror_request, MirrorEntry};
+
+type ProxyRequestBody = BoxBody<Bytes, Box<dyn std::error::Error + Send + Sync>>;
+
+type ProxyClient = Client<HttpConnector<CachingResolver>, ProxyRequestBody>;
+
+// RFC 9110 7.6.1, never forwarded.
+const HOP_BY_HOP_HEADERS: [HeaderName; 7] = [
+    header::CONNECTION,
+    HeaderName::from_static("keep-alive"),
+    HeaderName::from_static("proxy-connection"),
+    header::TE,
+    header::TRAILER,
+    header::TRANSFER_ENCODING,
+    header::UPGRADE,
+];
+
+const X_FORWARDED_FOR: HeaderName = HeaderName::from_static("x-forwarded-for");
+
+const X_FORWARDED_PROTO: HeaderName = HeaderName::from_static("x-forwarded-proto");
+
+// Names this proxy in Via headers.
+const VIA_PSEUDONYM: &str = "rhs";
+
+// Parses a proxy route upstream_url, which must be http with an authority.
+pub fn parse_upstream_url(upstream_url: &str) -> anyhow::Result<Uri> {
+    let uri: Uri = upstream_url
+        .parse()
+        .with_context(|| format!("invalid upstream_url '{}'", upstream_url))?;
+
+    if uri.scheme_str() != Some("http") {
+        anyhow::bail!("upstream_url '{}' must be http", upstream_url);
+    }
+
+    if uri.authority().is_none() {
+        anyhow::bail!("upstream_url '{}' has no host", upstream_url);
+    }
+
+    if uri.query().is_some() {
+        anyhow::bail!("upstream_url '{}' must not have a query", upstream_url);
+    }
+
+    Ok(uri)
+}
+
+fn remove_hop_by_hop_headers(headers: &mut HeaderMap) {
+    let connection_headers: Vec<HeaderName> = headers
+        .get_all(header::CONNECTION)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|name| HeaderName::try_from(name.trim()).ok())
+        .collect();
+
+    for name in connection_headers.iter().chain(HOP_BY_HOP_HEADERS.iter()) {
+        headers.remove(name);
+    }
+}
+
+// The client wraps the body error, look through the error sources.
+// RFC 9110 7.6.3, appends this proxy and the protocol the message was
+// received with to any Via values from earlier hops.
+fn append_via(headers: &mut HeaderMap, received_version: Version) -> anyhow::Result<()> {
+    let received_protocol = match received_version {
+        Version::HTTP_09 => "0.9",
+        Version::HTTP_10 => "1.0",
+        Version::HTTP_2 => "2",
+        Version::HTTP_3 => "3",
+        _ => "1.1",
+    };
+
+    let mut via: Vec<&str> = headers
+        .get_all(header::VIA)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .collect();
+
+    let this_hop = format!("{} {}", received_protocol, VIA_PSEUDONYM);
+    via.push(&this_hop);
+
+    let via = HeaderValue::try_from(via.join(", "))?;
+    headers.insert(header::VIA, via);
+
+    Ok(())
+}
+
+fn is_grpc_request(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|content_type| content_type.starts_with("application/grpc"))
+}
+
+fn is_length_limit_error(e: &(dyn std::error::Error + 'static)) -> bool {
+    let mut source = Some(e);
+
+    while let Some(e) = source {
+        if e.is::<LengthLimitError>() {
+            return true;
+        }
+        source = e.source();
+    }
+
+    false
+}
+
+fn find_cookie<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|cookie| cookie.trim().split_once('='))
+        .find(|(cookie_name, _)| *cookie_name == name)
+        .map(|(_, value)| value)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum UpstreamAssignment {
+    Stable,
+    Canary,
+}
+
+impl UpstreamAssignment {
+    fn parse(cookie_value: &str) -> Option<Self> {
+        match cookie_value {
+            "stable" => Some(Self::Stable),
+            "canary" => Some(Self::Canary),
+            _ => None,
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Stable => "stable",
+            Self::Canary => "canary",
+        }
+    }
+}
+
+#[derive(Debug)]
+struct CanaryEntry {
+    upstream_url: Uri,
+    fraction: f64,
+    header: Option<(HeaderName, HeaderValue)>,
+    sticky_cookie: Option<&'static str>,
+    circuit_breaker: Option<Arc<CircuitBreaker>>,
+}
+
+impl CanaryEntry {
+    fn new(
+        canary: &'static ProxyCanary,
+        circuit_breaker: Option<Arc<CircuitBreaker>>,
+    ) -> anyhow::Result<Self> {
+        if !(0.0..=100.0).contains(&canary.percent) {
+            anyhow::bail!("canary percent {} must be 0 to 100", canary.percent);
+        }
+
+        let header = canary
+            .header
+            .as_ref()
+            .map(|header| {
+                Ok::<_, anyhow::Error>((
+                    HeaderName::try_from(&header.name)
+                        .with_context(|| format!("invalid canary header '{}'", header.name))?,
+                    HeaderValue::try_from(&header.value).with_context(|| {
+                        format!("invalid canary header value '{}'", header.value)
+                    })?,
+                ))
+            })
+            .transpose()?;
+
+        if let Some(sticky_cookie) = &canary.sticky_cookie {
+            if sticky_cookie.is_empty()
+                || !sticky_cookie
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b == b'_' || b == b'-')
+            {
+                anyhow::bail!("invalid canary sticky_cookie '{}'", sticky_cookie);
+            }
+        }
+
+        Ok(Self {
+            upstream_url: parse_upstream_url(&canary.upstream_url)?,
+            fraction: canary.percent / 100.0,
+            header,
+            sticky_cookie: canary.sticky_cookie.as_deref(),
+            circuit_breaker,
+        })
+    }
+
+    // The upstream for a request and whether to set the sticky cookie on the
+    // response.  random is in [0, 1).
+    fn assign(&self, headers: &HeaderMap, random: f64) -> (UpstreamAssignment, bool) {
+        if let Some((name, value)) = &self.header {
+            if headers.get(name) == Some(value) {
+                return (UpstreamAssignment::Canary, false);
+            }
+        }
+
+        if let Some(sticky_cookie) = self.sticky_cookie {
+            if let Some(assignment) =
+                find_cookie(headers, sticky_cookie).and_then(UpstreamAssignment::parse)
+            {
+                return (assignment, false);
+            }
+        }
+
+        let assignment = if random < self.fraction {
+            UpstreamAssignment::Canary
+        } else {
+            UpstreamAssignment::Stable
+        };
+
+        (assignment, self.sticky_cookie.is_some())
+    }
+}
+
+#[derive(Debug)]
+struct ProxyRouteEntry {
+    path_prefix: &'static str,
+    upstream_url: Uri,
+    timeout: Duration,
+    preserve_host: bool,
+    canary: Option<CanaryEntry>,
+    circuit_breaker: Option<Arc<CircuitBreaker>>,
+    mirror: Option<MirrorEntry>,
+}
+
+impl ProxyRouteEntry {
+    fn new(proxy_route: &'static ProxyRoute) -> anyhow::Result<Self> {
+        if !proxy_route.path_prefix.starts_with('/') {
+            anyhow::bail!(
+                "proxy route path_prefix '{}' must start with '/'",
+                proxy_route.path_prefix
+            );
+        }
+
+        let new_circuit_breaker = |assignment: UpstreamAssignment| {
+            proxy_route.circuit_breaker.as_ref().map(|configuration| {
+                Arc::new(CircuitBreaker::new(
+                    format!("{} {}", proxy_route.path_prefix, assignment.as_str()),
+                    configuration,
+                ))
+            })
+        };
+
+        Ok(Self {
+            path_prefix: &proxy_route.path_prefix,
+            upstream_url: parse_upstream_url(&proxy_route.upstream_url)?,
+            timeout: proxy_route.timeout,
+            preserve_host: proxy_route.preserve_host,
+            canary: proxy_route
+                .canary
+                .as_ref()
+                .map(|canary| {
+                    CanaryEntry::new(canary, new_circuit_breaker(UpstreamAssignment::Canary))
+                })
+                .transpose()?,
+            circuit_breaker: new_circuit_breaker(UpstreamAssignment::Stable),
+            mirror: proxy_route
+                .mirror
+                .as_ref()
+                .map(|mirror| {
+                    if !(0.0..=100.0).contains(&mirror.percent) {
+                        anyhow::bail!("mirror percent {} must be 0 to 100", mirror.percent);
+                    }
+
+                    Ok(MirrorEntry {
+                        upstream_url: parse_upstream_url(&mirror.upstream_url)?,
+                        fraction: mirror.percent / 100.0,
+                    })
+                })
+                .transpose()?,
+        })
+    }
+
+    fn assign_upstream(&self, request: &HttpRequest) -> (UpstreamAssignment, bool) {
+        match &self.canary {
+            None => (UpstreamAssignment::Stable, false),
+            Some(canary) => canary.assign(request.hyper_request.headers(), random_fraction()),
+        }
+    }
+
+    fn upstream_url(&self, assignment: UpstreamAssignment) -> &Uri {
+        match (assignment, &self.canary) {
+            (UpstreamAssignment::Canary, Some(canary)) => &canary.upstream_url,
+            _ => &self.upstream_url,
+        }
+    }
+
+    fn circuit_breaker(&self, assignment: UpstreamAssignment) -> Option<&CircuitBreaker> {
+        match (assignment, &self.canary) {
+            (UpstreamAssignment::Canary, Some(canary)) => canary.circuit_breaker.as_deref(),
+            _ => self.circuit_breaker.as_deref(),
+        }
+    }
+
+    // The stable upstream, then the canary.
+    fn upstreams(
+        &self,
+    ) -> impl Iterator<Item = (UpstreamAssignment, &Uri, Option<&Arc<CircuitBreaker>>)> {
+        std::iter::once((
+            UpstreamAssignment::Stable,
+            &self.upstream_url,
+            self.circuit_breaker.as_ref(),
+        ))
+        .chain(self.canary.iter().map(|canary| {
+            (
+                UpstreamAssignment::Canary,
+                &canary.upstream_url,
+                canary.circuit_breaker.as_ref(),
+            )
+        }))
+    }
+
+    // path_prefix matches whole path segments, "/api" does not match "/apix".
+    fn matches(&self, path: &str) -> bool {
+        path.strip_prefix(self.path_prefix).is_some_and(|rest| {
+            rest.is_empty() || rest.starts_with('/') || self.path_prefix.ends_with('/')
+        })
+    }
+
+    // The request's path with path_prefix replaced by the upstream path.
+    fn upstream_uri(&self, upstream_url: &Uri, request_uri: &Uri) -> anyhow::Result<Uri> {
+        let path = request_uri.path();
+        let rest = path.strip_prefix(self.path_prefix).unwrap_or(path);
+
+        let upstream_path = upstream_url.path();
+
+        let mut path_and_query = match (upstream_path.ends_with('/'), rest.starts_with('/')) {
+            (true, true) => format!("{}{}", upstream_path, &rest[1..]),
+            (false, false) if !rest.is_empty() => format!("{}/{}", upstream_path, rest),
+            _ => format!("{}{}", upstream_path, rest),
+        };
+
+        if let Some(query) = request_uri.query() {
+            path_and_query.push('?');
+            path_and_query.push_str(query);
+        }
+
+        let mut parts = upstream_url.clone().into_parts();
+        parts.path_and_query = Some(PathAndQuery::try_from(path_and_query)?);
+
+        Ok(Uri::from_parts(parts)?)
+    }
+}
+
+// Where upstream connections are made from.  Routes with equal bindings
+// share a client and its connection pool.
+#[derive(Debug, PartialEq, Eq)]
+struct UpstreamBinding {
+    local_address: Option<IpAddr>,
+    interface: Option<&'static str>,
+}
+
+impl From<&'static ProxyRoute> for UpstreamBinding {
+    fn from(proxy_route: &'static ProxyRoute) -> Self {
+        Self {
+            local_address: proxy_route.local_address,
+            interface: proxy_route.interface.as_deref(),
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn set_interface(
+    connector: &mut HttpConnector<CachingResolver>,
+    interface: &str,
+) -> anyhow::Result<()> {
+    connector.set_interface(interface);
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_interface(
+    _connector: &mut HttpConnector<CachingResolver>,
+    interface: &str,
+) -> anyhow::Result<()> {
+    anyhow::bail!("proxy interface '{}' is only supported on Linux", interface)
+}
+
+fn build_client(
+    binding: &UpstreamBinding,
+    resolver: &CachingResolver,
+    pool_idle_timeout: Duration,
+) -> anyhow::Result<ProxyClient> {
+    debug!("build_client binding = {:?}", binding);
+
+    let mut connector = HttpConnector::new_with_resolver(resolver.clone());
+    connector.set_keepalive(Some(pool_idle_timeout));
+    connector.set_local_address(binding.local_address);
+
+    if let Some(interface) = binding.interface {
+        set_interface(&mut connector, interface)?;
+    }
+
+    Ok(Client::builder(TokioExecutor::new())
+        .pool_idle_timeout(pool_idle_timeout)
+        .build(connector))
+}
+
+pub struct ProxyHandler {
+    routes: Vec<(ProxyRouteEntry, ProxyClient)>,
+    request_id_header: Option<HeaderName>,
+    next: Box<dyn RequestHandler>,
+}
+
+impl ProxyHandler {
+    pub fn new(next: Box<dyn RequestHandler>) -> anyhow::Result<Self> {
+        let configuration = crate::config::instance();
+
+        let proxy_configuration = &configuration.proxy_configuration;
+
+        debug!("proxy_configuration = {:?}", proxy_configuration);
+
+        let resolver = CachingResolver::new(&proxy_configuration.dns);
+
+        let mut clients: Vec<(UpstreamBinding, ProxyClient)> = Vec::new();
+
+        let routes = proxy_configuration
+            .routes
+            .iter()
+            .map(|proxy_route| {
+                let route = ProxyRouteEntry::new(proxy_route)?;
+
+                let binding = UpstreamBinding::from(proxy_route);
+
+                let client = match clients.iter().find(|(existing, _)| *existing == binding) {
+                    Some((_, client)) => client.clone(),
+                    None => {
+                        let client = build_client(
+                            &binding,
+                            &resolver,
+                            proxy_configuration.pool_idle_timeout,
+                        )?;
+                        clients.push((binding, client.clone()));
+                        client
+                    }
+                };
+
+                Ok((route, client))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()
+            .context("ProxyHandler::new: invalid proxy route")?;
+
+        let request_id_header = configuration
+            .request_id_configuration
+            .header
+            .as_deref()
+            .map(HeaderName::try_from)
+            .transpose()?;
+
+        Ok(Self {
+            routes,
+            request_id_header,
+            next,
+        })
+    }
+
+    // proxy_info, the upstreams of each route and their circuit breakers.
+    pub fn create_routes(&self) -> Vec<RouteInfo> {
+        let upstreams = self
+            .routes
+            .iter()
+            .flat_map(|(route, _)| {
+                route
+                    .upstreams()
+                    .map(
+                        |(assignment, upstream_url, circuit_breaker)| ProxyUpstreamInfo {
+                            path_prefix: route.path_prefix,
+                            assignment,
+                            upstream_url: upstream_url.to_string(),
+                            circuit_breaker: circuit_breaker.cloned(),
+                        },
+                    )
+            })
+            .collect();
+
+        vec![RouteInfo {
+            method: &Method::GET,
+            path_suffix: PathBuf::from("proxy_info"),
+            handler: Box::new(ProxyInfoHandler { upstreams }),
+            api_doc: RouteApiDoc::json::<ProxyInfoDTO>(
+                "Proxy upstreams and circuit breaker states",
+            ),
+        }]
+    }
+
+    fn find_route(&self, path: &str) -> Option<&(ProxyRouteEntry, ProxyClient)> {
+        self.routes.iter().find(|(route, _)| route.matches(path))
+    }
+
+    fn build_upstream_request(
+        &self,
+        route: &ProxyRouteEntry,
+        upstream_url: &Uri,
+        timeout: Duration,
+        request: &HttpRequest,
+    ) -> anyhow::Result<Request<ProxyRequestBody>> {
+        let hyper_request = &request.hyper_request;
+
+        let body = match request.take_body() {
+            Some(body) => Limited::new(body, request.max_body_bytes()).boxed(),
+            None => Empty::new().map_err(|never| match never {}).boxed(),
+        };
+
+        let mut upstream_request = Request::new(body);
+        *upstream_request.method_mut() = hyper_request.method().clone();
+        *upstream_request.uri_mut() = route.upstream_uri(upstream_url, hyper_request.uri())?;
+        *upstream_request.version_mut() = Version::HTTP_11;
+
+        let headers = upstream_request.headers_mut();
+        headers.clone_from(hyper_request.headers());
+
+        remove_hop_by_hop_headers(headers);
+
+        // HTTP/2 requests carry the host in the URI
+        let client_host = headers.get(header::HOST).cloned().or_else(|| {
+            hyper_request
+                .uri()
+                .authority()
+                .and_then(|authority| HeaderValue::from_str(authority.as_str()).ok())
+        });
+
+        match client_host {
+            Some(client_host) if route.preserve_host => {
+                headers.insert(header::HOST, client_host);
+            }
+            _ => {
+                // the client adds the upstream's Host
+                headers.remove(header::HOST);
+            }
+        }
+
+        if let Some(peer_addr) = request.peer_addr() {
+            let forwarded_for = match headers.get(X_FORWARDED_FOR).map(HeaderValue::to_str) {
+                Some(Ok(forwarded_for)) => format!("{}, {}", forwarded_for, peer_addr.ip()),
+                _ => peer_addr.ip().to_string(),
+            };
+            headers.insert(X_FORWARDED_FOR, HeaderValue::try_from(forwarded_for)?);
+        }
+
+        let proto = if request.tls_info().is_some() {
+            "https"
+        } else {
+            "http"
+        };
+        headers.insert(X_FORWARDED_PROTO, HeaderValue::from_static(proto));
+
+        append_via(headers, hyper_request.version())?;
+
+        // gRPC upstreams stop working on the request once what is left of
+        // its deadline has passed
+        if is_grpc_request(headers) {
+            headers.insert(
+                GRPC_TIMEOUT,
+                HeaderValue::try_from(format_grpc_timeout(timeout))?,
+            );
+        }
+
+        if let (Some(request_id_header), Some(ExternalRequestID(external_request_id))) =
+            (&self.request_id_header, request.extension())
+        {
+            headers.insert(
+                request_id_header.clone(),
+                HeaderValue::from_str(&external_request_id)?,
+            );
+        }
+
+        Ok(upstream_request)
+    }
+
+    async fn proxy(
+        &self,
+        route: &ProxyRouteEntry,
+        client: &ProxyClient,
+        request: &HttpRequest,
+    ) -> Response<ResponseBody> {
+        let (assignment, set_sticky_cookie) = route.assign_upstream(request);
+
+        let upstream_url = route.upstream_url(assignment);
+
+        // the route's timeout, cut short by the request's deadline
+        let timeout = request
+            .extension::<RequestDeadline>()
+            .map_or(route.timeout, |deadline| {
+                route.timeout.min(deadline.remaining())
+            });
+
+        if timeout.is_zero() {
+            warn!("ProxyHandler request deadline passed before proxying");
+            return build_status_code_response(StatusCode::GATEWAY_TIMEOUT, CacheControl::NoCache);
+        }
+
+        let circuit_breaker = route.circuit_breaker(assignment);
+
+        if circuit_breaker.is_some_and(|circuit_breaker| !circuit_breaker.allow(Instant::now())) {
+            debug!("upstream {:?} circuit breaker open", upstream_url);
+            return build_status_code_response(
+                StatusCode::SERVICE_UNAVAILABLE,
+                CacheControl::NoCache,
+            );
+        }
+
+        let upstream_request =
+            match self.build_upstream_request(route, upstream_url, timeout, request) {
+                Ok(upstream_request) => upstream_request,
+                Err(e) => {
+                    warn!("ProxyHandler build_upstream_request error: {:#}", e);
+                    return build_status_code_response(
+                        StatusCode::BAD_REQUEST,
+                        CacheControl::NoCache,
+                    );
+                }
+            };
+
+        debug!(
+            "upstream uri = {:?} assignment = {:?}",
+            upstream_request.uri(),
+            assignment
+        );
+
+        let upstream_request = match &route.mirror {
+            Some(mirror) if random_fraction() < mirror.fraction => {
+                match route.upstream_uri(&mirror.upstream_url, request.hyper_request.uri()) {
+                    Ok(mirror_uri) => {
+                        spawn_mirror_request(client, mirror_uri, route.timeout, upstream_request)
+                    }
+                    Err(e) => {
+                        warn!("ProxyHandler mirror uri error: {:#}", e);
+                        upstream_request
+                    }
+                }
+            }
+            _ => upstream_request,
+        };
+
+        let result = tokio::time::timeout(timeout, client.request(upstream_request)).await;
+
+        if let Some(circuit_breaker) = circuit_breaker {
+            // oversized request bodies are not the upstream's failure
+            let success = match &result {
+                Ok(Ok(response)) => Some(!response.status().is_server_error()),
+                Ok(Err(e)) if is_length_limit_error(e) => None,
+                _ => Some(false),
+            };
+
+            if let Some(success) = success {
+                circuit_breaker.record(success, Instant::now());
+            }
+        }
+
+        let response = match result {
+            Ok(Ok(response)) => response,
+            Ok(Err(e)) if is_length_limit_error(&e) => {
+                warn!("ProxyHandler request body exceeds max_body_bytes");
+                return build_deny_response(
+                    StatusCode::PAYLOAD_TOO_LARGE,
+                    DenyReason::RequestLimit,
+                );
+            }
+            Ok(Err(e)) => {
+                warn!("ProxyHandler upstream {:?} error: {}", upstream_url, e);
+                return build_status_code_response(StatusCode::BAD_GATEWAY, CacheControl::NoCache);
+            }
+            Err(_) => {
+                warn!(
+                    "ProxyHandler upstream {:?} timeout after {:?}",
+                    upstream_url, timeout
+                );
+                return build_status_code_response(
+                    StatusCode::GATEWAY_TIMEOUT,
+                    CacheControl::NoCache,
+                );
+            }
+        };
+
+        let (mut parts, body) = response.into_parts();
+
+        remove_hop_by_hop_headers(&mut parts.headers);
+
+        if let Err(e) = append_via(&mut parts.headers, parts.version) {
+            warn!(
+                "ProxyHandler upstream {:?} invalid Via: {:#}",
+                upstream_url, e
+            );
+        }
+
+        if let (true, Some(sticky_cookie)) = (
+            set_sticky_cookie,
+            route
+                .canary
+                .as_ref()
+                .and_then(|canary| canary.sticky_cookie),
+        ) {
+            let set_cookie = format!(
+                "{}={}; Path={}; HttpOnly; SameSite=Lax",
+                sticky_cookie,
+                assignment.as_str(),
+                route.path_prefix
+            );
+
+            if let Ok(set_cookie) = HeaderValue::try_from(set_cookie) {
+                parts.headers.append(header::SET_COOKIE, set_cookie);
+            }
+        }
+
+        Response::from_parts(parts, body.map_err(|e| e.into()).boxed())
+    }
+}
+
+#[derive(Debug, JsonSchema, Serialize)]
+struct ProxyUpstreamDTO {
+    path_prefix: &'static str,
+    assignment: &'static str,
+    upstream_url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    circuit_breaker: Option<CircuitBreakerSnapshot>,
+}
+
+#[derive(Debug, JsonSchema, Serialize)]
+struct ProxyInfoDTO {
+    upstreams: Vec<ProxyUpstreamDTO>,
+}
+
+struct ProxyUpstreamInfo {
+    path_prefix: &'static str,
+    assignment: UpstreamAssignment,
+    upstream_url: String,
+    circuit_breaker: Option<Arc<CircuitBreaker>>,
+}
+
+struct ProxyInfoHandler {
+    upstreams: Vec<ProxyUpstreamInfo>,
+}
+
+#[async_trait]
+impl RequestHandler for ProxyInfoHandler {
+    async fn handle(&self, _request: &HttpRequest) -> Response<ResponseBody> {
+        let dto = ProxyInfoDTO {
+            upstreams: self
+                .upstreams
+                .iter()
+                .map(|upstream| ProxyUpstreamDTO {
+                    path_prefix: upstream.path_prefix,
+                    assignment: upstream.assignment.as_str(),
+                    upstream_url: upstream.upstream_url.clone(),
+                    circuit_breaker: upstream
+                        .circuit_breaker
+                        .as_ref()
+                        .map(|circuit_breaker| circuit_breaker.snapshot()),
+                })
+                .collect(),
+        };
+
+        build_json_response(dto, CacheControl::NoCache)
+    }
+}
+
+#[async_trait]
+impl RequestHandler for ProxyHandler {
+    async fn handle(&self, request: &HttpRequest) -> Response<ResponseBody> {
+        match self.find_route(request.hyper_request.uri().path()) {
+            Some((route, client)) => self.proxy(route, client, request).await,
+            None => self.next.handle(request).await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use hyper::{body::Incoming, server::conn::http1, service::service_fn};
+
+    use hyper_util::rt::TokioIo;
+
+    use tokio::{
+        io::{AsyncRead, AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+        sync::oneshot,
+    };
+
+    use tokio_util::sync::CancellationToken;
+
+    use std::{convert::Infallible, net::SocketAddr, sync::Arc};
+
+    use crate::{
+        config::{ProxyCircuitBreaker, ProxyDnsConfiguration},
+        connection::{ConnectionID, SocketMetadata},
+        request::RequestID,
+    };
+
+    fn route_entry(path_prefix: &'static str, upstream_url: &str) -> ProxyRouteEntry {
+        ProxyRouteEntry {
+            path_prefix,
+            upstream_url: parse_upstream_url(upstream_url).unwrap(),
+            timeout: Duration::from_secs(1),
+            preserve_host: false,
+            canary: None,
+            circuit_breaker: None,
+            mirror: None,
+        }
+    }
+
+    #[test]
+    fn test_upstream_uri() {
+        let route = route_entry("/internal/", "http://127.0.0.1:9000/");
+        assert_eq!(
+            route
+                .upstream_uri(&route.upstream_url, &"/internal/a/b?c=d".parse().unwrap())
+                .unwrap(),
+            "http://127.0.0.1:9000/a/b?c=d"
+        );
+
+        let route = route_entry("/svc", "http://backend/v1");
+        assert_eq!(
+            route
+                .upstream_uri(&route.upstream_url, &"/svc/a".parse().unwrap())
+                .unwrap(),
+            "http://backend/v1/a"
+        );
+        assert_eq!(
+            route
+                .upstream_uri(&route.upstream_url, &"/svc".parse().unwrap())
+                .unwrap(),
+            "http://backend/v1"
+        );
+
+        assert!(parse_upstream_url("https://backend/").is_err());
+        assert!(parse_upstream_url("/relative").is_err());
+    }
+
+    #[test]
+    fn test_route_matches() {
+        let route = route_entry("/api", "http://backend/");
+        assert!(route.matches("/api"));
+        assert!(route.matches("/api/"));
+        assert!(route.matches("/api/a/b"));
+        assert!(!route.matches("/apix"));
+        assert!(!route.matches("/apix/a"));
+        assert!(!route.matches("/ap"));
+
+        let route = route_entry("/internal/", "http://backend/");
+        assert!(route.matches("/internal/a"));
+        assert!(route.matches("/internal/"));
+        assert!(!route.matches("/internal"));
+        assert!(!route.matches("/internalx"));
+
+        let route = route_entry("/", "http://backend/");
+        assert!(route.matches("/"));
+        assert!(route.matches("/anything"));
+    }
+
+    #[test]
+    fn test_canary_assign() {
+        let canary = CanaryEntry {
+            upstream_url: parse_upstream_url("http://canary/").unwrap(),
+            fraction: 0.25,
+            header: Some((
+                HeaderName::from_static("x-canary"),
+                HeaderValue::from_static("1"),
+            )),
+            sticky_cookie: Some("upstream"),
+            circuit_breaker: None,
+        };
+
+        let mut headers = HeaderMap::new();
+        assert_eq!(
+            canary.assign(&headers, 0.1),
+            (UpstreamAssignment::Canary, true)
+        );
+        assert_eq!(
+            canary.assign(&headers, 0.5),
+            (UpstreamAssignment::Stable, true)
+        );
+
+        headers.insert(
+            header::COOKIE,
+            HeaderValue::from_static("a=b; upstream=canary"),
+        );
+        assert_eq!(
+            canary.assign(&headers, 0.5),
+            (UpstreamAssignment::Canary, false)
+        );
+
+        headers.insert(header::COOKIE, HeaderValue::from_static("upstream=stable"));
+        assert_eq!(
+            canary.assign(&headers, 0.1),
+            (UpstreamAssignment::Stable, false)
+        );
+
+        headers.insert("x-canary", HeaderValue::from_static("1"));
+        assert_eq!(
+            canary.assign(&headers, 0.9),
+            (UpstreamAssignment::Canary, false)
+        );
+    }
+
+    #[test]
+    fn test_remove_hop_by_hop_headers() {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::CONNECTION, HeaderValue::from_static("x-private"));
+        headers.insert("x-private", HeaderValue::from_static("1"));
+        headers.insert(
+            header::TRANSFER_ENCODING,
+            HeaderValue::from_static("chunked"),
+        );
+        headers.insert(header::ACCEPT, HeaderValue::from_static("*/*"));
+
+        remove_hop_by_hop_headers(&mut headers);
+
+        assert_eq!(headers.len(), 1);
+        assert!(headers.contains_key(header::ACCEPT));
+    }
+
+    struct NotFoundHandler;
+
+    #[async_trait]
+    impl RequestHandler for NotFoundHandler {
+        async fn handle(&self, _request: &HttpRequest) -> Response<ResponseBody> {
+            build_status_code_response(StatusCode::NOT_FOUND, CacheControl::NoCache)
+        }
+    }
+
+    // Serves one HTTP/1 connection proxying /api to upstream_addr.
+    async fn start_proxy(upstream_addr: SocketAddr) -> SocketAddr {
+        start_proxy_route(proxy_test_route(upstream_addr)).await
+    }
+
+    fn proxy_test_route(upstream_addr: SocketAddr) -> ProxyRouteEntry {
+        let mut route = route_entry("/api", &format!("http://{}/", upstream_addr));
+        // longer than the tests wait, so the timeout never ends a request
+        route.timeout = Duration::from_secs(60);
+        route
+    }
+
+    async fn start_proxy_route(route: ProxyRouteEntry) -> SocketAddr {
+        let client = build_client(
+            &UpstreamBinding {
+                local_address: None,
+                interface: None,
+            },
+            &CachingResolver::new(&ProxyDnsConfiguration::default()),
+            Duration::from_secs(10),
+        )
+        .unwrap();
+
+        let handler = Arc::new(ProxyHandler {
+            routes: vec![(route, client)],
+            request_id_header: None,
+            next: Box::new(NotFoundHandler),
+        });
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy_addr = listener.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            let (stream, peer_addr) = listener.accept().await.unwrap();
+
+            let service = service_fn(move |hyper_request: Request<Incoming>| {
+                let handler = Arc::clone(&handler);
+
+                async move {
+                    let request = HttpRequest::new(
+                        ConnectionID::new_for_test(1),
+                        RequestID::new_for_test(1),
+                        hyper_request,
+                        1024 * 1024,
+                        Arc::new(SocketMetadata {
+                            peer_addr: Some(peer_addr),
+                            ..Default::default()
+                        }),
+                        Some(peer_addr.ip()),
+                        CancellationToken::new(),
+                    );
+
+                    if let Some(deadline) = RequestDeadline::new(
+                        request.hyper_request.headers(),
+                        tokio::time::Instant::now(),
+                        None,
+                    ) {
+                        request.insert_extension(deadline);
+                    }
+
+                    Ok::<_, Infallible>(handler.handle(&request).await)
+                }
+            });
+
+            let _ = http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), service)
+                .await;
+        });
+
+        proxy_addr
+    }
+
+    // Reads from stream until the bytes read so far end with end, returns them.
+    async fn read_until(stream: &mut (impl AsyncRead + Unpin), end: &[u8]) -> Vec<u8> {
+        let mut received = Vec::new();
+        let mut buffer = [0; 4096];
+
+        while !received.ends_with(end) {
+            let bytes_read = stream.read(&mut buffer).await.unwrap();
+            assert_ne!(bytes_read, 0, "unexpected EOF");
+            received.extend_from_slice(&buffer[..bytes_read]);
+        }
+
+        received
+    }
+
+    #[tokio::test]
+    async fn test_via_header() {
+        let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy_addr = start_proxy(upstream.local_addr().unwrap()).await;
+
+        let upstream_task = tokio::spawn(async move {
+            let (mut stream, _) = upstream.accept().await.unwrap();
+            let request_head = read_until(&mut stream, b"\r\n\r\n").await;
+
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\nVia: 1.1 origin\r\nContent-Length: 0\r\n\r\n")
+                .await
+                .unwrap();
+
+            String::from_utf8(request_head).unwrap().to_lowercase()
+        });
+
+        let stream = TcpStream::connect(proxy_addr).await.unwrap();
+        let (mut sender, connection) = hyper::client::conn::http1::handshake(TokioIo::new(stream))
+            .await
+            .unwrap();
+        tokio::spawn(connection);
+
+        let response = sender
+            .send_request(
+                Request::get("/api/hello")
+                    .header(header::HOST, "test")
+                    .header(header::VIA, "1.0 edge")
+                    .body(Empty::<Bytes>::new())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.headers()[header::VIA], "1.1 origin, 1.1 rhs");
+
+        let request_head = upstream_task.await.unwrap();
+        assert!(
+            request_head.contains("\r\nvia: 1.0 edge, 1.1 rhs\r\n"),
+            "{}",
+            request_head
+        );
+    }
+
+    // Answers every request with status, sending each request's path and body.
+    async fn start_recording_upstream(
+        status: StatusCode,
+    ) -> (
+        SocketAddr,
+        tokio::sync::mpsc::UnboundedReceiver<(String, Bytes)>,
+    ) {
+        let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_addr = upstream.local_addr().unwrap();
+
+        let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = upstream.accept().await.unwrap();
+                let sender = sender.clone();
+
+                tokio::spawn(async move {
+                    let service = service_fn(move |request: Request<Incoming>| {
+                        let sender = sender.clone();
+                        async move {
+                            let path = request.uri().path().to_owned();
+                            let body = request.into_body().collect().await.unwrap().to_bytes();
+                            let _ = sender.send((path, body));
+
+                            Ok::<_, Infallible>(
+                                Response::builder()
+                                    .status(status)
+                                    .body(Empty::<Bytes>::new())
+                                    .unwrap(),
+                            )
+                        }
+                    });
+
+                    let _ = http1::Builder::new()
+                        .serve_connection(TokioIo::new(stream), service)
+                        .await;
+                });
+            }
+        });
+
+        (upstream_addr, receiver)
+    }
+
+    #[tokio::test]
+    async fn test_circuit_breaker_cuts_off_failing_upstream() {
+        let (upstream_addr, mut upstream_requests) =
+            start_recording_upstream(StatusCode::BAD_GATEWAY).await;
+
+        let mut route = proxy_test_route(upstream_addr);
+        route.circuit_breaker = Some(Arc::new(CircuitBreaker::new(
+            "test".to_owned(),
+            &ProxyCircuitBreaker {
+                consecutive_failures: Some(2),
+                error_rate_percent: None,
+                window_requests: 20,
+                open_duration: Duration::from_secs(60),
+            },
+        )));
+        let proxy_addr = start_proxy_route(route).await;
+
+        let stream = TcpStream::connect(proxy_addr).await.unwrap();
+        let (mut sender, connection) = hyper::client::conn::http1::handshake(TokioIo::new(stream))
+            .await
+            .unwrap();
+        tokio::spawn(connection);
+
+        let mut statuses = Vec::new();
+        for _ in 0..3 {
+            sender.ready().await.unwrap();
+            let response = sender
+                .send_request(
+                    Request::get("/api/hello")
+                        .header(header::HOST, "test")
+                        .body(Empty::<Bytes>::new())
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            statuses.push(response.status());
+            response.collect().await.unwrap();
+        }
+
+        assert_eq!(
+            statuses,
+            [
+                StatusCode::BAD_GATEWAY,
+                StatusCode::BAD_GATEWAY,
+                StatusCode::SERVICE_UNAVAILABLE
+            ]
+        );
+        let mut num_upstream_requests = 0;
+        while upstream_requests.try_recv().is_ok() {
+            num_upstream_requests += 1;
+        }
+        assert_eq!(num_upstream_requests, 2);
+    }
+
+    #[tokio::test]
+    async fn test_mirror_request() {
+        let (upstream_addr, mut upstream_requests) = start_recording_upstream(StatusCode::OK).await;
+        let (mirror_addr, mut mirror_requests) =
+            start_recording_upstream(StatusCode::INTERNAL_SERVER_ERROR).await;
+
+        let mut route = proxy_test_route(upstream_addr);
+        route.mirror = Some(MirrorEntry {
+            upstream_url: parse_upstream_url(&format!("http://{}/shadow/", mirror_addr)).unwrap(),
+            fraction: 1.0,
+        });
+        let proxy_addr = start_proxy_route(route).await;
+
+        let stream = TcpStream::connect(proxy_addr).await.unwrap();
+        let (mut sender, connection) = hyper::client::conn::http1::handshake(TokioIo::new(stream))
+            .await
+            .unwrap();
+        tokio::spawn(connection);
+
+        let response = sender
+            .send_request(
+                Request::post("/api/upload")
+                    .header(header::HOST, "test")
+                    .body(http_body_util::Full::new(Bytes::from_static(
+                        b"request body",
+                    )))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        // the mirror's response is discarded
+        assert_eq!(response.status(), StatusCode::OK);
+
+        assert_eq!(
+            upstream_requests.recv().await.unwrap(),
+            ("/upload".to_owned(), Bytes::from_static(b"request body"))
+        );
+
+        let mirror_request = tokio::time::timeout(Duration::from_secs(5), mirror_requests.recv())
+            .await
+            .expect("request was not mirrored")
+            .unwrap();
+        assert_eq!(
+            mirror_request,
+            (
+                "/shadow/upload".to_owned(),
+                Bytes::from_static(b"request body")
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn test_request_deadline_limits_upstream_timeout() {
+        let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy_addr = start_proxy(upstream.local_addr().unwrap()).await;
+
+        // reads the request head and never answers
+        let upstream_task = tokio::spawn(async move {
+            let (mut stream, _) = upstream.accept().await.unwrap();
+            let request_head = read_until(&mut stream, b"\r\n\r\n").await;
+            (
+                stream,
+                String::from_utf8(request_head).unwrap().to_lowercase(),
+            )
+        });
+
+        let stream = TcpStream::connect(proxy_addr).await.unwrap();
+        let (mut sender, connection) = hyper::client::conn::http1::handshake(TokioIo::new(stream))
+            .await
+            .unwrap();
+        tokio::spawn(connection);
+
+        let response = tokio::time::timeout(
+            Duration::from_secs(5),
+            sender.send_request(
+                Request::post("/api/service/Method")
+                    .header(header::HOST, "test")
+                    .header(header::CONTENT_TYPE, "application/grpc")
+                    .header(GRPC_TIMEOUT, "200m")
+                    .body(Empty::<Bytes>::new())
+                    .unwrap(),
+            ),
+        )
+        .await
+        .expect("route timeout was not cut short by the deadline")
+        .unwrap();
+
+        assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+
+        let (_stream, request_head) = upstream_task.await.unwrap();
+
+        let upstream_timeout = request_head
+            .lines()
+            .find_map(|line| line.strip_prefix("grpc-timeout: "))
+            .and_then(crate::request::parse_grpc_timeout)
+            .unwrap();
+        assert!(upstream_timeout <= Duration::from_millis(200));
+        assert!(upstream_timeout > Duration::ZERO);
+    }
+
+    #[tokio::test]
+    async fn test_client_abort_cancels_upstream_request() {
+        let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy_addr = start_proxy(upstream.local_addr().unwrap()).await;
+
+        let mut client = TcpStream::connect(proxy_addr).await.unwrap();
+        client
+            .write_all(
+                b"POST /api/upload HTTP/1.1\r\nHost: test\r\nContent-Length: 1000\r\n\r\npartial body",
+            )
+            .await
+            .unwrap();
+
+        let (mut upstream_stream, _) = upstream.accept().await.unwrap();
+        read_until(&mut upstream_stream, b"partial body").await;
+
+        drop(client);
+
+        // the upstream connection is closed rather than left waiting for the
+        // rest of the body
+        let mut buffer = [0; 4096];
+        let result =
+            tokio::time::timeout(Duration::from_secs(5), upstream_stream.read(&mut buffer))
+                .await
+                .expect("upstream request was not cancelled");
+        assert!(matches!(result, Ok(0) | Err(_)), "{:?}", result);
+    }
+
+    #[tokio::test]
+    async fn test_upstream_abort_fails_client_response() {
+        let upstream_heads: [&[u8]; 2] = [
+            b"HTTP/1.1 200 OK\r\nContent-Length: 1000\r\n\r\npartial body",
+            b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\nc\r\npartial body\r\n",
+        ];
+
+        for upstream_head in upstream_heads {
+            let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let proxy_addr = start_proxy(upstream.local_addr().unwrap()).await;
+
+            let (reset_sender, reset_receiver) = oneshot::channel::<()>();
+
+            tokio::spawn(async move {
+                let (mut stream, _) = upstream.accept().await.unwrap();
+                read_until(&mut stream, b"\r\n\r\n").await;
+
+                stream.write_all(upstream_head).await.unwrap();
+
+                // reset once the client has the response head
+                let _ = reset_receiver.await;
+                socket2::SockRef::from(&stream)
+                    .set_linger(Some(Duration::ZERO))
+                    .unwrap();
+            });
+
+            let stream = TcpStream::connect(proxy_addr).await.unwrap();
+            let (mut sender, connection) =
+                hyper::client::conn::http1::handshake(TokioIo::new(stream))
+                    .await
+                    .unwrap();
+            tokio::spawn(connection);
+
+            let response = sender
+                .send_request(
+                    Request::get("/api/download")
+                        .header(header::HOST, "test")
+                        .body(Empty::<Bytes>::new())
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+
+            assert_eq!(response.status(), StatusCode::OK);
+
+            reset_sender.send(()).unwrap();
+
+            let result = tokio::time::timeout(Duration::from_secs(5), response.collect())
+                .await
+                .expect("client response was not ended");
+            assert!(result.is_err(), "truncated response completed");
+        }
+    }
+}
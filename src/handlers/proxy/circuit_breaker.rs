@@ -0,0 +1,238 @@
+use schemars::JsonSchema;
+
+use serde::Serialize;
+
+use tokio::time::Instant;
+
+use tracing::warn;
+
+use std::{collections::VecDeque, sync::Mutex};
+
+use crate::config::ProxyCircuitBreaker;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, JsonSchema, Serialize)]
+pub enum CircuitState {
+    #[serde(rename = "CLOSED")]
+    Closed,
+
+    #[serde(rename = "OPEN")]
+    Open,
+
+    #[serde(rename = "HALF_OPEN")]
+    HalfOpen,
+}
+
+#[derive(Debug)]
+enum State {
+    Closed,
+    Open { since: Instant },
+    // a probe started before open_duration ago is still outstanding
+    HalfOpen { probe_start: Instant },
+}
+
+#[derive(Debug)]
+struct Outcomes {
+    state: State,
+    consecutive_failures: u32,
+    // true for failures, newest last
+    window: VecDeque<bool>,
+    times_opened: u64,
+}
+
+#[derive(Debug, JsonSchema, Serialize)]
+pub struct CircuitBreakerSnapshot {
+    pub state: CircuitState,
+    pub consecutive_failures: u32,
+    pub window_requests: usize,
+    pub window_failures: usize,
+    pub times_opened: u64,
+}
+
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    name: String,
+    configuration: ProxyCircuitBreaker,
+    outcomes: Mutex<Outcomes>,
+}
+
+impl CircuitBreaker {
+    pub fn new(name: String, configuration: &ProxyCircuitBreaker) -> Self {
+        Self {
+            name,
+            configuration: configuration.clone(),
+            outcomes: Mutex::new(Outcomes {
+                state: State::Closed,
+                consecutive_failures: 0,
+                window: VecDeque::with_capacity(configuration.window_requests),
+                times_opened: 0,
+            }),
+        }
+    }
+
+    // False while the upstream is cut off.
+    pub fn allow(&self, now: Instant) -> bool {
+        let mut outcomes = self.outcomes.lock().unwrap();
+
+        match outcomes.state {
+            State::Closed => true,
+            State::Open { since } | State::HalfOpen { probe_start: since }
+                if now.duration_since(since) < self.configuration.open_duration =>
+            {
+                false
+            }
+            State::Open { .. } | State::HalfOpen { .. } => {
+                outcomes.state = State::HalfOpen { probe_start: now };
+                true
+            }
+        }
+    }
+
+    pub fn record(&self, success: bool, now: Instant) {
+        let mut outcomes = self.outcomes.lock().unwrap();
+
+        if outcomes.window.len() == self.configuration.window_requests {
+            outcomes.window.pop_front();
+        }
+        outcomes.window.push_back(!success);
+
+        outcomes.consecutive_failures = if success {
+            0
+        } else {
+            outcomes.consecutive_failures.saturating_add(1)
+        };
+
+        let open = match outcomes.state {
+            State::HalfOpen { .. } if success => {
+                outcomes.state = State::Closed;
+                outcomes.window.clear();
+                false
+            }
+            State::HalfOpen { .. } => true,
+            State::Closed => !success && self.over_threshold(&outcomes),
+            // outcomes of requests let through before the breaker opened
+            State::Open { .. } => false,
+        };
+
+        if open {
+            warn!(
+                "circuit breaker {} open for {:?}",
+                self.name, self.configuration.open_duration
+            );
+            outcomes.state = State::Open { since: now };
+            outcomes.times_opened += 1;
+        }
+    }
+
+    fn over_threshold(&self, outcomes: &Outcomes) -> bool {
+        let consecutive_over = self
+            .configuration
+            .consecutive_failures
+            .is_some_and(|threshold| outcomes.consecutive_failures >= threshold);
+
+        let window_full = outcomes.window.len() == self.configuration.window_requests;
+
+        let rate_over = self
+            .configuration
+            .error_rate_percent
+            .is_some_and(|percent| {
+                let failures = outcomes.window.iter().filter(|failure| **failure).count();
+                window_full && failures as f64 * 100.0 >= percent * outcomes.window.len() as f64
+            });
+
+        consecutive_over || rate_over
+    }
+
+    pub fn snapshot(&self) -> CircuitBreakerSnapshot {
+        let outcomes = self.outcomes.lock().unwrap();
+
+        CircuitBreakerSnapshot {
+            state: match outcomes.state {
+                State::Closed => CircuitState::Closed,
+                State::Open { .. } => CircuitState::Open,
+                State::HalfOpen { .. } => CircuitState::HalfOpen,
+            },
+            consecutive_failures: outcomes.consecutive_failures,
+            window_requests: outcomes.window.len(),
+            window_failures: outcomes.window.iter().filter(|failure| **failure).count(),
+            times_opened: outcomes.times_opened,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use tokio::time::Duration;
+
+    fn circuit_breaker(
+        consecutive_failures: Option<u32>,
+        error_rate_percent: Option<f64>,
+    ) -> CircuitBreaker {
+        CircuitBreaker::new(
+            "test".to_owned(),
+            &ProxyCircuitBreaker {
+                consecutive_failures,
+                error_rate_percent,
+                window_requests: 4,
+                open_duration: Duration::from_secs(10),
+            },
+        )
+    }
+
+    #[test]
+    fn test_consecutive_failures() {
+        let breaker = circuit_breaker(Some(3), None);
+        let now = Instant::now();
+
+        for _ in 0..2 {
+            breaker.record(false, now);
+        }
+        breaker.record(true, now);
+        for _ in 0..2 {
+            breaker.record(false, now);
+        }
+        assert!(breaker.allow(now));
+
+        breaker.record(false, now);
+        assert_eq!(breaker.snapshot().state, CircuitState::Open);
+        assert!(!breaker.allow(now + Duration::from_secs(9)));
+
+        // one probe at a time, a failed probe opens the breaker again
+        let probe = now + Duration::from_secs(10);
+        assert!(breaker.allow(probe));
+        assert_eq!(breaker.snapshot().state, CircuitState::HalfOpen);
+        assert!(!breaker.allow(probe));
+        breaker.record(false, probe);
+        assert_eq!(breaker.snapshot().state, CircuitState::Open);
+        assert_eq!(breaker.snapshot().times_opened, 2);
+
+        let probe = probe + Duration::from_secs(10);
+        assert!(breaker.allow(probe));
+        breaker.record(true, probe);
+        assert_eq!(breaker.snapshot().state, CircuitState::Closed);
+        assert!(breaker.allow(probe));
+    }
+
+    #[test]
+    fn test_error_rate() {
+        let breaker = circuit_breaker(None, Some(50.0));
+        let now = Instant::now();
+
+        // not judged until the window is full
+        breaker.record(false, now);
+        breaker.record(false, now);
+        breaker.record(true, now);
+        assert_eq!(breaker.snapshot().state, CircuitState::Closed);
+
+        breaker.record(true, now);
+        breaker.record(false, now);
+        assert_eq!(breaker.snapshot().state, CircuitState::Open);
+
+        // a probe left without an outcome is replaced after open_duration
+        let probe = now + Duration::from_secs(10);
+        assert!(breaker.allow(probe));
+        assert!(!breaker.allow(probe + Duration::from_secs(5)));
+        assert!(breaker.allow(probe + Duration::from_secs(10)));
+    }
+}
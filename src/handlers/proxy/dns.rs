@@ -0,0 +1,202 @@
+use hyper_util::client::legacy::connect::dns::Name;
+
+use tokio::time::{Duration, Instant};
+
+use tracing::{debug, warn};
+
+use std::{
+    collections::HashMap,
+    future::Future,
+    net::SocketAddr,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+
+use crate::config::{ProxyDnsConfiguration, ProxyDnsStrategy};
+
+#[derive(Debug)]
+struct CachedAddrs {
+    addrs: Arc<[SocketAddr]>,
+    resolved_time: Instant,
+    // round robin position
+    next_index: usize,
+}
+
+#[derive(Debug)]
+struct ResolverState {
+    refresh_interval: Duration,
+    strategy: ProxyDnsStrategy,
+    cache: Mutex<HashMap<Box<str>, CachedAddrs>>,
+}
+
+fn rotate_addrs(addrs: &[SocketAddr], start: usize) -> Vec<SocketAddr> {
+    let start = if addrs.is_empty() {
+        0
+    } else {
+        start % addrs.len()
+    };
+
+    addrs[start..]
+        .iter()
+        .chain(&addrs[..start])
+        .copied()
+        .collect()
+}
+
+impl ResolverState {
+    fn ordered_addrs(&self, cached_addrs: &mut CachedAddrs) -> Vec<SocketAddr> {
+        match self.strategy {
+            ProxyDnsStrategy::First => cached_addrs.addrs.to_vec(),
+            ProxyDnsStrategy::RoundRobin => {
+                let start = cached_addrs.next_index;
+                cached_addrs.next_index = cached_addrs.next_index.wrapping_add(1);
+                rotate_addrs(&cached_addrs.addrs, start)
+            }
+        }
+    }
+
+    fn cached(&self, host: &str, allow_stale: bool) -> Option<Vec<SocketAddr>> {
+        let mut cache = self.cache.lock().unwrap();
+
+        let cached_addrs = cache.get_mut(host)?;
+
+        if !allow_stale && cached_addrs.resolved_time.elapsed() >= self.refresh_interval {
+            return None;
+        }
+
+        Some(self.ordered_addrs(cached_addrs))
+    }
+
+    async fn resolve(&self, host: &str) -> std::io::Result<Vec<SocketAddr>> {
+        if let Some(addrs) = self.cached(host, false) {
+            return Ok(addrs);
+        }
+
+        // the connector sets the upstream port
+        let result = tokio::net::lookup_host((host, 0))
+            .await
+            .map(|addrs| addrs.collect::<Arc<[SocketAddr]>>());
+
+        match result {
+            Ok(addrs) if !addrs.is_empty() => {
+                debug!("resolved upstream host = {:?} addrs = {:?}", host, addrs);
+
+                let mut cache = self.cache.lock().unwrap();
+
+                let cached_addrs = cache.entry(host.into()).or_insert_with(|| CachedAddrs {
+                    addrs: Arc::clone(&addrs),
+                    resolved_time: Instant::now(),
+                    next_index: 0,
+                });
+
+                cached_addrs.addrs = addrs;
+                cached_addrs.resolved_time = Instant::now();
+
+                Ok(self.ordered_addrs(cached_addrs))
+            }
+            result => {
+                let e = match result {
+                    Err(e) => e,
+                    Ok(_) => std::io::Error::new(
+                        std::io::ErrorKind::NotFound,
+                        format!("no addresses for {}", host),
+                    ),
+                };
+
+                // keep using the last good addresses until a lookup succeeds
+                match self.cached(host, true) {
+                    Some(addrs) => {
+                        warn!(
+                            "upstream host = {:?} lookup error, using stale addresses: {}",
+                            host, e
+                        );
+                        Ok(addrs)
+                    }
+                    None => Err(e),
+                }
+            }
+        }
+    }
+}
+
+// Resolver for the proxy's HttpConnector.  Caches each upstream host's
+// addresses for refresh_interval, so upstream address changes are picked up
+// by new connections without a restart.
+#[derive(Clone, Debug)]
+pub struct CachingResolver {
+    state: Arc<ResolverState>,
+}
+
+impl CachingResolver {
+    pub fn new(dns_configuration: &ProxyDnsConfiguration) -> Self {
+        Self {
+            state: Arc::new(ResolverState {
+                refresh_interval: dns_configuration.refresh_interval,
+                strategy: dns_configuration.strategy,
+                cache: Mutex::new(HashMap::new()),
+            }),
+        }
+    }
+}
+
+impl tower_service::Service<Name> for CachingResolver {
+    type Response = std::vec::IntoIter<SocketAddr>;
+    type Error = std::io::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, name: Name) -> Self::Future {
+        let state = Arc::clone(&self.state);
+
+        Box::pin(async move {
+            state
+                .resolve(name.as_str())
+                .await
+                .map(|addrs| addrs.into_iter())
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_round_robin() {
+        let resolver = CachingResolver::new(&ProxyDnsConfiguration {
+            refresh_interval: Duration::from_secs(60),
+            strategy: ProxyDnsStrategy::RoundRobin,
+        });
+
+        let addrs: Vec<SocketAddr> = vec![
+            "10.0.0.1:0".parse().unwrap(),
+            "10.0.0.2:0".parse().unwrap(),
+            "10.0.0.3:0".parse().unwrap(),
+        ];
+
+        resolver.state.cache.lock().unwrap().insert(
+            "upstream".into(),
+            CachedAddrs {
+                addrs: addrs.clone().into(),
+                resolved_time: Instant::now(),
+                next_index: 0,
+            },
+        );
+
+        assert_eq!(resolver.state.resolve("upstream").await.unwrap(), addrs);
+        assert_eq!(
+            resolver.state.resolve("upstream").await.unwrap(),
+            vec![addrs[1], addrs[2], addrs[0]]
+        );
+        assert_eq!(
+            resolver.state.resolve("upstream").await.unwrap(),
+            vec![addrs[2], addrs[0], addrs[1]]
+        );
+
+        assert_eq!(rotate_addrs(&[], 5), vec![]);
+    }
+}
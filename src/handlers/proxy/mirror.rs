@@ -0,0 +1,179 @@
+use bytes::Bytes;
+
+use http_body_util::BodyExt;
+
+use hyper::{
+    body::{Body, Frame, SizeHint},
+    http::{Request, Uri},
+};
+
+use tokio::sync::mpsc;
+
+use tracing::debug;
+
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use super::{ProxyClient, ProxyRequestBody};
+
+// Request body frames buffered for a mirror, a mirror further behind is
+// abandoned rather than slowing the request.
+const MIRROR_BODY_FRAMES: usize = 16;
+
+#[derive(Debug, thiserror::Error)]
+#[error("mirror request body abandoned")]
+struct MirrorBodyAbandoned;
+
+// None marks the end of the body, the channel closing without it abandons
+// the mirror request.
+type MirrorFrame = Option<Frame<Bytes>>;
+
+// The request body, copying each frame to a mirror.
+struct TeeBody {
+    inner: ProxyRequestBody,
+    sender: Option<mpsc::Sender<MirrorFrame>>,
+}
+
+impl TeeBody {
+    fn send(&mut self, frame: MirrorFrame) {
+        if let Some(sender) = &self.sender {
+            if sender.try_send(frame).is_err() {
+                debug!("mirror request body abandoned");
+                self.sender = None;
+            }
+        }
+    }
+}
+
+impl Body for TeeBody {
+    type Data = Bytes;
+    type Error = Box<dyn std::error::Error + Send + Sync>;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, Self::Error>>> {
+        let result = Pin::new(&mut self.inner).poll_frame(cx);
+
+        match &result {
+            Poll::Ready(Some(Ok(frame))) => {
+                let frame = match (frame.data_ref(), frame.trailers_ref()) {
+                    (Some(data), _) => Some(Frame::data(data.clone())),
+                    (None, Some(trailers)) => Some(Frame::trailers(trailers.clone())),
+                    (None, None) => None,
+                };
+                if let Some(frame) = frame {
+                    self.send(Some(frame));
+                }
+            }
+            Poll::Ready(None) => {
+                self.send(None);
+                self.sender = None;
+            }
+            Poll::Ready(Some(Err(_))) => self.sender = None,
+            Poll::Pending => {}
+        }
+
+        result
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+struct MirrorBody {
+    receiver: mpsc::Receiver<MirrorFrame>,
+    size_hint: SizeHint,
+    ended: bool,
+}
+
+impl Body for MirrorBody {
+    type Data = Bytes;
+    type Error = Box<dyn std::error::Error + Send + Sync>;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, Self::Error>>> {
+        if self.ended {
+            return Poll::Ready(None);
+        }
+
+        match self.receiver.poll_recv(cx) {
+            Poll::Ready(Some(Some(frame))) => Poll::Ready(Some(Ok(frame))),
+            Poll::Ready(Some(None)) => {
+                self.ended = true;
+                Poll::Ready(None)
+            }
+            Poll::Ready(None) => Poll::Ready(Some(Err(Box::new(MirrorBodyAbandoned)))),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.ended
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.size_hint
+    }
+}
+
+#[derive(Debug)]
+pub struct MirrorEntry {
+    pub upstream_url: Uri,
+    pub fraction: f64,
+}
+
+// Sends a copy of upstream_request to mirror_uri and discards the response,
+// returns upstream_request with its body also feeding the copy.
+pub fn spawn_mirror_request(
+    client: &ProxyClient,
+    mirror_uri: Uri,
+    timeout: Duration,
+    upstream_request: Request<ProxyRequestBody>,
+) -> Request<ProxyRequestBody> {
+    let (parts, body) = upstream_request.into_parts();
+
+    let (sender, receiver) = mpsc::channel(MIRROR_BODY_FRAMES);
+
+    // hyper does not poll bodies that start ended
+    let mirror_body = MirrorBody {
+        receiver,
+        size_hint: body.size_hint(),
+        ended: body.is_end_stream(),
+    };
+
+    let mut mirror_request = Request::new(mirror_body.boxed());
+    *mirror_request.method_mut() = parts.method.clone();
+    *mirror_request.uri_mut() = mirror_uri;
+    *mirror_request.version_mut() = parts.version;
+    *mirror_request.headers_mut() = parts.headers.clone();
+
+    let client = client.clone();
+
+    tokio::spawn(async move {
+        let uri = mirror_request.uri().clone();
+
+        match tokio::time::timeout(timeout, client.request(mirror_request)).await {
+            Ok(Ok(response)) => debug!("mirror {:?} status {}", uri, response.status()),
+            Ok(Err(e)) => debug!("mirror {:?} error: {}", uri, e),
+            Err(_) => debug!("mirror {:?} timeout after {:?}", uri, timeout),
+        }
+    });
+
+    let body = TeeBody {
+        inner: body,
+        sender: Some(sender),
+    };
+
+    Request::from_parts(parts, body.boxed())
+}
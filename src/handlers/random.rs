@@ -0,0 +1,23 @@
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+};
+
+// Uniform in [0, 1), RandomState is seeded differently on every call.
+pub fn random_fraction() -> f64 {
+    let random = RandomState::new().build_hasher().finish();
+
+    (random >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_random_fraction() {
+        assert!((0..1000)
+            .map(|_| random_fraction())
+            .all(|fraction| (0.0..1.0).contains(&fraction)));
+    }
+}
@@ -0,0 +1,381 @@
+use async_trait::async_trait;
+
+use hyper::http::{header, HeaderMap, HeaderValue, Response, StatusCode};
+
+use tokio::time::{Duration, Instant};
+
+use tracing::{debug, warn};
+
+use std::{collections::HashMap, net::IpAddr, sync::Mutex};
+
+use crate::{
+    config::{ClientRateLimitConfiguration, PrincipalRateLimit},
+    handlers::{authorization, HttpRequest, RequestHandler, ResponseBody},
+    response::{build_deny_response, DenyReason},
+};
+
+struct RateLimitDecision {
+    allowed: bool,
+    limit: u32,
+    remaining: u32,
+    reset: Duration,
+}
+
+impl RateLimitDecision {
+    fn insert_headers(&self, headers: &mut HeaderMap) {
+        // whole seconds until the window resets, rounded up
+        let reset_seconds = self.reset.as_secs() + u64::from(self.reset.subsec_nanos() > 0);
+
+        headers.insert("x-ratelimit-limit", HeaderValue::from(self.limit));
+        headers.insert("x-ratelimit-remaining", HeaderValue::from(self.remaining));
+        headers.insert("x-ratelimit-reset", HeaderValue::from(reset_seconds));
+
+        if !self.allowed {
+            headers.insert(header::RETRY_AFTER, HeaderValue::from(reset_seconds));
+        }
+    }
+}
+
+// Fixed window counter for one principal.
+struct RateLimitWindow {
+    start: Instant,
+    count: u32,
+}
+
+impl RateLimitWindow {
+    fn acquire(&mut self, now: Instant, limit: u32, period: Duration) -> RateLimitDecision {
+        if now.duration_since(self.start) >= period {
+            self.start = now;
+            self.count = 0;
+        }
+
+        let allowed = self.count < limit;
+        if allowed {
+            self.count += 1;
+        }
+
+        RateLimitDecision {
+            allowed,
+            limit,
+            remaining: limit - self.count,
+            reset: period.saturating_sub(now.duration_since(self.start)),
+        }
+    }
+}
+
+// Per principal request quotas from rate_limit_configuration.  Principals are
+// matched by bearer token, then by unix peer uid; other requests pass through.
+pub struct PrincipalRateLimitHandler {
+    principals: &'static [PrincipalRateLimit],
+    windows: Mutex<Vec<RateLimitWindow>>,
+    next: Box<dyn RequestHandler>,
+}
+
+impl PrincipalRateLimitHandler {
+    pub fn new(next: Box<dyn RequestHandler>) -> Self {
+        let principals = &crate::config::instance()
+            .rate_limit_configuration
+            .principals;
+
+        debug!("rate limit principals = {:?}", principals);
+
+        let now = Instant::now();
+
+        Self {
+            principals,
+            windows: Mutex::new(
+                principals
+                    .iter()
+                    .map(|_| RateLimitWindow {
+                        start: now,
+                        count: 0,
+                    })
+                    .collect(),
+            ),
+            next,
+        }
+    }
+
+    fn find_principal(&self, request: &HttpRequest) -> Option<usize> {
+        let token = authorization::bearer_token(
+            request
+                .hyper_request
+                .headers()
+                .get(header::AUTHORIZATION)
+                .and_then(|value| value.to_str().ok()),
+        );
+
+        if let Some(token) = token {
+            let index = self.principals.iter().position(|principal| {
+                principal.bearer_token.as_ref().is_some_and(|bearer_token| {
+                    authorization::constant_time_eq(bearer_token.as_bytes(), token.as_bytes())
+                })
+            });
+
+            if index.is_some() {
+                return index;
+            }
+        }
+
+        let uid = request.peer_credentials()?.uid;
+
+        self.principals
+            .iter()
+            .position(|principal| principal.uid == Some(uid))
+    }
+}
+
+#[async_trait]
+impl RequestHandler for PrincipalRateLimitHandler {
+    async fn handle(&self, request: &HttpRequest) -> Response<ResponseBody> {
+        if self.principals.is_empty() {
+            return self.next.handle(request).await;
+        }
+
+        let Some(index) = self.find_principal(request) else {
+            return self.next.handle(request).await;
+        };
+
+        let principal = &self.principals[index];
+
+        let decision = self.windows.lock().unwrap()[index].acquire(
+            Instant::now(),
+            principal.requests,
+            principal.period,
+        );
+
+        let mut response = if decision.allowed {
+            self.next.handle(request).await
+        } else {
+            warn!("rate limit exceeded principal = {:?}", principal.name);
+            build_deny_response(StatusCode::TOO_MANY_REQUESTS, DenyReason::RateLimit)
+        };
+
+        decision.insert_headers(response.headers_mut());
+
+        response
+    }
+}
+
+// How often buckets refilled to burst are dropped, they are the same as new ones.
+const BUCKET_PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+struct TokenBucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    fn new(now: Instant, burst: u32) -> Self {
+        Self {
+            tokens: f64::from(burst),
+            updated: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant, requests_per_second: f64, burst: u32) {
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+
+        self.tokens = (self.tokens + elapsed * requests_per_second).min(f64::from(burst));
+        self.updated = now;
+    }
+
+    // Err with the time until a token is available.
+    fn acquire(
+        &mut self,
+        now: Instant,
+        requests_per_second: f64,
+        burst: u32,
+    ) -> Result<(), Duration> {
+        self.refill(now, requests_per_second, burst);
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            // requests_per_second is validated to be positive, a bucket that
+            // never refills would otherwise overflow the Duration
+            Err(
+                Duration::try_from_secs_f64((1.0 - self.tokens) / requests_per_second)
+                    .unwrap_or(Duration::MAX),
+            )
+        }
+    }
+}
+
+struct ClientBuckets {
+    buckets: HashMap<(IpAddr, usize), TokenBucket>,
+    last_prune: Instant,
+}
+
+// Token bucket per client address from rate_limit_configuration.per_client,
+// with separate buckets for the configured route prefixes.
+pub struct ClientRateLimitHandler {
+    configuration: Option<&'static ClientRateLimitConfiguration>,
+    client_buckets: Mutex<ClientBuckets>,
+    next: Box<dyn RequestHandler>,
+}
+
+impl ClientRateLimitHandler {
+    pub fn new(next: Box<dyn RequestHandler>) -> Self {
+        let configuration = crate::config::instance()
+            .rate_limit_configuration
+            .per_client
+            .as_ref();
+
+        debug!("client rate limit configuration = {:?}", configuration);
+
+        Self {
+            configuration,
+            client_buckets: Mutex::new(ClientBuckets {
+                buckets: HashMap::new(),
+                last_prune: Instant::now(),
+            }),
+            next,
+        }
+    }
+
+    // (index, requests_per_second, burst), routes.len() for the default rate.
+    fn find_rate(configuration: &ClientRateLimitConfiguration, path: &str) -> (usize, f64, u32) {
+        configuration
+            .routes
+            .iter()
+            .enumerate()
+            .find(|(_, route)| path.starts_with(&route.path_prefix))
+            .map_or(
+                (
+                    configuration.routes.len(),
+                    configuration.requests_per_second,
+                    configuration.burst,
+                ),
+                |(index, route)| (index, route.requests_per_second, route.burst),
+            )
+    }
+
+    fn acquire(
+        &self,
+        configuration: &ClientRateLimitConfiguration,
+        client: IpAddr,
+        path: &str,
+    ) -> Result<(), Duration> {
+        let (index, requests_per_second, burst) = Self::find_rate(configuration, path);
+
+        let now = Instant::now();
+
+        let mut client_buckets = self.client_buckets.lock().unwrap();
+
+        if now.duration_since(client_buckets.last_prune) >= BUCKET_PRUNE_INTERVAL {
+            client_buckets.last_prune = now;
+            client_buckets.buckets.retain(|(_, index), bucket| {
+                let (requests_per_second, burst) = configuration.routes.get(*index).map_or(
+                    (configuration.requests_per_second, configuration.burst),
+                    |route| (route.requests_per_second, route.burst),
+                );
+                bucket.refill(now, requests_per_second, burst);
+                bucket.tokens < f64::from(burst)
+            });
+        }
+
+        client_buckets
+            .buckets
+            .entry((client, index))
+            .or_insert_with(|| TokenBucket::new(now, burst))
+            .acquire(now, requests_per_second, burst)
+    }
+}
+
+#[async_trait]
+impl RequestHandler for ClientRateLimitHandler {
+    async fn handle(&self, request: &HttpRequest) -> Response<ResponseBody> {
+        let Some(configuration) = self.configuration else {
+            return self.next.handle(request).await;
+        };
+
+        // None for UNIX socket requests
+        let Some(client) = request.client_address() else {
+            return self.next.handle(request).await;
+        };
+
+        match self.acquire(configuration, client, request.hyper_request.uri().path()) {
+            Ok(()) => self.next.handle(request).await,
+            Err(retry_after) => {
+                warn!("client rate limit exceeded client = {}", client);
+
+                let mut response =
+                    build_deny_response(StatusCode::TOO_MANY_REQUESTS, DenyReason::RateLimit);
+
+                // whole seconds, rounded up
+                let retry_after_seconds = retry_after
+                    .as_secs()
+                    .saturating_add(u64::from(retry_after.subsec_nanos() > 0));
+
+                response
+                    .headers_mut()
+                    .insert(header::RETRY_AFTER, HeaderValue::from(retry_after_seconds));
+
+                response
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_rate_limit_window() {
+        let start = Instant::now();
+        let period = Duration::from_secs(10);
+
+        let mut window = RateLimitWindow { start, count: 0 };
+
+        let decision = window.acquire(start, 2, period);
+        assert!(decision.allowed);
+        assert_eq!(decision.remaining, 1);
+
+        let decision = window.acquire(start + Duration::from_secs(4), 2, period);
+        assert!(decision.allowed);
+        assert_eq!(decision.remaining, 0);
+        assert_eq!(decision.reset, Duration::from_secs(6));
+
+        let decision = window.acquire(start + Duration::from_secs(5), 2, period);
+        assert!(!decision.allowed);
+        assert_eq!(decision.remaining, 0);
+
+        let decision = window.acquire(start + Duration::from_secs(10), 2, period);
+        assert!(decision.allowed);
+        assert_eq!(decision.remaining, 1);
+        assert_eq!(decision.reset, period);
+    }
+
+    #[test]
+    fn test_token_bucket() {
+        let start = Instant::now();
+
+        let mut bucket = TokenBucket::new(start, 2);
+
+        assert!(bucket.acquire(start, 4.0, 2).is_ok());
+        assert!(bucket.acquire(start, 4.0, 2).is_ok());
+        assert_eq!(
+            bucket.acquire(start, 4.0, 2),
+            Err(Duration::from_millis(250))
+        );
+
+        // refills at requests_per_second up to burst
+        assert!(bucket
+            .acquire(start + Duration::from_millis(250), 4.0, 2)
+            .is_ok());
+        assert!(bucket
+            .acquire(start + Duration::from_millis(260), 4.0, 2)
+            .is_err());
+
+        let mut bucket = TokenBucket::new(start, 2);
+        bucket.refill(start + Duration::from_secs(10), 4.0, 2);
+        assert_eq!(bucket.tokens, 2.0);
+
+        let mut bucket = TokenBucket::new(start, 1);
+        assert!(bucket.acquire(start, 0.0, 1).is_ok());
+        assert_eq!(bucket.acquire(start, 0.0, 1), Err(Duration::MAX));
+    }
+}
@@ -0,0 +1,96 @@
+use async_trait::async_trait;
+
+use hyper::http::{HeaderName, HeaderValue, Response};
+
+use tracing::debug;
+
+use std::{sync::Arc, time::SystemTime};
+
+use crate::handlers::{HttpRequest, RequestHandler, ResponseBody};
+
+const MAX_INBOUND_REQUEST_ID_LENGTH: usize = 128;
+
+// Request ID shared with clients and other servers, from the inbound header
+// or generated.  Attached to the HttpRequest as an extension.
+#[derive(Clone, Debug)]
+pub struct ExternalRequestID(pub Arc<str>);
+
+fn valid_inbound_request_id(value: &str) -> bool {
+    (1..=MAX_INBOUND_REQUEST_ID_LENGTH).contains(&value.len())
+        && value.bytes().all(|b| b.is_ascii_graphic())
+}
+
+pub struct RequestIdHandler {
+    header: Option<HeaderName>,
+    accept_inbound: bool,
+    // distinguishes IDs across restarts, the request ID counter starts at 1
+    generated_prefix: String,
+    next: Box<dyn RequestHandler>,
+}
+
+impl RequestIdHandler {
+    pub fn new(next: Box<dyn RequestHandler>) -> anyhow::Result<Self> {
+        let request_id_configuration = &crate::config::instance().request_id_configuration;
+
+        debug!("request_id_configuration = {:?}", request_id_configuration);
+
+        let header = request_id_configuration
+            .header
+            .as_deref()
+            .map(HeaderName::try_from)
+            .transpose()?;
+
+        let start_seconds = crate::uptime::start_time()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+
+        Ok(Self {
+            header,
+            accept_inbound: request_id_configuration.accept_inbound,
+            generated_prefix: format!("{:x}", start_seconds),
+            next,
+        })
+    }
+
+    fn external_request_id(&self, header: &HeaderName, request: &HttpRequest) -> Arc<str> {
+        let inbound = self
+            .accept_inbound
+            .then(|| request.hyper_request.headers().get(header))
+            .flatten()
+            .and_then(|value| value.to_str().ok())
+            .filter(|value| valid_inbound_request_id(value));
+
+        match inbound {
+            Some(inbound) => Arc::from(inbound),
+            None => Arc::from(format!(
+                "{}-{}",
+                self.generated_prefix,
+                request.request_id.as_usize()
+            )),
+        }
+    }
+}
+
+#[async_trait]
+impl RequestHandler for RequestIdHandler {
+    async fn handle(&self, request: &HttpRequest) -> Response<ResponseBody> {
+        let Some(header) = &self.header else {
+            return self.next.handle(request).await;
+        };
+
+        let external_request_id = self.external_request_id(header, request);
+
+        tracing::Span::current().record("xreq_id", external_request_id.as_ref());
+
+        request.insert_extension(ExternalRequestID(Arc::clone(&external_request_id)));
+
+        let mut response = self.next.handle(request).await;
+
+        if let Ok(value) = HeaderValue::from_str(&external_request_id) {
+            response.headers_mut().insert(header.clone(), value);
+        }
+
+        response
+    }
+}
@@ -1,22 +1,51 @@
 use async_trait::async_trait;
 
-use hyper::http::{Method, Response, Version};
+use hyper::http::{Method, Response, StatusCode, Version};
+
+use schemars::JsonSchema;
 
 use serde::Serialize;
 
-use std::{collections::BTreeMap, path::PathBuf};
+use tracing::warn;
+
+use std::{
+    collections::BTreeMap,
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+};
 
 use crate::{
-    handlers::{route::RouteInfo, HttpRequest, RequestHandler},
-    response::{build_json_response, CacheControl, ResponseBody},
+    connection::PeerCredentials,
+    geoip::GeoInfo,
+    handlers::{
+        request_id::ExternalRequestID,
+        route::{RouteApiDoc, RouteInfo},
+        HttpRequest, RequestHandler,
+    },
+    request::RequestBodyError,
+    response::{build_json_response, build_status_code_response, CacheControl, ResponseBody},
 };
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, JsonSchema, Serialize)]
 struct RequestFields<'a> {
     connection_id: usize,
     http_version: &'a str,
     method: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    peer_addr: Option<SocketAddr>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    proxy_addr: Option<SocketAddr>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    client_address: Option<IpAddr>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    local_addr: Option<SocketAddr>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    peer_credentials: Option<PeerCredentials>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    geo_info: Option<&'a GeoInfo>,
     request_id: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    external_request_id: Option<String>,
     request_uri_path: &'a str,
 }
 
@@ -37,7 +66,16 @@ impl<'a> From<&'a HttpRequest> for RequestFields<'a> {
             connection_id: request.connection_id.as_usize(),
             http_version,
             method: hyper_request.method().as_str(),
+            peer_addr: request.peer_addr(),
+            proxy_addr: request.proxy_addr(),
+            client_address: request.client_address(),
+            local_addr: request.local_addr(),
+            peer_credentials: request.peer_credentials(),
+            geo_info: request.geo_info(),
             request_id: request.request_id.as_usize(),
+            external_request_id: request
+                .extension::<ExternalRequestID>()
+                .map(|ExternalRequestID(external_request_id)| external_request_id.to_string()),
             request_uri_path: hyper_request.uri().path(),
         }
     }
@@ -56,10 +94,28 @@ impl<'a> From<&'a HttpRequest> for SortedRequestHeaders<'a> {
     }
 }
 
-#[derive(Debug, Serialize)]
+type SortedQueryParams<'a> = BTreeMap<&'a str, Vec<&'a str>>;
+
+impl<'a> From<&'a HttpRequest> for SortedQueryParams<'a> {
+    fn from(request: &'a HttpRequest) -> Self {
+        let mut sorted_query_params = SortedQueryParams::new();
+
+        for (key, value) in request.query_params().iter() {
+            sorted_query_params.entry(key).or_default().push(value);
+        }
+
+        sorted_query_params
+    }
+}
+
+#[derive(Debug, JsonSchema, Serialize)]
 struct RequestInfoResponse<'a> {
     request_fields: RequestFields<'a>,
     request_headers: SortedRequestHeaders<'a>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    query_params: SortedQueryParams<'a>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_body_length: Option<usize>,
 }
 
 impl<'a> From<&'a HttpRequest> for RequestInfoResponse<'a> {
@@ -67,25 +123,57 @@ impl<'a> From<&'a HttpRequest> for RequestInfoResponse<'a> {
         Self {
             request_fields: request.into(),
             request_headers: request.into(),
+            query_params: request.into(),
+            request_body_length: None,
         }
     }
 }
 
+const MAX_REQUEST_BODY_BYTES: usize = 64 * 1024;
+
 struct RequestInfoHandler;
 
 #[async_trait]
 impl RequestHandler for RequestInfoHandler {
     async fn handle(&self, request: &HttpRequest) -> Response<ResponseBody> {
-        let response: RequestInfoResponse<'_> = request.into();
+        let mut response: RequestInfoResponse<'_> = request.into();
+
+        if request.hyper_request.method() == Method::POST {
+            match request.collect_body(MAX_REQUEST_BODY_BYTES).await {
+                Ok(body) => response.request_body_length = Some(body.len()),
+                Err(RequestBodyError::TooLarge(_)) => {
+                    return build_status_code_response(
+                        StatusCode::PAYLOAD_TOO_LARGE,
+                        CacheControl::NoCache,
+                    );
+                }
+                Err(e) => {
+                    warn!("RequestInfoHandler collect_body error: {}", e);
+                    return build_status_code_response(
+                        StatusCode::BAD_REQUEST,
+                        CacheControl::NoCache,
+                    );
+                }
+            }
+        }
 
         build_json_response(response, CacheControl::NoCache)
     }
 }
 
 pub fn create_routes() -> Vec<RouteInfo> {
-    vec![RouteInfo {
-        method: &Method::GET,
-        path_suffix: PathBuf::from("request_info"),
-        handler: Box::new(RequestInfoHandler),
-    }]
+    vec![
+        RouteInfo {
+            method: &Method::GET,
+            path_suffix: PathBuf::from("request_info"),
+            handler: Box::new(RequestInfoHandler),
+            api_doc: RouteApiDoc::json::<RequestInfoResponse<'static>>("Echo request information"),
+        },
+        RouteInfo {
+            method: &Method::POST,
+            path_suffix: PathBuf::from("request_info"),
+            handler: Box::new(RequestInfoHandler),
+            api_doc: RouteApiDoc::json::<RequestInfoResponse<'static>>("Echo request information"),
+        },
+    ]
 }
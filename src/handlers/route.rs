@@ -4,20 +4,54 @@ use async_trait::async_trait;
 
 use hyper::http::{Method, Response};
 
+use schemars::{generate::SchemaGenerator, JsonSchema, Schema};
+
 use tracing::debug;
 
 use std::{
     borrow::Cow,
     collections::HashMap,
     path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use crate::{
+    handlers::{
+        route_toggles, virtual_host::VirtualHosts, HttpRequest, RequestHandler, ResponseBody,
+    },
+    response::{build_deny_response, DenyReason},
 };
 
-use crate::handlers::{HttpRequest, RequestHandler, ResponseBody};
+// Describes a route's response for the generated OpenAPI document.
+pub struct RouteApiDoc {
+    pub summary: &'static str,
+    pub content_type: &'static str,
+    pub response_schema: Option<fn(&mut SchemaGenerator) -> Schema>,
+}
+
+impl RouteApiDoc {
+    pub fn json<T: JsonSchema>(summary: &'static str) -> Option<Self> {
+        Some(Self {
+            summary,
+            content_type: "application/json",
+            response_schema: Some(SchemaGenerator::subschema_for::<T>),
+        })
+    }
+
+    pub fn text(summary: &'static str) -> Option<Self> {
+        Some(Self {
+            summary,
+            content_type: "text/plain",
+            response_schema: None,
+        })
+    }
+}
 
 pub struct RouteInfo {
     pub method: &'static Method,
     pub path_suffix: PathBuf,
     pub handler: Box<dyn RequestHandler>,
+    pub api_doc: Option<RouteApiDoc>,
 }
 
 #[derive(Clone, Debug, Eq, PartialEq, Hash)]
@@ -35,8 +69,18 @@ impl<'a> From<&'a HttpRequest> for RouteKey<'a> {
     }
 }
 
+#[derive(Clone, Debug)]
+pub struct MatchedRoute(pub Arc<str>);
+
+struct RouteEntry {
+    matched_route: MatchedRoute,
+    path_suffix: PathBuf,
+    handler: Box<dyn RequestHandler>,
+}
+
 pub struct Router {
-    route_key_to_handler: HashMap<RouteKey<'static>, Box<dyn RequestHandler>>,
+    route_key_to_handler: HashMap<RouteKey<'static>, RouteEntry>,
+    virtual_hosts: VirtualHosts,
     default_route: Box<dyn RequestHandler>,
 }
 
@@ -47,21 +91,25 @@ impl Router {
     ) -> anyhow::Result<Self> {
         let mut router = Self {
             route_key_to_handler: HashMap::with_capacity(routes.len()),
+            virtual_hosts: VirtualHosts::new(),
             default_route,
         };
 
-        let context_path = Path::new(
-            &crate::config::instance()
-                .context_configuration
-                .dynamic_route_context,
-        );
-
         for route in routes {
-            let route_key = Self::build_route_key(context_path, &route)?;
+            let route_key = RouteKey {
+                method: route.method,
+                path: Cow::from(route_path(&route)?),
+            };
+
+            let route_entry = RouteEntry {
+                matched_route: MatchedRoute(Arc::from(route_key.path.as_ref())),
+                path_suffix: route.path_suffix,
+                handler: route.handler,
+            };
 
             if router
                 .route_key_to_handler
-                .insert(route_key.clone(), route.handler)
+                .insert(route_key.clone(), route_entry)
                 .is_some()
             {
                 anyhow::bail!(
@@ -72,28 +120,29 @@ impl Router {
         }
         Ok(router)
     }
+}
 
-    fn build_route_key(
-        context_path: &Path,
-        route: &RouteInfo,
-    ) -> anyhow::Result<RouteKey<'static>> {
-        let path = context_path.join(&route.path_suffix);
-
-        let path = path
-            .to_str()
-            .with_context(|| {
-                format!(
-                    "Router::build_route_key error: uri_pathbuf.to_str error uri_pathbuf = '{:?}'",
-                    path,
-                )
-            })?
-            .to_owned();
-
-        Ok(RouteKey {
-            method: route.method,
-            path: Cow::from(path),
-        })
-    }
+// Full request path for a route, path_suffix is relative to the dynamic route context.
+pub fn route_path(route: &RouteInfo) -> anyhow::Result<String> {
+    let context_path = Path::new(
+        &crate::config::instance()
+            .context_configuration
+            .dynamic_route_context,
+    );
+
+    let path = context_path.join(&route.path_suffix);
+
+    let path = path
+        .to_str()
+        .with_context(|| {
+            format!(
+                "route_path error: uri_pathbuf.to_str error uri_pathbuf = '{:?}'",
+                path,
+            )
+        })?
+        .to_owned();
+
+    Ok(path)
 }
 
 #[async_trait]
@@ -101,10 +150,25 @@ impl RequestHandler for Router {
     async fn handle(&self, request: &HttpRequest) -> Response<ResponseBody> {
         debug!("begin handle");
 
-        let handler_option = self.route_key_to_handler.get(&RouteKey::from(request));
-
-        let response = match handler_option {
-            Some(handler) => handler.handle(request).await,
+        // routes not served for the request's virtual host fall through to
+        // its static files
+        let route_entry_option = self
+            .route_key_to_handler
+            .get(&RouteKey::from(request))
+            .filter(|route_entry| {
+                self.virtual_hosts
+                    .serves_route(request, &route_entry.path_suffix)
+            });
+
+        let response = match route_entry_option {
+            Some(route_entry) => {
+                request.insert_extension(route_entry.matched_route.clone());
+
+                match route_toggles::disabled_status(&route_entry.path_suffix) {
+                    Some(status) => build_deny_response(status, DenyReason::RouteDisabled),
+                    None => route_entry.handler.handle(request).await,
+                }
+            }
             None => self.default_route.handle(request).await,
         };
 
@@ -0,0 +1,109 @@
+use async_trait::async_trait;
+
+use hyper::http::{Method, Response};
+
+use schemars::JsonSchema;
+
+use serde::Serialize;
+
+use std::path::PathBuf;
+
+use crate::{
+    handlers::{
+        route::{RouteApiDoc, RouteInfo},
+        HttpRequest, RequestHandler, ResponseBody,
+    },
+    resource_usage::ALLOCATIONS_TRACKED,
+    response::{build_json_response, CacheControl},
+    route_metrics::{MetricSummary, RouteMetrics},
+};
+
+#[derive(Debug, JsonSchema, Serialize)]
+struct MetricSummaryDTO {
+    count: u64,
+    mean: u64,
+    max: u64,
+}
+
+impl From<MetricSummary> for MetricSummaryDTO {
+    fn from(summary: MetricSummary) -> Self {
+        Self {
+            count: summary.count,
+            mean: summary.mean(),
+            max: summary.max,
+        }
+    }
+}
+
+#[derive(Debug, JsonSchema, Serialize)]
+struct RouteMetricsEntryDTO {
+    route: String,
+    request_header_bytes: MetricSummaryDTO,
+    response_header_bytes: MetricSummaryDTO,
+    // HTTP/1 only: first byte read until the request head was parsed
+    header_time_micros: MetricSummaryDTO,
+    // request head parsed until the handler returned response headers
+    ttfb_micros: MetricSummaryDTO,
+    // handler return until the response body was fully written or aborted
+    write_time_micros: MetricSummaryDTO,
+    // CPU time and allocations while the handler ran, with resource usage
+    // enabled; allocations need the alloc-tracking feature
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cpu_time_micros: Option<MetricSummaryDTO>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    allocations: Option<MetricSummaryDTO>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    allocated_bytes: Option<MetricSummaryDTO>,
+    // requests over the configured cpu time or allocated bytes budget
+    over_budget: u64,
+}
+
+#[derive(Debug, JsonSchema, Serialize)]
+struct RouteMetricsDTO {
+    routes: Vec<RouteMetricsEntryDTO>,
+}
+
+struct RouteMetricsHandler {
+    route_metrics: &'static RouteMetrics,
+}
+
+#[async_trait]
+impl RequestHandler for RouteMetricsHandler {
+    async fn handle(&self, _request: &HttpRequest) -> Response<ResponseBody> {
+        let routes = self
+            .route_metrics
+            .snapshot()
+            .into_iter()
+            .map(|(route, entry)| {
+                let measured = entry.cpu_time_micros.count > 0;
+                let allocations_measured = measured && ALLOCATIONS_TRACKED;
+
+                RouteMetricsEntryDTO {
+                    route: route.to_string(),
+                    request_header_bytes: entry.request_header_bytes.into(),
+                    response_header_bytes: entry.response_header_bytes.into(),
+                    header_time_micros: entry.header_time_micros.into(),
+                    ttfb_micros: entry.ttfb_micros.into(),
+                    write_time_micros: entry.write_time_micros.into(),
+                    cpu_time_micros: measured.then(|| entry.cpu_time_micros.into()),
+                    allocations: allocations_measured.then(|| entry.allocations.into()),
+                    allocated_bytes: allocations_measured.then(|| entry.allocated_bytes.into()),
+                    over_budget: entry.over_budget,
+                }
+            })
+            .collect();
+
+        build_json_response(RouteMetricsDTO { routes }, CacheControl::NoCache)
+    }
+}
+
+pub async fn create_routes() -> Vec<RouteInfo> {
+    vec![RouteInfo {
+        method: &Method::GET,
+        path_suffix: PathBuf::from("route_metrics"),
+        handler: Box::new(RouteMetricsHandler {
+            route_metrics: RouteMetrics::instance().await,
+        }),
+        api_doc: RouteApiDoc::json::<RouteMetricsDTO>("Header sizes and timings per route"),
+    }]
+}
@@ -0,0 +1,123 @@
+use hyper::http::StatusCode;
+
+use schemars::JsonSchema;
+
+use serde::Serialize;
+
+use tracing::info;
+
+use std::{path::Path, sync::RwLock, time::SystemTime};
+
+// Admin routes cannot be disabled, that would remove the way to re-enable them.
+const PROTECTED_PREFIX: &str = "admin";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, JsonSchema, Serialize)]
+pub enum DisabledRouteStatus {
+    #[serde(rename = "NOT_FOUND")]
+    NotFound,
+
+    #[serde(rename = "SERVICE_UNAVAILABLE")]
+    ServiceUnavailable,
+}
+
+impl DisabledRouteStatus {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "NOT_FOUND" => Some(Self::NotFound),
+            "SERVICE_UNAVAILABLE" => Some(Self::ServiceUnavailable),
+            _ => None,
+        }
+    }
+
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::NotFound => StatusCode::NOT_FOUND,
+            Self::ServiceUnavailable => StatusCode::SERVICE_UNAVAILABLE,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct RouteToggle {
+    pub prefix: String,
+    pub status: DisabledRouteStatus,
+    pub disabled_time: SystemTime,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum RouteToggleError {
+    #[error("admin routes cannot be disabled")]
+    ProtectedRoute,
+}
+
+// Routes disabled at runtime, matched by path_suffix prefix like
+// context_configuration.disabled_routes.  Cleared on restart.
+static DISABLED_ROUTES: RwLock<Vec<RouteToggle>> = RwLock::new(Vec::new());
+
+fn is_protected_prefix(prefix: &Path) -> bool {
+    Path::new(PROTECTED_PREFIX).starts_with(prefix) || prefix.starts_with(PROTECTED_PREFIX)
+}
+
+pub fn disable_routes(prefix: &str, status: DisabledRouteStatus) -> Result<(), RouteToggleError> {
+    if is_protected_prefix(Path::new(prefix)) {
+        return Err(RouteToggleError::ProtectedRoute);
+    }
+
+    info!("disable_routes prefix = {:?} status = {:?}", prefix, status);
+
+    let mut disabled_routes = DISABLED_ROUTES.write().unwrap();
+
+    disabled_routes.retain(|toggle| toggle.prefix != prefix);
+    disabled_routes.push(RouteToggle {
+        prefix: prefix.to_owned(),
+        status,
+        disabled_time: SystemTime::now(),
+    });
+
+    Ok(())
+}
+
+// Returns false if prefix was not disabled.
+pub fn enable_routes(prefix: &str) -> bool {
+    let mut disabled_routes = DISABLED_ROUTES.write().unwrap();
+
+    let len_before = disabled_routes.len();
+
+    disabled_routes.retain(|toggle| toggle.prefix != prefix);
+
+    let enabled = disabled_routes.len() != len_before;
+
+    if enabled {
+        info!("enable_routes prefix = {:?}", prefix);
+    }
+
+    enabled
+}
+
+pub fn disabled_routes() -> Vec<RouteToggle> {
+    DISABLED_ROUTES.read().unwrap().clone()
+}
+
+// Status to respond with if the route is currently disabled.
+pub fn disabled_status(path_suffix: &Path) -> Option<StatusCode> {
+    DISABLED_ROUTES
+        .read()
+        .unwrap()
+        .iter()
+        .find(|toggle| path_suffix.starts_with(&toggle.prefix))
+        .map(|toggle| toggle.status.status_code())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_is_protected_prefix() {
+        assert!(is_protected_prefix(Path::new("")));
+        assert!(is_protected_prefix(Path::new("admin")));
+        assert!(is_protected_prefix(Path::new("admin/config")));
+        assert!(!is_protected_prefix(Path::new("commands")));
+        assert!(!is_protected_prefix(Path::new("administrator")));
+    }
+}
@@ -0,0 +1,83 @@
+use async_trait::async_trait;
+
+use hyper::http::{Method, Response, StatusCode};
+
+use schemars::JsonSchema;
+
+use serde::Serialize;
+
+use tracing::warn;
+
+use std::path::PathBuf;
+
+use crate::{
+    config::RuntimeFlavor,
+    handlers::{
+        route::{RouteApiDoc, RouteInfo},
+        HttpRequest, RequestHandler, ResponseBody,
+    },
+    response::{build_json_response, build_status_code_response, CacheControl},
+};
+
+#[derive(Debug, JsonSchema, Serialize)]
+struct FdUsageDTO {
+    open_fds: Option<u64>,
+    soft_limit: u64,
+    hard_limit: u64,
+    usage_percent: Option<f64>,
+}
+
+#[derive(Debug, JsonSchema, Serialize)]
+struct RuntimeInfoDTO {
+    flavor: RuntimeFlavor,
+    num_workers: usize,
+    num_alive_tasks: usize,
+    global_queue_depth: usize,
+    // absent on platforms without RLIMIT_NOFILE
+    #[serde(skip_serializing_if = "Option::is_none")]
+    file_descriptors: Option<FdUsageDTO>,
+}
+
+struct RuntimeInfoHandler;
+
+#[async_trait]
+impl RequestHandler for RuntimeInfoHandler {
+    async fn handle(&self, _request: &HttpRequest) -> Response<ResponseBody> {
+        let fd_usage = match crate::fd_limits::current_fd_usage() {
+            Err(e) => {
+                warn!("RuntimeInfoHandler current_fd_usage error: {:#}", e);
+                return build_status_code_response(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    CacheControl::NoCache,
+                );
+            }
+            Ok(fd_usage) => fd_usage,
+        };
+
+        let metrics = tokio::runtime::Handle::current().metrics();
+
+        let dto = RuntimeInfoDTO {
+            flavor: crate::config::instance().runtime_configuration.flavor,
+            num_workers: metrics.num_workers(),
+            num_alive_tasks: metrics.num_alive_tasks(),
+            global_queue_depth: metrics.global_queue_depth(),
+            file_descriptors: fd_usage.map(|fd_usage| FdUsageDTO {
+                open_fds: fd_usage.open_fds,
+                soft_limit: fd_usage.soft_limit,
+                hard_limit: fd_usage.hard_limit,
+                usage_percent: fd_usage.usage_percent(),
+            }),
+        };
+
+        build_json_response(dto, CacheControl::NoCache)
+    }
+}
+
+pub fn create_routes() -> Vec<RouteInfo> {
+    vec![RouteInfo {
+        method: &Method::GET,
+        path_suffix: PathBuf::from("runtime_info"),
+        handler: Box::new(RuntimeInfoHandler),
+        api_doc: RouteApiDoc::json::<RuntimeInfoDTO>("Tokio runtime and file descriptor usage"),
+    }]
+}
@@ -0,0 +1,109 @@
+use async_trait::async_trait;
+
+use hyper::http::{Method, Response};
+
+use schemars::JsonSchema;
+
+use serde::Serialize;
+
+use std::{
+    collections::BTreeMap,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use crate::{
+    connection::{CompletedRequest, ConnectionObserver, ConnectionTracker},
+    handlers::{
+        route::{RouteApiDoc, RouteInfo},
+        time_utils::{local_date_time_to_string, LocalDateTime},
+        HttpRequest, RequestHandler, ResponseBody,
+    },
+    response::{build_json_response, CacheControl, DenyReason},
+    traffic_stats::TrafficStats,
+};
+
+#[derive(Debug, JsonSchema, Serialize)]
+struct ServerStatsDTO {
+    start_time: String,
+    #[serde(with = "humantime_serde")]
+    #[schemars(with = "String")]
+    uptime: Duration,
+    total_connections: usize,
+    open_connections: usize,
+    total_requests: usize,
+    client_aborted_requests: usize,
+    total_response_bytes: u64,
+    accept_fd_exhaustion_events: usize,
+    config_generation: u64,
+    config_load_time: String,
+    denied_requests: BTreeMap<DenyReason, usize>,
+}
+
+#[derive(Default)]
+struct DeniedRequestCounter {
+    counts: Mutex<BTreeMap<DenyReason, usize>>,
+}
+
+impl ConnectionObserver for DeniedRequestCounter {
+    fn on_request_complete(&self, request: &CompletedRequest<'_>) {
+        if let Some(deny_reason) = request.deny_reason {
+            *self.counts.lock().unwrap().entry(deny_reason).or_default() += 1;
+        }
+    }
+}
+
+struct ServerStatsHandler {
+    connection_tracker: &'static ConnectionTracker,
+    traffic_stats: &'static TrafficStats,
+    denied_request_counter: Arc<DeniedRequestCounter>,
+}
+
+#[async_trait]
+impl RequestHandler for ServerStatsHandler {
+    async fn handle(&self, _request: &HttpRequest) -> Response<ResponseBody> {
+        let state = self.connection_tracker.state().await;
+
+        let config_generation = crate::config::generation();
+
+        let dto = ServerStatsDTO {
+            start_time: local_date_time_to_string(
+                &LocalDateTime::from(crate::uptime::start_time()),
+            ),
+            // truncate to seconds
+            uptime: Duration::from_secs(crate::uptime::uptime().as_secs()),
+            total_connections: state.total_connections,
+            open_connections: state.open_connections.len() + state.untracked_connections,
+            total_requests: state.total_requests,
+            client_aborted_requests: state.client_aborted_requests,
+            total_response_bytes: self.traffic_stats.total_bytes(),
+            accept_fd_exhaustion_events: crate::server::fd_reserve_instance()
+                .fd_exhaustion_events(),
+            config_generation: config_generation.generation,
+            config_load_time: local_date_time_to_string(&LocalDateTime::from(
+                config_generation.load_time,
+            )),
+            denied_requests: self.denied_request_counter.counts.lock().unwrap().clone(),
+        };
+
+        build_json_response(dto, CacheControl::NoCache)
+    }
+}
+
+pub async fn create_routes() -> Vec<RouteInfo> {
+    let denied_request_counter = Arc::new(DeniedRequestCounter::default());
+
+    crate::connection::register_connection_observer(denied_request_counter.clone());
+
+    vec![RouteInfo {
+        method: &Method::GET,
+        path_suffix: PathBuf::from("server_stats"),
+        handler: Box::new(ServerStatsHandler {
+            connection_tracker: ConnectionTracker::instance().await,
+            traffic_stats: TrafficStats::instance().await,
+            denied_request_counter,
+        }),
+        api_doc: RouteApiDoc::json::<ServerStatsDTO>("Server uptime and lifetime totals"),
+    }]
+}
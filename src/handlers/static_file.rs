@@ -1,19 +1,36 @@
+use anyhow::Context;
+
 use async_trait::async_trait;
 
-use http_body_util::BodyExt;
+use http_body_util::{BodyExt, Full};
 
-use hyper::http::{header, Request as HyperHttpRequest, Response, StatusCode};
+use hyper::http::{
+    header, HeaderMap, HeaderValue, Request as HyperHttpRequest, Response, StatusCode,
+};
 
-use hyper_staticfile::{vfs::TokioFileOpener, ResolveResult, Resolver};
+use hyper_staticfile::Resolver;
 
 use tracing::{debug, warn};
 
-use tokio::time::Duration;
+use std::{path::PathBuf, sync::Arc, time::SystemTime};
+
+type ResolveResult = hyper_staticfile::ResolveResult<ChunkedFile>;
 
 use crate::{
-    handlers::{HttpRequest, RequestHandler, ResponseBody},
-    response::{build_status_code_response, CacheControl},
-    static_file::StaticFileRulesService,
+    config::{StaticFileConfiguration, StaticFileDirectoryListingRule},
+    handlers::{
+        time_utils::http_date_string, virtual_host::MatchedVirtualHost, HttpRequest,
+        RequestHandler, ResponseBody,
+    },
+    response::{
+        build_json_response, build_status_code_response, empty_response_body, CacheControl,
+        DenyReason,
+    },
+    static_file::{
+        build_file_response, listed_directory, read_directory, render_html, CacheRuleMatch,
+        ChunkedFile, ChunkedFileOpener, FilterAction, RangeOptions, StaticFileRulesService,
+        DEFAULT_READ_CHUNK_SIZE,
+    },
 };
 
 #[derive(thiserror::Error, Debug)]
@@ -27,24 +44,50 @@ enum StaticFileHandlerError {
     #[error("client error page build response error: {0}")]
     ClientErrorPageBuildResponse(hyper::http::Error),
 
+    #[error("rewrite request error: {0}")]
+    RewriteRequest(hyper::http::Error),
+
+    #[error("build redirect response error: {0}")]
+    BuildRedirectResponse(hyper::http::Error),
+
     #[error("resolve error: {0}")]
     ResolveRequest(std::io::Error),
 
     #[error("build response error: {0}")]
     BuildResponse(hyper::http::Error),
+
+    #[error("build directory listing response error: {0}")]
+    BuildDirectoryListingResponse(hyper::http::Error),
+}
+
+fn is_dot_path(str_path: &str) -> bool {
+    str_path.starts_with('.') || str_path.contains("/.")
 }
 
 struct StaticFileHandler {
-    resolver: Resolver<TokioFileOpener>,
+    resolver: Resolver<ChunkedFileOpener>,
+    root: PathBuf,
     client_error_page_path: &'static str,
-    static_file_rules_service: &'static StaticFileRulesService,
+    debug_headers: bool,
+    log_resolved_path: bool,
+    range_options: RangeOptions,
+    directory_listings: &'static [StaticFileDirectoryListingRule],
+    // None uses the reloadable rules of the top level configuration
+    rules_service: Option<Arc<StaticFileRulesService>>,
 }
 
 impl StaticFileHandler {
-    fn new() -> Self {
-        let static_file_configuration = &crate::config::instance().static_file_configuration;
-
-        let mut resolver = Resolver::new(&static_file_configuration.root);
+    fn new(
+        static_file_configuration: &'static StaticFileConfiguration,
+        rules_service: Option<Arc<StaticFileRulesService>>,
+    ) -> Self {
+        let mut resolver = Resolver::with_opener(ChunkedFileOpener::new(
+            &static_file_configuration.root,
+            static_file_configuration
+                .read_chunk_size
+                .unwrap_or(DEFAULT_READ_CHUNK_SIZE),
+            static_file_configuration.sequential_readahead,
+        ));
         resolver.allowed_encodings.gzip = static_file_configuration.precompressed.gz;
         resolver.allowed_encodings.br = static_file_configuration.precompressed.br;
 
@@ -55,22 +98,89 @@ impl StaticFileHandler {
 
         Self {
             resolver,
+            root: PathBuf::from(&static_file_configuration.root),
             client_error_page_path: &static_file_configuration.client_error_page_path,
-            static_file_rules_service: crate::static_file::rules_service_instance(),
+            debug_headers: static_file_configuration.debug_headers,
+            log_resolved_path: static_file_configuration.log_resolved_path,
+            range_options: RangeOptions {
+                multi_range_policy: static_file_configuration.multi_range_policy,
+                max_ranges: static_file_configuration.max_ranges,
+            },
+            directory_listings: &static_file_configuration.directory_listings,
+            rules_service,
         }
     }
 
-    fn build_cache_headers(&self, resolve_result: &ResolveResult) -> Option<u32> {
-        fn duration_to_u32_seconds(duration: Duration) -> u32 {
-            duration.as_secs().try_into().unwrap_or_default()
+    fn rules_service(&self) -> Arc<StaticFileRulesService> {
+        self.rules_service
+            .clone()
+            .unwrap_or_else(crate::static_file::rules_service_instance)
+    }
+
+    fn find_cache_rule(&self, resolve_result: &ResolveResult) -> Option<CacheRuleMatch> {
+        match resolve_result {
+            ResolveResult::Found(resolved_file) => {
+                self.rules_service().find_cache_rule(resolved_file)
+            }
+            _ => None,
         }
+    }
 
+    fn find_preload_links(&self, resolve_result: &ResolveResult) -> Vec<HeaderValue> {
         match resolve_result {
             ResolveResult::Found(resolved_file) => self
-                .static_file_rules_service
-                .build_cache_header(resolved_file)
-                .map(duration_to_u32_seconds),
-            _ => None,
+                .rules_service()
+                .find_preload_links(resolved_file)
+                .cloned()
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    fn insert_cache_headers(
+        &self,
+        cache_rule_match: Option<&CacheRuleMatch>,
+        headers: &mut HeaderMap,
+    ) {
+        if let Some(cache_control) =
+            cache_rule_match.and_then(|cache_rule_match| cache_rule_match.cache_control.clone())
+        {
+            headers.insert(header::CACHE_CONTROL, cache_control);
+        }
+
+        // hyper_staticfile sends Last-Modified with 200s but not with 304s
+        if let Some(last_modified) =
+            cache_rule_match.and_then(|cache_rule_match| cache_rule_match.last_modified)
+        {
+            if !headers.contains_key(header::LAST_MODIFIED) {
+                if let Ok(last_modified) = HeaderValue::from_str(&http_date_string(last_modified)) {
+                    headers.insert(header::LAST_MODIFIED, last_modified);
+                }
+            }
+        }
+
+        self.insert_debug_headers(cache_rule_match, headers);
+    }
+
+    fn insert_debug_headers(
+        &self,
+        cache_rule_match: Option<&CacheRuleMatch>,
+        headers: &mut HeaderMap,
+    ) {
+        if !self.debug_headers {
+            return;
+        }
+
+        let Some(cache_rule_match) = cache_rule_match else {
+            return;
+        };
+
+        if let Ok(rule_name) = HeaderValue::from_str(&cache_rule_match.rule_name) {
+            headers.insert("x-cache-rule", rule_name);
+        }
+
+        if let Some(max_age) = cache_rule_match.max_age {
+            headers.insert("x-cache-max-age", HeaderValue::from(max_age.as_secs()));
         }
     }
 
@@ -102,20 +212,76 @@ impl StaticFileHandler {
             .await
             .map_err(StaticFileHandlerError::ClientErrorPageResolveRequest)?;
 
+        let cache_rule_match = self.find_cache_rule(&resolve_result);
+
         let response = hyper_staticfile::ResponseBuilder::new()
             .request(&client_error_page_request)
-            .cache_headers(self.build_cache_headers(&resolve_result))
             .build(resolve_result)
             .map_err(StaticFileHandlerError::ClientErrorPageBuildResponse)?;
 
         let (mut parts, body) = response.into_parts();
         parts.status = status_code;
 
+        self.insert_cache_headers(cache_rule_match.as_ref(), &mut parts.headers);
+
         let boxed_body = body.map_err(|e| e.into()).boxed();
 
         Ok(Response::from_parts(parts, boxed_body))
     }
 
+    fn build_redirect_response(
+        &self,
+        status_code: StatusCode,
+        location: &str,
+    ) -> Result<Response<ResponseBody>, StaticFileHandlerError> {
+        Response::builder()
+            .status(status_code)
+            .header(header::LOCATION, location)
+            .header(header::CACHE_CONTROL, CacheControl::NoCache.header_value())
+            .body(empty_response_body())
+            .map_err(StaticFileHandlerError::BuildRedirectResponse)
+    }
+
+    fn rewrite_request(
+        &self,
+        request: &HyperHttpRequest<()>,
+        target: &str,
+    ) -> Result<HyperHttpRequest<()>, StaticFileHandlerError> {
+        let mut builder = HyperHttpRequest::builder()
+            .method(request.method().clone())
+            .uri(target)
+            .version(request.version());
+
+        if let Some(headers) = builder.headers_mut() {
+            headers.clone_from(request.headers());
+        }
+
+        builder
+            .body(())
+            .map_err(StaticFileHandlerError::RewriteRequest)
+    }
+
+    // Found files include any .br or .gz suffix picked for the request's
+    // Accept-Encoding, otherwise this is the path that was looked up.
+    fn record_resolved_path(
+        &self,
+        hyper_request: &HyperHttpRequest<()>,
+        resolve_result: &ResolveResult,
+    ) {
+        if !self.log_resolved_path {
+            return;
+        }
+
+        let resolved_path = match resolve_result {
+            ResolveResult::Found(resolved_file) => self.root.join(&resolved_file.path),
+            _ => self
+                .root
+                .join(hyper_request.uri().path().trim_start_matches('/')),
+        };
+
+        tracing::Span::current().record("resolved_path", tracing::field::debug(resolved_path));
+    }
+
     fn block_dot_paths(&self, resolve_result: &ResolveResult) -> bool {
         let str_path_option = match resolve_result {
             ResolveResult::Found(resolved_file) => resolved_file.path.to_str(),
@@ -125,7 +291,7 @@ impl StaticFileHandler {
 
         if let Some(str_path) = str_path_option {
             debug!("str_path = {}", str_path);
-            if str_path.starts_with('.') || str_path.contains("/.") {
+            if is_dot_path(str_path) {
                 warn!("blocking request for dot file path = {:?}", str_path);
                 return true;
             }
@@ -134,6 +300,68 @@ impl StaticFileHandler {
         false
     }
 
+    // Lists a directory without an index.html when a directory listing rule
+    // matches, None to answer 404 as usual.
+    async fn build_directory_listing_response(
+        &self,
+        request: &HttpRequest,
+        hyper_request: &HyperHttpRequest<()>,
+    ) -> Result<Option<Response<ResponseBody>>, StaticFileHandlerError> {
+        // prefixes are matched against the normalized path so '..' cannot leave them
+        let Some((directory, listed_path)) = listed_directory(hyper_request.uri().path()) else {
+            return Ok(None);
+        };
+
+        let Some(directory_listing) = self
+            .directory_listings
+            .iter()
+            .find(|directory_listing| listed_path.starts_with(&directory_listing.path_prefix))
+        else {
+            return Ok(None);
+        };
+
+        if is_dot_path(&listed_path) {
+            warn!(
+                "blocking directory listing for dot path = {:?}",
+                listed_path
+            );
+            return Ok(Some(
+                self.build_client_error_page_response(request, StatusCode::FORBIDDEN)
+                    .await?,
+            ));
+        }
+
+        let entries = match read_directory(&self.root.join(&directory)).await {
+            Err(e) => {
+                debug!("read_directory {:?} error: {}", listed_path, e);
+                return Ok(None);
+            }
+            Ok(entries) => entries,
+        };
+
+        let accepts_json = hyper_request
+            .headers()
+            .get(header::ACCEPT)
+            .and_then(|accept| accept.to_str().ok())
+            .is_some_and(|accept| accept.contains("application/json"));
+
+        if directory_listing.json && accepts_json {
+            return Ok(Some(build_json_response(entries, CacheControl::NoCache)));
+        }
+
+        Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "text/html; charset=utf-8")
+            .header(header::CACHE_CONTROL, CacheControl::NoCache.header_value())
+            .body(
+                Full::from(render_html(&listed_path, &entries))
+                    .map_err(|never| never.into())
+                    .boxed(),
+            )
+            .map(Some)
+            .map_err(StaticFileHandlerError::BuildDirectoryListingResponse)
+    }
+
     async fn handle_resolve_errors(
         &self,
         request: &HttpRequest,
@@ -169,29 +397,86 @@ impl StaticFileHandler {
     ) -> Result<Response<ResponseBody>, StaticFileHandlerError> {
         debug!("StaticFileHandler::try_handle request = {:?}", request);
 
+        let rules_service = self.rules_service();
+
+        let filter_rule_match = rules_service.find_filter_rule(&request.hyper_request);
+
+        let rewritten_request;
+        let hyper_request = match filter_rule_match {
+            None => &request.hyper_request,
+            Some(filter_rule_match) => {
+                debug!(
+                    "filter rule {:?} action = {:?}",
+                    filter_rule_match.rule_name, filter_rule_match.action
+                );
+
+                match filter_rule_match.action {
+                    FilterAction::Deny(status_code) => {
+                        let mut response = self
+                            .build_client_error_page_response(request, status_code)
+                            .await?;
+                        response.extensions_mut().insert(DenyReason::FilterRule);
+                        return Ok(response);
+                    }
+                    FilterAction::Redirect {
+                        status_code,
+                        location,
+                    } => return self.build_redirect_response(status_code, &location),
+                    FilterAction::Rewrite(target) => {
+                        rewritten_request =
+                            self.rewrite_request(&request.hyper_request, &target)?;
+                        &rewritten_request
+                    }
+                }
+            }
+        };
+
         let resolve_result = self
             .resolver
-            .resolve_request(&request.hyper_request)
+            .resolve_request(hyper_request)
             .await
             .map_err(StaticFileHandlerError::ResolveRequest)?;
 
         debug!("resolve_result = {:?}", resolve_result);
 
+        self.record_resolved_path(hyper_request, &resolve_result);
+
+        if matches!(resolve_result, ResolveResult::NotFound) && !self.directory_listings.is_empty()
+        {
+            if let Some(response) = self
+                .build_directory_listing_response(request, hyper_request)
+                .await?
+            {
+                return Ok(response);
+            }
+        }
+
         if let Some(response) = self.handle_resolve_errors(request, &resolve_result).await? {
             return Ok(response);
         }
 
-        let cache_headers = self.build_cache_headers(&resolve_result);
+        let cache_rule_match = self.find_cache_rule(&resolve_result);
 
-        debug!("cache_headers = {:?}", cache_headers);
+        let preload_links = self.find_preload_links(&resolve_result);
 
-        let response = hyper_staticfile::ResponseBuilder::new()
-            .request(&request.hyper_request)
-            .cache_headers(cache_headers)
-            .build(resolve_result)
-            .map_err(StaticFileHandlerError::BuildResponse)?;
+        let response = build_file_response(
+            hyper_request,
+            resolve_result,
+            self.range_options,
+            SystemTime::now(),
+        )
+        .map_err(StaticFileHandlerError::BuildResponse)?;
+
+        let (mut parts, body) = response.into_parts();
 
-        let (parts, body) = response.into_parts();
+        self.insert_cache_headers(cache_rule_match.as_ref(), &mut parts.headers);
+
+        // not worth sending with 304s and 206s, the page is already loading
+        if parts.status == StatusCode::OK {
+            for link in preload_links {
+                parts.headers.append(header::LINK, link);
+            }
+        }
 
         let boxed_body = body.map_err(|e| e.into()).boxed();
 
@@ -212,6 +497,48 @@ impl RequestHandler for StaticFileHandler {
     }
 }
 
-pub fn create_default_route() -> Box<dyn RequestHandler> {
-    Box::new(StaticFileHandler::new())
+// Serves each virtual host site from its own root and rules.
+struct VirtualHostStaticFileHandler {
+    default: StaticFileHandler,
+    sites: Vec<StaticFileHandler>,
+}
+
+#[async_trait]
+impl RequestHandler for VirtualHostStaticFileHandler {
+    async fn handle(&self, request: &HttpRequest) -> Response<ResponseBody> {
+        let handler = request
+            .extension::<MatchedVirtualHost>()
+            .and_then(|matched| self.sites.get(matched.index))
+            .unwrap_or(&self.default);
+
+        handler.handle(request).await
+    }
+}
+
+pub fn create_default_route() -> anyhow::Result<Box<dyn RequestHandler>> {
+    let configuration = crate::config::instance();
+
+    let default = StaticFileHandler::new(&configuration.static_file_configuration, None);
+
+    let sites = &configuration.virtual_host_configuration.sites;
+
+    if sites.is_empty() {
+        return Ok(Box::new(default));
+    }
+
+    let sites = sites
+        .iter()
+        .map(|site| {
+            let rules_service =
+                crate::static_file::create_rules_service(&site.static_file_configuration)
+                    .with_context(|| format!("virtual host site '{}'", site.name))?;
+
+            Ok(StaticFileHandler::new(
+                &site.static_file_configuration,
+                Some(rules_service),
+            ))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    Ok(Box::new(VirtualHostStaticFileHandler { default, sites }))
 }
@@ -1,4 +1,6 @@
-use chrono::prelude::{DateTime, Local, SecondsFormat};
+use chrono::prelude::{DateTime, Local, SecondsFormat, Utc};
+
+use std::time::SystemTime;
 
 pub type LocalDateTime = DateTime<Local>;
 
@@ -13,3 +15,25 @@ pub fn local_date_time_to_string(local_date_time: &LocalDateTime) -> String {
 pub fn current_local_date_time_string() -> String {
     local_date_time_to_string(&current_local_date_time())
 }
+
+// IMF-fixdate as used in Last-Modified and Date headers.
+pub fn http_date_string(system_time: SystemTime) -> String {
+    DateTime::<Utc>::from(system_time)
+        .format("%a, %d %b %Y %H:%M:%S GMT")
+        .to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::time::Duration;
+
+    #[test]
+    fn test_http_date_string() {
+        assert_eq!(
+            http_date_string(SystemTime::UNIX_EPOCH + Duration::from_millis(784_111_777_500)),
+            "Sun, 06 Nov 1994 08:49:37 GMT"
+        );
+    }
+}
@@ -0,0 +1,107 @@
+use async_trait::async_trait;
+
+use hyper::http::{Method, Response};
+
+use schemars::JsonSchema;
+
+use serde::Serialize;
+
+use std::{path::PathBuf, time::Duration};
+
+use crate::{
+    handlers::{
+        route::{RouteApiDoc, RouteInfo},
+        HttpRequest, RequestHandler, ResponseBody,
+    },
+    response::{build_json_response, CacheControl},
+    traffic_stats::{TrafficStats, UNKNOWN_CLIENT},
+};
+
+const DEFAULT_LIMIT: usize = 50;
+
+#[derive(Debug, JsonSchema, Serialize)]
+struct TrafficEntryDTO {
+    key: String,
+    bytes: u64,
+}
+
+#[derive(Debug, JsonSchema, Serialize)]
+struct TrafficStatsDTO {
+    #[serde(with = "humantime_serde")]
+    #[schemars(with = "String")]
+    window: Duration,
+    total_bytes: u64,
+    clients: Vec<TrafficEntryDTO>,
+    routes: Vec<TrafficEntryDTO>,
+}
+
+// Largest first, truncated to limit.
+fn sorted_entries(
+    entries: impl Iterator<Item = (String, u64)>,
+    limit: usize,
+) -> Vec<TrafficEntryDTO> {
+    let mut entries: Vec<TrafficEntryDTO> = entries
+        .map(|(key, bytes)| TrafficEntryDTO { key, bytes })
+        .collect();
+
+    entries.sort_by(|a, b| b.bytes.cmp(&a.bytes).then_with(|| a.key.cmp(&b.key)));
+    entries.truncate(limit);
+    entries
+}
+
+struct TrafficStatsHandler {
+    traffic_stats: &'static TrafficStats,
+}
+
+#[async_trait]
+impl RequestHandler for TrafficStatsHandler {
+    async fn handle(&self, request: &HttpRequest) -> Response<ResponseBody> {
+        let limit = request
+            .query_params()
+            .get("limit")
+            .and_then(|limit| limit.parse().ok())
+            .unwrap_or(DEFAULT_LIMIT);
+
+        let snapshot = self.traffic_stats.snapshot();
+
+        let total_bytes = snapshot.route_bytes.values().sum();
+
+        let clients = sorted_entries(
+            snapshot.client_bytes.into_iter().map(|(client, bytes)| {
+                (
+                    client.map_or_else(|| UNKNOWN_CLIENT.to_owned(), |ip| ip.to_string()),
+                    bytes,
+                )
+            }),
+            limit,
+        );
+
+        let routes = sorted_entries(
+            snapshot
+                .route_bytes
+                .into_iter()
+                .map(|(route, bytes)| (route.to_string(), bytes)),
+            limit,
+        );
+
+        let dto = TrafficStatsDTO {
+            window: snapshot.window,
+            total_bytes,
+            clients,
+            routes,
+        };
+
+        build_json_response(dto, CacheControl::NoCache)
+    }
+}
+
+pub async fn create_routes() -> Vec<RouteInfo> {
+    vec![RouteInfo {
+        method: &Method::GET,
+        path_suffix: PathBuf::from("traffic_stats"),
+        handler: Box::new(TrafficStatsHandler {
+            traffic_stats: TrafficStats::instance().await,
+        }),
+        api_doc: RouteApiDoc::json::<TrafficStatsDTO>("Traffic by client and route"),
+    }]
+}
@@ -5,9 +5,12 @@ use hyper::http::{Method, Response};
 use std::path::PathBuf;
 
 use crate::{
-    handlers::{route::RouteInfo, HttpRequest, RequestHandler, ResponseBody},
+    handlers::{
+        route::{RouteApiDoc, RouteInfo},
+        HttpRequest, RequestHandler, ResponseBody,
+    },
     response::{build_json_response, CacheControl},
-    version::get_verison_info,
+    version::{get_verison_info, VersionInfoMap},
 };
 
 struct VersionInfoHandler;
@@ -15,7 +18,7 @@ struct VersionInfoHandler;
 #[async_trait]
 impl RequestHandler for VersionInfoHandler {
     async fn handle(&self, _request: &HttpRequest) -> Response<ResponseBody> {
-        let version_info = get_verison_info().await;
+        let version_info = get_verison_info();
 
         build_json_response(version_info, CacheControl::NoCache)
     }
@@ -26,5 +29,6 @@ pub async fn create_routes() -> Vec<RouteInfo> {
         method: &Method::GET,
         path_suffix: PathBuf::from("version_info"),
         handler: Box::new(VersionInfoHandler),
+        api_doc: RouteApiDoc::json::<VersionInfoMap>("Server build and version information"),
     }]
 }
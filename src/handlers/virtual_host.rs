@@ -0,0 +1,147 @@
+use tracing::debug;
+
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use hyper::http::Request;
+
+use crate::{config::VirtualHostSite, handlers::HttpRequest};
+
+#[derive(Debug, PartialEq)]
+enum HostPattern {
+    Exact(String),
+    // "*.example.com" stored as ".example.com"
+    Subdomain(String),
+}
+
+impl HostPattern {
+    fn new(pattern: &str) -> Self {
+        let pattern = pattern.to_ascii_lowercase();
+
+        match pattern.strip_prefix('*') {
+            Some(suffix) => Self::Subdomain(suffix.to_owned()),
+            None => Self::Exact(pattern),
+        }
+    }
+
+    // host is lowercase and without port
+    fn matches(&self, host: &str) -> bool {
+        match self {
+            Self::Exact(exact) => host == exact,
+            Self::Subdomain(suffix) => host.len() > suffix.len() && host.ends_with(suffix.as_str()),
+        }
+    }
+}
+
+// Request extension naming the site that matched the request host.
+#[derive(Clone, Debug)]
+pub struct MatchedVirtualHost {
+    // index into virtual_host_configuration.sites
+    pub index: usize,
+    pub name: Arc<str>,
+}
+
+struct VirtualHost {
+    matched: MatchedVirtualHost,
+    host_patterns: Vec<HostPattern>,
+    routes: Option<Vec<PathBuf>>,
+}
+
+impl VirtualHost {
+    fn new(index: usize, site: &VirtualHostSite) -> Self {
+        Self {
+            matched: MatchedVirtualHost {
+                index,
+                name: Arc::from(site.name.as_str()),
+            },
+            host_patterns: site
+                .hosts
+                .iter()
+                .map(|host| HostPattern::new(host))
+                .collect(),
+            routes: site
+                .routes
+                .as_ref()
+                .map(|routes| routes.iter().map(PathBuf::from).collect()),
+        }
+    }
+}
+
+pub struct VirtualHosts {
+    virtual_hosts: Vec<VirtualHost>,
+}
+
+impl VirtualHosts {
+    pub fn new() -> Self {
+        let sites = &crate::config::instance().virtual_host_configuration.sites;
+
+        Self {
+            virtual_hosts: sites
+                .iter()
+                .enumerate()
+                .map(|(index, site)| VirtualHost::new(index, site))
+                .collect(),
+        }
+    }
+
+    fn find(&self, host: &str) -> Option<&VirtualHost> {
+        let host = crate::request::strip_port(host)
+            .trim_end_matches('.')
+            .to_ascii_lowercase();
+
+        self.virtual_hosts.iter().find(|virtual_host| {
+            virtual_host
+                .host_patterns
+                .iter()
+                .any(|host_pattern| host_pattern.matches(&host))
+        })
+    }
+
+    // The site serving the request's host, None for other hosts.  The
+    // connection handler adds it to the request as an extension.
+    pub fn match_site<B>(&self, hyper_request: &Request<B>) -> Option<MatchedVirtualHost> {
+        if self.virtual_hosts.is_empty() {
+            return None;
+        }
+
+        let virtual_host =
+            crate::request::request_host(hyper_request).and_then(|host| self.find(host))?;
+
+        debug!("virtual host site '{}'", virtual_host.matched.name);
+
+        Some(virtual_host.matched.clone())
+    }
+
+    // Whether the request's site serves the dynamic route with path_suffix.
+    // Requests for other hosts get every route.
+    pub fn serves_route(&self, request: &HttpRequest, path_suffix: &Path) -> bool {
+        let routes = request
+            .extension::<MatchedVirtualHost>()
+            .and_then(|matched| self.virtual_hosts.get(matched.index))
+            .and_then(|virtual_host| virtual_host.routes.as_ref());
+
+        routes.is_none_or(|routes| routes.iter().any(|route| path_suffix.starts_with(route)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_host_pattern() {
+        let exact = HostPattern::new("Example.com");
+        assert_eq!(exact, HostPattern::Exact("example.com".to_owned()));
+        assert!(exact.matches("example.com"));
+        assert!(!exact.matches("www.example.com"));
+
+        let subdomain = HostPattern::new("*.example.com");
+        assert!(subdomain.matches("www.example.com"));
+        assert!(subdomain.matches("a.b.example.com"));
+        assert!(!subdomain.matches("example.com"));
+        assert!(!subdomain.matches(".example.com"));
+        assert!(!subdomain.matches("badexample.com"));
+    }
+}
@@ -0,0 +1,242 @@
+use async_trait::async_trait;
+
+use hyper::{
+    http::{header, HeaderMap, HeaderValue, Method, Response, StatusCode, Version},
+    upgrade::Upgraded,
+};
+
+use hyper_util::rt::TokioIo;
+
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    sync::{broadcast, Mutex},
+};
+
+use tracing::{debug, info, warn, Instrument};
+
+use std::{path::PathBuf, sync::Arc};
+
+use crate::{
+    handlers::{
+        route::{RouteApiDoc, RouteInfo},
+        HttpRequest, RequestHandler, UpgradeHandler,
+    },
+    response::{build_status_code_response, empty_response_body, CacheControl, ResponseBody},
+    websocket::{
+        accept_key, Incoming, Message, WebSocketReader, WebSocketWriter, CLOSE_NORMAL,
+        WEBSOCKET_VERSION,
+    },
+};
+
+const BROADCAST_CHANNEL_CAPACITY: usize = 64;
+
+fn header_has_token(headers: &HeaderMap, name: header::HeaderName, token: &str) -> bool {
+    headers
+        .get_all(name)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|value| value.trim().eq_ignore_ascii_case(token))
+}
+
+fn upgrade_required_response() -> Response<ResponseBody> {
+    let mut response =
+        build_status_code_response(StatusCode::UPGRADE_REQUIRED, CacheControl::NoCache);
+
+    let headers = response.headers_mut();
+    headers.insert(header::UPGRADE, HeaderValue::from_static("websocket"));
+    headers.insert(
+        header::SEC_WEBSOCKET_VERSION,
+        HeaderValue::from_static(WEBSOCKET_VERSION),
+    );
+
+    response
+}
+
+// Completes the WebSocket handshake and hands the upgraded connection to an
+// UpgradeHandler.  Only HTTP/1.1 connections can be upgraded.
+struct WebSocketUpgradeHandler {
+    upgrade_handler: Arc<dyn UpgradeHandler>,
+}
+
+#[async_trait]
+impl RequestHandler for WebSocketUpgradeHandler {
+    async fn handle(&self, request: &HttpRequest) -> Response<ResponseBody> {
+        let headers = request.hyper_request.headers();
+
+        if request.hyper_request.version() != Version::HTTP_11
+            || !header_has_token(headers, header::CONNECTION, "upgrade")
+            || !header_has_token(headers, header::UPGRADE, "websocket")
+            || headers
+                .get(header::SEC_WEBSOCKET_VERSION)
+                .is_none_or(|version| version != WEBSOCKET_VERSION)
+        {
+            return upgrade_required_response();
+        }
+
+        let Some(key) = headers
+            .get(header::SEC_WEBSOCKET_KEY)
+            .filter(|key| !key.is_empty())
+        else {
+            return build_status_code_response(StatusCode::BAD_REQUEST, CacheControl::NoCache);
+        };
+
+        let Some(on_upgrade) = request.take_upgrade() else {
+            return upgrade_required_response();
+        };
+
+        let upgrade_handler = Arc::clone(&self.upgrade_handler);
+
+        tokio::spawn(
+            async move {
+                match on_upgrade.await {
+                    Ok(upgraded) => {
+                        info!("websocket connection upgraded");
+                        upgrade_handler.handle_upgrade(TokioIo::new(upgraded)).await;
+                        info!("websocket connection closed");
+                    }
+                    Err(e) => warn!("websocket upgrade error: {}", e),
+                }
+            }
+            .in_current_span(),
+        );
+
+        Response::builder()
+            .status(StatusCode::SWITCHING_PROTOCOLS)
+            .header(header::CONNECTION, "upgrade")
+            .header(header::UPGRADE, "websocket")
+            .header(header::SEC_WEBSOCKET_ACCEPT, accept_key(key.as_bytes()))
+            .body(empty_response_body())
+            .unwrap()
+    }
+}
+
+// Answers pings and the closing handshake, None once the connection is done.
+async fn next_message<R, W>(
+    reader: &mut WebSocketReader<R>,
+    writer: &Mutex<WebSocketWriter<W>>,
+) -> Option<Message>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    loop {
+        match reader.next().await {
+            Ok(Some(Incoming::Message(message))) => return Some(message),
+            Ok(Some(Incoming::Ping(payload))) => {
+                if writer.lock().await.pong(&payload).await.is_err() {
+                    return None;
+                }
+            }
+            Ok(Some(Incoming::Close(code))) => {
+                let _ = writer
+                    .lock()
+                    .await
+                    .close(Some(code.unwrap_or(CLOSE_NORMAL)))
+                    .await;
+                return None;
+            }
+            Ok(None) => return None,
+            Err(e) => {
+                debug!("websocket error: {}", e);
+                if let Some(code) = e.close_code() {
+                    let _ = writer.lock().await.close(Some(code)).await;
+                }
+                return None;
+            }
+        }
+    }
+}
+
+// Sends every message back to the client that sent it.
+struct EchoHandler {
+    max_message_bytes: usize,
+}
+
+#[async_trait]
+impl UpgradeHandler for EchoHandler {
+    async fn handle_upgrade(&self, stream: TokioIo<Upgraded>) {
+        let (mut reader, writer) = crate::websocket::split(stream, self.max_message_bytes);
+        let writer = Mutex::new(writer);
+
+        while let Some(message) = next_message(&mut reader, &writer).await {
+            if writer.lock().await.send(&message).await.is_err() {
+                break;
+            }
+        }
+    }
+}
+
+// Sends every message to all connected clients, including the sender.
+struct BroadcastHandler {
+    max_message_bytes: usize,
+    sender: broadcast::Sender<Message>,
+}
+
+#[async_trait]
+impl UpgradeHandler for BroadcastHandler {
+    async fn handle_upgrade(&self, stream: TokioIo<Upgraded>) {
+        let (mut reader, writer) = crate::websocket::split(stream, self.max_message_bytes);
+        let writer = Arc::new(Mutex::new(writer));
+
+        let mut receiver = self.sender.subscribe();
+
+        let forward_writer = Arc::clone(&writer);
+        let forward_task = tokio::spawn(
+            async move {
+                loop {
+                    match receiver.recv().await {
+                        Ok(message) => {
+                            if forward_writer.lock().await.send(&message).await.is_err() {
+                                break;
+                            }
+                        }
+                        Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                            warn!("websocket broadcast receiver skipped {} messages", skipped);
+                        }
+                        Err(broadcast::error::RecvError::Closed) => break,
+                    }
+                }
+            }
+            .in_current_span(),
+        );
+
+        while let Some(message) = next_message(&mut reader, &writer).await {
+            let _ = self.sender.send(message);
+        }
+
+        forward_task.abort();
+    }
+}
+
+pub fn create_routes() -> Vec<RouteInfo> {
+    // messages are held in memory like collected request bodies
+    let max_message_bytes = crate::config::instance()
+        .server_configuration
+        .limits
+        .max_body_bytes;
+
+    let (sender, _) = broadcast::channel(BROADCAST_CHANNEL_CAPACITY);
+
+    vec![
+        RouteInfo {
+            method: &Method::GET,
+            path_suffix: PathBuf::from("websocket/echo"),
+            handler: Box::new(WebSocketUpgradeHandler {
+                upgrade_handler: Arc::new(EchoHandler { max_message_bytes }),
+            }),
+            api_doc: RouteApiDoc::text("WebSocket echo"),
+        },
+        RouteInfo {
+            method: &Method::GET,
+            path_suffix: PathBuf::from("websocket/broadcast"),
+            handler: Box::new(WebSocketUpgradeHandler {
+                upgrade_handler: Arc::new(BroadcastHandler {
+                    max_message_bytes,
+                    sender,
+                }),
+            }),
+            api_doc: RouteApiDoc::text("WebSocket broadcast to all connected clients"),
+        },
+    ]
+}
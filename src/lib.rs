@@ -0,0 +1,163 @@
+mod access_log;
+mod build_id;
+mod client;
+mod config;
+mod connection;
+mod crash_report;
+mod fd_limits;
+mod geoip;
+mod handlers;
+mod reload;
+mod request;
+mod request_metrics;
+mod resource_usage;
+mod response;
+mod route_metrics;
+mod runtime;
+mod server;
+mod shutdown;
+mod snapshot;
+mod static_file;
+#[doc(hidden)]
+pub mod test_util;
+mod tracing_config;
+mod traffic_stats;
+mod uptime;
+mod version;
+mod websocket;
+
+use anyhow::Context;
+
+use tracing::{debug, error, info, instrument};
+
+use std::sync::Arc;
+
+use crate::shutdown::ShutdownTrigger;
+
+async fn log_version_info() {
+    info!("Version Info:");
+    for (key, value) in version::get_verison_info() {
+        info!("{}: {}", key, value);
+    }
+}
+
+fn app_name() -> String {
+    std::env::args().next().unwrap_or("[UNKNOWN]".to_owned())
+}
+
+async fn start_server() -> anyhow::Result<crate::server::Server> {
+    crate::static_file::create_rules_service_instance()?;
+
+    crate::geoip::create_geoip_service_instance()?;
+
+    crate::access_log::create_access_log_instance()?;
+
+    crate::server::create_fd_reserve_instance()?;
+
+    crate::fd_limits::spawn_fd_usage_monitor();
+
+    crate::snapshot::spawn_snapshot_writer();
+
+    crate::reload::spawn_reload_on_sighup()?;
+
+    if crate::config::instance()
+        .logging_configuration
+        .connection_events
+    {
+        crate::connection::register_connection_observer(Arc::new(
+            crate::connection::ConnectionEventLogObserver,
+        ));
+    }
+
+    let handlers = handlers::create_handlers().await?;
+
+    Ok(crate::server::Server::new(handlers).await)
+}
+
+async fn run_server() -> (ShutdownTrigger, anyhow::Result<()>) {
+    let server = match start_server().await {
+        Err(err) => return (ShutdownTrigger::Startup, Err(err)),
+        Ok(server) => server,
+    };
+
+    tokio::select! {
+        result = server.run() => (ShutdownTrigger::Server, result),
+        result = shutdown::wait_for_shutdown_signal() => match result {
+            Err(err) => (ShutdownTrigger::Startup, Err(err)),
+            Ok(signal) => (ShutdownTrigger::Signal(signal), Ok(())),
+        },
+        () = shutdown::wait_for_drain() => (ShutdownTrigger::Drain, Ok(())),
+    }
+}
+
+#[instrument]
+async fn try_main() -> anyhow::Result<()> {
+    log_version_info().await;
+
+    let (shutdown_trigger, result) = run_server().await;
+
+    if let ShutdownTrigger::Drain = shutdown_trigger {
+        shutdown::wait_for_drained_connections().await;
+    }
+
+    shutdown::log_shutdown_report(shutdown_trigger, result.as_ref().err()).await;
+
+    result
+}
+
+fn run() -> anyhow::Result<()> {
+    let mut args = std::env::args().skip(1).peekable();
+
+    if args.next_if(|arg| arg == "client").is_some() {
+        return crate::client::run(args);
+    }
+
+    let profile = match args.next_if(|arg| arg == "--profile") {
+        Some(_) => Some(args.next().context("--profile requires a profile name")?),
+        None => std::env::var(crate::config::PROFILE_ENV_VAR).ok(),
+    }
+    .map(|profile| profile.parse::<crate::config::ConfigurationProfile>())
+    .transpose()?;
+
+    let config_file = match args.next() {
+        Some(config_file) => config_file,
+        None if std::env::var_os(crate::config::CONFIG_ENV_VAR).is_some() => {
+            crate::config::env_config_file(crate::config::CONFIG_ENV_VAR)
+        }
+        None if profile.is_some() => crate::config::NO_CONFIG_FILE.to_owned(),
+        None => anyhow::bail!(
+            "config file required as command line argument: {} [--profile container] <config file | - | env:NAME>",
+            app_name(),
+        ),
+    };
+
+    crate::uptime::initialize();
+
+    crate::config::read_configuration(config_file, profile).context("read_configuration error")?;
+
+    tracing_config::initialize_tracing_subscriber(
+        &crate::config::instance().logging_configuration,
+    )?;
+
+    if let Some(profile) = crate::config::profile() {
+        info!("using configuration profile {:?}", profile);
+    }
+
+    debug!("configuration\n{:#?}", crate::config::instance());
+
+    crate::crash_report::install_panic_hook();
+
+    crate::fd_limits::raise_nofile_limit(&crate::config::instance().file_descriptor_configuration)?;
+
+    let runtime = crate::runtime::build_runtime()?;
+
+    runtime.block_on(try_main())
+}
+
+pub fn main() {
+    if let Err(err) = run() {
+        tracing_config::initialize_fallback_tracing_subscriber();
+        error!("fatal error in main:\n{:#}", err);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,207 @@
+use anyhow::Context;
+
+use serde_json::Value;
+
+use tracing::{info, warn};
+
+use std::sync::Mutex;
+
+use crate::config::Configuration;
+
+// SIGHUP and admin/config/reload may race.
+static RELOAD_MUTEX: Mutex<()> = Mutex::new(());
+
+// Configuration paths applied by a reload, changes anywhere else are logged
+// and take effect on the next restart.
+const RELOADABLE_PATHS: [&str; 7] = [
+    "logging_configuration.filter",
+    "server_configuration.connection.graceful_shutdown_timeout",
+    "server_configuration.connection.max_lifetime",
+    "static_file_configuration.cache_rule_matching",
+    "static_file_configuration.cache_rules",
+    "static_file_configuration.filter_rules",
+    "static_file_configuration.preload_rules",
+];
+
+#[derive(Debug, PartialEq)]
+struct ConfigurationChange {
+    path: String,
+    old_value: Value,
+    new_value: Value,
+}
+
+impl ConfigurationChange {
+    fn is_reloadable(&self) -> bool {
+        RELOADABLE_PATHS.iter().any(|reloadable_path| {
+            self.path == *reloadable_path
+                || self
+                    .path
+                    .strip_prefix(reloadable_path)
+                    .is_some_and(|rest| rest.starts_with(['.', '[']))
+        })
+    }
+}
+
+// Changed leaves between two serialized configurations, arrays of different
+// lengths are reported as a whole.
+fn diff_values(path: &str, old: &Value, new: &Value, changes: &mut Vec<ConfigurationChange>) {
+    match (old, new) {
+        (Value::Object(old_map), Value::Object(new_map)) => {
+            let mut keys: Vec<&String> = old_map.keys().chain(new_map.keys()).collect();
+            keys.sort_unstable();
+            keys.dedup();
+
+            for key in keys {
+                let child_path = if path.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{}.{}", path, key)
+                };
+
+                diff_values(
+                    &child_path,
+                    old_map.get(key).unwrap_or(&Value::Null),
+                    new_map.get(key).unwrap_or(&Value::Null),
+                    changes,
+                );
+            }
+        }
+        (Value::Array(old_array), Value::Array(new_array))
+            if old_array.len() == new_array.len() =>
+        {
+            for (index, (old, new)) in old_array.iter().zip(new_array).enumerate() {
+                diff_values(&format!("{}[{}]", path, index), old, new, changes);
+            }
+        }
+        _ if old != new => changes.push(ConfigurationChange {
+            path: path.to_owned(),
+            old_value: old.clone(),
+            new_value: new.clone(),
+        }),
+        _ => {}
+    }
+}
+
+fn configuration_changes(
+    old: &Configuration,
+    new: &Configuration,
+) -> anyhow::Result<Vec<ConfigurationChange>> {
+    let old = serde_json::to_value(old).context("serde_json::to_value error")?;
+    let new = serde_json::to_value(new).context("serde_json::to_value error")?;
+
+    let mut changes = Vec::new();
+    diff_values("", &old, &new, &mut changes);
+
+    Ok(changes)
+}
+
+fn apply_configuration(old: &Configuration, new: &Configuration) -> anyhow::Result<()> {
+    let changes = configuration_changes(old, new)?;
+
+    if changes.is_empty() {
+        info!("configuration reload: no changes");
+    }
+
+    for change in &changes {
+        if change.is_reloadable() {
+            info!(
+                "configuration reload: {} changed from {} to {}",
+                change.path, change.old_value, change.new_value
+            );
+        } else {
+            warn!(
+                "configuration reload: {} changed from {} to {}, restart required to apply",
+                change.path, change.old_value, change.new_value
+            );
+        }
+    }
+
+    crate::static_file::reload_rules_service_instance(&new.static_file_configuration)
+        .context("error reloading static file rules")?;
+
+    if old.logging_configuration.filter != new.logging_configuration.filter {
+        let filter = crate::tracing_config::reload_log_filter(&new.logging_configuration)?;
+        info!("log filter set to '{}'", filter);
+    }
+
+    Ok(())
+}
+
+// Re-reads, validates and applies the configuration file, returning the new
+// configuration generation.
+pub fn reload() -> anyhow::Result<u64> {
+    let _guard = RELOAD_MUTEX.lock().unwrap();
+
+    let old = crate::config::current();
+
+    let result = crate::config::reload_configuration(|new| apply_configuration(&old, new));
+
+    match &result {
+        Ok(generation) => info!("configuration reloaded generation = {}", generation),
+        Err(e) => warn!("configuration reload rejected: {:#}", e),
+    }
+
+    result
+}
+
+#[cfg(unix)]
+pub fn spawn_reload_on_sighup() -> anyhow::Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut sighup = signal(SignalKind::hangup()).context("error installing SIGHUP handler")?;
+
+    tokio::spawn(async move {
+        while sighup.recv().await.is_some() {
+            info!("received SIGHUP, reloading configuration");
+
+            // reload reads the file synchronously
+            if let Err(e) = tokio::task::spawn_blocking(reload).await {
+                warn!("reload spawn_blocking error: {}", e);
+            }
+        }
+    });
+
+    Ok(())
+}
+
+// There is no SIGHUP outside unix, POST admin/config/reload still works.
+#[cfg(not(unix))]
+pub fn spawn_reload_on_sighup() -> anyhow::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use serde_json::json;
+
+    #[test]
+    fn test_diff_values() {
+        let old = json!({
+            "logging_configuration": {"filter": null, "outputs": ["STDOUT"]},
+            "static_file_configuration": {"cache_rules": [{"duration": "1h"}], "root": "/a"},
+        });
+        let new = json!({
+            "logging_configuration": {"filter": "debug", "outputs": ["STDOUT", "SYSLOG"]},
+            "static_file_configuration": {"cache_rules": [{"duration": "5m"}], "root": "/a"},
+        });
+
+        let mut changes = Vec::new();
+        diff_values("", &old, &new, &mut changes);
+
+        let changes: Vec<_> = changes
+            .iter()
+            .map(|change| (change.path.as_str(), change.is_reloadable()))
+            .collect();
+
+        assert_eq!(
+            changes,
+            vec![
+                ("logging_configuration.filter", true),
+                ("logging_configuration.outputs", false),
+                ("static_file_configuration.cache_rules[0].duration", true),
+            ]
+        );
+    }
+}
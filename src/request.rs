@@ -1,8 +1,44 @@
-use hyper::{body::Incoming, http::Request};
+mod body;
+mod deadline;
+mod forwarded;
+mod limits;
+mod path;
+mod query;
+mod target;
 
-use std::sync::atomic::{AtomicUsize, Ordering};
+use bytes::Bytes;
 
-use crate::connection::ConnectionID;
+use hyper::{
+    body::Incoming,
+    http::{Extensions, Request},
+    upgrade::OnUpgrade,
+};
+
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, OnceLock,
+    },
+};
+
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    connection::{ConnectionID, PeerCredentials, SocketMetadata, TlsInfo},
+    geoip::GeoInfo,
+};
+
+pub use body::RequestBodyError;
+#[cfg(test)]
+pub use deadline::parse_grpc_timeout;
+pub use deadline::{format_grpc_timeout, RequestDeadline, GRPC_TIMEOUT};
+pub use forwarded::client_address;
+pub use limits::check_request_limits;
+pub use query::QueryParams;
+pub use target::{
+    canonical_redirect, normalize_request_target, request_host, strip_port, RequestTargetResult,
+};
 
 #[derive(Clone, Copy, Debug)]
 pub struct RequestID(usize);
@@ -11,13 +47,27 @@ impl RequestID {
     pub fn as_usize(&self) -> usize {
         self.0
     }
+
+    #[cfg(test)]
+    pub fn new_for_test(id: usize) -> Self {
+        Self(id)
+    }
 }
 
 #[derive(Debug)]
 pub struct HttpRequest {
     pub connection_id: ConnectionID,
     pub request_id: RequestID,
-    pub hyper_request: Request<Incoming>,
+    // The body is split off so handlers can take it through a shared reference.
+    pub hyper_request: Request<()>,
+    body: Mutex<Option<Incoming>>,
+    upgrade: Mutex<Option<OnUpgrade>>,
+    max_body_bytes: usize,
+    socket_metadata: Arc<SocketMetadata>,
+    client_address: Option<IpAddr>,
+    cancellation_token: CancellationToken,
+    query_params: OnceLock<QueryParams>,
+    extensions: Mutex<Extensions>,
 }
 
 impl HttpRequest {
@@ -25,13 +75,106 @@ impl HttpRequest {
         connection_id: ConnectionID,
         request_id: RequestID,
         hyper_request: Request<Incoming>,
+        max_body_bytes: usize,
+        socket_metadata: Arc<SocketMetadata>,
+        client_address: Option<IpAddr>,
+        cancellation_token: CancellationToken,
     ) -> Self {
+        let (mut parts, body) = hyper_request.into_parts();
+
+        let upgrade = parts.extensions.remove::<OnUpgrade>();
+
         Self {
             connection_id,
             request_id,
-            hyper_request,
+            hyper_request: Request::from_parts(parts, ()),
+            body: Mutex::new(Some(body)),
+            upgrade: Mutex::new(upgrade),
+            max_body_bytes,
+            socket_metadata,
+            client_address,
+            cancellation_token,
+            query_params: OnceLock::new(),
+            extensions: Mutex::new(Extensions::new()),
         }
     }
+
+    // Cancelled when the client goes away before the response is finished.
+    pub fn cancellation_token(&self) -> &CancellationToken {
+        &self.cancellation_token
+    }
+
+    // Takes the streaming request body, returns None if already taken.
+    pub fn take_body(&self) -> Option<Incoming> {
+        self.body.lock().unwrap().take()
+    }
+
+    // Resolves to the raw connection once a 101 response has been sent,
+    // None for HTTP/2 requests or if already taken.
+    pub fn take_upgrade(&self) -> Option<OnUpgrade> {
+        self.upgrade.lock().unwrap().take()
+    }
+
+    // The configured limit for bodies without a content-length, handlers
+    // streaming the body enforce it themselves.
+    pub fn max_body_bytes(&self) -> usize {
+        self.max_body_bytes
+    }
+
+    // Collects the request body into memory, failing if it exceeds max_bytes
+    // or max_body_bytes().
+    pub async fn collect_body(&self, max_bytes: usize) -> Result<Bytes, RequestBodyError> {
+        let body = self.take_body().ok_or(RequestBodyError::AlreadyTaken)?;
+
+        body::collect_limited(body, max_bytes.min(self.max_body_bytes)).await
+    }
+
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        self.socket_metadata.peer_addr
+    }
+
+    // The proxy that sent a PROXY protocol header, peer_addr is then the
+    // client it reported.
+    pub fn proxy_addr(&self) -> Option<SocketAddr> {
+        self.socket_metadata.proxy_addr
+    }
+
+    // The peer address, or for requests from trusted proxies the address
+    // they forwarded the request for.
+    pub fn client_address(&self) -> Option<IpAddr> {
+        self.client_address
+    }
+
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.socket_metadata.local_addr
+    }
+
+    pub fn peer_credentials(&self) -> Option<PeerCredentials> {
+        self.socket_metadata.peer_credentials
+    }
+
+    pub fn geo_info(&self) -> Option<&GeoInfo> {
+        self.socket_metadata.geo_info.as_ref()
+    }
+
+    pub fn tls_info(&self) -> Option<&TlsInfo> {
+        self.socket_metadata.tls_info.as_ref()
+    }
+
+    // Parsed lazily on first access.
+    pub fn query_params(&self) -> &QueryParams {
+        self.query_params
+            .get_or_init(|| QueryParams::parse(self.hyper_request.uri().query()))
+    }
+
+    // Per-request metadata attached by middleware and read by downstream handlers.
+    pub fn insert_extension<T: Clone + Send + Sync + 'static>(&self, value: T) -> Option<T> {
+        self.extensions.lock().unwrap().insert(value)
+    }
+
+    pub fn extension<T: Clone + Send + Sync + 'static>(&self) -> Option<T> {
+        self.extensions.lock().unwrap().get::<T>().cloned()
+    }
 }
 
 pub struct RequestIDFactory {
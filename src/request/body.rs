@@ -0,0 +1,25 @@
+use bytes::Bytes;
+
+use http_body_util::{BodyExt, LengthLimitError, Limited};
+
+use hyper::body::Incoming;
+
+#[derive(thiserror::Error, Debug)]
+pub enum RequestBodyError {
+    #[error("request body already taken")]
+    AlreadyTaken,
+
+    #[error("request body exceeds limit of {0} bytes")]
+    TooLarge(usize),
+
+    #[error("request body read error: {0}")]
+    Read(Box<dyn std::error::Error + Send + Sync>),
+}
+
+pub async fn collect_limited(body: Incoming, max_bytes: usize) -> Result<Bytes, RequestBodyError> {
+    match Limited::new(body, max_bytes).collect().await {
+        Ok(collected) => Ok(collected.to_bytes()),
+        Err(e) if e.is::<LengthLimitError>() => Err(RequestBodyError::TooLarge(max_bytes)),
+        Err(e) => Err(RequestBodyError::Read(e)),
+    }
+}
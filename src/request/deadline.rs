@@ -0,0 +1,137 @@
+use hyper::http::HeaderMap;
+
+use tokio::time::{Duration, Instant};
+
+pub const GRPC_TIMEOUT: &str = "grpc-timeout";
+
+// grpc-timeout units, shortest first.
+const GRPC_TIMEOUT_UNITS: [(char, u128); 6] = [
+    ('n', 1),
+    ('u', 1_000),
+    ('m', 1_000_000),
+    ('S', 1_000_000_000),
+    ('M', 60 * 1_000_000_000),
+    ('H', 60 * 60 * 1_000_000_000),
+];
+
+// grpc-timeout values have at most 8 digits.
+const GRPC_TIMEOUT_MAX_VALUE: u128 = 99_999_999;
+
+// When a request must be answered by: the earlier of the connection being
+// closed once its max_lifetime and graceful_shutdown_timeout have passed, and
+// the client's own grpc-timeout.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RequestDeadline(pub Instant);
+
+impl RequestDeadline {
+    pub fn new(
+        headers: &HeaderMap,
+        start: Instant,
+        connection_deadline: Option<Instant>,
+    ) -> Option<Self> {
+        let client_deadline = headers
+            .get(GRPC_TIMEOUT)
+            .and_then(|value| value.to_str().ok())
+            .and_then(parse_grpc_timeout)
+            .and_then(|timeout| start.checked_add(timeout));
+
+        client_deadline
+            .into_iter()
+            .chain(connection_deadline)
+            .min()
+            .map(Self)
+    }
+
+    pub fn remaining(&self) -> Duration {
+        self.0.saturating_duration_since(Instant::now())
+    }
+}
+
+// gRPC over HTTP/2 Timeout: 1 to 8 digits followed by a unit.
+pub fn parse_grpc_timeout(value: &str) -> Option<Duration> {
+    let unit = value.chars().last()?;
+    let digits = &value[..value.len() - unit.len_utf8()];
+
+    if digits.is_empty() || digits.len() > 8 || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+
+    let (_, nanos_per_unit) = GRPC_TIMEOUT_UNITS.iter().find(|(u, _)| *u == unit)?;
+
+    let nanos = digits.parse::<u128>().ok()? * nanos_per_unit;
+
+    Some(Duration::new(
+        u64::try_from(nanos / 1_000_000_000).ok()?,
+        u32::try_from(nanos % 1_000_000_000).ok()?,
+    ))
+}
+
+// The most precise unit that fits, rounding down.
+pub fn format_grpc_timeout(timeout: Duration) -> String {
+    let nanos = timeout.as_nanos();
+
+    let (unit, value) = GRPC_TIMEOUT_UNITS
+        .iter()
+        .map(|(unit, nanos_per_unit)| (unit, nanos / nanos_per_unit))
+        .find(|(_, value)| *value <= GRPC_TIMEOUT_MAX_VALUE)
+        .unwrap_or((&'H', GRPC_TIMEOUT_MAX_VALUE));
+
+    format!("{}{}", value, unit)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use hyper::http::HeaderValue;
+
+    #[test]
+    fn test_grpc_timeout() {
+        assert_eq!(parse_grpc_timeout("100m"), Some(Duration::from_millis(100)));
+        assert_eq!(parse_grpc_timeout("2H"), Some(Duration::from_secs(7200)));
+        assert_eq!(parse_grpc_timeout("5n"), Some(Duration::from_nanos(5)));
+        assert_eq!(
+            parse_grpc_timeout("99999999H"),
+            Some(Duration::from_secs(99_999_999 * 3600))
+        );
+        assert_eq!(parse_grpc_timeout("123456789S"), None);
+        assert_eq!(parse_grpc_timeout("m"), None);
+        assert_eq!(parse_grpc_timeout("1x"), None);
+        assert_eq!(parse_grpc_timeout("-1S"), None);
+
+        assert_eq!(format_grpc_timeout(Duration::from_nanos(5)), "5n");
+        assert_eq!(format_grpc_timeout(Duration::from_millis(1500)), "1500000u");
+        assert_eq!(format_grpc_timeout(Duration::from_secs(30)), "30000000u");
+        assert_eq!(format_grpc_timeout(Duration::from_secs(3600)), "3600000m");
+        assert_eq!(format_grpc_timeout(Duration::from_secs(200_000)), "200000S");
+    }
+
+    #[test]
+    fn test_request_deadline() {
+        let start = Instant::now();
+        let connection_deadline = start + Duration::from_secs(60);
+
+        let mut headers = HeaderMap::new();
+        assert_eq!(RequestDeadline::new(&headers, start, None), None);
+        assert_eq!(
+            RequestDeadline::new(&headers, start, Some(connection_deadline)),
+            Some(RequestDeadline(connection_deadline))
+        );
+
+        headers.insert(GRPC_TIMEOUT, HeaderValue::from_static("5S"));
+        assert_eq!(
+            RequestDeadline::new(&headers, start, Some(connection_deadline)),
+            Some(RequestDeadline(start + Duration::from_secs(5)))
+        );
+        assert_eq!(
+            RequestDeadline::new(&headers, start, None),
+            Some(RequestDeadline(start + Duration::from_secs(5)))
+        );
+
+        headers.insert(GRPC_TIMEOUT, HeaderValue::from_static("2M"));
+        assert_eq!(
+            RequestDeadline::new(&headers, start, Some(connection_deadline)),
+            Some(RequestDeadline(connection_deadline))
+        );
+    }
+}
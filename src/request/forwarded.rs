@@ -0,0 +1,178 @@
+use hyper::http::{header, HeaderMap};
+
+use ipnet::IpNet;
+
+use std::net::{IpAddr, SocketAddr};
+
+// A Forwarded for= value: an address with optional port, IPv6 in brackets,
+// possibly quoted.  Obfuscated identifiers and "unknown" give None.
+fn parse_forwarded_for(value: &str) -> Option<IpAddr> {
+    let value = value.trim().trim_matches('"');
+
+    value
+        .parse::<IpAddr>()
+        .ok()
+        .or_else(|| value.parse::<SocketAddr>().ok().map(|addr| addr.ip()))
+        .or_else(|| {
+            value
+                .strip_prefix('[')
+                .and_then(|value| value.strip_suffix(']'))
+                .and_then(|value| value.parse().ok())
+        })
+}
+
+// Addresses each proxy appended, from the Forwarded header if sent,
+// otherwise from X-Forwarded-For.  Unparseable entries are None.
+fn forwarded_addresses(headers: &HeaderMap) -> Vec<Option<IpAddr>> {
+    let header_values = |name| {
+        headers
+            .get_all(name)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+    };
+
+    if headers.contains_key(header::FORWARDED) {
+        header_values(header::FORWARDED)
+            .map(|element| {
+                element
+                    .split(';')
+                    .filter_map(|pair| pair.split_once('='))
+                    .find(|(name, _)| name.trim().eq_ignore_ascii_case("for"))
+                    .and_then(|(_, value)| parse_forwarded_for(value))
+            })
+            .collect()
+    } else {
+        header_values(header::HeaderName::from_static("x-forwarded-for"))
+            .map(|value| value.trim().parse().ok())
+            .collect()
+    }
+}
+
+// The client address for requests from peer: for peers in trusted_proxies
+// the rightmost forwarded address not itself in trusted_proxies, since each
+// proxy appends the address it received the request from.
+pub fn client_address(peer: IpAddr, headers: &HeaderMap, trusted_proxies: &[IpNet]) -> IpAddr {
+    let trusted = |address: &IpAddr| {
+        trusted_proxies
+            .iter()
+            .any(|network| network.contains(address))
+    };
+
+    if !trusted(&peer) {
+        return peer;
+    }
+
+    let mut client = peer;
+
+    for address in forwarded_addresses(headers).into_iter().rev() {
+        let Some(address) = address else {
+            break;
+        };
+
+        client = address;
+
+        if !trusted(&address) {
+            break;
+        }
+    }
+
+    client
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use hyper::http::HeaderValue;
+
+    #[test]
+    fn test_client_address() {
+        let trusted_proxies: Vec<IpNet> = vec![
+            "10.0.0.0/8".parse().unwrap(),
+            "2001:db8::/32".parse().unwrap(),
+        ];
+
+        let proxy: IpAddr = "10.1.2.3".parse().unwrap();
+        let other: IpAddr = "192.0.2.9".parse().unwrap();
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "x-forwarded-for",
+            HeaderValue::from_static("198.51.100.1, 203.0.113.7, 10.0.0.5"),
+        );
+
+        assert_eq!(
+            client_address(proxy, &headers, &trusted_proxies),
+            "203.0.113.7".parse::<IpAddr>().unwrap()
+        );
+        assert_eq!(client_address(other, &headers, &trusted_proxies), other);
+        assert_eq!(
+            client_address(proxy, &HeaderMap::new(), &trusted_proxies),
+            proxy
+        );
+
+        headers.insert(
+            header::FORWARDED,
+            HeaderValue::from_static(
+                r#"for=198.51.100.1;proto=https, for="[2001:db8::7]:4711", For="203.0.113.8:80""#,
+            ),
+        );
+        assert_eq!(
+            client_address(proxy, &headers, &trusted_proxies),
+            "203.0.113.8".parse::<IpAddr>().unwrap()
+        );
+
+        headers.insert(
+            header::FORWARDED,
+            HeaderValue::from_static("for=198.51.100.1, for=\"[2001:db8::7]:4711\""),
+        );
+        assert_eq!(
+            client_address(proxy, &headers, &trusted_proxies),
+            "198.51.100.1".parse::<IpAddr>().unwrap()
+        );
+
+        headers.insert(
+            header::FORWARDED,
+            HeaderValue::from_static("for=198.51.100.1, for=_hidden"),
+        );
+        assert_eq!(client_address(proxy, &headers, &trusted_proxies), proxy);
+    }
+
+    #[test]
+    fn test_client_address_x_forwarded_for() {
+        let trusted_proxies: Vec<IpNet> = vec![
+            "10.0.0.1/32".parse().unwrap(),
+            "10.0.0.2/32".parse().unwrap(),
+        ];
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "x-forwarded-for",
+            HeaderValue::from_static("1.1.1.1, 2.2.2.2, 10.0.0.2"),
+        );
+
+        let proxy: IpAddr = "10.0.0.1".parse().unwrap();
+        let other: IpAddr = "3.3.3.3".parse().unwrap();
+
+        assert_eq!(
+            client_address(proxy, &headers, &trusted_proxies),
+            "2.2.2.2".parse::<IpAddr>().unwrap()
+        );
+        assert_eq!(client_address(other, &headers, &trusted_proxies), other);
+        assert_eq!(
+            client_address(proxy, &HeaderMap::new(), &trusted_proxies),
+            proxy
+        );
+
+        // an unparseable entry stops the search at the last trusted address
+        headers.insert(
+            "x-forwarded-for",
+            HeaderValue::from_static("1.1.1.1, garbage, 10.0.0.2"),
+        );
+        assert_eq!(
+            client_address(proxy, &headers, &trusted_proxies),
+            "10.0.0.2".parse::<IpAddr>().unwrap()
+        );
+    }
+}
@@ -0,0 +1,112 @@
+use hyper::http::{header, Request, Response, StatusCode};
+
+use tracing::warn;
+
+use crate::{
+    config::ServerLimitsConfiguration,
+    response::{build_deny_response, DenyReason, ResponseBody},
+};
+
+// hyper enforces max_header_bytes while reading HTTP/1 and HTTP/2 headers,
+// this catches what gets through, such as HTTP/2 header lists counted
+// differently.  Bodies with a content-length over the limit are rejected
+// before they are read.
+pub fn check_request_limits<B>(
+    hyper_request: &Request<B>,
+    header_bytes: u64,
+    limits: &ServerLimitsConfiguration,
+) -> Option<Response<ResponseBody>> {
+    let uri_length = hyper_request
+        .uri()
+        .path_and_query()
+        .map_or(0, |path_and_query| path_and_query.as_str().len());
+
+    if uri_length > limits.max_uri_length {
+        warn!(
+            "uri length {} exceeds limit {}",
+            uri_length, limits.max_uri_length
+        );
+        return Some(build_deny_response(
+            StatusCode::URI_TOO_LONG,
+            DenyReason::RequestLimit,
+        ));
+    }
+
+    if header_bytes > limits.max_header_bytes as u64 {
+        warn!(
+            "header bytes {} exceed limit {}",
+            header_bytes, limits.max_header_bytes
+        );
+        return Some(build_deny_response(
+            StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE,
+            DenyReason::RequestLimit,
+        ));
+    }
+
+    let content_length = hyper_request
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok());
+
+    if content_length.is_some_and(|content_length| content_length > limits.max_body_bytes as u64) {
+        warn!(
+            "content-length {:?} exceeds limit {}",
+            content_length, limits.max_body_bytes
+        );
+        return Some(build_deny_response(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            DenyReason::RequestLimit,
+        ));
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn limits() -> ServerLimitsConfiguration {
+        ServerLimitsConfiguration {
+            max_header_bytes: 8192,
+            max_uri_length: 16,
+            max_body_bytes: 100,
+        }
+    }
+
+    fn status(hyper_request: &Request<()>, header_bytes: u64) -> Option<StatusCode> {
+        check_request_limits(hyper_request, header_bytes, &limits())
+            .map(|response| response.status())
+    }
+
+    #[test]
+    fn test_check_request_limits() {
+        let request = Request::builder().uri("/short").body(()).unwrap();
+        assert_eq!(status(&request, 100), None);
+        assert_eq!(
+            status(&request, 8193),
+            Some(StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE)
+        );
+
+        let request = Request::builder()
+            .uri("http://localhost/short?query=long")
+            .body(())
+            .unwrap();
+        assert_eq!(status(&request, 100), Some(StatusCode::URI_TOO_LONG));
+
+        let request = Request::builder()
+            .uri("/upload")
+            .header(header::CONTENT_LENGTH, "101")
+            .body(())
+            .unwrap();
+        assert_eq!(status(&request, 100), Some(StatusCode::PAYLOAD_TOO_LARGE));
+
+        let request = Request::builder()
+            .uri("/upload")
+            .header(header::CONTENT_LENGTH, "100")
+            .body(())
+            .unwrap();
+        assert_eq!(status(&request, 100), None);
+    }
+}
@@ -0,0 +1,121 @@
+use percent_encoding::{percent_decode_str, percent_encode, AsciiSet, CONTROLS};
+
+// Characters that must be re-encoded after decoding so the result is a valid URI path.
+const PATH_ENCODE_SET: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'[')
+    .add(b'\\')
+    .add(b']')
+    .add(b'^')
+    .add(b'`')
+    .add(b'{')
+    .add(b'|')
+    .add(b'}');
+
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum PathNormalizationError {
+    #[error("path does not start with '/'")]
+    NotAbsolute,
+
+    #[error("path contains NUL byte")]
+    NulByte,
+
+    #[error("path escapes root with '..'")]
+    EscapesRoot,
+}
+
+// Percent-decodes the path, collapses empty and '.' segments, resolves '..'
+// segments, and re-encodes the result.  Paths that contain NUL bytes or that
+// would escape the root are rejected.
+pub fn normalize_path(path: &str) -> Result<String, PathNormalizationError> {
+    if !path.starts_with('/') {
+        return Err(PathNormalizationError::NotAbsolute);
+    }
+
+    let decoded: Vec<u8> = percent_decode_str(path).collect();
+
+    if decoded.contains(&0) {
+        return Err(PathNormalizationError::NulByte);
+    }
+
+    let mut segments: Vec<&[u8]> = Vec::new();
+
+    for segment in decoded.split(|b| *b == b'/') {
+        match segment {
+            b"" | b"." => {}
+            b".." => {
+                if segments.pop().is_none() {
+                    return Err(PathNormalizationError::EscapesRoot);
+                }
+            }
+            _ => segments.push(segment),
+        }
+    }
+
+    let ends_with_slash = matches!(
+        decoded.rsplit(|b| *b == b'/').next(),
+        Some(b"" | b"." | b"..")
+    );
+
+    let mut normalized = String::with_capacity(path.len());
+
+    for segment in &segments {
+        normalized.push('/');
+        normalized.extend(percent_encode(segment, PATH_ENCODE_SET));
+    }
+
+    if segments.is_empty() || ends_with_slash {
+        normalized.push('/');
+    }
+
+    Ok(normalized)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_normalize_path() {
+        assert_eq!(normalize_path("/"), Ok("/".to_owned()));
+        assert_eq!(normalize_path("/a/b"), Ok("/a/b".to_owned()));
+        assert_eq!(normalize_path("/a/b/"), Ok("/a/b/".to_owned()));
+        assert_eq!(normalize_path("//a//b"), Ok("/a/b".to_owned()));
+        assert_eq!(normalize_path("/a/./b/."), Ok("/a/b/".to_owned()));
+        assert_eq!(normalize_path("/a/../b"), Ok("/b".to_owned()));
+        assert_eq!(normalize_path("/a/b/.."), Ok("/a/".to_owned()));
+        assert_eq!(normalize_path("/a%20b"), Ok("/a%20b".to_owned()));
+        assert_eq!(normalize_path("/%7Euser"), Ok("/~user".to_owned()));
+        assert_eq!(normalize_path("/a%2Fb"), Ok("/a/b".to_owned()));
+        assert_eq!(
+            normalize_path("/%2e%2e/x"),
+            Err(PathNormalizationError::EscapesRoot)
+        );
+        assert_eq!(
+            normalize_path("/%2egit/config"),
+            Ok("/.git/config".to_owned())
+        );
+        assert_eq!(
+            normalize_path("/.."),
+            Err(PathNormalizationError::EscapesRoot)
+        );
+        assert_eq!(
+            normalize_path("/a/../../b"),
+            Err(PathNormalizationError::EscapesRoot)
+        );
+        assert_eq!(
+            normalize_path("/a%00b"),
+            Err(PathNormalizationError::NulByte)
+        );
+        assert_eq!(
+            normalize_path("a"),
+            Err(PathNormalizationError::NotAbsolute)
+        );
+    }
+}
@@ -0,0 +1,61 @@
+#[derive(Debug, Default)]
+pub struct QueryParams {
+    params: Vec<(String, String)>,
+}
+
+impl QueryParams {
+    pub fn parse(query: Option<&str>) -> Self {
+        let params = query
+            .map(|query| {
+                form_urlencoded::parse(query.as_bytes())
+                    .map(|(key, value)| (key.into_owned(), value.into_owned()))
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        Self { params }
+    }
+
+    // First value for key.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    // All values for key in request order.
+    pub fn get_all<'a>(&'a self, key: &'a str) -> impl Iterator<Item = &'a str> {
+        self.params
+            .iter()
+            .filter(move |(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.params.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_query_params() {
+        let query_params = QueryParams::parse(Some("a=1&b=hello%20world&a=2&c=x+y&d"));
+
+        assert_eq!(query_params.get("a"), Some("1"));
+        assert_eq!(
+            query_params.get_all("a").collect::<Vec<_>>(),
+            vec!["1", "2"]
+        );
+        assert_eq!(query_params.get("b"), Some("hello world"));
+        assert_eq!(query_params.get("c"), Some("x y"));
+        assert_eq!(query_params.get("d"), Some(""));
+        assert_eq!(query_params.get("e"), None);
+        assert_eq!(query_params.iter().count(), 5);
+
+        assert_eq!(QueryParams::parse(None).iter().count(), 0);
+    }
+}
@@ -0,0 +1,487 @@
+use hyper::http::{
+    header,
+    uri::{PathAndQuery, Uri},
+    HeaderValue, Method, Request, Response, StatusCode, Version,
+};
+
+use tracing::{debug, warn};
+
+use crate::{
+    config::{
+        AbsoluteFormTargetAction, AsteriskFormTargetAction, RequestTargetConfiguration,
+        UnknownHostAction,
+    },
+    request::path::normalize_path,
+    response::{
+        build_deny_response, build_status_code_response, CacheControl, DenyReason, ResponseBody,
+    },
+};
+
+fn is_asterisk_form<B>(hyper_request: &Request<B>) -> bool {
+    hyper_request.uri() == "*"
+}
+
+// HTTP/2 requests always carry :scheme and :authority so hyper presents them
+// as absolute URIs, only HTTP/1.x absolute-form targets are special.
+fn is_absolute_form<B>(hyper_request: &Request<B>) -> bool {
+    hyper_request.version() < Version::HTTP_2 && hyper_request.uri().authority().is_some()
+}
+
+fn build_asterisk_form_response() -> Response<ResponseBody> {
+    let mut response = build_status_code_response(StatusCode::NO_CONTENT, CacheControl::NoCache);
+    response.headers_mut().insert(
+        header::ALLOW,
+        HeaderValue::from_static("GET, HEAD, OPTIONS"),
+    );
+    response
+}
+
+fn normalize_absolute_form<B>(hyper_request: &mut Request<B>) -> Option<()> {
+    let authority = hyper_request.uri().authority()?.clone();
+
+    // RFC 9112 section 3.2.2: the authority in an absolute-form target replaces the Host header.
+    let host_header_value = HeaderValue::from_str(authority.as_str()).ok()?;
+    hyper_request
+        .headers_mut()
+        .insert(header::HOST, host_header_value);
+
+    let path_and_query = hyper_request
+        .uri()
+        .path_and_query()
+        .map(|path_and_query| path_and_query.as_str())
+        .unwrap_or("/");
+
+    *hyper_request.uri_mut() = path_and_query.parse::<Uri>().ok()?;
+
+    Some(())
+}
+
+// Host header, or the :authority of HTTP/2 requests sent without one.
+pub fn request_host<B>(hyper_request: &Request<B>) -> Option<&str> {
+    hyper_request
+        .headers()
+        .get(header::HOST)
+        .and_then(|host| host.to_str().ok())
+        .or_else(|| {
+            hyper_request
+                .uri()
+                .authority()
+                .map(|authority| authority.as_str())
+        })
+}
+
+fn host_allowed(host: &str, allowed_hosts: &[String]) -> bool {
+    let host_without_port = strip_port(host);
+
+    allowed_hosts.iter().any(|allowed_host| {
+        allowed_host.eq_ignore_ascii_case(host)
+            || allowed_host.eq_ignore_ascii_case(host_without_port)
+    })
+}
+
+fn set_request_host<B>(hyper_request: &mut Request<B>, host: &str) -> Option<()> {
+    hyper_request
+        .headers_mut()
+        .insert(header::HOST, HeaderValue::from_str(host).ok()?);
+
+    if hyper_request.uri().authority().is_some() {
+        let mut uri_parts = hyper_request.uri().clone().into_parts();
+        uri_parts.authority = Some(host.parse().ok()?);
+        *hyper_request.uri_mut() = Uri::from_parts(uri_parts).ok()?;
+    }
+
+    Some(())
+}
+
+// Checks the request host against allowed_hosts, so unexpected hosts never
+// reach redirects or logs.  Returns the response for rejected requests.
+fn validate_host<B>(
+    hyper_request: &mut Request<B>,
+    request_target_configuration: &RequestTargetConfiguration,
+) -> Option<Response<ResponseBody>> {
+    if request_target_configuration.allowed_hosts.is_empty() {
+        return None;
+    }
+
+    let host = request_host(hyper_request);
+
+    if host.is_some_and(|host| host_allowed(host, &request_target_configuration.allowed_hosts)) {
+        return None;
+    }
+
+    match (
+        request_target_configuration.unknown_host,
+        &request_target_configuration.default_host,
+    ) {
+        (UnknownHostAction::DefaultHost, Some(default_host)) => {
+            debug!("using default host for host {:?}", host);
+            match set_request_host(hyper_request, default_host) {
+                Some(()) => None,
+                None => Some(build_deny_response(
+                    StatusCode::BAD_REQUEST,
+                    DenyReason::UnknownHost,
+                )),
+            }
+        }
+        _ => {
+            warn!("rejecting unknown host {:?}", host);
+            let status_code = if host.is_some() {
+                StatusCode::MISDIRECTED_REQUEST
+            } else {
+                StatusCode::BAD_REQUEST
+            };
+            Some(build_deny_response(status_code, DenyReason::UnknownHost))
+        }
+    }
+}
+
+pub fn strip_port(host: &str) -> &str {
+    match host.rsplit_once(':') {
+        // not the colons of an IPv6 literal without a port
+        Some((host_without_port, port)) if !port.contains(']') => host_without_port,
+        _ => host,
+    }
+}
+
+// 301 to canonical_host and https, applied to normalized requests.  The
+// scheme is https only on TLS connections, the HTTP/2 :scheme is chosen by the
+// client and an h2c request claiming https is still plaintext.
+pub fn canonical_redirect<B>(
+    hyper_request: &Request<B>,
+    request_target_configuration: &RequestTargetConfiguration,
+    tls: bool,
+) -> Option<Response<ResponseBody>> {
+    let scheme = if tls { "https" } else { "http" };
+    let host = request_host(hyper_request)?;
+
+    let redirect_scheme = if request_target_configuration.force_https {
+        "https"
+    } else {
+        scheme
+    };
+
+    let redirect_host = match &request_target_configuration.canonical_host {
+        Some(canonical_host) => canonical_host.as_str(),
+        // the plaintext port is not the https port
+        None if redirect_scheme != scheme => strip_port(host),
+        None => host,
+    };
+
+    if redirect_scheme == scheme && redirect_host.eq_ignore_ascii_case(host) {
+        return None;
+    }
+
+    let path_and_query = hyper_request
+        .uri()
+        .path_and_query()
+        .map_or("/", |path_and_query| path_and_query.as_str());
+
+    let location = format!("{}://{}{}", redirect_scheme, redirect_host, path_and_query);
+
+    debug!("canonical redirect to {:?}", location);
+
+    let location = HeaderValue::from_str(&location).ok()?;
+
+    let mut response =
+        build_status_code_response(StatusCode::MOVED_PERMANENTLY, CacheControl::NoCache);
+    response.headers_mut().insert(header::LOCATION, location);
+    Some(response)
+}
+
+fn normalize_uri_path<B>(hyper_request: &mut Request<B>) -> anyhow::Result<()> {
+    let uri = hyper_request.uri();
+
+    let normalized_path = normalize_path(uri.path())?;
+
+    if normalized_path == uri.path() {
+        return Ok(());
+    }
+
+    let path_and_query = match uri.query() {
+        Some(query) => format!("{}?{}", normalized_path, query),
+        None => normalized_path,
+    };
+
+    let mut uri_parts = uri.clone().into_parts();
+    uri_parts.path_and_query = Some(PathAndQuery::try_from(path_and_query)?);
+
+    let normalized_uri = Uri::from_parts(uri_parts)?;
+
+    debug!(
+        "normalized uri {} to {}",
+        hyper_request.uri(),
+        normalized_uri
+    );
+
+    *hyper_request.uri_mut() = normalized_uri;
+
+    Ok(())
+}
+
+pub enum RequestTargetResult<B> {
+    Continue(Request<B>),
+    Respond(Response<ResponseBody>),
+}
+
+pub fn normalize_request_target<B>(
+    mut hyper_request: Request<B>,
+    request_target_configuration: &RequestTargetConfiguration,
+) -> RequestTargetResult<B> {
+    if is_asterisk_form(&hyper_request) {
+        if hyper_request.method() != Method::OPTIONS {
+            warn!(
+                "asterisk-form target with method {}",
+                hyper_request.method()
+            );
+            return RequestTargetResult::Respond(build_deny_response(
+                StatusCode::BAD_REQUEST,
+                DenyReason::InvalidTarget,
+            ));
+        }
+
+        return RequestTargetResult::Respond(match request_target_configuration.asterisk_form {
+            AsteriskFormTargetAction::Respond => build_asterisk_form_response(),
+            AsteriskFormTargetAction::Reject => {
+                build_deny_response(StatusCode::BAD_REQUEST, DenyReason::InvalidTarget)
+            }
+        });
+    }
+
+    if is_absolute_form(&hyper_request) {
+        match request_target_configuration.absolute_form {
+            AbsoluteFormTargetAction::Normalize => {
+                if normalize_absolute_form(&mut hyper_request).is_none() {
+                    warn!("unable to normalize absolute-form target");
+                    return RequestTargetResult::Respond(build_deny_response(
+                        StatusCode::BAD_REQUEST,
+                        DenyReason::InvalidTarget,
+                    ));
+                }
+                debug!("normalized absolute-form target to {}", hyper_request.uri());
+            }
+            AbsoluteFormTargetAction::Reject => {
+                return RequestTargetResult::Respond(build_deny_response(
+                    StatusCode::BAD_REQUEST,
+                    DenyReason::InvalidTarget,
+                ));
+            }
+        }
+    }
+
+    if let Some(response) = validate_host(&mut hyper_request, request_target_configuration) {
+        return RequestTargetResult::Respond(response);
+    }
+
+    if let Err(e) = normalize_uri_path(&mut hyper_request) {
+        warn!(
+            "rejecting request path {:?}: {}",
+            hyper_request.uri().path(),
+            e
+        );
+        return RequestTargetResult::Respond(build_deny_response(
+            StatusCode::BAD_REQUEST,
+            DenyReason::InvalidTarget,
+        ));
+    }
+
+    RequestTargetResult::Continue(hyper_request)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    impl<B> RequestTargetResult<B> {
+        fn unwrap_continue(self) -> Request<B> {
+            match self {
+                Self::Continue(request) => request,
+                Self::Respond(_) => panic!("expected RequestTargetResult::Continue"),
+            }
+        }
+
+        fn unwrap_respond(self) -> Response<ResponseBody> {
+            match self {
+                Self::Continue(_) => panic!("expected RequestTargetResult::Respond"),
+                Self::Respond(response) => response,
+            }
+        }
+    }
+
+    fn build_request(method: Method, version: Version, uri: &str) -> Request<()> {
+        Request::builder()
+            .method(method)
+            .version(version)
+            .uri(uri)
+            .header(header::HOST, "original")
+            .body(())
+            .unwrap()
+    }
+
+    #[test]
+    fn test_absolute_form_normalized() {
+        let request = build_request(
+            Method::GET,
+            Version::HTTP_11,
+            "http://example.com:8080/a/b?c=d",
+        );
+
+        let request = normalize_request_target(request, &RequestTargetConfiguration::default())
+            .unwrap_continue();
+
+        assert_eq!(request.uri(), "/a/b?c=d");
+        assert_eq!(request.headers()[header::HOST], "example.com:8080");
+    }
+
+    #[test]
+    fn test_absolute_form_rejected() {
+        let request = build_request(Method::GET, Version::HTTP_11, "http://example.com/");
+
+        let configuration = RequestTargetConfiguration {
+            absolute_form: AbsoluteFormTargetAction::Reject,
+            ..Default::default()
+        };
+
+        let response = normalize_request_target(request, &configuration).unwrap_respond();
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn test_http2_uri_untouched() {
+        let request = build_request(Method::GET, Version::HTTP_2, "https://example.com/a");
+
+        let configuration = RequestTargetConfiguration {
+            absolute_form: AbsoluteFormTargetAction::Reject,
+            ..Default::default()
+        };
+
+        let request = normalize_request_target(request, &configuration).unwrap_continue();
+
+        assert_eq!(request.uri(), "https://example.com/a");
+        assert_eq!(request.headers()[header::HOST], "original");
+    }
+
+    #[test]
+    fn test_path_normalized() {
+        let request = build_request(Method::GET, Version::HTTP_11, "//a/./%2e%2egit/../b?c=%2F");
+
+        let request = normalize_request_target(request, &RequestTargetConfiguration::default())
+            .unwrap_continue();
+
+        assert_eq!(request.uri(), "/a/b?c=%2F");
+
+        let request = build_request(
+            Method::GET,
+            Version::HTTP_2,
+            "https://example.com/a/../../b",
+        );
+
+        let response = normalize_request_target(request, &RequestTargetConfiguration::default())
+            .unwrap_respond();
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn test_asterisk_form() {
+        let request = build_request(Method::OPTIONS, Version::HTTP_11, "*");
+
+        let response = normalize_request_target(request, &RequestTargetConfiguration::default())
+            .unwrap_respond();
+
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert_eq!(response.headers()[header::ALLOW], "GET, HEAD, OPTIONS");
+
+        let request = build_request(Method::GET, Version::HTTP_11, "*");
+
+        let response = normalize_request_target(request, &RequestTargetConfiguration::default())
+            .unwrap_respond();
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn test_allowed_hosts() {
+        let configuration = RequestTargetConfiguration {
+            allowed_hosts: vec!["example.com".to_owned(), "[::1]:8080".to_owned()],
+            ..Default::default()
+        };
+
+        for host in ["example.com", "EXAMPLE.com:8443", "[::1]:8080"] {
+            let mut request = build_request(Method::GET, Version::HTTP_11, "/");
+            request
+                .headers_mut()
+                .insert(header::HOST, HeaderValue::from_static(host));
+            normalize_request_target(request, &configuration).unwrap_continue();
+        }
+
+        let request = build_request(Method::GET, Version::HTTP_11, "/");
+        let response = normalize_request_target(request, &configuration).unwrap_respond();
+        assert_eq!(response.status(), StatusCode::MISDIRECTED_REQUEST);
+
+        let mut request = build_request(Method::GET, Version::HTTP_10, "/");
+        request.headers_mut().remove(header::HOST);
+        let response = normalize_request_target(request, &configuration).unwrap_respond();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let configuration = RequestTargetConfiguration {
+            unknown_host: UnknownHostAction::DefaultHost,
+            default_host: Some("example.com".to_owned()),
+            ..configuration
+        };
+
+        let request = build_request(Method::GET, Version::HTTP_2, "https://evil.com/a");
+        let request = normalize_request_target(request, &configuration).unwrap_continue();
+        assert_eq!(request.uri(), "https://example.com/a");
+        assert_eq!(request.headers()[header::HOST], "example.com");
+    }
+
+    #[test]
+    fn test_canonical_redirect() {
+        let configuration = RequestTargetConfiguration {
+            canonical_host: Some("www.example.com".to_owned()),
+            ..Default::default()
+        };
+
+        let request = build_request(Method::GET, Version::HTTP_11, "/a/./b?c=d");
+        let request = normalize_request_target(request, &configuration).unwrap_continue();
+        let response = canonical_redirect(&request, &configuration, false).unwrap();
+        assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+        assert_eq!(
+            response.headers()[header::LOCATION],
+            "http://www.example.com/a/b?c=d"
+        );
+
+        let configuration = RequestTargetConfiguration {
+            canonical_host: None,
+            force_https: true,
+            ..Default::default()
+        };
+
+        let mut request = build_request(Method::GET, Version::HTTP_11, "/a");
+        request
+            .headers_mut()
+            .insert(header::HOST, HeaderValue::from_static("example.com:8080"));
+        let response = canonical_redirect(&request, &configuration, false).unwrap();
+        assert_eq!(
+            response.headers()[header::LOCATION],
+            "https://example.com/a"
+        );
+
+        // h2c requests are plaintext whatever :scheme they send
+        let mut request = build_request(Method::GET, Version::HTTP_2, "https://example.com/a");
+        request.headers_mut().remove(header::HOST);
+        let response = canonical_redirect(&request, &configuration, false).unwrap();
+        assert_eq!(
+            response.headers()[header::LOCATION],
+            "https://example.com/a"
+        );
+        assert!(canonical_redirect(&request, &configuration, true).is_none());
+
+        let mut request = build_request(Method::GET, Version::HTTP_11, "/a");
+        request
+            .headers_mut()
+            .insert(header::HOST, HeaderValue::from_static("example.com"));
+        assert!(canonical_redirect(&request, &configuration, true).is_none());
+    }
+}
@@ -0,0 +1,176 @@
+use hyper::http::{HeaderMap, StatusCode};
+
+use tokio::{sync::OnceCell, time::Duration};
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+// Upper bounds of the request latency histogram buckets, a last +Inf bucket
+// is implied.
+pub const LATENCY_BUCKETS: [Duration; 12] = [
+    Duration::from_millis(1),
+    Duration::from_millis(5),
+    Duration::from_millis(10),
+    Duration::from_millis(25),
+    Duration::from_millis(50),
+    Duration::from_millis(100),
+    Duration::from_millis(250),
+    Duration::from_millis(500),
+    Duration::from_secs(1),
+    Duration::from_millis(2500),
+    Duration::from_secs(5),
+    Duration::from_secs(10),
+];
+
+// The trace id of a W3C traceparent header, e.g.
+// "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01".
+pub fn traceparent_trace_id(headers: &HeaderMap) -> Option<u128> {
+    let traceparent = headers.get("traceparent")?.to_str().ok()?;
+
+    let mut fields = traceparent.split('-');
+
+    let version = fields.next()?;
+    let trace_id = fields.next()?;
+
+    if version.len() != 2 || version == "ff" || trace_id.len() != 32 {
+        return None;
+    }
+
+    // all zeros is an invalid trace id
+    u128::from_str_radix(trace_id, 16)
+        .ok()
+        .filter(|trace_id| *trace_id != 0)
+}
+
+// The latest traced request in a latency bucket.
+#[derive(Clone, Copy, Debug)]
+pub struct LatencyExemplar {
+    pub trace_id: u128,
+    pub duration: Duration,
+    pub time: SystemTime,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LatencyHistogram {
+    // not cumulative, bucket_counts[i] counts requests at most LATENCY_BUCKETS[i]
+    // and above the previous bound
+    pub bucket_counts: [u64; LATENCY_BUCKETS.len()],
+    pub count: u64,
+    pub sum: Duration,
+    // per bucket, the last one for +Inf
+    pub exemplars: [Option<LatencyExemplar>; LATENCY_BUCKETS.len() + 1],
+}
+
+impl LatencyHistogram {
+    fn record(&mut self, duration: Duration, trace_id: Option<u128>) {
+        let index = LATENCY_BUCKETS
+            .iter()
+            .position(|bound| duration <= *bound)
+            .unwrap_or(LATENCY_BUCKETS.len());
+
+        if let Some(bucket_count) = self.bucket_counts.get_mut(index) {
+            *bucket_count += 1;
+        }
+        self.count += 1;
+        self.sum += duration;
+
+        if let Some(trace_id) = trace_id {
+            self.exemplars[index] = Some(LatencyExemplar {
+                trace_id,
+                duration,
+                time: SystemTime::now(),
+            });
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct RouteRequestMetrics {
+    pub status_counts: BTreeMap<u16, u64>,
+    pub latency: LatencyHistogram,
+}
+
+// Request counts by status and handler latency per route since startup,
+// recorded for every request including redirects and denials.
+pub struct RequestMetrics {
+    routes: Mutex<HashMap<Arc<str>, RouteRequestMetrics>>,
+}
+
+impl RequestMetrics {
+    fn new() -> Self {
+        Self {
+            routes: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn record(
+        &self,
+        route: &Arc<str>,
+        status: StatusCode,
+        duration: Duration,
+        trace_id: Option<u128>,
+    ) {
+        let mut routes = self.routes.lock().unwrap();
+
+        let entry = match routes.get_mut(route) {
+            Some(entry) => entry,
+            None => routes.entry(Arc::clone(route)).or_default(),
+        };
+
+        *entry.status_counts.entry(status.as_u16()).or_default() += 1;
+        entry.latency.record(duration, trace_id);
+    }
+
+    pub fn snapshot(&self) -> Vec<(Arc<str>, RouteRequestMetrics)> {
+        let mut entries: Vec<_> = self
+            .routes
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(route, entry)| (Arc::clone(route), entry.clone()))
+            .collect();
+
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        entries
+    }
+
+    pub async fn instance() -> &'static Self {
+        static INSTANCE: OnceCell<RequestMetrics> = OnceCell::const_new();
+
+        INSTANCE.get_or_init(|| async { Self::new() }).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use hyper::http::HeaderValue;
+
+    #[test]
+    fn test_traceparent_trace_id() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(traceparent_trace_id(&headers), None);
+
+        headers.insert(
+            "traceparent",
+            HeaderValue::from_static("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"),
+        );
+        assert_eq!(
+            traceparent_trace_id(&headers),
+            Some(0x4bf92f3577b34da6a3ce929d0e0e4736)
+        );
+
+        headers.insert(
+            "traceparent",
+            HeaderValue::from_static("00-00000000000000000000000000000000-00f067aa0ba902b7-01"),
+        );
+        assert_eq!(traceparent_trace_id(&headers), None);
+
+        headers.insert("traceparent", HeaderValue::from_static("00-4bf92f35-01"));
+        assert_eq!(traceparent_trace_id(&headers), None);
+    }
+}
@@ -0,0 +1,123 @@
+#[cfg(feature = "alloc-tracking")]
+mod alloc_tracking;
+
+use std::{
+    future::Future,
+    ops::{AddAssign, Sub},
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+// True when built with the alloc-tracking feature, otherwise allocations
+// are always zero.
+pub const ALLOCATIONS_TRACKED: bool = cfg!(feature = "alloc-tracking");
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ResourceUsage {
+    pub cpu_time: Duration,
+    pub allocations: u64,
+    pub allocated_bytes: u64,
+}
+
+impl AddAssign for ResourceUsage {
+    fn add_assign(&mut self, other: Self) {
+        self.cpu_time += other.cpu_time;
+        self.allocations += other.allocations;
+        self.allocated_bytes += other.allocated_bytes;
+    }
+}
+
+impl Sub for ResourceUsage {
+    type Output = Self;
+
+    fn sub(self, other: Self) -> Self {
+        Self {
+            cpu_time: self.cpu_time.saturating_sub(other.cpu_time),
+            allocations: self.allocations.saturating_sub(other.allocations),
+            allocated_bytes: self.allocated_bytes.saturating_sub(other.allocated_bytes),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn thread_cpu_time() -> Duration {
+    use nix::time::{clock_gettime, ClockId};
+
+    clock_gettime(ClockId::CLOCK_THREAD_CPUTIME_ID)
+        .map(Duration::from)
+        .unwrap_or_default()
+}
+
+#[cfg(not(unix))]
+fn thread_cpu_time() -> Duration {
+    Duration::ZERO
+}
+
+#[cfg(feature = "alloc-tracking")]
+fn thread_allocations() -> (u64, u64) {
+    alloc_tracking::thread_allocations()
+}
+
+#[cfg(not(feature = "alloc-tracking"))]
+fn thread_allocations() -> (u64, u64) {
+    (0, 0)
+}
+
+// Usage of the current thread since it started.
+fn thread_usage() -> ResourceUsage {
+    let (allocations, allocated_bytes) = thread_allocations();
+
+    ResourceUsage {
+        cpu_time: thread_cpu_time(),
+        allocations,
+        allocated_bytes,
+    }
+}
+
+// Adds up the thread usage during each poll of future, so work done for other
+// tasks on the same worker thread is not counted.  Tasks the future spawns,
+// such as streaming response bodies, are not counted either.
+pub struct Measured<F> {
+    future: F,
+    usage: ResourceUsage,
+}
+
+impl<F: Future + Unpin> Future for Measured<F> {
+    type Output = (F::Output, ResourceUsage);
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let start = thread_usage();
+
+        let poll = Pin::new(&mut self.future).poll(cx);
+
+        let usage = thread_usage() - start;
+        self.usage += usage;
+
+        poll.map(|output| (output, self.usage))
+    }
+}
+
+pub fn measure<F: Future + Unpin>(future: F) -> Measured<F> {
+    Measured {
+        future,
+        usage: ResourceUsage::default(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_measure() {
+        let (sum, usage) = measure(Box::pin(async {
+            tokio::task::yield_now().await;
+            (0..1_000_000u64).map(std::hint::black_box).sum::<u64>()
+        }))
+        .await;
+
+        assert_eq!(sum, 499_999_500_000);
+        assert!(usage.cpu_time > Duration::ZERO || cfg!(not(unix)));
+    }
+}
@@ -0,0 +1,52 @@
+// GlobalAlloc is an unsafe trait, this module is only built with the
+// alloc-tracking feature.
+#![allow(unsafe_code)]
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    cell::Cell,
+};
+
+thread_local! {
+    static ALLOCATIONS: Cell<(u64, u64)> = const { Cell::new((0, 0)) };
+}
+
+// Counts allocations and allocated bytes per thread, frees are not counted.
+struct TrackingAllocator;
+
+fn count(size: usize) {
+    // fails during thread teardown
+    let _ = ALLOCATIONS.try_with(|allocations| {
+        let (count, bytes) = allocations.get();
+        allocations.set((count + 1, bytes + size as u64));
+    });
+}
+
+unsafe impl GlobalAlloc for TrackingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        count(layout.size());
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        count(layout.size());
+        unsafe { System.alloc_zeroed(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        count(new_size);
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: TrackingAllocator = TrackingAllocator;
+
+// Allocations and allocated bytes on the current thread since it started.
+pub fn thread_allocations() -> (u64, u64) {
+    ALLOCATIONS.try_with(Cell::get).unwrap_or_default()
+}
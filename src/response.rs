@@ -1,22 +1,40 @@
-use bytes::Bytes;
+use bytes::{Bytes, BytesMut};
 
 use http_body_util::{
     combinators::BoxBody,
     {BodyExt, Empty, Full},
 };
 
-use hyper::http::{header, HeaderValue, Response, StatusCode};
+use hyper::{
+    body::{Body, Frame},
+    http::{header, HeaderValue, Response, StatusCode},
+};
+
+use schemars::JsonSchema;
 
 use serde::Serialize;
 
-use tracing::warn;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt},
+    sync::mpsc,
+};
+
+use tokio_util::sync::CancellationToken;
+
+use tracing::{debug, warn};
+
+use std::{
+    convert::Infallible,
+    pin::Pin,
+    task::{Context, Poll},
+};
 
-use std::convert::Infallible;
+use crate::request::QueryParams;
 
 #[derive(Clone, Copy, Debug)]
 pub enum CacheControl {
     NoCache,
-    // Cache { max_age_seconds: u32 },
+    Cache { max_age_seconds: u32 },
 }
 
 impl CacheControl {
@@ -25,9 +43,9 @@ impl CacheControl {
 
         match self {
             CacheControl::NoCache => NO_CACHE_VALUE.clone(),
-            // CacheControl::Cache { max_age_seconds } => {
-            //    format!("public, max-age={}", max_age_seconds)
-            // }
+            CacheControl::Cache { max_age_seconds } => {
+                HeaderValue::try_from(format!("public, max-age={}", max_age_seconds)).unwrap()
+            }
         }
     }
 }
@@ -36,6 +54,9 @@ impl CacheControl {
 pub enum ResponseBodyError {
     #[error("io error: {0}")]
     IoError(#[from] std::io::Error),
+
+    #[error("hyper error: {0}")]
+    HyperError(#[from] hyper::Error),
 }
 
 impl From<Infallible> for ResponseBodyError {
@@ -83,6 +104,65 @@ pub fn build_json_response(
     }
 }
 
+// Output options for JSON API responses, from ?pretty=true and ?fields=a,b,c
+#[derive(Debug, Default)]
+pub struct JsonOutputOptions {
+    pretty: bool,
+    fields: Option<Vec<String>>,
+}
+
+impl JsonOutputOptions {
+    pub fn is_default(&self) -> bool {
+        !self.pretty && self.fields.is_none()
+    }
+}
+
+impl From<&QueryParams> for JsonOutputOptions {
+    fn from(query_params: &QueryParams) -> Self {
+        let pretty = matches!(query_params.get("pretty"), Some("true" | "1"));
+
+        let fields = query_params.get("fields").map(|fields| {
+            fields
+                .split(',')
+                .map(str::trim)
+                .filter(|field| !field.is_empty())
+                .map(str::to_owned)
+                .collect()
+        });
+
+        Self { pretty, fields }
+    }
+}
+
+fn select_json_fields(value: &mut serde_json::Value, fields: &[String]) {
+    match value {
+        serde_json::Value::Object(map) => map.retain(|key, _| fields.contains(key)),
+        serde_json::Value::Array(values) => values
+            .iter_mut()
+            .for_each(|value| select_json_fields(value, fields)),
+        _ => {}
+    }
+}
+
+// Reformats a serialized JSON body.  Field selection applies to top level object
+// keys, or to each object in a top level array.
+pub fn format_json_body(
+    json_body: &[u8],
+    options: &JsonOutputOptions,
+) -> serde_json::Result<Vec<u8>> {
+    let mut value: serde_json::Value = serde_json::from_slice(json_body)?;
+
+    if let Some(fields) = &options.fields {
+        select_json_fields(&mut value, fields);
+    }
+
+    if options.pretty {
+        serde_json::to_vec_pretty(&value)
+    } else {
+        serde_json::to_vec(&value)
+    }
+}
+
 pub fn build_status_code_response(
     status_code: StatusCode,
     cache_control: CacheControl,
@@ -94,6 +174,75 @@ pub fn build_status_code_response(
         .unwrap()
 }
 
+// Why a request was rejected before reaching its handler.  Attached to the
+// response as an extension so logs, observers and stats can tell denials apart.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, JsonSchema, Serialize)]
+pub enum DenyReason {
+    #[serde(rename = "invalid_target")]
+    InvalidTarget,
+
+    #[serde(rename = "peer_credential")]
+    PeerCredential,
+
+    #[serde(rename = "geoip")]
+    GeoIp,
+
+    #[serde(rename = "endpoint_auth")]
+    EndpointAuth,
+
+    #[serde(rename = "route_disabled")]
+    RouteDisabled,
+
+    #[serde(rename = "rate_limit")]
+    RateLimit,
+
+    #[serde(rename = "command_queue")]
+    CommandQueue,
+
+    #[serde(rename = "filter_rule")]
+    FilterRule,
+
+    #[serde(rename = "load_shedding")]
+    LoadShedding,
+
+    #[serde(rename = "unknown_host")]
+    UnknownHost,
+
+    #[serde(rename = "fault_injection")]
+    FaultInjection,
+
+    #[serde(rename = "request_limit")]
+    RequestLimit,
+}
+
+impl DenyReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::InvalidTarget => "invalid_target",
+            Self::PeerCredential => "peer_credential",
+            Self::GeoIp => "geoip",
+            Self::EndpointAuth => "endpoint_auth",
+            Self::RouteDisabled => "route_disabled",
+            Self::RateLimit => "rate_limit",
+            Self::CommandQueue => "command_queue",
+            Self::FilterRule => "filter_rule",
+            Self::LoadShedding => "load_shedding",
+            Self::UnknownHost => "unknown_host",
+            Self::FaultInjection => "fault_injection",
+            Self::RequestLimit => "request_limit",
+        }
+    }
+}
+
+pub fn build_deny_response(
+    status_code: StatusCode,
+    deny_reason: DenyReason,
+) -> Response<ResponseBody> {
+    let mut response = build_status_code_response(status_code, CacheControl::NoCache);
+    response.extensions_mut().insert(deny_reason);
+    response
+}
+
 pub fn empty_response_body() -> ResponseBody {
     Empty::new().map_err(|never| never.into()).boxed()
 }
@@ -101,3 +250,144 @@ pub fn empty_response_body() -> ResponseBody {
 pub fn static_string_response_body(s: &'static str) -> ResponseBody {
     Full::from(s).map_err(|e| e.into()).boxed()
 }
+
+// Body fed by a bounded channel, senders wait when the client is slow to read.
+struct ChannelBody {
+    receiver: mpsc::Receiver<Result<Bytes, ResponseBodyError>>,
+}
+
+impl Body for ChannelBody {
+    type Data = Bytes;
+    type Error = ResponseBodyError;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        self.receiver
+            .poll_recv(cx)
+            .map(|option| option.map(|result| result.map(Frame::data)))
+    }
+}
+
+pub type ResponseBodySender = mpsc::Sender<Result<Bytes, ResponseBodyError>>;
+
+// Returns a sender and a body that streams what is sent.  Sends fail once the
+// response body is dropped, e.g. when the client disconnects.
+pub fn channel_response_body(buffer: usize) -> (ResponseBodySender, ResponseBody) {
+    let (sender, receiver) = mpsc::channel(buffer);
+
+    (sender, ChannelBody { receiver }.boxed())
+}
+
+// Streams reader in chunk_size chunks from a spawned task.  The reader is
+// dropped when it reaches EOF, fails, the response body is dropped, or the
+// client goes away while a read is pending.
+pub fn async_read_response_body(
+    mut reader: impl AsyncRead + Send + Unpin + 'static,
+    chunk_size: usize,
+    cancellation_token: CancellationToken,
+) -> ResponseBody {
+    let (sender, body) = channel_response_body(1);
+
+    tokio::spawn(async move {
+        loop {
+            let mut buf = BytesMut::with_capacity(chunk_size);
+
+            let result = tokio::select! {
+                result = reader.read_buf(&mut buf) => result,
+                _ = cancellation_token.cancelled() => {
+                    debug!("async_read_response_body cancelled");
+                    break;
+                }
+            };
+
+            let result = match result {
+                Ok(0) => break,
+                Ok(_) => Ok(buf.freeze()),
+                Err(e) => Err(e.into()),
+            };
+
+            let is_err = result.is_err();
+
+            if sender.send(result).await.is_err() {
+                debug!("async_read_response_body receiver dropped");
+                break;
+            }
+
+            if is_err {
+                break;
+            }
+        }
+    });
+
+    body
+}
+
+// Streams records as newline delimited JSON, serializing each record only when
+// the client is ready for more.
+pub fn build_ndjson_response<I>(records: I, cache_control: CacheControl) -> Response<ResponseBody>
+where
+    I: IntoIterator + Send + 'static,
+    I::IntoIter: Send,
+    I::Item: Serialize + Send,
+{
+    const NDJSON_CHANNEL_BUFFER: usize = 16;
+
+    let (sender, body) = channel_response_body(NDJSON_CHANNEL_BUFFER);
+
+    tokio::spawn(async move {
+        for record in records {
+            let result = match serde_json::to_vec(&record) {
+                Ok(mut line) => {
+                    line.push(b'\n');
+                    Ok(Bytes::from(line))
+                }
+                Err(e) => {
+                    warn!("build_ndjson_response serialization error {}", e);
+                    Err(std::io::Error::from(e).into())
+                }
+            };
+
+            let is_err = result.is_err();
+
+            if sender.send(result).await.is_err() || is_err {
+                break;
+            }
+        }
+    });
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "application/x-ndjson")
+        .header(header::CACHE_CONTROL, cache_control.header_value())
+        .body(body)
+        .unwrap()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_format_json_body() {
+        let options = JsonOutputOptions {
+            pretty: false,
+            fields: Some(vec!["a".to_owned(), "c".to_owned()]),
+        };
+
+        let body = format_json_body(br#"{"a":1,"b":2,"c":3}"#, &options).unwrap();
+        assert_eq!(body, br#"{"a":1,"c":3}"#);
+
+        let body = format_json_body(br#"[{"a":1,"b":2},{"b":3}]"#, &options).unwrap();
+        assert_eq!(body, br#"[{"a":1},{}]"#);
+
+        let options = JsonOutputOptions {
+            pretty: true,
+            fields: None,
+        };
+
+        let body = format_json_body(br#"{"a":1}"#, &options).unwrap();
+        assert_eq!(body, b"{\n  \"a\": 1\n}");
+    }
+}
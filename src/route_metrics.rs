@@ -0,0 +1,134 @@
+use hyper::http::HeaderMap;
+
+use tokio::{sync::OnceCell, time::Duration};
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use crate::resource_usage::ResourceUsage;
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MetricSummary {
+    pub count: u64,
+    pub sum: u64,
+    pub max: u64,
+}
+
+impl MetricSummary {
+    fn record(&mut self, value: u64) {
+        self.count += 1;
+        self.sum = self.sum.saturating_add(value);
+        self.max = self.max.max(value);
+    }
+
+    pub fn mean(&self) -> u64 {
+        self.sum.checked_div(self.count).unwrap_or_default()
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RouteMetricsEntry {
+    pub request_header_bytes: MetricSummary,
+    pub response_header_bytes: MetricSummary,
+    pub header_time_micros: MetricSummary,
+    pub ttfb_micros: MetricSummary,
+    pub write_time_micros: MetricSummary,
+    // only recorded with resource usage enabled
+    pub cpu_time_micros: MetricSummary,
+    pub allocations: MetricSummary,
+    pub allocated_bytes: MetricSummary,
+    pub over_budget: u64,
+}
+
+// One completed request.  header_time is only known for HTTP/1 where the
+// start of a request can be told apart on the connection.
+pub struct RouteTimingSample {
+    pub request_header_bytes: u64,
+    pub response_header_bytes: u64,
+    pub header_time: Option<Duration>,
+    pub ttfb: Duration,
+    pub write_time: Duration,
+    // until the handler returned, None unless resource usage is enabled
+    pub resource_usage: Option<ResourceUsage>,
+    pub over_budget: bool,
+}
+
+fn duration_micros(duration: Duration) -> u64 {
+    duration.as_micros().try_into().unwrap_or(u64::MAX)
+}
+
+// Approximate HTTP/1 wire size of a header block: "name: value\r\n" per field.
+pub fn header_bytes(headers: &HeaderMap) -> u64 {
+    headers
+        .iter()
+        .map(|(name, value)| (name.as_str().len() + value.len() + 4) as u64)
+        .sum()
+}
+
+// Request and response header sizes and timings per route since startup.
+// Comparing header_time and write_time against ttfb separates slow clients
+// from slow handlers.
+pub struct RouteMetrics {
+    routes: Mutex<HashMap<Arc<str>, RouteMetricsEntry>>,
+}
+
+impl RouteMetrics {
+    fn new() -> Self {
+        Self {
+            routes: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn record(&self, route: Arc<str>, sample: &RouteTimingSample) {
+        let mut routes = self.routes.lock().unwrap();
+
+        let entry = routes.entry(route).or_default();
+
+        entry
+            .request_header_bytes
+            .record(sample.request_header_bytes);
+        entry
+            .response_header_bytes
+            .record(sample.response_header_bytes);
+        if let Some(header_time) = sample.header_time {
+            entry
+                .header_time_micros
+                .record(duration_micros(header_time));
+        }
+        entry.ttfb_micros.record(duration_micros(sample.ttfb));
+        entry
+            .write_time_micros
+            .record(duration_micros(sample.write_time));
+        if let Some(resource_usage) = sample.resource_usage {
+            entry
+                .cpu_time_micros
+                .record(duration_micros(resource_usage.cpu_time));
+            entry.allocations.record(resource_usage.allocations);
+            entry.allocated_bytes.record(resource_usage.allocated_bytes);
+        }
+        if sample.over_budget {
+            entry.over_budget += 1;
+        }
+    }
+
+    pub fn snapshot(&self) -> Vec<(Arc<str>, RouteMetricsEntry)> {
+        let mut entries: Vec<_> = self
+            .routes
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(route, entry)| (Arc::clone(route), *entry))
+            .collect();
+
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        entries
+    }
+
+    pub async fn instance() -> &'static Self {
+        static INSTANCE: OnceCell<RouteMetrics> = OnceCell::const_new();
+
+        INSTANCE.get_or_init(|| async { Self::new() }).await
+    }
+}
@@ -0,0 +1,108 @@
+use anyhow::Context;
+
+use core_affinity::CoreId;
+
+use tokio::runtime::{Builder, Runtime};
+
+use tracing::{info, warn};
+
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+use crate::config::{RuntimeConfiguration, RuntimeFlavor};
+
+fn configured_core_ids(runtime_configuration: &RuntimeConfiguration) -> Vec<CoreId> {
+    if runtime_configuration.cpu_affinity.is_empty() {
+        return Vec::new();
+    }
+
+    let Some(available_core_ids) = core_affinity::get_core_ids() else {
+        warn!("cpu_affinity configured but core ids are not available on this platform, not pinning threads");
+        return Vec::new();
+    };
+
+    runtime_configuration
+        .cpu_affinity
+        .iter()
+        .filter_map(|id| {
+            let core_id = available_core_ids.iter().find(|core_id| core_id.id == *id);
+            if core_id.is_none() {
+                warn!("cpu_affinity core {} is not available, ignoring", id);
+            }
+            core_id.copied()
+        })
+        .collect()
+}
+
+fn pin_current_thread(core_id: CoreId) {
+    let thread = std::thread::current();
+    let thread_name = thread.name().unwrap_or("[unnamed]");
+
+    if core_affinity::set_for_current(core_id) {
+        info!("pinned thread {} to core {}", thread_name, core_id.id);
+    } else {
+        warn!(
+            "failed to pin thread {} to core {}",
+            thread_name, core_id.id
+        );
+    }
+}
+
+pub fn build_runtime() -> anyhow::Result<Runtime> {
+    let runtime_configuration = &crate::config::instance().runtime_configuration;
+
+    info!("building runtime {:?}", runtime_configuration);
+
+    let core_ids = configured_core_ids(runtime_configuration);
+
+    let mut builder = match runtime_configuration.flavor {
+        RuntimeFlavor::MultiThread => {
+            let mut builder = Builder::new_multi_thread();
+
+            let worker_threads = runtime_configuration.worker_threads.unwrap_or_else(|| {
+                std::thread::available_parallelism()
+                    .map(|n| n.get())
+                    .unwrap_or(1)
+            });
+            builder.worker_threads(worker_threads);
+
+            if !core_ids.is_empty() {
+                // Worker threads are started first when the runtime is built,
+                // so only the first worker_threads threads are pinned.
+                // Blocking pool threads are left unpinned.
+                let started_threads = Arc::new(AtomicUsize::new(0));
+                let core_ids = core_ids.clone();
+                builder.on_thread_start(move || {
+                    let index = started_threads.fetch_add(1, Ordering::Relaxed);
+                    if index < worker_threads {
+                        pin_current_thread(core_ids[index % core_ids.len()]);
+                    }
+                });
+            }
+
+            builder
+        }
+        RuntimeFlavor::CurrentThread => {
+            // The current thread runtime runs on the calling thread.
+            if let Some(core_id) = core_ids.first() {
+                pin_current_thread(*core_id);
+            }
+            Builder::new_current_thread()
+        }
+    };
+
+    if let Some(max_blocking_threads) = runtime_configuration.max_blocking_threads {
+        builder.max_blocking_threads(max_blocking_threads);
+    }
+
+    if let Some(thread_name) = &runtime_configuration.thread_name {
+        builder.thread_name(thread_name);
+    }
+
+    builder
+        .enable_all()
+        .build()
+        .context("build_runtime: builder.build error")
+}
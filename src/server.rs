@@ -1,20 +1,59 @@
+mod accept;
 mod handler;
+#[cfg(windows)]
+mod named_pipe;
+mod proxy_protocol;
+mod socket;
 mod tcp;
+mod timing;
+mod tls;
+#[cfg(unix)]
 mod unix;
 
 use anyhow::Context;
 
-use tokio::task::JoinSet;
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    task::JoinSet,
+};
 
-use std::sync::Arc;
+use std::{future::Future, sync::Arc};
 
 use crate::{config::ServerSocketType, handlers::RequestHandler, request::RequestIDFactory};
 
-use self::{handler::ConnectionHandler, tcp::TCPServer, unix::UnixServer};
+use self::{handler::ConnectionHandler, tcp::TCPServer};
 
-trait HyperReadWrite: hyper::rt::Read + hyper::rt::Write + Send + Unpin + 'static {}
+#[cfg(windows)]
+use self::named_pipe::NamedPipeServer;
 
-impl<T> HyperReadWrite for T where T: hyper::rt::Read + hyper::rt::Write + Send + Unpin + 'static {}
+#[cfg(unix)]
+use self::unix::UnixServer;
+
+pub use self::accept::{create_fd_reserve_instance, fd_reserve_instance};
+pub use self::handler::serve_duplex_connection;
+
+// Anything ConnectionHandler can serve: sockets, named pipes or in-memory
+// tokio::io::duplex streams.
+trait AsyncReadWrite: AsyncRead + AsyncWrite + Send + Unpin + 'static {}
+
+impl<T> AsyncReadWrite for T where T: AsyncRead + AsyncWrite + Send + Unpin + 'static {}
+
+async fn run_accept_loops(
+    accept_loops: impl IntoIterator<Item = impl Future<Output = anyhow::Result<()>> + Send + 'static>,
+) -> anyhow::Result<()> {
+    let mut join_set = JoinSet::new();
+
+    for accept_loop in accept_loops {
+        join_set.spawn(accept_loop);
+    }
+
+    let result = join_set
+        .join_next()
+        .await
+        .context("accept loop join_set.join_next returned None")?;
+
+    result.context("accept loop join_next JoinError")?
+}
 
 pub struct Server {
     join_set: JoinSet<anyhow::Result<()>>,
@@ -23,7 +62,7 @@ pub struct Server {
 impl Server {
     pub async fn new(handlers: Box<dyn RequestHandler>) -> Self {
         let request_id_factory = RequestIDFactory::new();
-        let connection_handler = ConnectionHandler::new(handlers, request_id_factory);
+        let connection_handler = ConnectionHandler::new(handlers, request_id_factory).await;
 
         let mut join_set = JoinSet::new();
 
@@ -35,14 +74,37 @@ impl Server {
                 match listener_configuration.socket_type {
                     ServerSocketType::Tcp => {
                         let server =
-                            TCPServer::new(connection_handler_clone, listener_configuration).await;
+                            TCPServer::new(connection_handler_clone, listener_configuration)
+                                .await?;
                         server.run().await?;
                     }
+                    #[cfg(unix)]
                     ServerSocketType::Unix => {
                         let server =
                             UnixServer::new(connection_handler_clone, listener_configuration).await;
                         server.run().await?;
                     }
+                    #[cfg(not(unix))]
+                    ServerSocketType::Unix => {
+                        anyhow::bail!(
+                            "unix socket listener '{}' is not supported on this platform",
+                            listener_configuration.bind_address
+                        );
+                    }
+                    #[cfg(windows)]
+                    ServerSocketType::NamedPipe => {
+                        let server =
+                            NamedPipeServer::new(connection_handler_clone, listener_configuration)
+                                .await;
+                        server.run().await?;
+                    }
+                    #[cfg(not(windows))]
+                    ServerSocketType::NamedPipe => {
+                        anyhow::bail!(
+                            "named pipe listener '{}' is not supported on this platform",
+                            listener_configuration.bind_address
+                        );
+                    }
                 };
                 Ok(())
             });
@@ -0,0 +1,153 @@
+use anyhow::Context;
+
+#[cfg(unix)]
+use nix::errno::Errno;
+
+use tokio::time::Duration;
+
+use tracing::warn;
+
+use std::{
+    fs::File,
+    future::Future,
+    io,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex, OnceLock,
+    },
+};
+
+const SHED_ACCEPT_TIMEOUT: Duration = Duration::from_millis(10);
+
+const TRANSIENT_ERROR_BACKOFF: Duration = Duration::from_millis(10);
+
+#[cfg(unix)]
+const SPARE_FILE_PATH: &str = "/dev/null";
+
+#[cfg(not(unix))]
+const SPARE_FILE_PATH: &str = "NUL";
+
+// Holds one spare file descriptor.  On EMFILE/ENFILE the spare is closed so
+// the pending connection can be accepted and immediately closed, which clears
+// it from the listen queue instead of leaving accept spinning on the error.
+pub struct FdReserve {
+    spare: Mutex<Option<File>>,
+    fd_exhaustion_events: AtomicUsize,
+}
+
+impl FdReserve {
+    fn open_spare() -> io::Result<File> {
+        File::open(SPARE_FILE_PATH)
+    }
+
+    pub fn fd_exhaustion_events(&self) -> usize {
+        self.fd_exhaustion_events.load(Ordering::Relaxed)
+    }
+
+    async fn shed_pending_connection<T>(&self, accept: impl Future<Output = io::Result<T>>) {
+        self.fd_exhaustion_events.fetch_add(1, Ordering::Relaxed);
+
+        drop(self.spare.lock().unwrap().take());
+
+        match tokio::time::timeout(SHED_ACCEPT_TIMEOUT, accept).await {
+            Ok(Ok(connection)) => drop(connection),
+            Ok(Err(e)) => warn!("shed_pending_connection accept error: {}", e),
+            Err(_) => warn!("shed_pending_connection accept timeout"),
+        }
+
+        match Self::open_spare() {
+            Ok(spare) => *self.spare.lock().unwrap() = Some(spare),
+            Err(e) => warn!("shed_pending_connection error reopening spare fd: {}", e),
+        }
+    }
+}
+
+static FD_RESERVE: OnceLock<FdReserve> = OnceLock::new();
+
+pub fn create_fd_reserve_instance() -> anyhow::Result<()> {
+    let spare = FdReserve::open_spare().context("error opening spare fd")?;
+
+    FD_RESERVE
+        .set(FdReserve {
+            spare: Mutex::new(Some(spare)),
+            fd_exhaustion_events: AtomicUsize::new(0),
+        })
+        .ok()
+        .context("FD_RESERVE.set error")?;
+
+    Ok(())
+}
+
+pub fn fd_reserve_instance() -> &'static FdReserve {
+    FD_RESERVE.get().unwrap()
+}
+
+#[cfg(unix)]
+fn is_fd_exhaustion(error: &io::Error) -> bool {
+    matches!(
+        error.raw_os_error().map(Errno::from_raw),
+        Some(Errno::EMFILE | Errno::ENFILE)
+    )
+}
+
+// Windows reports socket exhaustion as WSAENOBUFS, which is treated as transient.
+#[cfg(not(unix))]
+fn is_fd_exhaustion(_error: &io::Error) -> bool {
+    false
+}
+
+// Per-connection errors that should not stop the accept loop.
+#[cfg(unix)]
+fn is_transient(error: &io::Error) -> bool {
+    matches!(
+        error.raw_os_error().map(Errno::from_raw),
+        Some(
+            Errno::ECONNABORTED
+                | Errno::ECONNRESET
+                | Errno::EINTR
+                | Errno::ENOBUFS
+                | Errno::ENOMEM
+                | Errno::EPROTO
+                | Errno::EPERM
+        )
+    )
+}
+
+#[cfg(not(unix))]
+fn is_transient(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::Interrupted
+            | io::ErrorKind::OutOfMemory
+    ) || matches!(
+        error.raw_os_error(),
+        // WSAENOBUFS, WSAEMFILE
+        Some(10055 | 10024)
+    )
+}
+
+// Called with the failed accept error and a retry of the same accept.
+// Returns Err only for errors the accept loop cannot recover from.
+pub async fn handle_accept_error<T>(
+    error: io::Error,
+    retry_accept: impl Future<Output = io::Result<T>>,
+) -> anyhow::Result<()> {
+    if is_fd_exhaustion(&error) {
+        warn!(
+            "accept fd exhaustion, shedding pending connection: {}",
+            error
+        );
+        fd_reserve_instance()
+            .shed_pending_connection(retry_accept)
+            .await;
+        Ok(())
+    } else if is_transient(&error) {
+        warn!("transient accept error: {}", error);
+        tokio::time::sleep(TRANSIENT_ERROR_BACKOFF).await;
+        Ok(())
+    } else {
+        Err(error).context("accept error")
+    }
+}
@@ -1,68 +1,138 @@
+use http_body_util::BodyExt;
+
 use hyper::{
-    http::{Request, Response},
+    body::Body,
+    http::{header, Method, Request, Response, StatusCode, Version},
     service::service_fn,
 };
 
-use hyper_util::{rt::TokioExecutor, server::conn::auto::Builder as HyperConnAutoBuilder};
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo},
+    server::conn::auto::Builder as HyperConnAutoBuilder,
+};
+
+use ipnet::IpNet;
 
 use tokio::{
+    io::DuplexStream,
     pin,
     time::{Duration, Instant},
 };
 
 use tracing::{debug, info, instrument, warn, Instrument};
 
-use std::{convert::Infallible, sync::Arc};
+use std::{convert::Infallible, sync::Arc, time::SystemTime};
 
 use crate::{
-    connection::{ConnectionGuard, ConnectionID},
-    handlers::RequestHandler,
-    request::{HttpRequest, RequestID, RequestIDFactory},
-    response::ResponseBody,
-    server::HyperReadWrite,
+    access_log::{AccessLogBody, AccessLogRecord, AccessLogs},
+    config::{
+        RequestTargetConfiguration, ResourceUsageConfiguration, ServerLimitsConfiguration,
+        ServerSocketType,
+    },
+    connection::{
+        notify_connection_observers, AcceptedConnection, ClosedConnection, CompletedRequest,
+        ConnectionGuard, ConnectionID, ConnectionTracker, SocketMetadata, StreamGuard,
+    },
+    handlers::{ExternalRequestID, MatchedRoute, RequestHandler, VirtualHosts},
+    request::{
+        canonical_redirect, check_request_limits, normalize_request_target, HttpRequest,
+        RequestDeadline, RequestID, RequestIDFactory, RequestTargetResult,
+    },
+    request_metrics::{traceparent_trace_id, RequestMetrics},
+    resource_usage::ResourceUsage,
+    response::{DenyReason, ResponseBody},
+    route_metrics::{header_bytes, RouteMetrics, RouteTimingSample},
+    server::{
+        timing::{RequestStartMarker, RequestStartTrackingStream, ResponseTimingBody},
+        AsyncReadWrite,
+    },
+    traffic_stats::{TrafficCountingBody, TrafficStats, DEFAULT_ROUTE},
 };
 
+// Connection level timing state handed to each request.
+struct RequestTiming {
+    request_start: Arc<RequestStartMarker>,
+    header_time: Option<Duration>,
+    stream: StreamGuard,
+    // when the connection is closed with any requests still open
+    connection_deadline: Option<Instant>,
+}
+
 pub struct ConnectionHandler {
     request_handler: Box<dyn RequestHandler>,
     request_id_factory: RequestIDFactory,
-    connection_timeout_durations: Vec<Duration>,
+    request_target_configuration: &'static RequestTargetConfiguration,
+    limits: &'static ServerLimitsConfiguration,
+    traffic_stats: &'static TrafficStats,
+    route_metrics: &'static RouteMetrics,
+    request_metrics: &'static RequestMetrics,
+    exemplars: bool,
+    resource_usage: &'static ResourceUsageConfiguration,
+    access_logs: &'static AccessLogs,
+    virtual_hosts: VirtualHosts,
+    trusted_proxies: &'static [IpNet],
     tokio_executor: TokioExecutor,
 }
 
 impl ConnectionHandler {
-    pub fn new(
+    pub async fn new(
         request_handler: Box<dyn RequestHandler>,
         request_id_factory: RequestIDFactory,
     ) -> Arc<Self> {
-        let server_configuration = &crate::config::instance().server_configuration;
-
-        let connection_timeout_durations = vec![
-            server_configuration.connection.max_lifetime,
-            server_configuration.connection.graceful_shutdown_timeout,
-        ];
-
-        debug!(
-            "connection_timeout_durations = {:?}",
-            connection_timeout_durations
-        );
+        let configuration = crate::config::instance();
 
         Arc::new(Self {
             request_handler,
             request_id_factory,
-            connection_timeout_durations,
+            request_target_configuration: &configuration.request_target_configuration,
+            limits: &configuration.server_configuration.limits,
+            traffic_stats: TrafficStats::instance().await,
+            route_metrics: RouteMetrics::instance().await,
+            request_metrics: RequestMetrics::instance().await,
+            exemplars: configuration.metrics_configuration.exemplars,
+            resource_usage: &configuration.metrics_configuration.resource_usage,
+            access_logs: crate::access_log::access_logs_instance(),
+            virtual_hosts: VirtualHosts::new(),
+            trusted_proxies: &configuration.client_address_configuration.trusted_proxies,
             tokio_executor: TokioExecutor::new(),
         })
     }
 
+    fn over_budget(&self, route: &str, usage: ResourceUsage) -> bool {
+        let cpu_time_over = self
+            .resource_usage
+            .cpu_time_budget
+            .is_some_and(|budget| usage.cpu_time > budget);
+
+        let allocated_bytes_over = self
+            .resource_usage
+            .allocated_bytes_budget
+            .is_some_and(|budget| usage.allocated_bytes > budget);
+
+        if cpu_time_over || allocated_bytes_over {
+            warn!(
+                "route {} over budget cpu_time = {:?} allocations = {} allocated_bytes = {}",
+                route, usage.cpu_time, usage.allocations, usage.allocated_bytes
+            );
+        }
+
+        cpu_time_over || allocated_bytes_over
+    }
+
     #[instrument(
         name = "request",
         skip_all,
         fields(
-            id = request_id.as_usize(),
+            req_id = request_id.as_usize(),
+            xreq_id,
             method = %hyper_request.method(),
             uri = %hyper_request.uri(),
+            client,
             micros,
             status,
+            route,
+            deny_reason,
+            resolved_path,
         )
     )]
     async fn handle_request(
@@ -70,21 +140,150 @@ impl ConnectionHandler {
         connection_id: ConnectionID,
         request_id: RequestID,
         hyper_request: Request<hyper::body::Incoming>,
+        socket_metadata: Arc<SocketMetadata>,
+        request_timing: RequestTiming,
     ) -> Result<Response<ResponseBody>, Infallible> {
         let start_time = Instant::now();
 
-        let http_request = HttpRequest::new(connection_id, request_id, hyper_request);
+        let span = tracing::Span::current();
+
+        let mut request_timing = request_timing;
 
-        let result = self.request_handler.handle(&http_request).await;
+        request_timing.stream.set_span(span.clone());
+
+        let client = socket_metadata.peer_addr.map(|peer_addr| {
+            crate::request::client_address(
+                peer_addr.ip(),
+                hyper_request.headers(),
+                self.trusted_proxies,
+            )
+        });
+
+        // only recorded when it differs from the connection's peer
+        if let Some(client) = client.filter(|client| {
+            socket_metadata
+                .peer_addr
+                .is_some_and(|peer_addr| peer_addr.ip() != *client)
+        }) {
+            span.record("client", tracing::field::display(client));
+        }
+
+        let mut route: Arc<str> = Arc::from(DEFAULT_ROUTE);
+
+        let method = hyper_request.method().clone();
+
+        let request_header_bytes = header_bytes(hyper_request.headers());
+
+        let trace_id = if self.exemplars {
+            traceparent_trace_id(hyper_request.headers())
+        } else {
+            None
+        };
+
+        // the access log records the target as the client sent it
+        let access_log_request = (!self.access_logs.is_empty()).then(|| {
+            (
+                SystemTime::now(),
+                hyper_request.uri().clone(),
+                hyper_request.version(),
+            )
+        });
+
+        let mut external_request_id = None;
+
+        let mut resource_usage = None;
+
+        let target_result =
+            match check_request_limits(&hyper_request, request_header_bytes, self.limits) {
+                Some(response) => RequestTargetResult::Respond(response),
+                None => normalize_request_target(hyper_request, self.request_target_configuration),
+            };
+
+        // matched after normalization, which may replace an unknown Host
+        let site = match &target_result {
+            RequestTargetResult::Continue(hyper_request) => {
+                self.virtual_hosts.match_site(hyper_request)
+            }
+            RequestTargetResult::Respond(_) => None,
+        };
+
+        let target_result = match target_result {
+            // local socket requests are never redirected
+            RequestTargetResult::Continue(hyper_request) if socket_metadata.peer_addr.is_some() => {
+                match canonical_redirect(
+                    &hyper_request,
+                    self.request_target_configuration,
+                    socket_metadata.tls_info.is_some(),
+                ) {
+                    Some(response) => RequestTargetResult::Respond(response),
+                    None => RequestTargetResult::Continue(hyper_request),
+                }
+            }
+            target_result => target_result,
+        };
+
+        let result = match target_result {
+            RequestTargetResult::Respond(response) => response,
+            RequestTargetResult::Continue(hyper_request) => {
+                let http_request = HttpRequest::new(
+                    connection_id,
+                    request_id,
+                    hyper_request,
+                    self.limits.max_body_bytes,
+                    socket_metadata,
+                    client,
+                    request_timing.stream.cancellation_token(),
+                );
+
+                if let Some(site) = &site {
+                    http_request.insert_extension(site.clone());
+                }
+
+                if let Some(deadline) = RequestDeadline::new(
+                    http_request.hyper_request.headers(),
+                    start_time,
+                    request_timing.connection_deadline,
+                ) {
+                    http_request.insert_extension(deadline);
+                }
+
+                let result = if self.resource_usage.enabled {
+                    let (result, usage) =
+                        crate::resource_usage::measure(self.request_handler.handle(&http_request))
+                            .await;
+                    resource_usage = Some(usage);
+                    result
+                } else {
+                    self.request_handler.handle(&http_request).await
+                };
+
+                if let Some(MatchedRoute(matched_route)) = http_request.extension::<MatchedRoute>()
+                {
+                    span.record("route", matched_route.as_ref());
+                    route = matched_route;
+                }
+
+                if let Some(ExternalRequestID(id)) = http_request.extension::<ExternalRequestID>() {
+                    external_request_id = Some(id);
+                }
+
+                result
+            }
+        };
 
         let duration = Instant::now() - start_time;
 
         let status = result.status();
 
-        tracing::Span::current()
-            .record("micros", duration.as_micros())
+        span.record("micros", duration.as_micros())
             .record("status", status.as_u16());
 
+        let deny_reason = result.extensions().get::<DenyReason>().copied();
+
+        if let Some(deny_reason) = deny_reason {
+            span.record("deny_reason", deny_reason.as_str());
+        }
+
         if status.is_informational() || status.is_success() || status.is_redirection() {
             debug!("request complete");
         } else if status.is_client_error() {
@@ -93,67 +292,355 @@ impl ConnectionHandler {
             warn!("request complete");
         };
 
-        Ok(result)
+        self.request_metrics
+            .record(&route, status, duration, trace_id);
+
+        notify_connection_observers(|observer| {
+            observer.on_request_complete(&CompletedRequest {
+                connection_id,
+                method: &method,
+                route: &route,
+                status,
+                deny_reason,
+                duration,
+            })
+        });
+
+        let over_budget = resource_usage.is_some_and(|usage| self.over_budget(&route, usage));
+
+        let sample = RouteTimingSample {
+            request_header_bytes,
+            response_header_bytes: header_bytes(result.headers()),
+            header_time: request_timing.header_time,
+            ttfb: duration,
+            write_time: Duration::ZERO,
+            resource_usage,
+            over_budget,
+        };
+
+        // bytes hyper writes before it stops polling the body, if known
+        let body_length = if method == Method::HEAD
+            || status.is_informational()
+            || status == StatusCode::NO_CONTENT
+            || status == StatusCode::NOT_MODIFIED
+        {
+            Some(0)
+        } else {
+            result
+                .headers()
+                .get(header::CONTENT_LENGTH)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.parse().ok())
+                .or_else(|| result.body().size_hint().exact())
+        };
+
+        let (parts, body) = result.into_parts();
+
+        let body =
+            TrafficCountingBody::new(body, self.traffic_stats, client, Arc::clone(&route)).boxed();
+
+        let body = ResponseTimingBody::new(
+            body,
+            self.route_metrics,
+            route,
+            sample,
+            request_timing.request_start,
+            request_timing.stream,
+            body_length,
+        )
+        .boxed();
+
+        let access_log = access_log_request.as_ref().and_then(|(_, uri, _)| {
+            self.access_logs
+                .find(site.map(|site| site.index), uri.path())
+        });
+
+        let body = match (access_log, access_log_request) {
+            (Some(access_log), Some((time, uri, version))) => AccessLogBody::new(
+                body,
+                access_log,
+                AccessLogRecord {
+                    time,
+                    client,
+                    method,
+                    uri,
+                    version,
+                    connection_id,
+                    request_id,
+                    external_request_id,
+                    status,
+                    deny_reason,
+                    response_bytes: 0,
+                    duration: Duration::ZERO,
+                },
+                start_time,
+            )
+            .boxed(),
+            _ => body,
+        };
+
+        Ok(Response::from_parts(parts, body))
     }
 
     #[instrument(
         name = "conn",
         skip_all,
         fields(
-            id = connection.id.as_usize(),
+            conn_id = connection.id.as_usize(),
             sock = ?connection.server_socket_type,
+            peer = tracing::field::Empty,
+            local = tracing::field::Empty,
+            proto = tracing::field::Empty,
+            // recorded by TLS listeners
+            alpn = tracing::field::Empty,
+            sni = tracing::field::Empty,
         )
     )]
     async fn handle_connection(
         self: Arc<Self>,
-        stream: impl HyperReadWrite,
+        stream: impl AsyncReadWrite,
         connection: ConnectionGuard,
     ) {
+        let request_start = Arc::new(RequestStartMarker::default());
+
+        let stream = TokioIo::new(RequestStartTrackingStream::new(
+            stream,
+            Arc::clone(&request_start),
+        ));
+
+        let span = tracing::Span::current();
+
+        if let Some(peer_addr) = connection.socket_metadata.peer_addr {
+            span.record("peer", tracing::field::display(peer_addr));
+        }
+
+        if let Some(tls_info) = &connection.socket_metadata.tls_info {
+            if let Some(alpn) = &tls_info.alpn {
+                span.record("alpn", alpn.as_str());
+            }
+            if let Some(sni) = &tls_info.sni {
+                span.record("sni", sni.as_str());
+            }
+        }
+
+        if let Some(local_addr) = connection.socket_metadata.local_addr {
+            span.record("local", tracing::field::display(local_addr));
+        } else if let Some(local_path) = &connection.socket_metadata.local_path {
+            span.record("local", tracing::field::debug(local_path));
+        }
+
         debug!("begin handle_connection");
 
+        notify_connection_observers(|observer| {
+            observer.on_accept(&AcceptedConnection {
+                id: connection.id,
+                server_socket_type: connection.server_socket_type,
+                socket_metadata: &connection.socket_metadata,
+            })
+        });
+
+        // read per connection, timeouts can change on configuration reload
+        let configuration = crate::config::current();
+        let connection_configuration = &configuration.server_configuration.connection;
+
+        let connection_deadline = connection_configuration
+            .max_lifetime
+            .checked_add(connection_configuration.graceful_shutdown_timeout)
+            .and_then(|lifetime| Instant::now().checked_add(lifetime));
+
         let service = service_fn(|hyper_request| {
             connection.increment_num_requests();
 
+            // record proto once, fmt layers append every recorded value
+            let first_request = connection.negotiated_protocol().is_none();
+
+            connection.record_request_version(hyper_request.version());
+
+            if let Some(negotiated_protocol) =
+                connection.negotiated_protocol().filter(|_| first_request)
+            {
+                span.record("proto", tracing::field::debug(negotiated_protocol));
+            }
+
             let request_id = self.request_id_factory.new_request_id();
 
+            // from the first byte read until hyper finished parsing the request head
+            let header_time = if hyper_request.version() <= Version::HTTP_11 {
+                request_start.elapsed()
+            } else {
+                None
+            };
+
             Arc::clone(&self)
-                .handle_request(connection.id, request_id, hyper_request)
+                .handle_request(
+                    connection.id,
+                    request_id,
+                    hyper_request,
+                    Arc::clone(&connection.socket_metadata),
+                    RequestTiming {
+                        request_start: Arc::clone(&request_start),
+                        header_time,
+                        stream: connection.open_stream(),
+                        connection_deadline,
+                    },
+                )
                 .in_current_span()
         });
 
-        let builder = HyperConnAutoBuilder::new(self.tokio_executor.clone());
+        let mut builder = HyperConnAutoBuilder::new(self.tokio_executor.clone());
+
+        // hyper answers HTTP/1 heads over the buffer size with 431, the
+        // configured size is validated at startup to be between hyper's
+        // minimum buffer size and u32::MAX
+        builder.http1().max_buf_size(self.limits.max_header_bytes);
+        builder
+            .http2()
+            .max_header_list_size(u32::try_from(self.limits.max_header_bytes).unwrap_or(u32::MAX));
 
-        let hyper_conn = builder.serve_connection(stream, service);
+        let hyper_conn = builder.serve_connection_with_upgrades(stream, service);
         pin!(hyper_conn);
 
-        for (iter, sleep_duration) in self.connection_timeout_durations.iter().enumerate() {
+        // a drain counts as reaching max_lifetime
+        let mut graceful_shutdown_called = false;
+
+        let connection_timeout_durations = [
+            connection_configuration.max_lifetime,
+            connection_configuration.graceful_shutdown_timeout,
+        ];
+
+        for (iter, sleep_duration) in connection_timeout_durations.iter().enumerate() {
             debug!("iter = {} sleep_duration = {:?}", iter, sleep_duration);
             tokio::select! {
                 res = hyper_conn.as_mut() => {
                     match res {
                         Ok(()) => debug!("after polling conn, no error"),
-                        Err(e) =>  warn!("error serving connection: {:?}", e),
+                        Err(e) => {
+                            warn!("error serving connection: {:?}", e);
+                            connection.record_error();
+                        }
                     };
                     break;
                 }
                 _ = tokio::time::sleep(*sleep_duration) => {
                     info!("iter = {} got timeout_interval, calling conn.graceful_shutdown", iter);
                     hyper_conn.as_mut().graceful_shutdown();
+                    graceful_shutdown_called = true;
+                }
+                _ = crate::shutdown::wait_for_drain(), if !graceful_shutdown_called => {
+                    info!("iter = {} draining, calling conn.graceful_shutdown", iter);
+                    hyper_conn.as_mut().graceful_shutdown();
+                    graceful_shutdown_called = true;
                 }
             }
         }
 
         debug!(
-            "end handle_connection num_requests = {}",
-            connection.num_requests()
+            "end handle_connection num_requests = {} negotiated_protocol = {:?}",
+            connection.num_requests(),
+            connection.negotiated_protocol(),
         );
+
+        let closed_connection = ClosedConnection {
+            id: connection.id,
+            server_socket_type: connection.server_socket_type,
+            age: connection.creation_instant.elapsed(),
+            num_requests: connection.num_requests(),
+            negotiated_protocol: connection.negotiated_protocol(),
+            had_error: connection.had_error(),
+        };
+
+        notify_connection_observers(|observer| observer.on_close(&closed_connection));
     }
 
     pub fn start_connection_handler(
         self: &Arc<Self>,
-        stream: impl HyperReadWrite,
+        stream: impl AsyncReadWrite,
         connection: ConnectionGuard,
     ) {
         tokio::spawn(Arc::clone(self).handle_connection(stream, connection));
     }
 }
+
+// Serves one in-memory connection with the test configuration, returns the
+// client end of the duplex stream.  Shared by unit tests and benches.
+pub async fn serve_duplex_connection(request_handler: Box<dyn RequestHandler>) -> DuplexStream {
+    crate::config::set_test_instance();
+
+    let connection_handler = ConnectionHandler::new(request_handler, RequestIDFactory::new()).await;
+
+    let connection = ConnectionTracker::instance()
+        .await
+        .add_connection(ServerSocketType::Tcp, SocketMetadata::default())
+        .await
+        .unwrap();
+
+    let (client_stream, server_stream) = tokio::io::duplex(64 * 1024);
+
+    connection_handler.start_connection_handler(server_stream, connection);
+
+    client_stream
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use bytes::Bytes;
+
+    use http_body_util::Empty;
+
+    use crate::test_util::HelloHandler;
+
+    #[tokio::test]
+    async fn test_http1_duplex_connection() {
+        let client_stream = serve_duplex_connection(Box::new(HelloHandler)).await;
+
+        let (mut sender, client_connection) =
+            hyper::client::conn::http1::handshake(TokioIo::new(client_stream))
+                .await
+                .unwrap();
+        tokio::spawn(client_connection);
+
+        for _ in 0..2 {
+            let request = Request::builder()
+                .uri("/hello")
+                .header(header::HOST, "localhost")
+                .body(Empty::<Bytes>::new())
+                .unwrap();
+
+            let response = sender.send_request(request).await.unwrap();
+
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(response.version(), Version::HTTP_11);
+
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            assert_eq!(body, "hello");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_http2_duplex_connection() {
+        let client_stream = serve_duplex_connection(Box::new(HelloHandler)).await;
+
+        let (mut sender, client_connection) = hyper::client::conn::http2::handshake(
+            TokioExecutor::new(),
+            TokioIo::new(client_stream),
+        )
+        .await
+        .unwrap();
+        tokio::spawn(client_connection);
+
+        let request = Request::builder()
+            .uri("http://localhost/hello")
+            .body(Empty::<Bytes>::new())
+            .unwrap();
+
+        let response = sender.send_request(request).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.version(), Version::HTTP_2);
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, "hello");
+    }
+}
@@ -0,0 +1,106 @@
+use anyhow::Context;
+
+use tracing::{info, warn};
+
+use tokio::net::windows::named_pipe::{self, ServerOptions};
+
+use std::{path::PathBuf, sync::Arc};
+
+use crate::{
+    config::ServerSocketType,
+    connection::{ConnectionTracker, SocketMetadata},
+    server::{handler::ConnectionHandler, run_accept_loops},
+};
+
+// Local only listener for Windows, the counterpart of UnixServer.  Each
+// accept task waits on its own pipe instance and creates the next one as soon
+// as a client connects.
+pub struct NamedPipeServer {
+    connection_handler: Arc<ConnectionHandler>,
+    connection_tracker: &'static ConnectionTracker,
+    listener_configuration: &'static crate::config::ServerListenerConfiguration,
+}
+
+impl NamedPipeServer {
+    pub async fn new(
+        connection_handler: Arc<ConnectionHandler>,
+        listener_configuration: &'static crate::config::ServerListenerConfiguration,
+    ) -> Self {
+        Self {
+            connection_handler,
+            connection_tracker: ConnectionTracker::instance().await,
+            listener_configuration,
+        }
+    }
+
+    fn create_instance(
+        &self,
+        first_pipe_instance: bool,
+    ) -> anyhow::Result<named_pipe::NamedPipeServer> {
+        let pipe_name = &self.listener_configuration.bind_address;
+
+        ServerOptions::new()
+            .first_pipe_instance(first_pipe_instance)
+            .reject_remote_clients(true)
+            .create(pipe_name)
+            .with_context(|| format!("NAMED_PIPE server create error name = {:?}", pipe_name))
+    }
+
+    pub async fn run(self) -> anyhow::Result<()> {
+        // first_pipe_instance fails if another process already owns the name.
+        let mut pipe_instances = Vec::with_capacity(self.listener_configuration.accept_tasks());
+        for i in 0..self.listener_configuration.accept_tasks() {
+            pipe_instances.push(self.create_instance(i == 0)?);
+        }
+
+        info!(
+            "listening on named pipe {:?}",
+            self.listener_configuration.bind_address
+        );
+
+        if self.listener_configuration.socket_options.reuse_port {
+            warn!("reuse_port is not supported for named pipes, ignoring");
+        }
+
+        let server = Arc::new(self);
+
+        run_accept_loops(
+            pipe_instances
+                .into_iter()
+                .map(|pipe_instance| Arc::clone(&server).accept_loop(pipe_instance)),
+        )
+        .await
+    }
+
+    async fn accept_loop(
+        self: Arc<Self>,
+        mut pipe_instance: named_pipe::NamedPipeServer,
+    ) -> anyhow::Result<()> {
+        loop {
+            let connect_result = pipe_instance.connect().await;
+
+            let connected_pipe =
+                std::mem::replace(&mut pipe_instance, self.create_instance(false)?);
+
+            if let Err(e) = connect_result {
+                // the client went away before the connection completed
+                warn!("named pipe connect error: {}", e);
+                continue;
+            }
+
+            let socket_metadata = SocketMetadata {
+                local_path: Some(PathBuf::from(&self.listener_configuration.bind_address)),
+                ..Default::default()
+            };
+
+            if let Some(connection) = self
+                .connection_tracker
+                .add_connection(ServerSocketType::NamedPipe, socket_metadata)
+                .await
+            {
+                self.connection_handler
+                    .start_connection_handler(connected_pipe, connection);
+            }
+        }
+    }
+}
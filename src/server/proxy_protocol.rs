@@ -0,0 +1,272 @@
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use tracing::debug;
+
+use std::{
+    fmt::Debug,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+};
+
+use crate::config::ListenerProxyProtocolConfiguration;
+
+const V2_SIGNATURE: [u8; 12] = [
+    0x0d, 0x0a, 0x0d, 0x0a, 0x00, 0x0d, 0x0a, 0x51, 0x55, 0x49, 0x54, 0x0a,
+];
+
+// v1 headers are one line of at most 107 bytes including "\r\n".
+const V1_MAX_LENGTH: usize = 107;
+
+const V2_COMMAND_LOCAL: u8 = 0x0;
+const V2_COMMAND_PROXY: u8 = 0x1;
+
+const V2_FAMILY_INET: u8 = 0x1;
+const V2_FAMILY_INET6: u8 = 0x2;
+
+#[derive(thiserror::Error, Debug)]
+pub enum ProxyProtocolError {
+    #[error("read error: {0}")]
+    Read(#[from] std::io::Error),
+
+    #[error("missing PROXY protocol header")]
+    MissingHeader,
+
+    #[error("invalid v1 header: {0}")]
+    InvalidV1Header(&'static str),
+
+    #[error("invalid v2 header: {0}")]
+    InvalidV2Header(&'static str),
+}
+
+// The client address the proxy received the connection from.  None for
+// health checks from the proxy itself and for address families other than
+// TCP over IPv4 or IPv6.
+#[derive(Debug, PartialEq)]
+pub struct ProxyHeader {
+    pub source: Option<SocketAddr>,
+}
+
+impl ProxyHeader {
+    fn local() -> Self {
+        Self { source: None }
+    }
+}
+
+fn parse_v1(line: &str) -> Result<ProxyHeader, ProxyProtocolError> {
+    let mut fields = line.split(' ');
+
+    if fields.next() != Some("PROXY") {
+        return Err(ProxyProtocolError::InvalidV1Header("missing PROXY"));
+    }
+
+    match fields.next() {
+        Some("TCP4" | "TCP6") => {}
+        Some("UNKNOWN") => return Ok(ProxyHeader::local()),
+        _ => return Err(ProxyProtocolError::InvalidV1Header("invalid protocol")),
+    }
+
+    let mut next_field = || {
+        fields
+            .next()
+            .ok_or(ProxyProtocolError::InvalidV1Header("missing field"))
+    };
+
+    let source_ip: IpAddr = next_field()?
+        .parse()
+        .map_err(|_| ProxyProtocolError::InvalidV1Header("invalid source address"))?;
+    next_field()?
+        .parse::<IpAddr>()
+        .map_err(|_| ProxyProtocolError::InvalidV1Header("invalid destination address"))?;
+    let source_port: u16 = next_field()?
+        .parse()
+        .map_err(|_| ProxyProtocolError::InvalidV1Header("invalid source port"))?;
+    next_field()?
+        .parse::<u16>()
+        .map_err(|_| ProxyProtocolError::InvalidV1Header("invalid destination port"))?;
+
+    Ok(ProxyHeader {
+        source: Some(SocketAddr::new(source_ip, source_port)),
+    })
+}
+
+fn parse_v2_addresses(family: u8, addresses: &[u8]) -> Result<ProxyHeader, ProxyProtocolError> {
+    let port = |bytes: &[u8]| u16::from_be_bytes([bytes[0], bytes[1]]);
+
+    match family {
+        V2_FAMILY_INET => {
+            let addresses = addresses
+                .get(..12)
+                .ok_or(ProxyProtocolError::InvalidV2Header("short IPv4 addresses"))?;
+
+            let ip = |bytes: &[u8]| {
+                IpAddr::from(Ipv4Addr::from([bytes[0], bytes[1], bytes[2], bytes[3]]))
+            };
+
+            Ok(ProxyHeader {
+                source: Some(SocketAddr::new(
+                    ip(&addresses[0..4]),
+                    port(&addresses[8..10]),
+                )),
+            })
+        }
+        V2_FAMILY_INET6 => {
+            let addresses = addresses
+                .get(..36)
+                .ok_or(ProxyProtocolError::InvalidV2Header("short IPv6 addresses"))?;
+
+            let ip = |bytes: &[u8]| {
+                let mut octets = [0; 16];
+                octets.copy_from_slice(bytes);
+                IpAddr::from(Ipv6Addr::from(octets))
+            };
+
+            Ok(ProxyHeader {
+                source: Some(SocketAddr::new(
+                    ip(&addresses[0..16]),
+                    port(&addresses[32..34]),
+                )),
+            })
+        }
+        // UNSPEC and UNIX
+        _ => Ok(ProxyHeader::local()),
+    }
+}
+
+async fn read_v1<S: AsyncRead + Unpin>(
+    stream: &mut S,
+    start: &[u8],
+) -> Result<ProxyHeader, ProxyProtocolError> {
+    let mut line = start.to_vec();
+
+    // one byte at a time to leave the request that follows unread
+    while !line.ends_with(b"\r\n") {
+        if line.len() >= V1_MAX_LENGTH {
+            return Err(ProxyProtocolError::InvalidV1Header("header too long"));
+        }
+        line.push(stream.read_u8().await?);
+    }
+
+    let line = std::str::from_utf8(&line[..line.len() - 2])
+        .map_err(|_| ProxyProtocolError::InvalidV1Header("not ASCII"))?;
+
+    parse_v1(line)
+}
+
+async fn read_v2<S: AsyncRead + Unpin>(stream: &mut S) -> Result<ProxyHeader, ProxyProtocolError> {
+    let mut header = [0; 4];
+    stream.read_exact(&mut header).await?;
+
+    let [version_command, family_protocol, length_high, length_low] = header;
+
+    if version_command >> 4 != 2 {
+        return Err(ProxyProtocolError::InvalidV2Header("unsupported version"));
+    }
+
+    // the addresses and TLVs are read even when unused
+    let mut addresses = vec![0; usize::from(u16::from_be_bytes([length_high, length_low]))];
+    stream.read_exact(&mut addresses).await?;
+
+    match version_command & 0x0f {
+        V2_COMMAND_LOCAL => Ok(ProxyHeader::local()),
+        V2_COMMAND_PROXY => parse_v2_addresses(family_protocol >> 4, &addresses),
+        _ => Err(ProxyProtocolError::InvalidV2Header("unsupported command")),
+    }
+}
+
+// Reads a v1 or v2 header from the start of a connection, leaving what
+// follows it unread.
+pub async fn read_proxy_header<S: AsyncRead + Unpin>(
+    stream: &mut S,
+) -> Result<ProxyHeader, ProxyProtocolError> {
+    // the shortest v1 header "PROXY UNKNOWN\r\n" is longer than the v2 signature
+    let mut start = [0; V2_SIGNATURE.len()];
+    stream.read_exact(&mut start).await?;
+
+    if start == V2_SIGNATURE {
+        read_v2(stream).await
+    } else if start.starts_with(b"PROXY ") {
+        read_v1(stream, &start).await
+    } else {
+        Err(ProxyProtocolError::MissingHeader)
+    }
+}
+
+// None if the header is missing, invalid or late, the connection is then
+// closed without a response.
+pub async fn read_proxy_header_with_timeout<S: AsyncRead + Unpin>(
+    stream: &mut S,
+    configuration: &ListenerProxyProtocolConfiguration,
+    peer: impl Debug,
+) -> Option<ProxyHeader> {
+    match tokio::time::timeout(configuration.header_timeout, read_proxy_header(stream)).await {
+        Ok(Ok(proxy_header)) => Some(proxy_header),
+        Ok(Err(e)) => {
+            debug!("PROXY protocol error peer = {:?}: {}", peer, e);
+            None
+        }
+        Err(_) => {
+            debug!("PROXY protocol header timeout peer = {:?}", peer);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_read_v1() {
+        let mut input: &[u8] = b"PROXY TCP4 192.0.2.1 198.51.100.2 56324 443\r\nGET / HTTP/1.1\r\n";
+
+        let header = read_proxy_header(&mut input).await.unwrap();
+        assert_eq!(header.source, Some("192.0.2.1:56324".parse().unwrap()));
+        assert_eq!(input, b"GET / HTTP/1.1\r\n");
+
+        let mut input: &[u8] = b"PROXY TCP6 2001:db8::1 2001:db8::2 1 2\r\n";
+        let header = read_proxy_header(&mut input).await.unwrap();
+        assert_eq!(header.source, Some("[2001:db8::1]:1".parse().unwrap()));
+
+        let mut input: &[u8] = b"PROXY UNKNOWN\r\n";
+        assert_eq!(
+            read_proxy_header(&mut input).await.unwrap(),
+            ProxyHeader::local()
+        );
+
+        let mut input: &[u8] = b"PROXY TCP4 192.0.2.1 198.51.100.2 56324\r\n";
+        assert!(read_proxy_header(&mut input).await.is_err());
+
+        let mut input: &[u8] = b"GET / HTTP/1.1\r\nHost: example.com\r\n";
+        assert!(matches!(
+            read_proxy_header(&mut input).await,
+            Err(ProxyProtocolError::MissingHeader)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_read_v2() {
+        let mut bytes = V2_SIGNATURE.to_vec();
+        bytes.extend_from_slice(&[0x21, 0x11, 0x00, 0x0f]);
+        bytes.extend_from_slice(&[192, 0, 2, 1, 198, 51, 100, 2, 0xdc, 0x04, 0x01, 0xbb]);
+        // a TLV the reader skips
+        bytes.extend_from_slice(&[0x04, 0x00, 0x00]);
+        bytes.extend_from_slice(b"GET");
+
+        let mut input = bytes.as_slice();
+        let header = read_proxy_header(&mut input).await.unwrap();
+        assert_eq!(header.source, Some("192.0.2.1:56324".parse().unwrap()));
+        assert_eq!(input, b"GET");
+
+        let mut bytes = V2_SIGNATURE.to_vec();
+        bytes.extend_from_slice(&[0x20, 0x00, 0x00, 0x00]);
+        let mut input = bytes.as_slice();
+        assert_eq!(
+            read_proxy_header(&mut input).await.unwrap(),
+            ProxyHeader::local()
+        );
+
+        let mut bytes = V2_SIGNATURE.to_vec();
+        bytes.extend_from_slice(&[0x21, 0x11, 0x00, 0x04, 1, 2, 3, 4]);
+        let mut input = bytes.as_slice();
+        assert!(read_proxy_header(&mut input).await.is_err());
+    }
+}
@@ -0,0 +1,133 @@
+use anyhow::Context;
+
+use socket2::{Domain, SockRef, Socket, TcpKeepalive, Type};
+
+use tokio::net::{TcpListener, TcpStream};
+
+use tracing::debug;
+
+use std::net::SocketAddr;
+
+use crate::config::ListenerSocketOptions;
+
+const DEFAULT_BACKLOG: i32 = 1024;
+
+fn apply_listener_options(
+    socket: &Socket,
+    socket_options: &ListenerSocketOptions,
+) -> anyhow::Result<()> {
+    // Buffer sizes set on the listener are inherited by accepted sockets.
+    if let Some(recv_buffer_size) = socket_options.recv_buffer_size {
+        socket
+            .set_recv_buffer_size(recv_buffer_size)
+            .context("set_recv_buffer_size error")?;
+    }
+
+    if let Some(send_buffer_size) = socket_options.send_buffer_size {
+        socket
+            .set_send_buffer_size(send_buffer_size)
+            .context("set_send_buffer_size error")?;
+    }
+
+    Ok(())
+}
+
+fn listen(socket: &Socket, socket_options: &ListenerSocketOptions) -> anyhow::Result<()> {
+    let backlog = socket_options
+        .backlog
+        .map(|backlog| backlog.try_into().unwrap_or(i32::MAX))
+        .unwrap_or(DEFAULT_BACKLOG);
+
+    debug!("listen backlog = {}", backlog);
+
+    socket.listen(backlog).context("listen error")?;
+
+    socket
+        .set_nonblocking(true)
+        .context("set_nonblocking error")?;
+
+    Ok(())
+}
+
+pub async fn bind_tcp_listener(
+    address: &str,
+    socket_options: &ListenerSocketOptions,
+) -> anyhow::Result<TcpListener> {
+    let socket_addr: SocketAddr = tokio::net::lookup_host(address)
+        .await
+        .context("lookup_host error")?
+        .next()
+        .context("lookup_host returned no addresses")?;
+
+    let socket = Socket::new(Domain::for_address(socket_addr), Type::STREAM, None)
+        .context("Socket::new error")?;
+
+    socket
+        .set_reuse_address(true)
+        .context("set_reuse_address error")?;
+
+    // SO_REUSEPORT is unix only, elsewhere TCPServer shares one socket.
+    #[cfg(unix)]
+    if socket_options.reuse_port {
+        socket
+            .set_reuse_port(true)
+            .context("set_reuse_port error")?;
+    }
+
+    apply_listener_options(&socket, socket_options)?;
+
+    socket.bind(&socket_addr.into()).context("bind error")?;
+
+    listen(&socket, socket_options)?;
+
+    TcpListener::from_std(socket.into()).context("TcpListener::from_std error")
+}
+
+#[cfg(unix)]
+pub fn bind_unix_listener(
+    path: &str,
+    socket_options: &ListenerSocketOptions,
+) -> anyhow::Result<tokio::net::UnixListener> {
+    use socket2::SockAddr;
+
+    let socket = Socket::new(Domain::UNIX, Type::STREAM, None).context("Socket::new error")?;
+
+    apply_listener_options(&socket, socket_options)?;
+
+    let sock_addr = SockAddr::unix(path).context("SockAddr::unix error")?;
+
+    socket.bind(&sock_addr).context("bind error")?;
+
+    listen(&socket, socket_options)?;
+
+    let std_listener: std::os::unix::net::UnixListener = socket.into();
+
+    tokio::net::UnixListener::from_std(std_listener).context("UnixListener::from_std error")
+}
+
+pub fn apply_tcp_stream_options(
+    tcp_stream: &TcpStream,
+    socket_options: &ListenerSocketOptions,
+) -> std::io::Result<()> {
+    tcp_stream.set_nodelay(socket_options.tcp_nodelay.unwrap_or(true))?;
+
+    if let Some(keepalive_configuration) = &socket_options.tcp_keepalive {
+        let mut keepalive = TcpKeepalive::new();
+
+        if let Some(time) = keepalive_configuration.time {
+            keepalive = keepalive.with_time(time);
+        }
+
+        if let Some(interval) = keepalive_configuration.interval {
+            keepalive = keepalive.with_interval(interval);
+        }
+
+        if let Some(retries) = keepalive_configuration.retries {
+            keepalive = keepalive.with_retries(retries);
+        }
+
+        SockRef::from(tcp_stream).set_tcp_keepalive(&keepalive)?;
+    }
+
+    Ok(())
+}
@@ -1,39 +1,65 @@
 use anyhow::Context;
 
-use hyper_util::rt::TokioIo;
+use tracing::{debug, info, warn};
 
-use tracing::{info, warn};
+use tokio::net::{TcpListener, TcpStream};
 
-use tokio::net::TcpListener;
+use tokio_rustls::TlsAcceptor;
 
-use std::sync::Arc;
+use std::{net::SocketAddr, sync::Arc};
 
 use crate::{
-    config::ServerSocketType, connection::ConnectionTracker, server::handler::ConnectionHandler,
+    config::ServerSocketType,
+    connection::{ConnectionTracker, PendingConnection, SocketMetadata},
+    geoip::GeoIpService,
+    server::{
+        accept::handle_accept_error,
+        handler::ConnectionHandler,
+        proxy_protocol::read_proxy_header_with_timeout,
+        run_accept_loops,
+        socket::{apply_tcp_stream_options, bind_tcp_listener},
+        tls::{build_tls_acceptor, tls_info},
+    },
 };
 
 pub struct TCPServer {
     connection_handler: Arc<ConnectionHandler>,
     connection_tracker: &'static ConnectionTracker,
+    geoip_service: &'static GeoIpService,
     listener_configuration: &'static crate::config::ServerListenerConfiguration,
+    tls_acceptor: Option<TlsAcceptor>,
 }
 
 impl TCPServer {
     pub async fn new(
         connection_handler: Arc<ConnectionHandler>,
         listener_configuration: &'static crate::config::ServerListenerConfiguration,
-    ) -> Self {
-        Self {
+    ) -> anyhow::Result<Self> {
+        let tls_acceptor = listener_configuration
+            .tls
+            .as_ref()
+            .map(build_tls_acceptor)
+            .transpose()
+            .with_context(|| {
+                format!(
+                    "TCP server TLS error address = {:?}",
+                    listener_configuration.bind_address
+                )
+            })?;
+
+        Ok(Self {
             connection_handler,
             connection_tracker: ConnectionTracker::instance().await,
+            geoip_service: crate::geoip::geoip_service_instance(),
             listener_configuration,
-        }
+            tls_acceptor,
+        })
     }
 
-    pub async fn run(self) -> anyhow::Result<()> {
+    async fn bind(&self) -> anyhow::Result<TcpListener> {
         let address = &self.listener_configuration.bind_address;
 
-        let tcp_listener = TcpListener::bind(address)
+        let tcp_listener = bind_tcp_listener(address, &self.listener_configuration.socket_options)
             .await
             .with_context(|| format!("TCP server bind error address = {:?}", address))?;
 
@@ -41,24 +67,174 @@ impl TCPServer {
             .local_addr()
             .with_context(|| format!("TCP server local_addr error address = {:?}", address))?;
 
-        info!("listening on tcp {:?}", local_addr);
+        info!(
+            "listening on tcp {:?} tls = {}",
+            local_addr,
+            self.tls_acceptor.is_some()
+        );
+
+        Ok(tcp_listener)
+    }
+
+    pub async fn run(self) -> anyhow::Result<()> {
+        let accept_tasks = self.listener_configuration.accept_tasks();
+
+        // With reuse_port each accept task gets its own socket and the kernel
+        // balances connections, otherwise all accept tasks share one socket.
+        let reuse_port = self.listener_configuration.socket_options.reuse_port;
+        if reuse_port && !cfg!(unix) {
+            warn!(
+                "reuse_port is not supported on this platform, accept tasks will share one socket"
+            );
+        }
+
+        let mut tcp_listeners = Vec::with_capacity(accept_tasks);
+        if reuse_port && cfg!(unix) {
+            for _ in 0..accept_tasks {
+                tcp_listeners.push(Arc::new(self.bind().await?));
+            }
+        } else {
+            let tcp_listener = Arc::new(self.bind().await?);
+            tcp_listeners.resize(accept_tasks, tcp_listener);
+        }
+
+        let server = Arc::new(self);
+
+        run_accept_loops(
+            tcp_listeners
+                .into_iter()
+                .map(|tcp_listener| Arc::clone(&server).accept_loop(tcp_listener)),
+        )
+        .await
+    }
+
+    async fn accept_loop(self: Arc<Self>, tcp_listener: Arc<TcpListener>) -> anyhow::Result<()> {
+        let socket_options = &self.listener_configuration.socket_options;
 
         loop {
-            let (tcp_stream, _remote_addr) = tcp_listener.accept().await?;
+            let (tcp_stream, remote_addr) = match tcp_listener.accept().await {
+                Ok(result) => result,
+                Err(e) => {
+                    handle_accept_error(e, tcp_listener.accept()).await?;
+                    continue;
+                }
+            };
+
+            if let Err(e) = apply_tcp_stream_options(&tcp_stream, socket_options) {
+                warn!("error applying tcp socket options {:?}", e);
+                continue;
+            };
+
+            if let Some(proxy_protocol) = &self.listener_configuration.proxy_protocol {
+                if !proxy_protocol.allows_source(remote_addr.ip()) {
+                    debug!("PROXY protocol peer not allowed peer = {:?}", remote_addr);
+                    continue;
+                }
+            }
+
+            if self.tls_acceptor.is_some() || self.listener_configuration.proxy_protocol.is_some() {
+                // handshakes and PROXY protocol headers are read off the accept
+                // loop, holding a connection_limit slot meanwhile
+                let Some(pending_connection) = self
+                    .connection_tracker
+                    .reserve_connection(ServerSocketType::Tcp)
+                    .await
+                else {
+                    continue;
+                };
 
-            if let Err(e) = tcp_stream.set_nodelay(true) {
-                warn!("error setting tcp no delay {:?}", e);
+                tokio::spawn(Arc::clone(&self).start_connection(
+                    tcp_stream,
+                    remote_addr,
+                    pending_connection,
+                ));
                 continue;
+            }
+
+            let socket_metadata = self.socket_metadata(&tcp_stream, remote_addr);
+
+            if let Some(connection) = self
+                .connection_tracker
+                .add_connection(ServerSocketType::Tcp, socket_metadata)
+                .await
+            {
+                self.connection_handler
+                    .start_connection_handler(tcp_stream, connection);
+            }
+        }
+    }
+
+    fn socket_metadata(&self, tcp_stream: &TcpStream, remote_addr: SocketAddr) -> SocketMetadata {
+        SocketMetadata {
+            peer_addr: Some(remote_addr),
+            geo_info: self.geoip_service.lookup(remote_addr.ip()),
+            local_addr: tcp_stream.local_addr().ok(),
+            ..Default::default()
+        }
+    }
+
+    async fn start_connection(
+        self: Arc<Self>,
+        mut tcp_stream: TcpStream,
+        remote_addr: SocketAddr,
+        pending_connection: PendingConnection,
+    ) {
+        let mut socket_metadata = self.socket_metadata(&tcp_stream, remote_addr);
+
+        if let Some(proxy_protocol) = &self.listener_configuration.proxy_protocol {
+            let Some(proxy_header) =
+                read_proxy_header_with_timeout(&mut tcp_stream, proxy_protocol, remote_addr).await
+            else {
+                return;
             };
 
+            // LOCAL headers are the proxy's own health checks
+            if let Some(source) = proxy_header.source {
+                socket_metadata = self.socket_metadata(&tcp_stream, source);
+                socket_metadata.proxy_addr = Some(remote_addr);
+            }
+        }
+
+        let (Some(tls_acceptor), Some(tls_configuration)) =
+            (&self.tls_acceptor, &self.listener_configuration.tls)
+        else {
             if let Some(connection) = self
                 .connection_tracker
-                .add_connection(ServerSocketType::Tcp)
+                .add_pending_connection(pending_connection, socket_metadata)
                 .await
             {
                 self.connection_handler
-                    .start_connection_handler(TokioIo::new(tcp_stream), connection);
+                    .start_connection_handler(tcp_stream, connection);
             }
+            return;
+        };
+
+        let tls_stream = match tokio::time::timeout(
+            tls_configuration.handshake_timeout,
+            tls_acceptor.accept(tcp_stream),
+        )
+        .await
+        {
+            Ok(Ok(tls_stream)) => tls_stream,
+            Ok(Err(e)) => {
+                debug!("TLS handshake error peer = {:?}: {}", remote_addr, e);
+                return;
+            }
+            Err(_) => {
+                debug!("TLS handshake timeout peer = {:?}", remote_addr);
+                return;
+            }
+        };
+
+        socket_metadata.tls_info = Some(tls_info(tls_stream.get_ref().1));
+
+        if let Some(connection) = self
+            .connection_tracker
+            .add_pending_connection(pending_connection, socket_metadata)
+            .await
+        {
+            self.connection_handler
+                .start_connection_handler(tls_stream, connection);
         }
     }
 }
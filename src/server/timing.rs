@@ -0,0 +1,213 @@
+use bytes::Bytes;
+
+use hyper::body::{Body, Frame, SizeHint};
+
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    time::{Duration, Instant},
+};
+
+use std::{
+    io,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+
+use crate::{
+    connection::StreamGuard,
+    response::{ResponseBody, ResponseBodyError},
+    route_metrics::{RouteMetrics, RouteTimingSample},
+};
+
+// Time the first byte of the current request was read.  The mark is set by
+// the first read after the previous response finished, so it is only
+// meaningful for HTTP/1 where requests on a connection do not overlap.
+#[derive(Default)]
+pub struct RequestStartMarker(Mutex<Option<Instant>>);
+
+impl RequestStartMarker {
+    fn mark(&self) {
+        let mut request_start = self.0.lock().unwrap();
+        if request_start.is_none() {
+            *request_start = Some(Instant::now());
+        }
+    }
+
+    fn clear(&self) {
+        *self.0.lock().unwrap() = None;
+    }
+
+    pub fn elapsed(&self) -> Option<Duration> {
+        self.0
+            .lock()
+            .unwrap()
+            .map(|request_start| request_start.elapsed())
+    }
+}
+
+pub struct RequestStartTrackingStream<S> {
+    inner: S,
+    request_start: Arc<RequestStartMarker>,
+}
+
+impl<S> RequestStartTrackingStream<S> {
+    pub fn new(inner: S, request_start: Arc<RequestStartMarker>) -> Self {
+        Self {
+            inner,
+            request_start,
+        }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for RequestStartTrackingStream<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let filled_before = buf.filled().len();
+
+        let poll = Pin::new(&mut self.inner).poll_read(cx, buf);
+
+        if matches!(poll, Poll::Ready(Ok(()))) && buf.filled().len() > filled_before {
+            self.request_start.mark();
+        }
+
+        poll
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for RequestStartTrackingStream<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+// Gaps between body polls shorter than this are scheduling noise, not stalls.
+const WRITE_STALL_THRESHOLD: Duration = Duration::from_millis(1);
+
+// Measures response write time from handler return until the body is dropped,
+// then records the request's sample in RouteMetrics.  Also closes the request
+// stream and accumulates write stalls on it.
+pub struct ResponseTimingBody {
+    inner: ResponseBody,
+    route_metrics: &'static RouteMetrics,
+    route: Arc<str>,
+    sample: RouteTimingSample,
+    write_start: Instant,
+    request_start: Arc<RequestStartMarker>,
+    stream: StreamGuard,
+    // set when a frame was handed to hyper, cleared while the inner body is pending
+    last_frame: Option<Instant>,
+    // bytes left of a known length body, hyper stops polling once they are written
+    remaining_bytes: Option<u64>,
+}
+
+impl ResponseTimingBody {
+    pub fn new(
+        inner: ResponseBody,
+        route_metrics: &'static RouteMetrics,
+        route: Arc<str>,
+        sample: RouteTimingSample,
+        request_start: Arc<RequestStartMarker>,
+        stream: StreamGuard,
+        body_length: Option<u64>,
+    ) -> Self {
+        Self {
+            inner,
+            route_metrics,
+            route,
+            sample,
+            write_start: Instant::now(),
+            request_start,
+            stream,
+            last_frame: None,
+            remaining_bytes: body_length,
+        }
+    }
+}
+
+impl Body for ResponseTimingBody {
+    type Data = Bytes;
+    type Error = ResponseBodyError;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let now = Instant::now();
+
+        if let Some(last_frame) = self.last_frame.take() {
+            let stall = now - last_frame;
+            if stall >= WRITE_STALL_THRESHOLD {
+                self.stream.record_write_stall(stall);
+            }
+        }
+
+        let poll = Pin::new(&mut self.inner).poll_frame(cx);
+
+        match &poll {
+            Poll::Ready(Some(Ok(frame))) => {
+                self.last_frame = Some(now);
+
+                if let (Some(remaining_bytes), Some(data)) =
+                    (self.remaining_bytes, frame.data_ref())
+                {
+                    self.remaining_bytes = Some(remaining_bytes.saturating_sub(data.len() as u64));
+                }
+            }
+            Poll::Ready(None) => self.stream.finish(),
+            _ => {}
+        }
+
+        poll
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+impl Drop for ResponseTimingBody {
+    fn drop(&mut self) {
+        self.sample.write_time = self.write_start.elapsed();
+
+        if self.inner.is_end_stream() || self.remaining_bytes == Some(0) {
+            self.stream.finish();
+        }
+
+        self.route_metrics
+            .record(Arc::clone(&self.route), &self.sample);
+
+        self.request_start.clear();
+    }
+}
@@ -0,0 +1,47 @@
+use anyhow::Context;
+
+use tokio_rustls::{
+    rustls::{
+        pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer},
+        ServerConfig, ServerConnection,
+    },
+    TlsAcceptor,
+};
+
+use tracing::debug;
+
+use std::sync::Arc;
+
+use crate::{config::ListenerTlsConfiguration, connection::TlsInfo};
+
+pub fn build_tls_acceptor(
+    tls_configuration: &ListenerTlsConfiguration,
+) -> anyhow::Result<TlsAcceptor> {
+    debug!("tls_configuration = {:?}", tls_configuration);
+
+    let certs = CertificateDer::pem_file_iter(&tls_configuration.cert_path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .with_context(|| format!("error reading cert_path '{}'", tls_configuration.cert_path))?;
+
+    let key = PrivateKeyDer::from_pem_file(&tls_configuration.key_path)
+        .with_context(|| format!("error reading key_path '{}'", tls_configuration.key_path))?;
+
+    let mut server_config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .context("invalid TLS certificate or key")?;
+
+    // hyper's auto builder serves either protocol
+    server_config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+
+    Ok(TlsAcceptor::from(Arc::new(server_config)))
+}
+
+pub fn tls_info(server_connection: &ServerConnection) -> TlsInfo {
+    TlsInfo {
+        sni: server_connection.server_name().map(str::to_owned),
+        alpn: server_connection
+            .alpn_protocol()
+            .map(|alpn| String::from_utf8_lossy(alpn).into_owned()),
+    }
+}
@@ -1,15 +1,19 @@
 use anyhow::Context;
 
-use hyper_util::rt::TokioIo;
+use tracing::{debug, info, warn};
 
-use tracing::{debug, info};
-
-use tokio::net::UnixListener;
+use tokio::net::{UnixListener, UnixStream};
 
 use std::sync::Arc;
 
 use crate::{
-    config::ServerSocketType, connection::ConnectionTracker, server::handler::ConnectionHandler,
+    config::ServerSocketType,
+    connection::{ConnectionTracker, PeerCredentials, PendingConnection, SocketMetadata},
+    server::{
+        accept::handle_accept_error, handler::ConnectionHandler,
+        proxy_protocol::read_proxy_header_with_timeout, run_accept_loops,
+        socket::bind_unix_listener,
+    },
 };
 
 pub struct UnixServer {
@@ -37,7 +41,7 @@ impl UnixServer {
         let remove_result = tokio::fs::remove_file(path).await;
         debug!("remove_result = {:?}", remove_result);
 
-        let unix_listener = UnixListener::bind(path)
+        let unix_listener = bind_unix_listener(path, &self.listener_configuration.socket_options)
             .with_context(|| format!("UNIX server bind path = {:?}", path))?;
 
         let local_addr = unix_listener
@@ -46,17 +50,117 @@ impl UnixServer {
 
         info!("listening on unix {:?}", local_addr);
 
+        if self.listener_configuration.socket_options.reuse_port {
+            warn!(
+                "reuse_port is not supported for unix sockets, accept tasks will share one socket"
+            );
+        }
+
+        let unix_listener = Arc::new(unix_listener);
+
+        let server = Arc::new(self);
+
+        run_accept_loops(
+            (0..server.listener_configuration.accept_tasks())
+                .map(|_| Arc::clone(&server).accept_loop(Arc::clone(&unix_listener))),
+        )
+        .await
+    }
+
+    async fn accept_loop(self: Arc<Self>, unix_listener: Arc<UnixListener>) -> anyhow::Result<()> {
         loop {
-            let (unix_stream, _remote_addr) = unix_listener.accept().await?;
-
-            if let Some(connection) = self
-                .connection_tracker
-                .add_connection(ServerSocketType::Unix)
-                .await
-            {
-                self.connection_handler
-                    .start_connection_handler(TokioIo::new(unix_stream), connection);
+            let (unix_stream, _remote_addr) = match unix_listener.accept().await {
+                Ok(result) => result,
+                Err(e) => {
+                    handle_accept_error(e, unix_listener.accept()).await?;
+                    continue;
+                }
+            };
+
+            let peer_credentials = match unix_stream.peer_cred() {
+                Ok(ucred) => Some(PeerCredentials {
+                    uid: ucred.uid(),
+                    gid: ucred.gid(),
+                    pid: ucred.pid(),
+                }),
+                Err(e) => {
+                    warn!("error getting unix peer credentials {:?}", e);
+                    None
+                }
+            };
+
+            let socket_metadata = SocketMetadata {
+                local_path: unix_stream
+                    .local_addr()
+                    .ok()
+                    .and_then(|addr| addr.as_pathname().map(|p| p.to_path_buf())),
+                peer_credentials,
+                ..Default::default()
+            };
+
+            if self.listener_configuration.proxy_protocol.is_some() {
+                // PROXY protocol headers are read off the accept loop, holding
+                // a connection_limit slot meanwhile
+                let Some(pending_connection) = self
+                    .connection_tracker
+                    .reserve_connection(ServerSocketType::Unix)
+                    .await
+                else {
+                    continue;
+                };
+
+                tokio::spawn(Arc::clone(&self).start_proxied_connection(
+                    unix_stream,
+                    socket_metadata,
+                    pending_connection,
+                ));
+                continue;
             }
+
+            self.add_connection(unix_stream, socket_metadata).await;
+        }
+    }
+
+    async fn start_proxied_connection(
+        self: Arc<Self>,
+        mut unix_stream: UnixStream,
+        mut socket_metadata: SocketMetadata,
+        pending_connection: PendingConnection,
+    ) {
+        let Some(proxy_protocol) = &self.listener_configuration.proxy_protocol else {
+            return;
+        };
+
+        let Some(proxy_header) = read_proxy_header_with_timeout(
+            &mut unix_stream,
+            proxy_protocol,
+            &socket_metadata.peer_credentials,
+        )
+        .await
+        else {
+            return;
+        };
+
+        socket_metadata.peer_addr = proxy_header.source;
+
+        if let Some(connection) = self
+            .connection_tracker
+            .add_pending_connection(pending_connection, socket_metadata)
+            .await
+        {
+            self.connection_handler
+                .start_connection_handler(unix_stream, connection);
+        }
+    }
+
+    async fn add_connection(&self, unix_stream: UnixStream, socket_metadata: SocketMetadata) {
+        if let Some(connection) = self
+            .connection_tracker
+            .add_connection(ServerSocketType::Unix, socket_metadata)
+            .await
+        {
+            self.connection_handler
+                .start_connection_handler(unix_stream, connection);
         }
     }
 }
@@ -0,0 +1,192 @@
+use anyhow::Context;
+
+use serde::Serialize;
+
+use tokio_util::sync::CancellationToken;
+
+use tracing::{info, warn};
+
+use std::{
+    sync::LazyLock,
+    time::{Duration, SystemTime},
+};
+
+use crate::{
+    connection::ConnectionTracker,
+    handlers::time_utils::{local_date_time_to_string, LocalDateTime},
+};
+
+#[derive(Clone, Copy, Debug)]
+pub enum ShutdownTrigger {
+    Startup,
+    Server,
+    Signal(&'static str),
+    Drain,
+}
+
+// Cancelled when a drain is requested with POST admin/drain.
+static DRAIN_TOKEN: LazyLock<CancellationToken> = LazyLock::new(CancellationToken::new);
+
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+// Connections close at most graceful_shutdown_timeout after a drain starts.
+const DRAIN_TIMEOUT_MARGIN: Duration = Duration::from_secs(1);
+
+// Returns false if a drain was already in progress.
+pub fn start_drain() -> bool {
+    if DRAIN_TOKEN.is_cancelled() {
+        return false;
+    }
+
+    info!("drain requested, closing listeners");
+
+    DRAIN_TOKEN.cancel();
+
+    true
+}
+
+pub async fn wait_for_drain() {
+    DRAIN_TOKEN.cancelled().await
+}
+
+// After listeners are closed, waits for open connections to finish their
+// in-flight requests.
+pub async fn wait_for_drained_connections() {
+    let connection_tracker = ConnectionTracker::instance().await;
+
+    let timeout = crate::config::current()
+        .server_configuration
+        .connection
+        .graceful_shutdown_timeout
+        + DRAIN_TIMEOUT_MARGIN;
+
+    let result = tokio::time::timeout(timeout, async {
+        loop {
+            let state = connection_tracker.state().await;
+
+            if state.open_connections.is_empty() && state.untracked_connections == 0 {
+                break;
+            }
+
+            tokio::time::sleep(DRAIN_POLL_INTERVAL).await;
+        }
+    })
+    .await;
+
+    match result {
+        Ok(()) => info!("drain complete"),
+        Err(_) => warn!("drain timed out after {:?}", timeout),
+    }
+}
+
+#[cfg(unix)]
+pub async fn wait_for_shutdown_signal() -> anyhow::Result<&'static str> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut sigint = signal(SignalKind::interrupt()).context("error installing SIGINT handler")?;
+    let mut sigterm =
+        signal(SignalKind::terminate()).context("error installing SIGTERM handler")?;
+
+    let signal_name = tokio::select! {
+        _ = sigint.recv() => "SIGINT",
+        _ = sigterm.recv() => "SIGTERM",
+    };
+
+    info!("received {}, shutting down", signal_name);
+
+    Ok(signal_name)
+}
+
+// There is no SIGTERM outside unix, only Ctrl-C is handled.
+#[cfg(not(unix))]
+pub async fn wait_for_shutdown_signal() -> anyhow::Result<&'static str> {
+    tokio::signal::ctrl_c()
+        .await
+        .context("error installing Ctrl-C handler")?;
+
+    let signal_name = "CTRL_C";
+
+    info!("received {}, shutting down", signal_name);
+
+    Ok(signal_name)
+}
+
+#[derive(Debug, Serialize)]
+struct ShutdownReport {
+    trigger: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    signal: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    start_time: String,
+    shutdown_time: String,
+    #[serde(with = "humantime_serde")]
+    uptime: Duration,
+    total_connections: usize,
+    total_requests: usize,
+    open_connections: usize,
+    connection_errors: usize,
+    connection_limit_hits: usize,
+}
+
+impl ShutdownReport {
+    async fn new(trigger: ShutdownTrigger, error: Option<&anyhow::Error>) -> Self {
+        let (trigger, signal) = match trigger {
+            ShutdownTrigger::Startup => ("STARTUP", None),
+            ShutdownTrigger::Server => ("SERVER", None),
+            ShutdownTrigger::Signal(signal) => ("SIGNAL", Some(signal)),
+            ShutdownTrigger::Drain => ("DRAIN", None),
+        };
+
+        let state = ConnectionTracker::instance().await.state().await;
+
+        Self {
+            trigger,
+            signal,
+            error: error.map(|error| format!("{:#}", error)),
+            start_time: local_date_time_to_string(
+                &LocalDateTime::from(crate::uptime::start_time()),
+            ),
+            shutdown_time: local_date_time_to_string(&LocalDateTime::from(SystemTime::now())),
+            // truncate to milliseconds
+            uptime: Duration::from_millis(crate::uptime::uptime().as_millis() as u64),
+            total_connections: state.total_connections,
+            total_requests: state.total_requests,
+            open_connections: state.open_connections.len(),
+            connection_errors: state.connection_errors,
+            connection_limit_hits: state.connection_limit_hits,
+        }
+    }
+}
+
+pub async fn log_shutdown_report(trigger: ShutdownTrigger, error: Option<&anyhow::Error>) {
+    let report = ShutdownReport::new(trigger, error).await;
+
+    info!(
+        trigger = report.trigger,
+        signal = report.signal,
+        uptime = ?report.uptime,
+        total_connections = report.total_connections,
+        total_requests = report.total_requests,
+        open_connections = report.open_connections,
+        connection_errors = report.connection_errors,
+        connection_limit_hits = report.connection_limit_hits,
+        "shutdown report",
+    );
+
+    let Some(report_path) = &crate::config::instance().shutdown_configuration.report_path else {
+        return;
+    };
+
+    let result = match serde_json::to_vec_pretty(&report) {
+        Err(e) => Err(anyhow::Error::from(e)),
+        Ok(json) => tokio::fs::write(report_path, json)
+            .await
+            .map_err(anyhow::Error::from),
+    };
+
+    match result {
+        Err(e) => warn!("error writing shutdown report to {:?}: {}", report_path, e),
+        Ok(()) => info!("wrote shutdown report to {:?}", report_path),
+    }
+}
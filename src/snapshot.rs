@@ -0,0 +1,229 @@
+use anyhow::Context;
+
+use chrono::prelude::{DateTime, Utc};
+
+use serde::Serialize;
+
+use tracing::{debug, info, warn};
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use crate::{
+    config::SnapshotConfiguration,
+    connection::{ConnectionProtocol, ConnectionTracker},
+    handlers::time_utils::{local_date_time_to_string, LocalDateTime},
+    request_metrics::RequestMetrics,
+    traffic_stats::TrafficStats,
+};
+
+const SNAPSHOT_PREFIX: &str = "rhs-snapshot-";
+const SNAPSHOT_SUFFIX: &str = ".json";
+
+#[derive(Debug, Serialize)]
+struct ConnectionSnapshot {
+    open_connections: usize,
+    max_open_connections: usize,
+    total_connections: usize,
+    total_requests: usize,
+    connection_errors: usize,
+    connection_limit_hits: usize,
+    client_aborted_requests: usize,
+    connections_by_protocol: BTreeMap<ConnectionProtocol, usize>,
+}
+
+#[derive(Debug, Serialize)]
+struct RouteSnapshot {
+    route: String,
+    status_counts: BTreeMap<u16, u64>,
+    #[serde(with = "humantime_serde")]
+    total_latency: Duration,
+    response_bytes: u64,
+}
+
+#[derive(Debug, Serialize)]
+struct Snapshot {
+    time: String,
+    #[serde(with = "humantime_serde")]
+    uptime: Duration,
+    connections: ConnectionSnapshot,
+    routes: Vec<RouteSnapshot>,
+}
+
+async fn take_snapshot() -> Snapshot {
+    let state = ConnectionTracker::instance().await.state().await;
+
+    let route_total_bytes: BTreeMap<_, _> = TrafficStats::instance()
+        .await
+        .route_total_bytes()
+        .into_iter()
+        .collect();
+
+    let routes = RequestMetrics::instance()
+        .await
+        .snapshot()
+        .into_iter()
+        .map(|(route, entry)| RouteSnapshot {
+            response_bytes: route_total_bytes.get(&route).copied().unwrap_or_default(),
+            route: route.to_string(),
+            status_counts: entry.status_counts,
+            total_latency: entry.latency.sum,
+        })
+        .collect();
+
+    Snapshot {
+        time: local_date_time_to_string(&LocalDateTime::from(SystemTime::now())),
+        // truncate to milliseconds
+        uptime: Duration::from_millis(crate::uptime::uptime().as_millis() as u64),
+        connections: ConnectionSnapshot {
+            open_connections: state.open_connections.len() + state.untracked_connections,
+            max_open_connections: state.max_open_connections,
+            total_connections: state.total_connections,
+            total_requests: state.total_requests,
+            connection_errors: state.connection_errors,
+            connection_limit_hits: state.connection_limit_hits,
+            client_aborted_requests: state.client_aborted_requests,
+            connections_by_protocol: state.connections_by_protocol,
+        },
+        routes,
+    }
+}
+
+// UTC so file names sort in time order.
+fn snapshot_file_name(time: SystemTime) -> String {
+    format!(
+        "{}{}{}",
+        SNAPSHOT_PREFIX,
+        DateTime::<Utc>::from(time).format("%Y%m%dT%H%M%S%.3fZ"),
+        SNAPSHOT_SUFFIX
+    )
+}
+
+// Snapshot file names to delete so at most max_files remain, oldest first.
+fn expired_snapshots(mut file_names: Vec<String>, max_files: usize) -> Vec<String> {
+    file_names.retain(|file_name| {
+        file_name.starts_with(SNAPSHOT_PREFIX) && file_name.ends_with(SNAPSHOT_SUFFIX)
+    });
+
+    file_names.sort_unstable();
+
+    let expired = file_names.len().saturating_sub(max_files);
+
+    file_names.truncate(expired);
+    file_names
+}
+
+async fn write_snapshot(directory: &Path) -> anyhow::Result<PathBuf> {
+    let snapshot = take_snapshot().await;
+
+    let json = serde_json::to_vec_pretty(&snapshot).context("serde_json::to_vec_pretty error")?;
+
+    let path = directory.join(snapshot_file_name(SystemTime::now()));
+
+    // readers never see a partial snapshot
+    let temp_path = path.with_extension("tmp");
+
+    tokio::fs::write(&temp_path, json)
+        .await
+        .with_context(|| format!("error writing {:?}", temp_path))?;
+
+    tokio::fs::rename(&temp_path, &path)
+        .await
+        .with_context(|| format!("error renaming {:?}", temp_path))?;
+
+    Ok(path)
+}
+
+async fn remove_expired_snapshots(directory: &Path, max_files: usize) -> anyhow::Result<()> {
+    let mut read_dir = tokio::fs::read_dir(directory)
+        .await
+        .with_context(|| format!("error reading directory {:?}", directory))?;
+
+    let mut file_names = Vec::new();
+
+    while let Some(entry) = read_dir.next_entry().await? {
+        if let Ok(file_name) = entry.file_name().into_string() {
+            file_names.push(file_name);
+        }
+    }
+
+    for file_name in expired_snapshots(file_names, max_files) {
+        let path = directory.join(file_name);
+
+        tokio::fs::remove_file(&path)
+            .await
+            .with_context(|| format!("error removing {:?}", path))?;
+    }
+
+    Ok(())
+}
+
+pub fn spawn_snapshot_writer() {
+    let snapshot_configuration: &'static SnapshotConfiguration =
+        &crate::config::instance().snapshot_configuration;
+
+    let Some(directory) = &snapshot_configuration.directory else {
+        return;
+    };
+
+    let directory = Path::new(directory);
+
+    info!(
+        "writing snapshots to {:?} every {:?}",
+        directory, snapshot_configuration.interval
+    );
+
+    let mut interval = tokio::time::interval(snapshot_configuration.interval);
+
+    tokio::spawn(async move {
+        // the first tick completes immediately
+        interval.tick().await;
+
+        loop {
+            interval.tick().await;
+
+            match write_snapshot(directory).await {
+                Err(e) => warn!("snapshot error: {:#}", e),
+                Ok(path) => debug!("wrote snapshot {:?}", path),
+            }
+
+            if let Err(e) =
+                remove_expired_snapshots(directory, snapshot_configuration.max_files).await
+            {
+                warn!("snapshot retention error: {:#}", e);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_expired_snapshots() {
+        let time = SystemTime::UNIX_EPOCH + Duration::from_millis(1_700_000_000_123);
+
+        assert_eq!(
+            snapshot_file_name(time),
+            "rhs-snapshot-20231114T221320.123Z.json"
+        );
+
+        let file_names = vec![
+            snapshot_file_name(time + Duration::from_secs(60)),
+            snapshot_file_name(time),
+            "rhs-snapshot-20231114T221320.123Z.tmp".to_owned(),
+            "other.json".to_owned(),
+            snapshot_file_name(time + Duration::from_secs(120)),
+        ];
+
+        assert_eq!(
+            expired_snapshots(file_names.clone(), 2),
+            vec![snapshot_file_name(time)]
+        );
+        assert!(expired_snapshots(file_names, 3).is_empty());
+    }
+}
@@ -1,20 +1,46 @@
+mod filter;
+mod listing;
+mod opener;
+mod preload;
+mod range;
+
 use anyhow::Context;
 
-use tokio::{sync::OnceCell, time::Duration};
+use tokio::time::Duration;
+
+use tracing::{debug, warn};
+
+use hyper::http::{HeaderValue, Request};
 
-use tracing::debug;
+use std::{
+    cmp::Reverse,
+    fmt::Debug,
+    sync::{Arc, RwLock},
+    time::SystemTime,
+};
 
-use std::{fmt::Debug, time::SystemTime};
+use crate::config::{
+    CacheRuleMatching, CacheVisibility, StaticFileCacheRule, StaticFileCacheRuleType,
+    StaticFileConfiguration,
+};
 
-use crate::config::StaticFileCacheRuleType;
+pub use filter::{FilterAction, FilterRule, FilterRuleMatch};
+pub use listing::{listed_directory, read_directory, render_html};
+pub use opener::{ChunkedFile, ChunkedFileOpener, DEFAULT_READ_CHUNK_SIZE};
+pub use preload::PreloadRule;
+pub use range::{build_file_response, RangeOptions};
+
+pub type ResolvedFile = hyper_staticfile::ResolvedFile<ChunkedFile>;
 
 trait CacheRule: Send + Sync + Debug {
     fn matches(&self, resolved_path: &str) -> bool;
 
-    fn build_cache_header(
-        &self,
-        resolved_file: &hyper_staticfile::ResolvedFile,
-    ) -> Option<Duration>;
+    fn build_cache_header(&self, resolved_file: &ResolvedFile) -> Option<Duration>;
+
+    // Last-Modified to send with the response, including 304s.
+    fn last_modified(&self, _: &ResolvedFile) -> Option<SystemTime> {
+        None
+    }
 }
 
 #[derive(Debug)]
@@ -37,11 +63,27 @@ impl CacheRule for FixedTimeCacheHeaderRule {
         self.path_regex.is_match(resolved_path)
     }
 
-    fn build_cache_header(&self, _: &hyper_staticfile::ResolvedFile) -> Option<Duration> {
+    fn build_cache_header(&self, _: &ResolvedFile) -> Option<Duration> {
         Some(self.file_cache_duration)
     }
 }
 
+// NO_STORE and CUSTOM rules, the Cache-Control value does not depend on the file.
+#[derive(Debug)]
+struct FixedHeaderRule {
+    path_regex: regex::Regex,
+}
+
+impl CacheRule for FixedHeaderRule {
+    fn matches(&self, resolved_path: &str) -> bool {
+        self.path_regex.is_match(resolved_path)
+    }
+
+    fn build_cache_header(&self, _: &ResolvedFile) -> Option<Duration> {
+        None
+    }
+}
+
 #[derive(Debug)]
 struct ModificationTimePlusDeltaCacheHeaderRule {
     path_regex: regex::Regex,
@@ -62,10 +104,7 @@ impl CacheRule for ModificationTimePlusDeltaCacheHeaderRule {
         self.path_regex.is_match(resolved_path)
     }
 
-    fn build_cache_header(
-        &self,
-        resolved_file: &hyper_staticfile::ResolvedFile,
-    ) -> Option<Duration> {
+    fn build_cache_header(&self, resolved_file: &ResolvedFile) -> Option<Duration> {
         match resolved_file.modified {
             None => Some(Duration::from_secs(0)),
             Some(modified) => {
@@ -85,71 +124,367 @@ impl CacheRule for ModificationTimePlusDeltaCacheHeaderRule {
             }
         }
     }
+
+    // Files regenerated in place, clients revalidate with If-Modified-Since
+    // once max-age runs out and get a 304 until the next regeneration.
+    fn last_modified(&self, resolved_file: &ResolvedFile) -> Option<SystemTime> {
+        resolved_file.modified
+    }
+}
+
+// Cache-Control for a rule, max_age is the rule's computed max-age or None to
+// use its configured duration.
+pub fn build_cache_control(
+    cache_rule: &StaticFileCacheRule,
+    max_age: Option<Duration>,
+) -> anyhow::Result<HeaderValue> {
+    let value = match cache_rule.rule_type {
+        StaticFileCacheRuleType::NoStore => "no-store".to_owned(),
+        StaticFileCacheRuleType::Custom => cache_rule
+            .cache_control
+            .clone()
+            .context("CUSTOM cache rule requires cache_control")?,
+        StaticFileCacheRuleType::FixedTime | StaticFileCacheRuleType::ModTimePlusDelta => {
+            let visibility = match cache_rule.visibility {
+                CacheVisibility::Public => "public",
+                CacheVisibility::Private => "private",
+            };
+
+            let mut value = format!(
+                "{}, max-age={}",
+                visibility,
+                max_age.unwrap_or(cache_rule.duration).as_secs()
+            );
+
+            if let Some(stale_while_revalidate) = cache_rule.stale_while_revalidate {
+                value.push_str(&format!(
+                    ", stale-while-revalidate={}",
+                    stale_while_revalidate.as_secs()
+                ));
+            }
+
+            if cache_rule.immutable {
+                value.push_str(", immutable");
+            }
+
+            value
+        }
+    };
+
+    HeaderValue::from_str(&value).context("invalid cache_control header value")
+}
+
+fn cache_rule_name(index: usize, cache_rule: &StaticFileCacheRule) -> String {
+    cache_rule.name.clone().unwrap_or_else(|| index.to_string())
+}
+
+// Cache rules with their configured index, in the order they are tried.
+fn ordered_cache_rules(
+    cache_rules: &[StaticFileCacheRule],
+    cache_rule_matching: CacheRuleMatching,
+) -> Vec<(usize, &StaticFileCacheRule)> {
+    let mut cache_rules: Vec<_> = cache_rules.iter().enumerate().collect();
+
+    // stable sorts, ties keep configured order
+    match cache_rule_matching {
+        CacheRuleMatching::FirstMatch => {}
+        CacheRuleMatching::LongestPattern => {
+            cache_rules.sort_by_key(|(_, cache_rule)| Reverse(cache_rule.path_regex.len()))
+        }
+        CacheRuleMatching::Priority => {
+            cache_rules.sort_by_key(|(_, cache_rule)| Reverse(cache_rule.priority))
+        }
+    }
+
+    cache_rules
+}
+
+// Paths a catch-all rule like '.*' matches.
+const CATCH_ALL_PROBES: &[&str] = &["", "a", "index.html", "a/b/c.png", ".hidden"];
+
+fn is_catch_all(cache_rule: &StaticFileCacheRule) -> bool {
+    regex::Regex::new(&cache_rule.path_regex).is_ok_and(|path_regex| {
+        CATCH_ALL_PROBES
+            .iter()
+            .all(|probe| path_regex.is_match(probe))
+    })
+}
+
+// Rules that can never match, as (shadowed rule name, shadowing rule name):
+// an earlier rule has the same path_regex or matches every path.
+pub fn shadowed_cache_rules(
+    cache_rules: &[StaticFileCacheRule],
+    cache_rule_matching: CacheRuleMatching,
+) -> Vec<(String, String)> {
+    let cache_rules = ordered_cache_rules(cache_rules, cache_rule_matching);
+
+    cache_rules
+        .iter()
+        .enumerate()
+        .filter_map(|(position, (index, cache_rule))| {
+            cache_rules[..position]
+                .iter()
+                .find(|(_, earlier)| {
+                    earlier.path_regex == cache_rule.path_regex || is_catch_all(earlier)
+                })
+                .map(|(earlier_index, earlier)| {
+                    (
+                        cache_rule_name(*index, cache_rule),
+                        cache_rule_name(*earlier_index, earlier),
+                    )
+                })
+        })
+        .collect()
+}
+
+#[derive(Debug)]
+struct NamedCacheRule {
+    name: Arc<str>,
+    configuration: StaticFileCacheRule,
+    rule: Box<dyn CacheRule>,
+}
+
+pub struct CacheRuleMatch {
+    pub rule_name: Arc<str>,
+    pub max_age: Option<Duration>,
+    pub cache_control: Option<HeaderValue>,
+    pub last_modified: Option<SystemTime>,
 }
 
 #[derive(Debug)]
 pub struct StaticFileRulesService {
-    cache_rules: Vec<Box<dyn CacheRule>>,
+    filter_rules: Vec<FilterRule>,
+    cache_rules: Vec<NamedCacheRule>,
+    preload_rules: Vec<PreloadRule>,
 }
 
 impl StaticFileRulesService {
-    fn new() -> anyhow::Result<Self> {
-        let static_file_configuration = &crate::config::instance().static_file_configuration;
+    fn new(static_file_configuration: &StaticFileConfiguration) -> anyhow::Result<Self> {
+        let filter_rules = static_file_configuration
+            .filter_rules
+            .iter()
+            .enumerate()
+            .map(|(index, filter_rule)| {
+                FilterRule::new(index, filter_rule)
+                    .context("StaticFileRulesService::new: invalid filter rule")
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        debug!("filter_rules = {:?}", filter_rules);
 
-        let mut cache_rules: Vec<Box<dyn CacheRule>> =
-            Vec::with_capacity(static_file_configuration.cache_rules.len());
+        let mut cache_rules = Vec::with_capacity(static_file_configuration.cache_rules.len());
 
-        for cache_rule in &static_file_configuration.cache_rules {
+        for (index, cache_rule) in ordered_cache_rules(
+            &static_file_configuration.cache_rules,
+            static_file_configuration.cache_rule_matching,
+        ) {
             let path_regex = regex::Regex::new(&cache_rule.path_regex)
                 .context("StaticFileRulesService::new: error parsing regex")?;
 
-            match cache_rule.rule_type {
-                StaticFileCacheRuleType::FixedTime => {
-                    cache_rules.push(Box::new(FixedTimeCacheHeaderRule::new(
-                        path_regex,
-                        cache_rule.duration,
-                    )));
+            build_cache_control(cache_rule, None)
+                .context("StaticFileRulesService::new: invalid cache rule")?;
+
+            let rule: Box<dyn CacheRule> = match cache_rule.rule_type {
+                StaticFileCacheRuleType::FixedTime => Box::new(FixedTimeCacheHeaderRule::new(
+                    path_regex,
+                    cache_rule.duration,
+                )),
+                StaticFileCacheRuleType::ModTimePlusDelta => Box::new(
+                    ModificationTimePlusDeltaCacheHeaderRule::new(path_regex, cache_rule.duration),
+                ),
+                StaticFileCacheRuleType::NoStore | StaticFileCacheRuleType::Custom => {
+                    Box::new(FixedHeaderRule { path_regex })
                 }
-                StaticFileCacheRuleType::ModTimePlusDelta => {
-                    cache_rules.push(Box::new(ModificationTimePlusDeltaCacheHeaderRule::new(
-                        path_regex,
-                        cache_rule.duration,
-                    )));
-                }
-            }
+            };
+
+            cache_rules.push(NamedCacheRule {
+                name: Arc::from(cache_rule_name(index, cache_rule)),
+                configuration: cache_rule.clone(),
+                rule,
+            });
         }
 
         debug!("cache_rules = {:?}", cache_rules,);
 
-        Ok(Self { cache_rules })
+        for (shadowed, shadowing) in shadowed_cache_rules(
+            &static_file_configuration.cache_rules,
+            static_file_configuration.cache_rule_matching,
+        ) {
+            warn!(
+                "cache rule {:?} is shadowed by earlier rule {:?} and never matches",
+                shadowed, shadowing
+            );
+        }
+
+        let preload_rules = static_file_configuration
+            .preload_rules
+            .iter()
+            .map(|preload_rule| {
+                PreloadRule::new(preload_rule)
+                    .context("StaticFileRulesService::new: invalid preload rule")
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        debug!("preload_rules = {:?}", preload_rules);
+
+        Ok(Self {
+            filter_rules,
+            cache_rules,
+            preload_rules,
+        })
+    }
+
+    pub fn find_filter_rule<B>(&self, request: &Request<B>) -> Option<FilterRuleMatch<'_>> {
+        self.filter_rules.iter().find_map(|filter_rule| {
+            filter_rule.apply(request).map(|action| FilterRuleMatch {
+                rule_name: &filter_rule.name,
+                action,
+            })
+        })
     }
 
-    pub fn build_cache_header(
-        &self,
-        resolved_file: &hyper_staticfile::ResolvedFile,
-    ) -> Option<Duration> {
+    pub fn find_cache_rule(&self, resolved_file: &ResolvedFile) -> Option<CacheRuleMatch> {
         let str_path = resolved_file.path.to_str().unwrap_or_default();
 
         self.cache_rules
             .iter()
-            .find(|rule| rule.matches(str_path))
-            .map(|rule| rule.build_cache_header(resolved_file))
-            .unwrap_or(None)
+            .find(|named_rule| named_rule.rule.matches(str_path))
+            .map(|named_rule| {
+                let max_age = named_rule.rule.build_cache_header(resolved_file);
+
+                CacheRuleMatch {
+                    rule_name: Arc::clone(&named_rule.name),
+                    max_age,
+                    cache_control: build_cache_control(&named_rule.configuration, max_age).ok(),
+                    last_modified: named_rule.rule.last_modified(resolved_file),
+                }
+            })
+    }
+
+    // Link header values of every preload rule matching resolved_file.
+    pub fn find_preload_links<'a>(
+        &'a self,
+        resolved_file: &'a ResolvedFile,
+    ) -> impl Iterator<Item = &'a HeaderValue> {
+        let str_path = resolved_file.path.to_str().unwrap_or_default();
+
+        self.preload_rules
+            .iter()
+            .filter(move |preload_rule| preload_rule.matches(str_path))
+            .map(PreloadRule::link)
     }
 }
 
-static RULES_SERVICE_INSTANCE: OnceCell<StaticFileRulesService> = OnceCell::const_new();
+// Replaced on configuration reload, replaced services are dropped once no
+// request holds them.
+static RULES_SERVICE_INSTANCE: RwLock<Option<Arc<StaticFileRulesService>>> = RwLock::new(None);
 
 pub fn create_rules_service_instance() -> anyhow::Result<()> {
-    let static_file_rules_service = StaticFileRulesService::new()?;
+    reload_rules_service_instance(&crate::config::instance().static_file_configuration)
+}
 
-    RULES_SERVICE_INSTANCE
-        .set(static_file_rules_service)
-        .context("RULES_SERVICE_INSTANCE.set error")?;
+pub fn reload_rules_service_instance(
+    static_file_configuration: &StaticFileConfiguration,
+) -> anyhow::Result<()> {
+    let static_file_rules_service = StaticFileRulesService::new(static_file_configuration)?;
+
+    *RULES_SERVICE_INSTANCE.write().unwrap() = Some(Arc::new(static_file_rules_service));
 
     Ok(())
 }
 
-pub fn rules_service_instance() -> &'static StaticFileRulesService {
-    RULES_SERVICE_INSTANCE.get().unwrap()
+// Rules of a virtual host site, which are not reloaded.
+pub fn create_rules_service(
+    static_file_configuration: &StaticFileConfiguration,
+) -> anyhow::Result<Arc<StaticFileRulesService>> {
+    Ok(Arc::new(StaticFileRulesService::new(
+        static_file_configuration,
+    )?))
+}
+
+pub fn rules_service_instance() -> Arc<StaticFileRulesService> {
+    Arc::clone(RULES_SERVICE_INSTANCE.read().unwrap().as_ref().unwrap())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn cache_rule(rule_type: StaticFileCacheRuleType) -> StaticFileCacheRule {
+        StaticFileCacheRule {
+            name: None,
+            path_regex: ".*".to_owned(),
+            rule_type,
+            duration: Duration::from_secs(60),
+            visibility: CacheVisibility::Public,
+            immutable: false,
+            stale_while_revalidate: None,
+            cache_control: None,
+            priority: 0,
+        }
+    }
+
+    #[test]
+    fn test_shadowed_cache_rules() {
+        let mut html = cache_rule(StaticFileCacheRuleType::NoStore);
+        html.name = Some("html".to_owned());
+        html.path_regex = r"\.html$".to_owned();
+
+        let mut default = cache_rule(StaticFileCacheRuleType::FixedTime);
+        default.name = Some("default".to_owned());
+
+        let cache_rules = [default, html];
+
+        assert_eq!(
+            shadowed_cache_rules(&cache_rules, CacheRuleMatching::FirstMatch),
+            [("html".to_owned(), "default".to_owned())]
+        );
+        assert!(shadowed_cache_rules(&cache_rules, CacheRuleMatching::LongestPattern).is_empty());
+        assert_eq!(
+            shadowed_cache_rules(&cache_rules, CacheRuleMatching::Priority).len(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_build_cache_control() {
+        let mut rule = cache_rule(StaticFileCacheRuleType::FixedTime);
+        assert_eq!(
+            build_cache_control(&rule, None).unwrap(),
+            "public, max-age=60"
+        );
+
+        rule.visibility = CacheVisibility::Private;
+        rule.immutable = true;
+        rule.stale_while_revalidate = Some(Duration::from_secs(30));
+        assert_eq!(
+            build_cache_control(&rule, Some(Duration::from_secs(10))).unwrap(),
+            "private, max-age=10, stale-while-revalidate=30, immutable"
+        );
+
+        let rule = cache_rule(StaticFileCacheRuleType::NoStore);
+        assert_eq!(build_cache_control(&rule, None).unwrap(), "no-store");
+
+        let mut rule = cache_rule(StaticFileCacheRuleType::Custom);
+        assert!(build_cache_control(&rule, None).is_err());
+
+        rule.cache_control = Some("no-cache, must-revalidate".to_owned());
+        assert_eq!(
+            build_cache_control(&rule, None).unwrap(),
+            "no-cache, must-revalidate"
+        );
+    }
+
+    #[test]
+    fn test_reload_drops_replaced_rules_service() {
+        crate::config::set_test_instance();
+
+        let static_file_configuration = &crate::config::instance().static_file_configuration;
+
+        reload_rules_service_instance(static_file_configuration).unwrap();
+        let replaced = Arc::downgrade(&rules_service_instance());
+
+        reload_rules_service_instance(static_file_configuration).unwrap();
+        assert!(replaced.upgrade().is_none());
+    }
 }
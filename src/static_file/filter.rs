@@ -0,0 +1,242 @@
+use anyhow::Context;
+
+use hyper::http::{HeaderName, Method, Request, StatusCode};
+
+use regex::Regex;
+
+use crate::config::{StaticFileFilterActionType, StaticFileFilterRule};
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum FilterAction {
+    Deny(StatusCode),
+    Redirect {
+        status_code: StatusCode,
+        location: String,
+    },
+    // path and query to resolve instead of the request's
+    Rewrite(String),
+}
+
+pub struct FilterRuleMatch<'a> {
+    pub rule_name: &'a str,
+    pub action: FilterAction,
+}
+
+#[derive(Debug)]
+struct HeaderMatch {
+    name: HeaderName,
+    value_regex: Regex,
+}
+
+#[derive(Debug)]
+pub struct FilterRule {
+    pub name: String,
+    path_regex: Regex,
+    methods: Vec<Method>,
+    headers: Vec<HeaderMatch>,
+    action_type: StaticFileFilterActionType,
+    status_code: StatusCode,
+    target: String,
+}
+
+impl FilterRule {
+    pub fn new(index: usize, filter_rule: &StaticFileFilterRule) -> anyhow::Result<Self> {
+        let path_regex = Regex::new(&filter_rule.path_regex)
+            .with_context(|| format!("invalid path_regex '{}'", filter_rule.path_regex))?;
+
+        let methods = filter_rule
+            .methods
+            .iter()
+            .map(|method| {
+                Method::from_bytes(method.as_bytes())
+                    .with_context(|| format!("invalid method '{}'", method))
+            })
+            .collect::<anyhow::Result<_>>()?;
+
+        let headers = filter_rule
+            .headers
+            .iter()
+            .map(|header_match| {
+                Ok(HeaderMatch {
+                    name: HeaderName::from_bytes(header_match.name.as_bytes())
+                        .with_context(|| format!("invalid header name '{}'", header_match.name))?,
+                    value_regex: Regex::new(&header_match.value_regex).with_context(|| {
+                        format!("invalid value_regex '{}'", header_match.value_regex)
+                    })?,
+                })
+            })
+            .collect::<anyhow::Result<_>>()?;
+
+        let (default_status, valid_status): (u16, fn(&StatusCode) -> bool) =
+            match filter_rule.action {
+                StaticFileFilterActionType::Deny => (403, |status_code| {
+                    status_code.is_client_error() || status_code.is_server_error()
+                }),
+                StaticFileFilterActionType::Redirect => {
+                    (302, |status_code| status_code.is_redirection())
+                }
+                StaticFileFilterActionType::Rewrite => (200, |_| true),
+            };
+
+        let status_code = StatusCode::from_u16(filter_rule.status.unwrap_or(default_status))
+            .context("invalid status")?;
+
+        if !valid_status(&status_code) {
+            anyhow::bail!(
+                "status {} not valid for {:?}",
+                status_code,
+                filter_rule.action
+            );
+        }
+
+        let target = match filter_rule.action {
+            StaticFileFilterActionType::Deny => String::new(),
+            StaticFileFilterActionType::Redirect | StaticFileFilterActionType::Rewrite => {
+                let target = filter_rule
+                    .target
+                    .clone()
+                    .with_context(|| format!("target is required for {:?}", filter_rule.action))?;
+
+                if matches!(filter_rule.action, StaticFileFilterActionType::Rewrite)
+                    && !target.starts_with('/')
+                {
+                    anyhow::bail!("REWRITE target '{}' must start with '/'", target);
+                }
+
+                target
+            }
+        };
+
+        Ok(Self {
+            name: filter_rule
+                .name
+                .clone()
+                .unwrap_or_else(|| index.to_string()),
+            path_regex,
+            methods,
+            headers,
+            action_type: filter_rule.action,
+            status_code,
+            target,
+        })
+    }
+
+    // Returns the action to take if the rule matches request.
+    pub fn apply<B>(&self, request: &Request<B>) -> Option<FilterAction> {
+        if !self.methods.is_empty() && !self.methods.contains(request.method()) {
+            return None;
+        }
+
+        let headers_match = self.headers.iter().all(|header_match| {
+            request
+                .headers()
+                .get(&header_match.name)
+                .and_then(|value| value.to_str().ok())
+                .is_some_and(|value| header_match.value_regex.is_match(value))
+        });
+
+        if !headers_match {
+            return None;
+        }
+
+        let captures = self.path_regex.captures(request.uri().path())?;
+
+        let mut target = String::new();
+        captures.expand(&self.target, &mut target);
+
+        // keep the query unless the target sets its own
+        if let Some(query) = request.uri().query() {
+            if !target.contains('?') {
+                target.push('?');
+                target.push_str(query);
+            }
+        }
+
+        Some(match self.action_type {
+            StaticFileFilterActionType::Deny => FilterAction::Deny(self.status_code),
+            StaticFileFilterActionType::Redirect => FilterAction::Redirect {
+                status_code: self.status_code,
+                location: target,
+            },
+            StaticFileFilterActionType::Rewrite => FilterAction::Rewrite(target),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn filter_rule(
+        path_regex: &str,
+        action: StaticFileFilterActionType,
+        target: Option<&str>,
+    ) -> StaticFileFilterRule {
+        StaticFileFilterRule {
+            name: None,
+            path_regex: path_regex.to_owned(),
+            methods: vec![],
+            headers: vec![],
+            action,
+            status: None,
+            target: target.map(str::to_owned),
+        }
+    }
+
+    #[test]
+    fn test_filter_rule_apply() {
+        let request = Request::get("/old/page.html?x=1").body(()).unwrap();
+
+        let rule = FilterRule::new(
+            0,
+            &filter_rule(
+                "^/old/(.*)$",
+                StaticFileFilterActionType::Redirect,
+                Some("/new/$1"),
+            ),
+        )
+        .unwrap();
+        assert_eq!(
+            rule.apply(&request),
+            Some(FilterAction::Redirect {
+                status_code: StatusCode::FOUND,
+                location: "/new/page.html?x=1".to_owned(),
+            })
+        );
+
+        let rule = FilterRule::new(
+            0,
+            &filter_rule(
+                "^/old/(.*)$",
+                StaticFileFilterActionType::Rewrite,
+                Some("/archive/$1?y=2"),
+            ),
+        )
+        .unwrap();
+        assert_eq!(
+            rule.apply(&request),
+            Some(FilterAction::Rewrite("/archive/page.html?y=2".to_owned()))
+        );
+
+        let mut configuration = filter_rule(".*", StaticFileFilterActionType::Deny, None);
+        configuration.methods = vec!["POST".to_owned()];
+        let rule = FilterRule::new(0, &configuration).unwrap();
+        assert_eq!(rule.apply(&request), None);
+
+        configuration.methods = vec!["GET".to_owned()];
+        let rule = FilterRule::new(0, &configuration).unwrap();
+        assert_eq!(
+            rule.apply(&request),
+            Some(FilterAction::Deny(StatusCode::FORBIDDEN))
+        );
+
+        configuration.status = Some(302);
+        assert!(FilterRule::new(0, &configuration).is_err());
+
+        assert!(FilterRule::new(
+            0,
+            &filter_rule(".*", StaticFileFilterActionType::Rewrite, None)
+        )
+        .is_err());
+    }
+}
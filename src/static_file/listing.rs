@@ -0,0 +1,239 @@
+use chrono::prelude::{DateTime, SecondsFormat, Utc};
+
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, CONTROLS};
+
+use serde::Serialize;
+
+use std::{
+    fmt::Write,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+// Encoded in listing links, names are relative to the listed directory.
+const NAME_ENCODE_SET: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'&')
+    .add(b'\'')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'\\')
+    .add(b'/');
+
+// The decoded directory for a request path ending with '/', relative to the
+// root, and its normalized request path.  None for other paths and for paths
+// with '..' segments, backslashes or NUL bytes.
+pub fn listed_directory(request_path: &str) -> Option<(PathBuf, String)> {
+    if !request_path.ends_with('/') {
+        return None;
+    }
+
+    let decoded_path = percent_decode_str(request_path).decode_utf8().ok()?;
+
+    let mut directory = PathBuf::new();
+    let mut listed_path = String::from("/");
+
+    for segment in decoded_path
+        .split('/')
+        .filter(|segment| !segment.is_empty() && *segment != ".")
+    {
+        if segment == ".." || segment.contains(['\\', '\0']) {
+            return None;
+        }
+
+        directory.push(segment);
+        listed_path.push_str(segment);
+        listed_path.push('/');
+    }
+
+    Some((directory, listed_path))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub enum DirectoryEntryType {
+    #[serde(rename = "FILE")]
+    File,
+
+    #[serde(rename = "DIRECTORY")]
+    Directory,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DirectoryEntry {
+    pub name: String,
+    pub entry_type: DirectoryEntryType,
+    // None for directories
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    #[serde(serialize_with = "serialize_modified")]
+    pub modified: Option<SystemTime>,
+}
+
+fn format_modified(modified: SystemTime) -> String {
+    DateTime::<Utc>::from(modified).to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+fn serialize_modified<S: serde::Serializer>(
+    modified: &Option<SystemTime>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match modified {
+        None => serializer.serialize_none(),
+        Some(modified) => serializer.serialize_str(&format_modified(*modified)),
+    }
+}
+
+// Entries of directory, directories first then by name.  Dot files are left
+// out like they are blocked when requested, and so are names that are not
+// UTF-8.
+pub async fn read_directory(directory: &Path) -> std::io::Result<Vec<DirectoryEntry>> {
+    let mut read_dir = tokio::fs::read_dir(directory).await?;
+
+    let mut entries = Vec::new();
+
+    while let Some(entry) = read_dir.next_entry().await? {
+        let Ok(name) = entry.file_name().into_string() else {
+            continue;
+        };
+
+        if name.starts_with('.') {
+            continue;
+        }
+
+        // follows symlinks like the resolver does
+        let Ok(metadata) = tokio::fs::metadata(entry.path()).await else {
+            continue;
+        };
+
+        let entry_type = if metadata.is_dir() {
+            DirectoryEntryType::Directory
+        } else {
+            DirectoryEntryType::File
+        };
+
+        entries.push(DirectoryEntry {
+            name,
+            entry_type,
+            size: (entry_type == DirectoryEntryType::File).then_some(metadata.len()),
+            modified: metadata.modified().ok(),
+        });
+    }
+
+    entries.sort_by(|a, b| {
+        (a.entry_type != DirectoryEntryType::Directory, &a.name)
+            .cmp(&(b.entry_type != DirectoryEntryType::Directory, &b.name))
+    });
+
+    Ok(entries)
+}
+
+fn escape_html(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+// request_path is the listed directory's path, ending with '/'.
+pub fn render_html(request_path: &str, entries: &[DirectoryEntry]) -> String {
+    let title = format!("Index of {}", escape_html(request_path));
+
+    let mut html = String::new();
+
+    let _ = write!(
+        html,
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n</head>\n<body>\n<h1>{}</h1>\n<table>\n<tr><th>Name</th><th>Size</th><th>Modified</th></tr>\n",
+        title, title
+    );
+
+    if request_path != "/" {
+        html.push_str("<tr><td><a href=\"../\">../</a></td><td></td><td></td></tr>\n");
+    }
+
+    for entry in entries {
+        let suffix = match entry.entry_type {
+            DirectoryEntryType::Directory => "/",
+            DirectoryEntryType::File => "",
+        };
+
+        let _ = writeln!(
+            html,
+            "<tr><td><a href=\"{}{}\">{}{}</a></td><td>{}</td><td>{}</td></tr>",
+            utf8_percent_encode(&entry.name, NAME_ENCODE_SET),
+            suffix,
+            escape_html(&entry.name),
+            suffix,
+            entry.size.map(|size| size.to_string()).unwrap_or_default(),
+            entry.modified.map(format_modified).unwrap_or_default(),
+        );
+    }
+
+    html.push_str("</table>\n</body>\n</html>\n");
+
+    html
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::time::Duration;
+
+    #[test]
+    fn test_listed_directory() {
+        assert_eq!(
+            listed_directory("/files/sub%20dir//./"),
+            Some((PathBuf::from("files/sub dir"), "/files/sub dir/".to_owned()))
+        );
+        assert_eq!(
+            listed_directory("/"),
+            Some((PathBuf::new(), "/".to_owned()))
+        );
+        assert_eq!(listed_directory("/files/a.txt"), None);
+        assert_eq!(listed_directory("/files/../etc/"), None);
+        assert_eq!(listed_directory("/files/%2e%2e/"), None);
+    }
+
+    #[test]
+    fn test_render_html() {
+        let entries = vec![
+            DirectoryEntry {
+                name: "sub dir".to_owned(),
+                entry_type: DirectoryEntryType::Directory,
+                size: None,
+                modified: None,
+            },
+            DirectoryEntry {
+                name: "<a>&b.txt".to_owned(),
+                entry_type: DirectoryEntryType::File,
+                size: Some(10),
+                modified: Some(SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000)),
+            },
+        ];
+
+        let html = render_html("/files/", &entries);
+
+        assert!(html.contains("<title>Index of /files/</title>"));
+        assert!(html.contains("<a href=\"../\">"));
+        assert!(html.contains("<a href=\"sub%20dir/\">sub dir/</a>"));
+        assert!(html.contains(
+            "<a href=\"%3Ca%3E%26b.txt\">&lt;a&gt;&amp;b.txt</a></td><td>10</td><td>2023-11-14T22:13:20Z</td>"
+        ));
+
+        assert!(!render_html("/", &[]).contains("../"));
+    }
+}
@@ -0,0 +1,170 @@
+use bytes::Bytes;
+
+use hyper_staticfile::vfs::{FileAccess, FileOpener, FileWithMetadata, IntoFileAccess};
+
+use tokio::{
+    fs::File,
+    io::{AsyncRead, AsyncSeek, ReadBuf},
+};
+
+use tracing::debug;
+
+use std::{
+    fs::OpenOptions,
+    future::Future,
+    io::SeekFrom,
+    path::{Path, PathBuf},
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+// hyper_staticfile's TokioFileOpener read size.
+pub const DEFAULT_READ_CHUNK_SIZE: usize = 8 * 1024;
+
+// Like hyper_staticfile's TokioFileOpener with a configurable read size per
+// body chunk and optional sequential readahead advice for the kernel.
+pub struct ChunkedFileOpener {
+    root: PathBuf,
+    read_chunk_size: usize,
+    sequential_readahead: bool,
+}
+
+impl ChunkedFileOpener {
+    pub fn new(
+        root: impl Into<PathBuf>,
+        read_chunk_size: usize,
+        sequential_readahead: bool,
+    ) -> Self {
+        debug!(
+            "ChunkedFileOpener read_chunk_size = {} sequential_readahead = {}",
+            read_chunk_size, sequential_readahead
+        );
+
+        Self {
+            root: root.into(),
+            read_chunk_size,
+            sequential_readahead,
+        }
+    }
+}
+
+fn open_file(
+    full_path: &Path,
+    read_chunk_size: usize,
+    sequential_readahead: bool,
+) -> std::io::Result<FileWithMetadata<ChunkedFile>> {
+    let mut options = OpenOptions::new();
+    options.read(true);
+
+    // FILE_FLAG_BACKUP_SEMANTICS, needed to open directories
+    #[cfg(windows)]
+    std::os::windows::fs::OpenOptionsExt::custom_flags(&mut options, 0x0200_0000);
+
+    let file = options.open(full_path)?;
+    let metadata = file.metadata()?;
+
+    if sequential_readahead && metadata.is_file() {
+        advise_sequential(&file);
+    }
+
+    let mut file = File::from_std(file);
+
+    // tokio caps each blocking read at 2MiB by default
+    file.set_max_buf_size(read_chunk_size);
+
+    Ok(FileWithMetadata {
+        handle: ChunkedFile {
+            file,
+            read_chunk_size,
+        },
+        size: metadata.len(),
+        modified: metadata.modified().ok(),
+        is_dir: metadata.is_dir(),
+    })
+}
+
+// Linux doubles the readahead window for files advised as sequential.
+#[cfg(target_os = "linux")]
+fn advise_sequential(file: &std::fs::File) {
+    use nix::fcntl::{posix_fadvise, PosixFadviseAdvice};
+
+    if let Err(e) = posix_fadvise(file, 0, 0, PosixFadviseAdvice::POSIX_FADV_SEQUENTIAL) {
+        debug!("posix_fadvise error: {}", e);
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn advise_sequential(_: &std::fs::File) {}
+
+impl FileOpener for ChunkedFileOpener {
+    type File = ChunkedFile;
+    type Future =
+        Pin<Box<dyn Future<Output = std::io::Result<FileWithMetadata<ChunkedFile>>> + Send>>;
+
+    fn open(&self, path: &Path) -> Self::Future {
+        let mut full_path = self.root.clone();
+        full_path.extend(path);
+
+        let read_chunk_size = self.read_chunk_size;
+        let sequential_readahead = self.sequential_readahead;
+
+        Box::pin(async move {
+            tokio::task::spawn_blocking(move || {
+                open_file(&full_path, read_chunk_size, sequential_readahead)
+            })
+            .await
+            .map_err(std::io::Error::other)?
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct ChunkedFile {
+    file: File,
+    read_chunk_size: usize,
+}
+
+impl IntoFileAccess for ChunkedFile {
+    type Output = ChunkedFileAccess;
+
+    fn into_file_access(self) -> Self::Output {
+        ChunkedFileAccess {
+            file: self.file,
+            read_buf: vec![0; self.read_chunk_size],
+        }
+    }
+}
+
+pub struct ChunkedFileAccess {
+    file: File,
+    read_buf: Vec<u8>,
+}
+
+impl AsyncSeek for ChunkedFileAccess {
+    fn start_seek(mut self: Pin<&mut Self>, position: SeekFrom) -> std::io::Result<()> {
+        Pin::new(&mut self.file).start_seek(position)
+    }
+
+    fn poll_complete(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<u64>> {
+        Pin::new(&mut self.file).poll_complete(cx)
+    }
+}
+
+impl FileAccess for ChunkedFileAccess {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        len: usize,
+    ) -> Poll<std::io::Result<Bytes>> {
+        let Self { file, read_buf } = &mut *self;
+
+        let len = len.min(read_buf.len());
+        let mut read_buf = ReadBuf::new(&mut read_buf[..len]);
+
+        match Pin::new(file).poll_read(cx, &mut read_buf) {
+            Poll::Ready(Ok(())) => Poll::Ready(Ok(Bytes::copy_from_slice(read_buf.filled()))),
+            Poll::Ready(Err(e)) => Poll::Ready(Err(e)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
@@ -0,0 +1,93 @@
+use anyhow::Context;
+
+use hyper::http::HeaderValue;
+
+use regex::Regex;
+
+use crate::config::{PreloadLink, StaticFilePreloadRule};
+
+fn link_value(link: &PreloadLink) -> String {
+    let mut value = format!("<{}>; rel=preload; as={}", link.href, link.destination);
+
+    if link.crossorigin {
+        value.push_str("; crossorigin");
+    }
+
+    value
+}
+
+#[derive(Debug)]
+pub struct PreloadRule {
+    path_regex: Regex,
+    // all of the rule's links, comma separated
+    link: HeaderValue,
+}
+
+impl PreloadRule {
+    pub fn new(preload_rule: &StaticFilePreloadRule) -> anyhow::Result<Self> {
+        let path_regex = Regex::new(&preload_rule.path_regex)
+            .with_context(|| format!("invalid path_regex '{}'", preload_rule.path_regex))?;
+
+        if preload_rule.links.is_empty() {
+            anyhow::bail!("links must not be empty");
+        }
+
+        let link = preload_rule
+            .links
+            .iter()
+            .map(link_value)
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        let link = HeaderValue::from_str(&link)
+            .with_context(|| format!("invalid Link header value '{}'", link))?;
+
+        Ok(Self { path_regex, link })
+    }
+
+    pub fn matches(&self, resolved_path: &str) -> bool {
+        self.path_regex.is_match(resolved_path)
+    }
+
+    pub fn link(&self) -> &HeaderValue {
+        &self.link
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_preload_rule() {
+        let mut preload_rule = StaticFilePreloadRule {
+            path_regex: r"^index\.html$".to_owned(),
+            links: vec![
+                PreloadLink {
+                    href: "/assets/app.js".to_owned(),
+                    destination: "script".to_owned(),
+                    crossorigin: false,
+                },
+                PreloadLink {
+                    href: "/fonts/a.woff2".to_owned(),
+                    destination: "font".to_owned(),
+                    crossorigin: true,
+                },
+            ],
+        };
+
+        let rule = PreloadRule::new(&preload_rule).unwrap();
+        assert!(rule.matches("index.html"));
+        assert!(!rule.matches("a/index.html"));
+        assert_eq!(
+            rule.link(),
+            "</assets/app.js>; rel=preload; as=script, </fonts/a.woff2>; rel=preload; as=font; crossorigin"
+        );
+
+        preload_rule.links[0].href = "/bad\n".to_owned();
+        assert!(PreloadRule::new(&preload_rule).is_err());
+
+        preload_rule.links.clear();
+        assert!(PreloadRule::new(&preload_rule).is_err());
+    }
+}
@@ -0,0 +1,488 @@
+use chrono::DateTime;
+
+use hyper::http::{header, HeaderMap, Request, Response, StatusCode};
+
+use hyper_staticfile::{vfs::IntoFileAccess, Body, ResolveResult, ResponseBuilder};
+
+use std::time::{Duration, SystemTime};
+
+use crate::{config::MultiRangePolicy, handlers::time_utils::http_date_string};
+
+// A Last-Modified this close to the response time is a weak validator.
+const WEAK_LAST_MODIFIED: Duration = Duration::from_secs(1);
+
+// hyper_staticfile ignores modification times this close to the epoch.
+const MIN_VALID_MTIME: Duration = Duration::from_secs(2);
+
+// The weak ETag hyper_staticfile sends, from the size and modification time.
+fn file_etag(size: u64, modified_unix: Duration) -> String {
+    format!(
+        "W/\"{:x}-{:x}.{:x}\"",
+        size,
+        modified_unix.as_secs(),
+        modified_unix.subsec_nanos()
+    )
+}
+
+// Weak comparison per RFC 9110 13.1.2, If-None-Match may list several etags.
+fn if_none_match_matches(request_headers: &HeaderMap, etag: &str) -> bool {
+    let etag = etag.trim_start_matches("W/");
+
+    request_headers
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .any(|candidate| candidate == "*" || candidate.trim_start_matches("W/") == etag)
+}
+
+// RFC 9110 13.2.2: If-None-Match is evaluated instead of If-Modified-Since
+// when both are sent.  hyper_staticfile only knows If-Modified-Since and
+// answers it without validators, so both are evaluated here.
+fn not_modified_response<O>(
+    request_headers: &HeaderMap,
+    size: u64,
+    modified: Option<SystemTime>,
+    if_modified_since: Option<SystemTime>,
+) -> Option<hyper::http::Result<Response<Body<O>>>> {
+    let modified = modified?;
+
+    let modified_unix = modified
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .ok()
+        .filter(|modified_unix| *modified_unix >= MIN_VALID_MTIME)?;
+
+    let etag = file_etag(size, modified_unix);
+
+    let not_modified = if request_headers.contains_key(header::IF_NONE_MATCH) {
+        if_none_match_matches(request_headers, &etag)
+    } else {
+        // whole seconds, HTTP dates have no fractional part
+        if_modified_since
+            .and_then(|if_modified_since| {
+                if_modified_since
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .ok()
+            })
+            .is_some_and(|if_modified_since_unix| {
+                modified_unix.as_secs() <= if_modified_since_unix.as_secs()
+            })
+    };
+
+    not_modified.then(|| {
+        Response::builder()
+            .status(StatusCode::NOT_MODIFIED)
+            .header(header::ETAG, etag)
+            .header(header::LAST_MODIFIED, http_date_string(modified))
+            .body(Body::Empty)
+    })
+}
+
+// RFC 9110 13.1.5: a Range is only honored when If-Range strongly matches the
+// current representation.  hyper_staticfile compares If-Range with its weak
+// ETag, which must never match, so If-Range is evaluated here instead.
+fn if_range_matches(if_range: &str, last_modified: Option<SystemTime>, now: SystemTime) -> bool {
+    // ETags from hyper_staticfile are weak, no entity tag matches strongly
+    if if_range.starts_with('"') || if_range.starts_with("W/") {
+        return false;
+    }
+
+    let (Some(last_modified), Ok(if_range_date)) =
+        (last_modified, DateTime::parse_from_rfc2822(if_range))
+    else {
+        return false;
+    };
+
+    let Ok(last_modified_unix) = last_modified.duration_since(SystemTime::UNIX_EPOCH) else {
+        return false;
+    };
+
+    let strong = now
+        .duration_since(last_modified)
+        .is_ok_and(|age| age >= WEAK_LAST_MODIFIED);
+
+    strong && u64::try_from(if_range_date.timestamp()) == Ok(last_modified_unix.as_secs())
+}
+
+// Inclusive byte ranges in a Range header for a file of size bytes, leaving
+// out unsatisfiable ranges.  None if the header is not a valid bytes range.
+fn parse_byte_ranges(range: &str, size: u64) -> Option<Vec<(u64, u64)>> {
+    let (unit, range_set) = range.split_once('=')?;
+
+    if !unit.trim().eq_ignore_ascii_case("bytes") {
+        return None;
+    }
+
+    let mut byte_ranges = Vec::new();
+
+    for range_spec in range_set.split(',').map(str::trim) {
+        if range_spec.is_empty() {
+            continue;
+        }
+
+        let (first, last) = range_spec.split_once('-')?;
+        let (first, last) = (first.trim(), last.trim());
+
+        if first.is_empty() {
+            let suffix_length: u64 = last.parse().ok()?;
+
+            if suffix_length > 0 && size > 0 {
+                byte_ranges.push((size.saturating_sub(suffix_length), size - 1));
+            }
+            continue;
+        }
+
+        let first: u64 = first.parse().ok()?;
+        let last: Option<u64> = match last {
+            "" => None,
+            last => Some(last.parse().ok()?),
+        };
+
+        if last.is_some_and(|last| last < first) {
+            return None;
+        }
+
+        if first < size {
+            byte_ranges.push((first, last.map_or(size - 1, |last| last.min(size - 1))));
+        }
+    }
+
+    Some(byte_ranges)
+}
+
+fn coalesce_byte_ranges(mut byte_ranges: Vec<(u64, u64)>) -> Vec<(u64, u64)> {
+    byte_ranges.sort_unstable();
+
+    let mut coalesced: Vec<(u64, u64)> = Vec::with_capacity(byte_ranges.len());
+
+    for (first, last) in byte_ranges {
+        match coalesced.last_mut() {
+            Some((_, previous_last)) if first <= previous_last.saturating_add(1) => {
+                *previous_last = (*previous_last).max(last);
+            }
+            _ => coalesced.push((first, last)),
+        }
+    }
+
+    coalesced
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RangeOptions {
+    pub multi_range_policy: MultiRangePolicy,
+    pub max_ranges: Option<usize>,
+}
+
+impl RangeOptions {
+    // The Range header to serve, or None to send the whole file.
+    fn apply(&self, range: &str, size: u64) -> Option<String> {
+        // hyper_staticfile ignores invalid headers and answers 416 when no
+        // range is satisfiable, leave those to it
+        let byte_ranges = match parse_byte_ranges(range, size) {
+            Some(byte_ranges) if byte_ranges.len() > 1 => byte_ranges,
+            _ => return Some(range.to_owned()),
+        };
+
+        if self
+            .max_ranges
+            .is_some_and(|max_ranges| byte_ranges.len() > max_ranges)
+        {
+            return None;
+        }
+
+        match self.multi_range_policy {
+            MultiRangePolicy::Multipart => Some(range.to_owned()),
+            MultiRangePolicy::Reject => None,
+            MultiRangePolicy::Coalesce => {
+                let byte_ranges: Vec<_> = coalesce_byte_ranges(byte_ranges)
+                    .into_iter()
+                    .map(|(first, last)| format!("{}-{}", first, last))
+                    .collect();
+
+                Some(format!("bytes={}", byte_ranges.join(",")))
+            }
+        }
+    }
+}
+
+// Builds the response for a resolved request like ResponseBuilder, with
+// conditional requests evaluated by not_modified_response and
+// if_range_matches, and range_options applied.
+pub fn build_file_response<B, F: IntoFileAccess>(
+    request: &Request<B>,
+    resolve_result: ResolveResult<F>,
+    range_options: RangeOptions,
+    now: SystemTime,
+) -> hyper::http::Result<Response<Body<F::Output>>> {
+    let mut response_builder = ResponseBuilder::new();
+    response_builder.request(request);
+
+    let file_response_builder = &mut response_builder.file_response_builder;
+
+    let if_modified_since = file_response_builder.if_modified_since.take();
+
+    if let ResolveResult::Found(resolved_file) = &resolve_result {
+        if let Some(response) = not_modified_response(
+            request.headers(),
+            resolved_file.size,
+            resolved_file.modified,
+            if_modified_since,
+        ) {
+            return response;
+        }
+    }
+
+    if let Some(if_range) = file_response_builder.if_range.take() {
+        let last_modified = match &resolve_result {
+            ResolveResult::Found(resolved_file) => resolved_file.modified,
+            _ => None,
+        };
+
+        if !if_range_matches(&if_range, last_modified, now) {
+            file_response_builder.range = None;
+        }
+    }
+
+    if let (Some(range), ResolveResult::Found(resolved_file)) =
+        (&file_response_builder.range, &resolve_result)
+    {
+        file_response_builder.range = range_options.apply(range, resolved_file.size);
+    }
+
+    response_builder.build(resolve_result)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use hyper::http::{header, StatusCode};
+
+    use hyper_staticfile::{vfs::MemoryFs, Resolver};
+
+    use crate::handlers::time_utils::http_date_string;
+
+    const MODIFIED_UNIX_SECONDS: u64 = 1_700_000_000;
+
+    fn modified() -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(MODIFIED_UNIX_SECONDS)
+    }
+
+    async fn conditional_range_response(
+        headers: &[(header::HeaderName, &str)],
+        now: SystemTime,
+    ) -> Response<Body<std::io::Cursor<bytes::Bytes>>> {
+        let mut memory_fs = MemoryFs::default();
+        memory_fs.add("file.txt", "0123456789".into(), Some(modified()));
+
+        let resolver = Resolver::with_opener(memory_fs);
+
+        let mut request = Request::get("/file.txt");
+        for (name, value) in headers {
+            request = request.header(name, *value);
+        }
+        let request = request.body(()).unwrap();
+
+        let resolve_result = resolver.resolve_request(&request).await.unwrap();
+
+        build_file_response(&request, resolve_result, RangeOptions::default(), now).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_conditional_range_requests() {
+        let now = modified() + Duration::from_secs(60);
+        let last_modified = http_date_string(modified());
+
+        let response = conditional_range_response(&[(header::RANGE, "bytes=0-3")], now).await;
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+
+        let etag = conditional_range_response(&[], now)
+            .await
+            .headers()
+            .get(header::ETAG)
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_owned();
+        assert!(etag.starts_with("W/"));
+
+        // weak ETags never satisfy If-Range
+        let response = conditional_range_response(
+            &[(header::RANGE, "bytes=0-3"), (header::IF_RANGE, &etag)],
+            now,
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = conditional_range_response(
+            &[
+                (header::RANGE, "bytes=0-3"),
+                (header::IF_RANGE, "\"strong\""),
+            ],
+            now,
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = conditional_range_response(
+            &[
+                (header::RANGE, "bytes=0-3"),
+                (header::IF_RANGE, &last_modified),
+            ],
+            now,
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(
+            response.headers().get(header::CONTENT_RANGE).unwrap(),
+            "bytes 0-3/10"
+        );
+
+        // modified in the same second as the response, the date is weak
+        let response = conditional_range_response(
+            &[
+                (header::RANGE, "bytes=0-3"),
+                (header::IF_RANGE, &last_modified),
+            ],
+            modified(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = conditional_range_response(
+            &[
+                (header::RANGE, "bytes=0-3"),
+                (
+                    header::IF_RANGE,
+                    &http_date_string(modified() - Duration::from_secs(1)),
+                ),
+            ],
+            now,
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // If-Modified-Since is evaluated before Range
+        let response = conditional_range_response(
+            &[
+                (header::RANGE, "bytes=0-3"),
+                (header::IF_MODIFIED_SINCE, &last_modified),
+            ],
+            now,
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+
+        let response = conditional_range_response(&[(header::RANGE, "bytes=20-30")], now).await;
+        assert_eq!(response.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+    }
+
+    #[tokio::test]
+    async fn test_not_modified() {
+        let now = modified() + Duration::from_secs(60);
+        let last_modified = http_date_string(modified());
+
+        let response = conditional_range_response(&[], now).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let etag = response
+            .headers()
+            .get(header::ETAG)
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_owned();
+        assert_eq!(
+            etag,
+            file_etag(10, Duration::from_secs(MODIFIED_UNIX_SECONDS))
+        );
+
+        let response = conditional_range_response(&[(header::IF_NONE_MATCH, &etag)], now).await;
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(response.headers().get(header::ETAG).unwrap(), etag.as_str());
+        assert_eq!(
+            response.headers().get(header::LAST_MODIFIED).unwrap(),
+            last_modified.as_str()
+        );
+
+        let strong_etag = etag.trim_start_matches("W/").to_owned();
+        let response = conditional_range_response(
+            &[(
+                header::IF_NONE_MATCH,
+                &format!("\"other\", {}", strong_etag),
+            )],
+            now,
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+
+        let response = conditional_range_response(&[(header::IF_NONE_MATCH, "*")], now).await;
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+
+        // If-None-Match takes precedence over If-Modified-Since
+        let response = conditional_range_response(
+            &[
+                (header::IF_NONE_MATCH, "\"other\""),
+                (header::IF_MODIFIED_SINCE, &last_modified),
+            ],
+            now,
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response =
+            conditional_range_response(&[(header::IF_MODIFIED_SINCE, &last_modified)], now).await;
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(response.headers().get(header::ETAG).unwrap(), etag.as_str());
+
+        let response = conditional_range_response(
+            &[(
+                header::IF_MODIFIED_SINCE,
+                &http_date_string(modified() - Duration::from_secs(1)),
+            )],
+            now,
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[test]
+    fn test_parse_byte_ranges() {
+        assert_eq!(
+            parse_byte_ranges("bytes=0-3, 8-, -2", 10),
+            Some(vec![(0, 3), (8, 9), (8, 9)])
+        );
+        assert_eq!(
+            parse_byte_ranges("bytes=5-100,20-30", 10),
+            Some(vec![(5, 9)])
+        );
+        assert_eq!(parse_byte_ranges("bytes=3-1", 10), None);
+        assert_eq!(parse_byte_ranges("items=0-1", 10), None);
+    }
+
+    #[test]
+    fn test_range_options() {
+        let mut range_options = RangeOptions::default();
+        assert_eq!(
+            range_options.apply("bytes=0-3,2-5", 10).as_deref(),
+            Some("bytes=0-3,2-5")
+        );
+
+        range_options.multi_range_policy = MultiRangePolicy::Coalesce;
+        assert_eq!(
+            range_options.apply("bytes=6-7,0-3,2-5,-1", 10).as_deref(),
+            Some("bytes=0-7,9-9")
+        );
+
+        range_options.multi_range_policy = MultiRangePolicy::Reject;
+        assert_eq!(range_options.apply("bytes=0-3,5-6", 10), None);
+        assert_eq!(
+            range_options.apply("bytes=0-3", 10).as_deref(),
+            Some("bytes=0-3")
+        );
+
+        range_options.multi_range_policy = MultiRangePolicy::Multipart;
+        range_options.max_ranges = Some(2);
+        assert_eq!(range_options.apply("bytes=0-1,3-4,6-7", 10), None);
+    }
+}
@@ -0,0 +1,58 @@
+// Shared by unit tests and the benches in benches/, not used by the server.
+
+use async_trait::async_trait;
+
+use hyper::http::{Method, Response, StatusCode};
+
+use std::path::PathBuf;
+
+use crate::{
+    handlers::{RequestHandler, RouteInfo, Router},
+    request::HttpRequest,
+    response::{build_status_code_response, static_string_response_body, ResponseBody},
+};
+
+pub use crate::{
+    config::{set_test_instance, StaticFileConfiguration},
+    request::QueryParams,
+    response::{build_json_response, format_json_body, CacheControl, JsonOutputOptions},
+    server::serve_duplex_connection,
+    static_file::{
+        create_rules_service, ChunkedFileOpener, StaticFileRulesService, DEFAULT_READ_CHUNK_SIZE,
+    },
+};
+
+pub struct HelloHandler;
+
+#[async_trait]
+impl RequestHandler for HelloHandler {
+    async fn handle(&self, _request: &HttpRequest) -> Response<ResponseBody> {
+        Response::new(static_string_response_body("hello"))
+    }
+}
+
+struct NotFoundHandler;
+
+#[async_trait]
+impl RequestHandler for NotFoundHandler {
+    async fn handle(&self, _request: &HttpRequest) -> Response<ResponseBody> {
+        build_status_code_response(StatusCode::NOT_FOUND, CacheControl::NoCache)
+    }
+}
+
+// GET routes route_0 to route_<num_routes - 1> under the dynamic route context
+// answering hello, other paths get 404.
+pub fn hello_router(num_routes: usize) -> anyhow::Result<Router> {
+    set_test_instance();
+
+    let routes = (0..num_routes)
+        .map(|i| RouteInfo {
+            method: &Method::GET,
+            path_suffix: PathBuf::from(format!("route_{}", i)),
+            handler: Box::new(HelloHandler),
+            api_doc: None,
+        })
+        .collect();
+
+    Router::new(routes, Box::new(NotFoundHandler))
+}
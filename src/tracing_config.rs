@@ -1,21 +1,159 @@
-use tracing_subscriber::{filter::LevelFilter, fmt, prelude::*, EnvFilter};
+mod syslog;
 
-pub fn initialize_tracing_subscriber() {
-    let env_filter = EnvFilter::builder()
-        .with_default_directive(LevelFilter::INFO.into())
-        .from_env_lossy();
+use anyhow::Context;
 
+use tracing_subscriber::{
+    filter::LevelFilter, fmt, prelude::*, reload, EnvFilter, Layer, Registry,
+};
+
+use std::sync::OnceLock;
+
+use crate::config::{LogFormat, LogOutput, LoggingConfiguration};
+
+pub use syslog::SyslogSender;
+
+type BoxedLayer = Box<dyn Layer<Registry> + Send + Sync>;
+
+static LOG_FILTER_HANDLE: OnceLock<reload::Handle<EnvFilter, Registry>> = OnceLock::new();
+
+fn env_filter_builder() -> tracing_subscriber::filter::Builder {
+    EnvFilter::builder().with_default_directive(LevelFilter::INFO.into())
+}
+
+fn env_filter() -> EnvFilter {
+    env_filter_builder().from_env_lossy()
+}
+
+// logging_configuration.filter if set, otherwise RUST_LOG.
+fn configured_filter(logging_configuration: &LoggingConfiguration) -> anyhow::Result<EnvFilter> {
+    match &logging_configuration.filter {
+        None => Ok(env_filter()),
+        Some(directives) => env_filter_builder()
+            .parse(directives)
+            .with_context(|| format!("invalid log filter '{}'", directives)),
+    }
+}
+
+fn env_log_format() -> LogFormat {
     let log_format_value = std::env::var("LOG_FORMAT").unwrap_or_else(|_| "dev".to_string());
 
     if log_format_value.eq_ignore_ascii_case("prod") {
-        tracing_subscriber::registry()
-            .with(env_filter)
-            .with(fmt::layer().with_ansi(false).without_time())
-            .init();
+        LogFormat::Prod
+    } else if log_format_value.eq_ignore_ascii_case("json") {
+        LogFormat::Json
     } else {
-        tracing_subscriber::registry()
-            .with(env_filter)
-            .with(fmt::layer())
-            .init();
+        LogFormat::Dev
+    }
+}
+
+fn stdout_layer(log_format: Option<LogFormat>) -> BoxedLayer {
+    match log_format.unwrap_or_else(env_log_format) {
+        LogFormat::Dev => fmt::layer().boxed(),
+        LogFormat::Prod => fmt::layer().with_ansi(false).without_time().boxed(),
+        // one object per line with span fields, for log collectors
+        LogFormat::Json => fmt::layer().json().with_current_span(true).boxed(),
+    }
+}
+
+fn output_layer(
+    output: LogOutput,
+    logging_configuration: &LoggingConfiguration,
+) -> anyhow::Result<BoxedLayer> {
+    let layer = match output {
+        LogOutput::Stdout => stdout_layer(logging_configuration.format),
+        LogOutput::Syslog => {
+            let syslog_configuration = logging_configuration
+                .syslog
+                .as_ref()
+                .context("SYSLOG output requires syslog configuration")?;
+
+            fmt::layer()
+                .with_ansi(false)
+                .without_time()
+                .with_level(false)
+                .fmt_fields(syslog::SyslogFields::default())
+                .with_writer(syslog::SyslogMakeWriter::new(syslog_configuration)?)
+                .boxed()
+        }
+        #[cfg(unix)]
+        LogOutput::Journald => tracing_journald::layer()
+            .context("error connecting to journald")?
+            .with_field_prefix(None)
+            .with_syslog_identifier(syslog::app_name())
+            .boxed(),
+        #[cfg(not(unix))]
+        LogOutput::Journald => {
+            anyhow::bail!("JOURNALD output is not supported on this platform")
+        }
     };
+
+    Ok(layer)
+}
+
+pub fn initialize_tracing_subscriber(
+    logging_configuration: &LoggingConfiguration,
+) -> anyhow::Result<()> {
+    let mut layers = Vec::with_capacity(logging_configuration.outputs.len());
+
+    for output in &logging_configuration.outputs {
+        layers.push(output_layer(*output, logging_configuration)?);
+    }
+
+    let (filter, filter_handle) = reload::Layer::new(configured_filter(logging_configuration)?);
+
+    tracing_subscriber::registry()
+        .with(layers.with_filter(filter))
+        .try_init()
+        .context("tracing subscriber try_init error")?;
+
+    let _ = LOG_FILTER_HANDLE.set(filter_handle);
+
+    Ok(())
+}
+
+// The current filter directives, initially from RUST_LOG.
+pub fn log_filter() -> anyhow::Result<String> {
+    let filter_handle = LOG_FILTER_HANDLE
+        .get()
+        .context("tracing subscriber not initialized")?;
+
+    filter_handle
+        .with_current(|filter| filter.to_string())
+        .context("log filter with_current error")
+}
+
+fn replace_log_filter(filter: EnvFilter) -> anyhow::Result<String> {
+    let filter_handle = LOG_FILTER_HANDLE
+        .get()
+        .context("tracing subscriber not initialized")?;
+
+    let filter_string = filter.to_string();
+
+    filter_handle
+        .reload(filter)
+        .context("log filter reload error")?;
+
+    Ok(filter_string)
+}
+
+// Replaces the filter with directives in RUST_LOG syntax, e.g. "debug" or
+// "info,rhs::handlers=trace".
+pub fn set_log_filter(directives: &str) -> anyhow::Result<String> {
+    replace_log_filter(
+        env_filter_builder()
+            .parse(directives)
+            .with_context(|| format!("invalid log filter '{}'", directives))?,
+    )
+}
+
+// Applies logging_configuration.filter after a configuration reload.
+pub fn reload_log_filter(logging_configuration: &LoggingConfiguration) -> anyhow::Result<String> {
+    replace_log_filter(configured_filter(logging_configuration)?)
+}
+
+// Used when configuration could not be read, ignores errors if a subscriber is already set.
+pub fn initialize_fallback_tracing_subscriber() {
+    let _ = tracing_subscriber::registry()
+        .with(stdout_layer(None).with_filter(env_filter()))
+        .try_init();
 }
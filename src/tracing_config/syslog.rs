@@ -0,0 +1,219 @@
+use anyhow::Context;
+
+use chrono::{SecondsFormat, Utc};
+
+use tracing::{Level, Metadata};
+
+use tracing_subscriber::{
+    field::RecordFields,
+    fmt::{
+        format::{DefaultFields, Writer},
+        FormatFields, MakeWriter,
+    },
+};
+
+use std::{
+    io::{self, Write},
+    net::UdpSocket,
+    sync::Arc,
+};
+
+#[cfg(unix)]
+use std::os::unix::net::UnixDatagram;
+
+use crate::config::{SyslogConfiguration, SyslogTransport};
+
+// Span fields are cached per formatter type, so a separate type keeps the
+// syslog layer from sharing (and appending to) the stdout layer's ansi fields.
+#[derive(Default)]
+pub struct SyslogFields(DefaultFields);
+
+impl<'writer> FormatFields<'writer> for SyslogFields {
+    fn format_fields<R: RecordFields>(
+        &self,
+        writer: Writer<'writer>,
+        fields: R,
+    ) -> std::fmt::Result {
+        self.0.format_fields(writer, fields)
+    }
+}
+
+pub fn app_name() -> String {
+    std::env::args()
+        .next()
+        .and_then(|arg| {
+            std::path::Path::new(&arg)
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+        })
+        .unwrap_or_else(|| "rhs".to_owned())
+}
+
+fn hostname() -> String {
+    std::fs::read_to_string("/proc/sys/kernel/hostname")
+        .ok()
+        .map(|hostname| hostname.trim().to_owned())
+        .filter(|hostname| !hostname.is_empty())
+        .unwrap_or_else(|| "-".to_owned())
+}
+
+// RFC 5424 section 6.2.1 severities.
+fn severity(level: &Level) -> u8 {
+    match *level {
+        Level::ERROR => 3,
+        Level::WARN => 4,
+        Level::INFO => 6,
+        Level::DEBUG | Level::TRACE => 7,
+    }
+}
+
+enum SyslogSocket {
+    Udp(UdpSocket),
+    #[cfg(unix)]
+    Unix(UnixDatagram),
+}
+
+impl SyslogSocket {
+    fn send(&self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Udp(socket) => socket.send(buf),
+            #[cfg(unix)]
+            Self::Unix(socket) => socket.send(buf),
+        }
+    }
+}
+
+// Sends RFC 5424 messages, one datagram each.  Also used by syslog access logs.
+pub struct SyslogSender {
+    socket: SyslogSocket,
+    facility: u8,
+    header_suffix: String,
+}
+
+impl SyslogSender {
+    pub fn new(syslog_configuration: &SyslogConfiguration) -> anyhow::Result<Self> {
+        let address = &syslog_configuration.address;
+
+        let socket = match syslog_configuration.transport {
+            SyslogTransport::Udp => {
+                let socket = UdpSocket::bind("[::]:0")
+                    .or_else(|_| UdpSocket::bind("0.0.0.0:0"))
+                    .context("syslog UdpSocket::bind error")?;
+                socket
+                    .connect(address)
+                    .with_context(|| format!("syslog udp connect error address = {:?}", address))?;
+                SyslogSocket::Udp(socket)
+            }
+            #[cfg(unix)]
+            SyslogTransport::Unix => {
+                let socket = UnixDatagram::unbound().context("syslog UnixDatagram error")?;
+                socket
+                    .connect(address)
+                    .with_context(|| format!("syslog unix connect error path = {:?}", address))?;
+                SyslogSocket::Unix(socket)
+            }
+            #[cfg(not(unix))]
+            SyslogTransport::Unix => {
+                anyhow::bail!("syslog UNIX transport is not supported on this platform")
+            }
+        };
+
+        // HOSTNAME APP-NAME PROCID MSGID STRUCTURED-DATA
+        let header_suffix = format!(
+            "{} {} {} - -",
+            hostname(),
+            syslog_configuration
+                .app_name
+                .clone()
+                .unwrap_or_else(app_name),
+            std::process::id(),
+        );
+
+        Ok(Self {
+            socket,
+            facility: syslog_configuration.facility,
+            header_suffix,
+        })
+    }
+
+    pub fn send(&self, level: &Level, message: &str) -> io::Result<()> {
+        let datagram = format!(
+            "<{}>1 {} {} {}",
+            u16::from(self.facility) * 8 + u16::from(severity(level)),
+            Utc::now().to_rfc3339_opts(SecondsFormat::Micros, true),
+            self.header_suffix,
+            message,
+        );
+
+        self.socket.send(datagram.as_bytes())?;
+
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+pub struct SyslogMakeWriter {
+    sender: Arc<SyslogSender>,
+}
+
+impl SyslogMakeWriter {
+    pub fn new(syslog_configuration: &SyslogConfiguration) -> anyhow::Result<Self> {
+        Ok(Self {
+            sender: Arc::new(SyslogSender::new(syslog_configuration)?),
+        })
+    }
+}
+
+pub struct SyslogWriter {
+    sender: Arc<SyslogSender>,
+    level: Level,
+    buf: Vec<u8>,
+}
+
+impl Write for SyslogWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// The fmt layer writes one event per writer, so each writer becomes one datagram.
+impl Drop for SyslogWriter {
+    fn drop(&mut self) {
+        let message = String::from_utf8_lossy(&self.buf);
+        let message = message.trim_end();
+
+        if message.is_empty() {
+            return;
+        }
+
+        // Nowhere to report logging failures, drop the message.
+        let _ = self.sender.send(&self.level, message);
+    }
+}
+
+impl SyslogMakeWriter {
+    fn writer(&self, level: &Level) -> SyslogWriter {
+        SyslogWriter {
+            sender: Arc::clone(&self.sender),
+            level: *level,
+            buf: Vec::with_capacity(256),
+        }
+    }
+}
+
+impl<'a> MakeWriter<'a> for SyslogMakeWriter {
+    type Writer = SyslogWriter;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self.writer(&Level::INFO)
+    }
+
+    fn make_writer_for(&'a self, meta: &Metadata<'_>) -> Self::Writer {
+        self.writer(meta.level())
+    }
+}
@@ -0,0 +1,221 @@
+use bytes::Bytes;
+
+use hyper::body::{Body, Frame, SizeHint};
+
+use tokio::{
+    sync::OnceCell,
+    time::{Duration, Instant},
+};
+
+use std::{
+    collections::{HashMap, VecDeque},
+    net::IpAddr,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll},
+};
+
+use crate::response::{ResponseBody, ResponseBodyError};
+
+pub const UNKNOWN_CLIENT: &str = "[unknown]";
+pub const DEFAULT_ROUTE: &str = "[default]";
+
+struct TrafficBucket {
+    start: Instant,
+    client_bytes: HashMap<Option<IpAddr>, u64>,
+    route_bytes: HashMap<Arc<str>, u64>,
+}
+
+impl TrafficBucket {
+    fn new(start: Instant) -> Self {
+        Self {
+            start,
+            client_bytes: HashMap::new(),
+            route_bytes: HashMap::new(),
+        }
+    }
+}
+
+pub struct TrafficStatsSnapshot {
+    pub window: Duration,
+    pub client_bytes: HashMap<Option<IpAddr>, u64>,
+    pub route_bytes: HashMap<Arc<str>, u64>,
+}
+
+// Response bytes per client ip and per route over a sliding window made of
+// fixed size buckets.  Expired buckets are dropped as new records arrive.
+pub struct TrafficStats {
+    window: Duration,
+    bucket_duration: Duration,
+    buckets: Mutex<VecDeque<TrafficBucket>>,
+    total_bytes: AtomicU64,
+    route_total_bytes: Mutex<HashMap<Arc<str>, u64>>,
+}
+
+impl TrafficStats {
+    fn new() -> Self {
+        let traffic_stats_configuration = &crate::config::instance().traffic_stats_configuration;
+
+        let window = traffic_stats_configuration.window;
+        let num_buckets = traffic_stats_configuration.num_buckets.max(1);
+
+        Self {
+            window,
+            bucket_duration: window / num_buckets,
+            buckets: Mutex::new(VecDeque::with_capacity(num_buckets as usize)),
+            total_bytes: AtomicU64::new(0),
+            route_total_bytes: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn expire_buckets(&self, buckets: &mut VecDeque<TrafficBucket>, now: Instant) {
+        while buckets
+            .front()
+            .is_some_and(|bucket| now.duration_since(bucket.start) >= self.window)
+        {
+            buckets.pop_front();
+        }
+    }
+
+    pub fn record(&self, client: Option<IpAddr>, route: Arc<str>, bytes: u64) {
+        self.total_bytes.fetch_add(bytes, Ordering::Relaxed);
+
+        *self
+            .route_total_bytes
+            .lock()
+            .unwrap()
+            .entry(Arc::clone(&route))
+            .or_default() += bytes;
+
+        let now = Instant::now();
+
+        let mut buckets = self.buckets.lock().unwrap();
+
+        self.expire_buckets(&mut buckets, now);
+
+        if buckets
+            .back()
+            .is_none_or(|bucket| now.duration_since(bucket.start) >= self.bucket_duration)
+        {
+            buckets.push_back(TrafficBucket::new(now));
+        }
+
+        let bucket = buckets.back_mut().unwrap();
+
+        *bucket.client_bytes.entry(client).or_default() += bytes;
+        *bucket.route_bytes.entry(route).or_default() += bytes;
+    }
+
+    pub fn snapshot(&self) -> TrafficStatsSnapshot {
+        let mut buckets = self.buckets.lock().unwrap();
+
+        self.expire_buckets(&mut buckets, Instant::now());
+
+        let mut snapshot = TrafficStatsSnapshot {
+            window: self.window,
+            client_bytes: HashMap::new(),
+            route_bytes: HashMap::new(),
+        };
+
+        for bucket in buckets.iter() {
+            for (client, bytes) in &bucket.client_bytes {
+                *snapshot.client_bytes.entry(*client).or_default() += bytes;
+            }
+            for (route, bytes) in &bucket.route_bytes {
+                *snapshot.route_bytes.entry(Arc::clone(route)).or_default() += bytes;
+            }
+        }
+
+        snapshot
+    }
+
+    // Response bytes since startup, not limited to the window.
+    pub fn total_bytes(&self) -> u64 {
+        self.total_bytes.load(Ordering::Relaxed)
+    }
+
+    // Response bytes per route since startup.
+    pub fn route_total_bytes(&self) -> Vec<(Arc<str>, u64)> {
+        let mut entries: Vec<_> = self
+            .route_total_bytes
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(route, bytes)| (Arc::clone(route), *bytes))
+            .collect();
+
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        entries
+    }
+
+    pub async fn instance() -> &'static Self {
+        static INSTANCE: OnceCell<TrafficStats> = OnceCell::const_new();
+
+        INSTANCE.get_or_init(|| async { Self::new() }).await
+    }
+}
+
+// Wraps a response body and records the bytes written when the body is dropped,
+// which covers both completed and aborted responses.
+pub struct TrafficCountingBody {
+    inner: ResponseBody,
+    traffic_stats: &'static TrafficStats,
+    client: Option<IpAddr>,
+    route: Arc<str>,
+    bytes: u64,
+}
+
+impl TrafficCountingBody {
+    pub fn new(
+        inner: ResponseBody,
+        traffic_stats: &'static TrafficStats,
+        client: Option<IpAddr>,
+        route: Arc<str>,
+    ) -> Self {
+        Self {
+            inner,
+            traffic_stats,
+            client,
+            route,
+            bytes: 0,
+        }
+    }
+}
+
+impl Body for TrafficCountingBody {
+    type Data = Bytes;
+    type Error = ResponseBodyError;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let poll = Pin::new(&mut self.inner).poll_frame(cx);
+
+        if let Poll::Ready(Some(Ok(frame))) = &poll {
+            if let Some(data) = frame.data_ref() {
+                self.bytes += data.len() as u64;
+            }
+        }
+
+        poll
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+impl Drop for TrafficCountingBody {
+    fn drop(&mut self) {
+        self.traffic_stats
+            .record(self.client, Arc::clone(&self.route), self.bytes);
+    }
+}
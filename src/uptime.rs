@@ -0,0 +1,27 @@
+use std::{
+    sync::LazyLock,
+    time::{Duration, Instant, SystemTime},
+};
+
+struct StartTime {
+    system_time: SystemTime,
+    instant: Instant,
+}
+
+static START_TIME: LazyLock<StartTime> = LazyLock::new(|| StartTime {
+    system_time: SystemTime::now(),
+    instant: Instant::now(),
+});
+
+// Call early in main so uptime covers configuration and startup.
+pub fn initialize() {
+    LazyLock::force(&START_TIME);
+}
+
+pub fn start_time() -> SystemTime {
+    START_TIME.system_time
+}
+
+pub fn uptime() -> Duration {
+    START_TIME.instant.elapsed()
+}
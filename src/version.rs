@@ -1,10 +1,8 @@
-use std::collections::BTreeMap;
-
-use tokio::sync::OnceCell;
+use std::{collections::BTreeMap, sync::LazyLock};
 
 pub type VersionInfoMap = BTreeMap<&'static str, &'static str>;
 
-async fn build_version_info_map() -> VersionInfoMap {
+fn build_version_info_map() -> VersionInfoMap {
     let mut map = VersionInfoMap::new();
 
     map.insert("build_timestamp", env!("VERGEN_BUILD_TIMESTAMP"));
@@ -17,6 +15,10 @@ async fn build_version_info_map() -> VersionInfoMap {
 
     map.insert("cargo_target_triple", env!("VERGEN_CARGO_TARGET_TRIPLE"));
 
+    map.insert("git_dirty", env!("VERGEN_GIT_DIRTY"));
+
+    map.insert("git_sha", env!("VERGEN_GIT_SHA"));
+
     map.insert("rustc_channel", env!("VERGEN_RUSTC_CHANNEL"));
 
     map.insert("rustc_semver", env!("VERGEN_RUSTC_SEMVER"));
@@ -24,8 +26,9 @@ async fn build_version_info_map() -> VersionInfoMap {
     map
 }
 
-pub async fn get_verison_info() -> &'static VersionInfoMap {
-    static INSTANCE: OnceCell<VersionInfoMap> = OnceCell::const_new();
+// Not async so the panic hook can use it.
+pub fn get_verison_info() -> &'static VersionInfoMap {
+    static INSTANCE: LazyLock<VersionInfoMap> = LazyLock::new(build_version_info_map);
 
-    INSTANCE.get_or_init(build_version_info_map).await
+    &INSTANCE
 }
@@ -0,0 +1,329 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+use sha1::{Digest, Sha1};
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadHalf, WriteHalf};
+
+// RFC 6455 section 1.3
+const ACCEPT_GUID: &[u8] = b"258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+pub const WEBSOCKET_VERSION: &str = "13";
+
+const OPCODE_CONTINUATION: u8 = 0x0;
+const OPCODE_TEXT: u8 = 0x1;
+const OPCODE_BINARY: u8 = 0x2;
+const OPCODE_CLOSE: u8 = 0x8;
+const OPCODE_PING: u8 = 0x9;
+const OPCODE_PONG: u8 = 0xa;
+
+const MAX_CONTROL_PAYLOAD_BYTES: u64 = 125;
+
+pub const CLOSE_NORMAL: u16 = 1000;
+const CLOSE_PROTOCOL_ERROR: u16 = 1002;
+const CLOSE_INVALID_DATA: u16 = 1007;
+const CLOSE_MESSAGE_TOO_BIG: u16 = 1009;
+
+// The Sec-WebSocket-Accept value for a client's Sec-WebSocket-Key.
+pub fn accept_key(key: &[u8]) -> String {
+    let mut hasher = Sha1::new();
+    hasher.update(key);
+    hasher.update(ACCEPT_GUID);
+
+    STANDARD.encode(hasher.finalize())
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Incoming {
+    Message(Message),
+    Ping(Vec<u8>),
+    Close(Option<u16>),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum WebSocketError {
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("protocol error: {0}")]
+    Protocol(&'static str),
+
+    #[error("message exceeds {0} bytes")]
+    MessageTooBig(usize),
+
+    #[error("text message is not valid UTF-8")]
+    InvalidUtf8,
+}
+
+impl WebSocketError {
+    // The close frame status to send before dropping the connection, None
+    // if the connection is already unusable.
+    pub fn close_code(&self) -> Option<u16> {
+        match self {
+            Self::Io(_) => None,
+            Self::Protocol(_) => Some(CLOSE_PROTOCOL_ERROR),
+            Self::MessageTooBig(_) => Some(CLOSE_MESSAGE_TOO_BIG),
+            Self::InvalidUtf8 => Some(CLOSE_INVALID_DATA),
+        }
+    }
+}
+
+struct Frame {
+    fin: bool,
+    opcode: u8,
+    payload: Vec<u8>,
+}
+
+// Server side of a connection, client frames must be masked.
+pub struct WebSocketReader<R> {
+    reader: R,
+    max_message_bytes: usize,
+    // a fragmented message in progress, control frames can arrive between fragments
+    fragments: Option<(u8, Vec<u8>)>,
+}
+
+impl<R: AsyncRead + Unpin> WebSocketReader<R> {
+    pub fn new(reader: R, max_message_bytes: usize) -> Self {
+        Self {
+            reader,
+            max_message_bytes,
+            fragments: None,
+        }
+    }
+
+    // Fragmented messages are reassembled and pongs are skipped.  Returns
+    // None when the client closes the stream without a close frame.
+    pub async fn next(&mut self) -> Result<Option<Incoming>, WebSocketError> {
+        loop {
+            let Some(frame) = self.read_frame().await? else {
+                return Ok(None);
+            };
+
+            match frame.opcode {
+                OPCODE_PING => return Ok(Some(Incoming::Ping(frame.payload))),
+                OPCODE_PONG => continue,
+                OPCODE_CLOSE => {
+                    let code = frame
+                        .payload
+                        .get(0..2)
+                        .map(|code| u16::from_be_bytes([code[0], code[1]]));
+                    return Ok(Some(Incoming::Close(code)));
+                }
+                OPCODE_CONTINUATION => {
+                    let Some((_, data)) = self.fragments.as_mut() else {
+                        return Err(WebSocketError::Protocol("unexpected continuation frame"));
+                    };
+
+                    if data.len() + frame.payload.len() > self.max_message_bytes {
+                        return Err(WebSocketError::MessageTooBig(self.max_message_bytes));
+                    }
+
+                    data.extend_from_slice(&frame.payload);
+                }
+                OPCODE_TEXT | OPCODE_BINARY => {
+                    if self.fragments.is_some() {
+                        return Err(WebSocketError::Protocol("expected continuation frame"));
+                    }
+
+                    self.fragments = Some((frame.opcode, frame.payload));
+                }
+                _ => return Err(WebSocketError::Protocol("unknown opcode")),
+            }
+
+            if frame.fin {
+                let Some((opcode, data)) = self.fragments.take() else {
+                    continue;
+                };
+
+                let message = if opcode == OPCODE_TEXT {
+                    Message::Text(String::from_utf8(data).map_err(|_| WebSocketError::InvalidUtf8)?)
+                } else {
+                    Message::Binary(data)
+                };
+
+                return Ok(Some(Incoming::Message(message)));
+            }
+        }
+    }
+
+    async fn read_frame(&mut self) -> Result<Option<Frame>, WebSocketError> {
+        let mut header = [0; 2];
+
+        match self.reader.read_exact(&mut header).await {
+            Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+            result => result?,
+        };
+
+        let fin = header[0] & 0x80 != 0;
+        let opcode = header[0] & 0x0f;
+
+        // no extensions are negotiated
+        if header[0] & 0x70 != 0 {
+            return Err(WebSocketError::Protocol("reserved bits set"));
+        }
+
+        if header[1] & 0x80 == 0 {
+            return Err(WebSocketError::Protocol("client frame not masked"));
+        }
+
+        let payload_length = match header[1] & 0x7f {
+            126 => u64::from(self.reader.read_u16().await?),
+            127 => self.reader.read_u64().await?,
+            payload_length => u64::from(payload_length),
+        };
+
+        if opcode >= OPCODE_CLOSE && (!fin || payload_length > MAX_CONTROL_PAYLOAD_BYTES) {
+            return Err(WebSocketError::Protocol("invalid control frame"));
+        }
+
+        if payload_length > self.max_message_bytes as u64 {
+            return Err(WebSocketError::MessageTooBig(self.max_message_bytes));
+        }
+
+        let mut mask = [0; 4];
+        self.reader.read_exact(&mut mask).await?;
+
+        let mut payload = vec![0; payload_length as usize];
+        self.reader.read_exact(&mut payload).await?;
+
+        for (i, byte) in payload.iter_mut().enumerate() {
+            *byte ^= mask[i % 4];
+        }
+
+        Ok(Some(Frame {
+            fin,
+            opcode,
+            payload,
+        }))
+    }
+}
+
+// Server frames are never masked or fragmented.
+pub struct WebSocketWriter<W> {
+    writer: W,
+}
+
+impl<W: AsyncWrite + Unpin> WebSocketWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+
+    pub async fn send(&mut self, message: &Message) -> std::io::Result<()> {
+        match message {
+            Message::Text(text) => self.write_frame(OPCODE_TEXT, text.as_bytes()).await,
+            Message::Binary(data) => self.write_frame(OPCODE_BINARY, data).await,
+        }
+    }
+
+    pub async fn pong(&mut self, payload: &[u8]) -> std::io::Result<()> {
+        self.write_frame(OPCODE_PONG, payload).await
+    }
+
+    pub async fn close(&mut self, code: Option<u16>) -> std::io::Result<()> {
+        let payload = code.map(u16::to_be_bytes);
+
+        self.write_frame(OPCODE_CLOSE, payload.as_ref().map_or(&[], |code| code))
+            .await?;
+
+        self.writer.shutdown().await
+    }
+
+    async fn write_frame(&mut self, opcode: u8, payload: &[u8]) -> std::io::Result<()> {
+        let mut header = Vec::with_capacity(10);
+        header.push(0x80 | opcode);
+
+        match payload.len() {
+            len if len < 126 => header.push(len as u8),
+            len if len <= usize::from(u16::MAX) => {
+                header.push(126);
+                header.extend_from_slice(&(len as u16).to_be_bytes());
+            }
+            len => {
+                header.push(127);
+                header.extend_from_slice(&(len as u64).to_be_bytes());
+            }
+        }
+
+        self.writer.write_all(&header).await?;
+        self.writer.write_all(payload).await?;
+        self.writer.flush().await
+    }
+}
+
+pub fn split<S: AsyncRead + AsyncWrite>(
+    stream: S,
+    max_message_bytes: usize,
+) -> (WebSocketReader<ReadHalf<S>>, WebSocketWriter<WriteHalf<S>>) {
+    let (reader, writer) = tokio::io::split(stream);
+
+    (
+        WebSocketReader::new(reader, max_message_bytes),
+        WebSocketWriter::new(writer),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn masked_frame(first_byte: u8, payload: &[u8]) -> Vec<u8> {
+        let mask = [1, 2, 3, 4];
+
+        let mut frame = vec![first_byte, 0x80 | payload.len() as u8];
+        frame.extend_from_slice(&mask);
+        frame.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+        frame
+    }
+
+    #[test]
+    fn test_accept_key() {
+        assert_eq!(
+            accept_key(b"dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    #[tokio::test]
+    async fn test_reader() {
+        let mut input = masked_frame(OPCODE_TEXT, b"hel");
+        input.extend(masked_frame(0x80 | OPCODE_PING, b"p"));
+        input.extend(masked_frame(0x80 | OPCODE_CONTINUATION, b"lo"));
+        input.extend(masked_frame(0x80 | OPCODE_BINARY, &[0xff; 20]));
+        input.extend(masked_frame(0x80 | OPCODE_CLOSE, &1000u16.to_be_bytes()));
+
+        let mut reader = WebSocketReader::new(input.as_slice(), 16);
+
+        assert_eq!(
+            reader.next().await.unwrap(),
+            Some(Incoming::Ping(b"p".to_vec()))
+        );
+        assert_eq!(
+            reader.next().await.unwrap(),
+            Some(Incoming::Message(Message::Text("hello".to_owned())))
+        );
+        assert!(matches!(
+            reader.next().await,
+            Err(WebSocketError::MessageTooBig(16))
+        ));
+
+        let input = [0x81, 0x01, b'a'];
+        let mut reader = WebSocketReader::new(input.as_slice(), 16);
+        assert!(matches!(
+            reader.next().await,
+            Err(WebSocketError::Protocol(_))
+        ));
+
+        let input = masked_frame(0x80 | OPCODE_CLOSE, &1001u16.to_be_bytes());
+        let mut reader = WebSocketReader::new(input.as_slice(), 16);
+        assert_eq!(
+            reader.next().await.unwrap(),
+            Some(Incoming::Close(Some(1001)))
+        );
+        assert_eq!(reader.next().await.unwrap(), None);
+    }
+}